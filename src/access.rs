@@ -0,0 +1,97 @@
+//! Access control, for embedding the database behind a multi-user service
+//!
+//! A `Role` grants permissions per table or view. Once a handle has a role,
+//! see `SrimDB::with_role`, its queries and deltas and those of its
+//! transactions and snapshots fail unless the role has the permissions
+//! they need. Views are checked by their own name, not by the tables they read.
+
+use {Delta, Query, TableName, QueryError, ApplyError};
+
+/// Kind of access to a table or view; none of them implies the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// Query the rows
+    Read,
+    /// Insert, update and remove rows
+    Write,
+    /// Create, drop, rename and alter, including indexes, triggers and
+    /// policies; on all tables, also create and drop functions and procedures
+    Schema,
+}
+
+/// Set of permissions granted to the users of a handle
+#[derive(Debug, Clone, Default)]
+pub struct Role {
+    /// Table or view each permission is granted on, none for all of them
+    grants: Vec<(Option<TableName>, Permission)>,
+}
+impl Role {
+    /// Role without permissions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grant `permission` on table or view `table`, which need not exist yet
+    pub fn grant(mut self, table: &str, permission: Permission) -> Self {
+        self.grants.push((Some(table.to_owned()), permission));
+        self
+    }
+
+    /// Grant `permission` on all tables and views, including those created later
+    pub fn grant_all(mut self, permission: Permission) -> Self {
+        self.grants.push((None, permission));
+        self
+    }
+
+    pub fn allows(&self, table: &str, permission: Permission) -> bool {
+        self.grants.iter().any(|(t, p)| *p == permission && t.as_ref().map_or(true, |t| t == table))
+    }
+
+    fn allows_all(&self, permission: Permission) -> bool {
+        self.grants.iter().any(|(t, p)| *p == permission && t.is_none())
+    }
+
+    /// Check that the role can read every table and view `query` reads
+    pub(crate) fn check_query(&self, query: &Query) -> Result<(), QueryError> {
+        match query.tables().into_iter().find(|t| !self.allows(t, Permission::Read)) {
+            Some(table) => Err(QueryError::AccessDenied(table.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_delta(&self, delta: &Delta) -> Result<(), ApplyError> {
+        use Delta::*;
+        let require = |name: &str, permission: Permission| if self.allows(name, permission) {
+            Ok(())
+        } else {
+            Err(ApplyError::AccessDenied(name.to_owned(), permission))
+        };
+        match delta {
+            Truncate(_)
+            | AddRow(_, _)
+            | AddNamedRow(_, _)
+            | AddRows(_, _)
+            | RemoveRow(_, _)
+            | UpdateRow(_, _, _)
+            | RemoveRowById(_, _)
+            | UpdateRowById(_, _, _)
+            | RemoveWhere(_, _)
+            | UpdateWhere(_, _, _)
+            | Upsert(_, _) => delta.tables().iter().map(|t| require(t, Permission::Write)).collect(),
+            CreateView(name, query) | CreateMaterializedView(name, query) => {
+                require(name, Permission::Schema)?;
+                query.tables().into_iter().map(|t| require(t, Permission::Read)).collect()
+            },
+            RefreshView(name) | DropView(name) => require(name, Permission::Schema),
+            CreateFunction(name, _)
+            | DropFunction(name)
+            | CreateProcedure(name, _)
+            | DropProcedure(name) => if self.allows_all(Permission::Schema) {
+                Ok(())
+            } else {
+                Err(ApplyError::AccessDenied(name.clone(), Permission::Schema))
+            },
+            _ => delta.tables().iter().map(|t| require(t, Permission::Schema)).collect(),
+        }
+    }
+}
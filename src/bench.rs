@@ -0,0 +1,207 @@
+//! Standard workloads for comparing configurations and versions of SrimDB
+//!
+//! Enabled with the `bench` feature.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use {SrimDB, Delta, Query, QueryField, Table, TableField, FieldKind, IntSize, Row, Value};
+use query::Condition;
+use function::{FunctionCall, Argument};
+
+/// Sizes of the generated workloads
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Rows in the main table
+    pub rows: usize,
+    /// Number of distinct values in the grouping column
+    pub groups: usize,
+    /// Point lookups to run
+    pub lookups: usize,
+    /// Filtered scans to run
+    pub scans: usize,
+    /// Rows in each side of the join workload
+    pub join_rows: usize,
+    /// Operations in the mixed workload, half of them writes
+    pub mixed_operations: usize,
+}
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            rows: 10_000,
+            groups: 100,
+            lookups: 100,
+            scans: 10,
+            join_rows: 200,
+            mixed_operations: 200,
+        }
+    }
+}
+
+/// Timing results of a single workload
+#[derive(Debug, Clone)]
+pub struct WorkloadReport {
+    pub name: &'static str,
+    pub total: Duration,
+    /// Latency of each operation, sorted ascending
+    pub latencies: Vec<Duration>,
+}
+impl WorkloadReport {
+    fn new(name: &'static str, total: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        Self { name, total, latencies }
+    }
+
+    pub fn operations(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Operations per second
+    pub fn throughput(&self) -> f64 {
+        self.operations() as f64 / duration_secs(self.total)
+    }
+
+    /// Latency at percentile `p` (0..=100)
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+        let index = ((p / 100.0) * (self.latencies.len() - 1) as f64).round() as usize;
+        self.latencies[index.min(self.latencies.len() - 1)]
+    }
+}
+impl fmt::Display for WorkloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<16} {:>8} ops {:>12.1} ops/s  p50 {:>10.1}us  p99 {:>10.1}us",
+            self.name,
+            self.operations(),
+            self.throughput(),
+            duration_secs(self.percentile(50.0)) * 1e6,
+            duration_secs(self.percentile(99.0)) * 1e6,
+        )
+    }
+}
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+fn timed<F: FnMut(usize)>(name: &'static str, count: usize, mut f: F) -> WorkloadReport {
+    let mut latencies = Vec::with_capacity(count);
+    let start = Instant::now();
+    for i in 0..count {
+        let op_start = Instant::now();
+        f(i);
+        latencies.push(op_start.elapsed());
+    }
+    WorkloadReport::new(name, start.elapsed(), latencies)
+}
+
+fn items_table(name: &str) -> Table {
+    Table::new(name, vec![
+        TableField::new("id".to_owned(),    FieldKind::Integer(IntSize::N64, false)),
+        TableField::new("group".to_owned(), FieldKind::Text),
+        TableField::new("value".to_owned(), FieldKind::Real),
+    ])
+}
+
+fn item_row(i: usize, groups: usize) -> Row {
+    Row::new(vec![
+        Value::Unsigned(i as u128),
+        Value::Text(format!("Group {}", i % groups.max(1)).into()),
+        Value::Real(i as f64 * 0.5),
+    ])
+}
+
+fn field_equals(field: QueryField, value: Value) -> Condition {
+    Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+        Argument::QueryField(field),
+        Argument::Value(value),
+    ]))
+}
+
+/// Insert `config.rows` rows into a new table "Items", one delta at a time
+pub fn bulk_load(config: &BenchConfig) -> (SrimDB, WorkloadReport) {
+    let mut db = SrimDB::new();
+    db.apply(Delta::CreateTable(items_table("Items"))).unwrap();
+
+    let report = timed("bulk_load", config.rows, |i| {
+        db.apply(Delta::AddRow("Items".to_owned(), item_row(i, config.groups))).unwrap();
+    });
+    (db, report)
+}
+
+/// Look up single rows of "Items" by id
+pub fn point_lookups(db: &SrimDB, config: &BenchConfig) -> WorkloadReport {
+    let rows = config.rows.max(1);
+    timed("point_lookups", config.lookups, |i| {
+        let id = (i * 7919) % rows;
+        db.query(Query::Filter(
+            field_equals(QueryField::new("id".to_owned()), Value::Unsigned(id as u128)),
+            Box::new(Query::Table("Items".to_owned()))
+        )).unwrap();
+    })
+}
+
+/// Scan "Items" for all rows of a single group
+pub fn filtered_scans(db: &SrimDB, config: &BenchConfig) -> WorkloadReport {
+    timed("filtered_scans", config.scans, |i| {
+        let group = format!("Group {}", i % config.groups.max(1));
+        db.query(Query::Project(
+            vec![QueryField::new("value".to_owned())],
+            Box::new(Query::Filter(
+                field_equals(QueryField::new("group".to_owned()), Value::Text(group.into())),
+                Box::new(Query::Table("Items".to_owned()))
+            ))
+        )).unwrap();
+    })
+}
+
+/// Equi-join two tables of `config.join_rows` rows on their id columns
+pub fn joins(config: &BenchConfig) -> WorkloadReport {
+    let mut db = SrimDB::new();
+    db.apply(Delta::CreateTable(items_table("Left"))).unwrap();
+    db.apply(Delta::CreateTable(items_table("Right"))).unwrap();
+    for i in 0..config.join_rows {
+        db.apply(Delta::AddRow("Left".to_owned(), item_row(i, config.groups))).unwrap();
+        db.apply(Delta::AddRow("Right".to_owned(), item_row(i, config.groups))).unwrap();
+    }
+
+    timed("joins", config.scans, |_| {
+        db.query(Query::JoinOn(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new("id".to_owned()).from_table("Left".to_owned())),
+                Argument::QueryField(QueryField::new("id".to_owned()).from_table("Right".to_owned())),
+            ])),
+            Box::new(Query::Table("Left".to_owned())),
+            Box::new(Query::Table("Right".to_owned()))
+        )).unwrap();
+    })
+}
+
+/// Alternate inserts into and point lookups from "Items"
+pub fn mixed_read_write(db: &mut SrimDB, config: &BenchConfig) -> WorkloadReport {
+    let base = config.rows;
+    timed("mixed", config.mixed_operations, |i| {
+        if i % 2 == 0 {
+            db.apply(Delta::AddRow("Items".to_owned(), item_row(base + i, config.groups))).unwrap();
+        }
+        else {
+            db.query(Query::Filter(
+                field_equals(QueryField::new("id".to_owned()), Value::Unsigned((base + i - 1) as u128)),
+                Box::new(Query::Table("Items".to_owned()))
+            )).unwrap();
+        }
+    })
+}
+
+/// Run every standard workload and return their reports in order
+pub fn run_all(config: &BenchConfig) -> Vec<WorkloadReport> {
+    let (mut db, load) = bulk_load(config);
+    let mut reports = vec![load];
+    reports.push(point_lookups(&db, config));
+    reports.push(filtered_scans(&db, config));
+    reports.push(joins(config));
+    reports.push(mixed_read_write(&mut db, config));
+    reports
+}
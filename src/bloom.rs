@@ -0,0 +1,75 @@
+//! Bloom filters over rows, for skipping rows that can't equal any row of another input
+//!
+//! A filter never misses a row it was built over, but may report rows it wasn't.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use {Row, Value};
+use codec::Encode;
+
+/// Inputs with fewer rows are compared without a filter, as building one costs more than it saves
+pub const MIN_ROWS: usize = 256;
+
+const BITS_PER_ROW: usize = 10;
+const HASHES: u64 = 7;
+
+/// Encoded values, none if equal values may have different encodings
+///
+/// Values containing floating-point numbers may be equal despite different encodings.
+fn key(values: &[Value]) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+    for value in values {
+        match value {
+            Value::Array(_) | Value::Vector(_) | Value::GeoPoint(_, _) => return None,
+            Value::Real(r) if *r == 0.0 => Value::Real(0.0).encode(&mut key),
+            Value::Real(r) if r.is_nan() => Value::Real(::std::f64::NAN).encode(&mut key),
+            value => value.encode(&mut key),
+        }
+    }
+    Some(key)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+}
+impl BloomFilter {
+    /// Filter over the values of the rows at `columns`, none if any of them can't be hashed
+    pub(crate) fn over<'a, I>(rows: I, columns: &[usize]) -> Option<Self>
+        where I: ExactSizeIterator<Item = &'a Row>
+    {
+        let mut filter = BloomFilter { bits: vec![0; (rows.len() * BITS_PER_ROW / 64).max(1)] };
+        for row in rows {
+            let values: Vec<Value> = columns.iter().map(|&i| row.value(i).clone()).collect();
+            filter.insert(&key(&values)?);
+        }
+        Some(filter)
+    }
+
+    /// Positions of the bits for the key, by double hashing
+    fn positions(&self, key: &[u8]) -> Vec<usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        1u8.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize).collect()
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        for position in self.positions(key) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    /// Whether the values of the row at `columns` may equal those of a row the filter is over
+    pub(crate) fn may_contain(&self, row: &Row, columns: &[usize]) -> bool {
+        let values: Vec<Value> = columns.iter().map(|&i| row.value(i).clone()).collect();
+        match key(&values) {
+            Some(key) => self.positions(&key).into_iter().all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0),
+            None => true,
+        }
+    }
+}
@@ -1,10 +1,30 @@
 // use reduce::Reduce;
 
+use std::cmp::Ordering;
+use std::iter;
+
 use Value;
+use Row;
 use QueryError;
-use function::NativeFunction;
+use TypeError;
+use function::{NativeFunction, TableFunction, Signature};
+use value::{ValueKind, ArithmeticMode, CastMode};
+use uuid;
+use temporal::{self, Unit};
+use geo;
+use vector;
+use fulltext;
+use pattern;
+use collation::Collation;
+use digest;
+use random;
+#[cfg(feature = "regex")]
+use regex_functions;
 
 fn f_strict_eq(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.iter().any(|v| v.is_null()) {
+        return Ok(Value::Null);
+    }
     if values.len() < 2 {
         return Ok(Value::Boolean(true));
     }
@@ -19,20 +39,895 @@ fn f_strict_eq(values: Vec<Value>) -> Result<Value, QueryError> {
     Ok(Value::Boolean(true))
 }
 
-fn f_add(values: Vec<Value>) -> Result<Value, QueryError> {
+fn f_add(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
     if values.len() < 1 {
         return Err(QueryError::NotEnoughArguments(1));
     }
 
     let mut acc = values[0].clone();
     for value in values.iter().skip(1) {
-        acc = acc.binop_add(value.clone())?;
+        acc = acc.binop_add_in(value.clone(), mode)?;
     }
     Ok(acc)
 }
 
+fn f_mul(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+
+    let mut acc = values[0].clone();
+    for value in values.iter().skip(1) {
+        acc = acc.binop_mul_in(value.clone(), mode)?;
+    }
+    Ok(acc)
+}
+
+/// First two arguments, for binary operators
+fn operands(values: Vec<Value>) -> Result<(Value, Value), QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    let mut values = values.into_iter();
+    Ok((values.next().unwrap(), values.next().unwrap()))
+}
+
+fn f_sub(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    let (a, b) = operands(values)?;
+    a.binop_sub_in(b, mode)
+}
+
+fn f_div(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    let (a, b) = operands(values)?;
+    a.binop_div_in(b, mode)
+}
+
+fn f_mod(values: Vec<Value>) -> Result<Value, QueryError> {
+    let (a, b) = operands(values)?;
+    a.binop_mod(b)
+}
+
+fn f_pow(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    let (a, b) = operands(values)?;
+    a.binop_pow_in(b, mode)
+}
+
+fn f_neg(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    values[0].negate_in(mode)
+}
+
+fn f_abs(values: Vec<Value>, mode: ArithmeticMode) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    values[0].abs_in(mode)
+}
+
+/// Result of a floating-point function of the first argument widened to a real
+/// number, null if it is null
+fn map_real<F: Fn(f64) -> f64>(values: &[Value], f: F) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    match values[0] {
+        Value::Null => Ok(Value::Null),
+        Value::Unsigned(_) | Value::Signed(_) | Value::Real(_) | Value::Decimal(..) => match values[0].cast_to(ValueKind::Real)? {
+            Value::Real(x) => Ok(Value::Real(f(x))),
+            _ => unreachable!(),
+        },
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// The first argument cast to the kind with `Value::cast_to_in`, lossily
+/// if the second argument is true
+fn cast(values: Vec<Value>, kind: ValueKind) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    let mode = match values.get(1) {
+        Some(Value::Boolean(true)) => CastMode::Lossy,
+        Some(Value::Boolean(false)) | Some(Value::Null) | None => CastMode::Checked,
+        Some(_) => return Err(QueryError::TypeError(TypeError::NotBoolean)),
+    };
+    values[0].cast_to_in(kind, mode)
+}
+
+fn f_to_int(values: Vec<Value>) -> Result<Value, QueryError> {
+    cast(values, ValueKind::Signed)
+}
+
+fn f_to_unsigned(values: Vec<Value>) -> Result<Value, QueryError> {
+    cast(values, ValueKind::Unsigned)
+}
+
+fn f_to_real(values: Vec<Value>) -> Result<Value, QueryError> {
+    cast(values, ValueKind::Real)
+}
+
+fn f_to_text(values: Vec<Value>) -> Result<Value, QueryError> {
+    cast(values, ValueKind::Text)
+}
+
+fn f_to_blob(values: Vec<Value>) -> Result<Value, QueryError> {
+    cast(values, ValueKind::Blob)
+}
+
+/// Square root, NaN for negative numbers
+fn f_sqrt(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::sqrt)
+}
+
+fn f_floor(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::floor)
+}
+
+fn f_ceil(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::ceil)
+}
+
+/// Nearest integer, rounding half-way cases away from zero
+fn f_round(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::round)
+}
+
+/// Natural logarithm, NaN for negative numbers and negative infinity for zero
+fn f_ln(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::ln)
+}
+
+fn f_exp(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_real(&values, f64::exp)
+}
+
+/// Order of the first two arguments, none if either is null
+fn order_arguments(values: &[Value]) -> Result<Option<Ordering>, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    values[0].compare(&values[1])
+}
+
+fn f_lt(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(order_arguments(&values)?.map_or(Value::Null, |order| Value::Boolean(order == Ordering::Less)))
+}
+
+fn f_gt(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(order_arguments(&values)?.map_or(Value::Null, |order| Value::Boolean(order == Ordering::Greater)))
+}
+
+fn f_lte(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(order_arguments(&values)?.map_or(Value::Null, |order| Value::Boolean(order != Ordering::Greater)))
+}
+
+fn f_gte(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(order_arguments(&values)?.map_or(Value::Null, |order| Value::Boolean(order != Ordering::Less)))
+}
+
+/// Whether the first two arguments differ, after widening them to a common kind
+///
+/// Values of kinds without an order, like points, are compared strictly.
+fn f_neq(values: Vec<Value>) -> Result<Value, QueryError> {
+    match order_arguments(&values) {
+        Ok(order) => Ok(order.map_or(Value::Null, |order| Value::Boolean(order != Ordering::Equal))),
+        Err(QueryError::IncompatibleTypes) if values[0].kind() == values[1].kind() => Ok(Value::Boolean(values[0] != values[1])),
+        Err(error) => Err(error),
+    }
+}
+
+/// First argument that isn't null, null if all are
+fn f_coalesce(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    Ok(values.into_iter().find(|v| !v.is_null()).unwrap_or(Value::Null))
+}
+
+/// The first argument, or the second if the first is null
+fn f_ifnull(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    f_coalesce(values.into_iter().take(2).collect())
+}
+
+/// Whether a condition argument holds, false if it is null
+pub(crate) fn holds(value: &Value) -> Result<bool, QueryError> {
+    Ok(boolean_argument(value)?.unwrap_or(false))
+}
+
+/// Position of the value chosen by `case` with the arguments,
+/// none if no condition holds and there is no else value
+///
+/// `condition` gives the value of the argument at a position, so that
+/// arguments can be evaluated only as far as needed.
+pub(crate) fn case_branch<F: FnMut(usize) -> Result<Value, QueryError>>(arguments: usize, mut condition: F) -> Result<Option<usize>, QueryError> {
+    if arguments < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    for i in (0..arguments - 1).step_by(2) {
+        if holds(&condition(i)?)? {
+            return Ok(Some(i + 1));
+        }
+    }
+    Ok(if arguments % 2 == 1 { Some(arguments - 1) } else { None })
+}
+
+/// The second argument if the first holds, otherwise the third
+fn f_iif(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    f_case(values.into_iter().take(3).collect())
+}
+
+/// The value after the first condition that holds, in arguments alternating
+/// between conditions and values, otherwise the else value after the last
+/// pair, or null without one
+fn f_case(values: Vec<Value>) -> Result<Value, QueryError> {
+    let branch = case_branch(values.len(), |i| Ok(values[i].clone()))?;
+    Ok(branch.map_or(Value::Null, |i| values[i].clone()))
+}
+
+/// Truth value of a boolean argument, none if it is null
+fn boolean_argument(value: &Value) -> Result<Option<bool>, QueryError> {
+    match value {
+        Value::Boolean(b) => Ok(Some(*b)),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::TypeError(TypeError::NotBoolean)),
+    }
+}
+
+/// Whether all arguments are true
+///
+/// Like in SQL, the result is false if any argument is false, even if
+/// others are unknown.
+fn f_and(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    let mut result = Some(true);
+    for value in &values {
+        match boolean_argument(value)? {
+            Some(false) => result = Some(false),
+            None if result == Some(true) => result = None,
+            _ => {},
+        }
+    }
+    Ok(result.map_or(Value::Null, Value::Boolean))
+}
+
+/// Whether any argument is true
+///
+/// Like in SQL, the result is true if any argument is true, even if
+/// others are unknown.
+fn f_or(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    let mut result = Some(false);
+    for value in &values {
+        match boolean_argument(value)? {
+            Some(true) => result = Some(true),
+            None if result == Some(false) => result = None,
+            _ => {},
+        }
+    }
+    Ok(result.map_or(Value::Null, Value::Boolean))
+}
+
+fn f_not(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    Ok(boolean_argument(&values[0])?.map_or(Value::Null, |b| Value::Boolean(!b)))
+}
+
+/// Whether an odd number of arguments are true, null if any is null
+fn f_xor(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    let mut result = Some(false);
+    for value in &values {
+        let b = boolean_argument(value)?;
+        result = result.and_then(|r| b.map(|b| r != b));
+    }
+    Ok(result.map_or(Value::Null, Value::Boolean))
+}
+
+/// Whether the first argument is at least the second and at most the third
+///
+/// Like in SQL, the result is false if either bound is known not to hold,
+/// even if the other one is unknown.
+fn f_between(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    let above = values[0].compare(&values[1])?.map(|order| order != Ordering::Less);
+    let below = values[0].compare(&values[2])?.map(|order| order != Ordering::Greater);
+    match (above, below) {
+        (Some(false), _) | (_, Some(false)) => Ok(Value::Boolean(false)),
+        (Some(true), Some(true)) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Elements of an array argument, none if it is null
+fn array_argument(value: &Value) -> Result<Option<&[Value]>, QueryError> {
+    match value {
+        Value::Array(values) => Ok(Some(values)),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::TypeError(TypeError::NotArray)),
+    }
+}
+
+/// Whether the array has an element strictly equal to the value
+fn f_array_contains(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match array_argument(&values[0])? {
+        Some(_) if values[1].is_null() => Ok(Value::Null),
+        Some(elements) => Ok(Value::Boolean(elements.contains(&values[1]))),
+        None => Ok(Value::Null),
+    }
+}
+
+fn f_array_length(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    Ok(array_argument(&values[0])?.map_or(Value::Null, |elements| Value::Unsigned(elements.len() as u128)))
+}
+
+/// Element at a zero-based index, null if out of bounds
+fn f_array_get(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    let elements = match array_argument(&values[0])? {
+        Some(elements) => elements,
+        None => return Ok(Value::Null),
+    };
+    let index = match values[1] {
+        Value::Unsigned(i) => i,
+        Value::Signed(i) if i >= 0 => i as u128,
+        Value::Signed(_) | Value::Null => return Ok(Value::Null),
+        _ => return Err(QueryError::IncompatibleTypes),
+    };
+    if index >= elements.len() as u128 {
+        return Ok(Value::Null);
+    }
+    Ok(elements[index as usize].clone())
+}
 
-pub const FUNCTIONS: [(&'static str, NativeFunction); 2] = [
-    ("strict_eq", NativeFunction::new(&f_strict_eq)),
-    ("add", NativeFunction::new(&f_add)),
-];
+/// Latitude and longitude of a point argument, none if it is null
+fn point_argument(value: &Value) -> Result<Option<(f64, f64)>, QueryError> {
+    match value {
+        Value::GeoPoint(lat, lon) => Ok(Some((*lat, *lon))),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// Great-circle distance between two points in meters
+fn f_distance_m(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (point_argument(&values[0])?, point_argument(&values[1])?) {
+        (Some(a), Some(b)) => Ok(Value::Real(geo::distance_m(a, b))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Whether a point is at most the given number of meters from a center point
+fn f_within_radius(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    let radius = match values[2].cast_to(ValueKind::Real)? {
+        Value::Real(radius) => radius,
+        _ => return Ok(Value::Null),
+    };
+    match (point_argument(&values[0])?, point_argument(&values[1])?) {
+        (Some(point), Some(center)) => Ok(Value::Boolean(geo::distance_m(point, center) <= radius)),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Whether a point is in the box with the given south-west and north-east corner points
+fn f_within_bbox(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    match (point_argument(&values[0])?, point_argument(&values[1])?, point_argument(&values[2])?) {
+        (Some(point), Some(south_west), Some(north_east)) => Ok(Value::Boolean(geo::in_box(point, south_west, north_east))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Elements of two vector arguments, none if either is null
+fn vector_arguments(values: &[Value]) -> Result<Option<(&[f32], &[f32])>, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (&values[0], &values[1]) {
+        (Value::Vector(a), Value::Vector(b)) if a.len() == b.len() => Ok(Some((a, b))),
+        (Value::Null, _) | (_, Value::Null) => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+fn f_dot_product(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(vector_arguments(&values)?
+        .and_then(|(a, b)| vector::dot_product(a, b))
+        .map_or(Value::Null, Value::Real))
+}
+
+/// Cosine similarity of two vectors, null if either is zero
+fn f_cosine_similarity(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(vector_arguments(&values)?
+        .and_then(|(a, b)| vector::cosine_similarity(a, b))
+        .map_or(Value::Null, Value::Real))
+}
+
+/// Text of a text argument, none if it is null
+fn text_argument(value: &Value) -> Result<Option<&str>, QueryError> {
+    match value {
+        Value::Text(text) => Ok(Some(text)),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// Value of an integer argument, none if it is null
+fn integer_argument(value: &Value) -> Result<Option<i128>, QueryError> {
+    match value {
+        Value::Unsigned(v) if *v <= i128::max_value() as u128 => Ok(Some(*v as i128)),
+        Value::Unsigned(_) => Err(QueryError::OutOfRange),
+        Value::Signed(v) => Ok(Some(*v)),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// Result of a function of a single text argument, null if it is null
+fn map_text<F: Fn(&str) -> Value>(values: &[Value], f: F) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    Ok(text_argument(&values[0])?.map_or(Value::Null, f))
+}
+
+fn f_upper(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_text(&values, |text| Value::Text(text.to_uppercase().into()))
+}
+
+fn f_lower(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_text(&values, |text| Value::Text(text.to_lowercase().into()))
+}
+
+/// Number of characters, which are Unicode scalar values
+fn f_length(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_text(&values, |text| Value::Unsigned(text.chars().count() as u128))
+}
+
+/// Number of bytes of a blob
+fn f_blob_length(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_bytes(&values, |bytes| Value::Unsigned(bytes.len() as u128))
+}
+
+/// Text without leading and trailing whitespace
+fn f_trim(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_text(&values, |text| Value::Text(text.trim().into()))
+}
+
+/// Characters from a one-based start position, up to an optional count
+///
+/// Like in SQL, positions before the first character count towards the count.
+fn f_substr(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    let text = text_argument(&values[0])?;
+    let start = integer_argument(&values[1])?;
+    let count = match values.get(2) {
+        Some(count) => match integer_argument(count)? {
+            Some(count) if count < 0 => return Err(QueryError::OutOfRange),
+            Some(count) => Some(count),
+            None => return Ok(Value::Null),
+        },
+        None => None,
+    };
+    let (text, start) = match (text, start) {
+        (Some(text), Some(start)) => (text, start),
+        _ => return Ok(Value::Null),
+    };
+    let end = count.map(|count| start.saturating_add(count));
+    let skip = (start.max(1) - 1) as usize;
+    let take = end.map_or(usize::max_value(), |end| (end.max(1) - 1) as usize).saturating_sub(skip);
+    Ok(Value::Text(text.chars().skip(skip).take(take).collect::<String>().into()))
+}
+
+/// Text with every occurrence of a non-empty text replaced with another
+fn f_replace(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    match (text_argument(&values[0])?, text_argument(&values[1])?, text_argument(&values[2])?) {
+        (Some(text), Some(""), Some(_)) => Ok(Value::Text(text.into())),
+        (Some(text), Some(from), Some(to)) => Ok(Value::Text(text.replace(from, to).into())),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Texts joined together, null if any is null
+fn f_concat(values: Vec<Value>) -> Result<Value, QueryError> {
+    let mut result = String::new();
+    for value in &values {
+        match text_argument(value)? {
+            Some(text) => result.push_str(text),
+            None => return Ok(Value::Null),
+        }
+    }
+    Ok(Value::Text(result.into()))
+}
+
+/// Bytes of a blob argument, or of the UTF-8 encoding of a text argument, none if it is null
+fn bytes_argument(value: &Value) -> Result<Option<&[u8]>, QueryError> {
+    match value {
+        Value::Blob(bytes) => Ok(Some(bytes)),
+        Value::Text(text) => Ok(Some(text.as_bytes())),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// Result of a function of the bytes of the first argument, null if it is null
+fn map_bytes<F: Fn(&[u8]) -> Value>(values: &[Value], f: F) -> Result<Value, QueryError> {
+    if values.len() < 1 {
+        return Err(QueryError::NotEnoughArguments(1));
+    }
+    Ok(bytes_argument(&values[0])?.map_or(Value::Null, f))
+}
+
+/// SHA-256 digest as a blob
+fn f_sha256(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_bytes(&values, |bytes| Value::Blob(digest::sha256(bytes).to_vec()))
+}
+
+/// MD5 digest as a blob
+fn f_md5(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_bytes(&values, |bytes| Value::Blob(digest::md5(bytes).to_vec()))
+}
+
+fn f_crc32(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_bytes(&values, |bytes| Value::Unsigned(u128::from(digest::crc32(bytes))))
+}
+
+/// Lowercase hexadecimal text of the bytes
+fn f_hex(values: Vec<Value>) -> Result<Value, QueryError> {
+    map_bytes(&values, |bytes| Value::Text(digest::hex(bytes).into()))
+}
+
+/// Whether the text matches the SQL `LIKE` pattern, see `pattern::like`
+fn f_like(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (text_argument(&values[0])?, text_argument(&values[1])?) {
+        (Some(text), Some(p)) => Ok(Value::Boolean(pattern::like(text, p))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Key of the text in the named collation, which compares like the text does
+/// in it, see `Collation::key`
+fn f_collate(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    let collation = match text_argument(&values[1])? {
+        Some(name) => Collation::from_name(name).ok_or_else(|| QueryError::NoSuchCollation(name.to_owned()))?,
+        None => return Ok(Value::Null),
+    };
+    Ok(collation.key_value(&values[0]).into_owned())
+}
+
+/// Whether the text matches the glob pattern, see `pattern::glob`
+fn f_glob(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (text_argument(&values[0])?, text_argument(&values[1])?) {
+        (Some(text), Some(p)) => Ok(Value::Boolean(pattern::glob(text, p))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Whether the text has all words of the query, see `fulltext::matches`
+fn f_matches(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (text_argument(&values[0])?, text_argument(&values[1])?) {
+        (Some(text), Some(query)) => Ok(Value::Boolean(fulltext::matches(text, query))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Unit named by a text argument, none if it is null
+fn unit_argument(value: &Value) -> Result<Option<Unit>, QueryError> {
+    match text_argument(value)? {
+        Some(name) => Unit::parse(name).map(Some).ok_or(QueryError::IncompatibleTypes),
+        None => Ok(None),
+    }
+}
+
+/// Timestamp of a date or timestamp argument, and whether it is a date, none if it is null
+///
+/// Fails with `QueryError::OutOfRange` for dates beyond the range of timestamps.
+fn temporal_argument(value: &Value) -> Result<Option<(i64, bool)>, QueryError> {
+    match value {
+        Value::Date(days) => temporal::timestamp(*days, 0).map(|micros| Some((micros, true))).ok_or(QueryError::OutOfRange),
+        Value::Timestamp(micros) => Ok(Some((*micros, false))),
+        Value::Null => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// A date if the unit is at least a day and the argument was a date, otherwise a timestamp
+fn temporal_result(timestamp: i64, date: bool, unit: Unit) -> Value {
+    if date && unit.micros().map_or(true, |micros| micros % temporal::MICROS_PER_DAY == 0) {
+        Value::Date(temporal::split_timestamp(timestamp).0)
+    }
+    else {
+        Value::Timestamp(timestamp)
+    }
+}
+
+/// Current time as a timestamp, different on every call
+fn f_now(_values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(Value::Timestamp(temporal::now()))
+}
+
+/// The date or timestamp the amount of units later, see `temporal::add`
+fn f_date_add(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    match (unit_argument(&values[0])?, integer_argument(&values[1])?, temporal_argument(&values[2])?) {
+        (Some(unit), Some(amount), Some((timestamp, date))) => {
+            let amount = if amount.abs() <= i128::from(i64::max_value()) { amount as i64 } else { return Err(QueryError::OutOfRange) };
+            let result = temporal::add(timestamp, amount, unit).ok_or(QueryError::OutOfRange)?;
+            Ok(temporal_result(result, date, unit))
+        },
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Number of whole units from the start to the end, see `temporal::diff`
+fn f_date_diff(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 3 {
+        return Err(QueryError::NotEnoughArguments(3));
+    }
+    match (unit_argument(&values[0])?, temporal_argument(&values[1])?, temporal_argument(&values[2])?) {
+        (Some(unit), Some((start, _)), Some((end, _))) => Ok(Value::Signed(i128::from(temporal::diff(start, end, unit)))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Start of the unit the date or timestamp is in, for bucketing
+fn f_date_trunc(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (unit_argument(&values[0])?, temporal_argument(&values[1])?) {
+        (Some(unit), Some((timestamp, date))) => {
+            let start = temporal::truncate(timestamp, unit).ok_or(QueryError::OutOfRange)?;
+            Ok(temporal_result(start, date, unit))
+        },
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Date or timestamp formatted with a format, see `temporal::format`
+fn f_strftime(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (text_argument(&values[0])?, temporal_argument(&values[1])?) {
+        (Some(format), Some((timestamp, _))) => temporal::format(timestamp, format)
+            .map(|text| Value::Text(text.into()))
+            .ok_or_else(|| QueryError::InvalidPattern(format!("Unknown specifier in '{}'", format))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Timestamp parsed from text laid out as a format, see `temporal::parse`
+fn f_strptime(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (text_argument(&values[0])?, text_argument(&values[1])?) {
+        (Some(text), Some(format)) => temporal::parse(text, format).map(Value::Timestamp).ok_or(QueryError::IncompatibleTypes),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// New random UUID on every call
+///
+/// Undo can't revert inserts whose defaults or triggers call this, since it
+/// computes the inserted row in advance.
+fn f_gen_uuid_v4(_values: Vec<Value>) -> Result<Value, QueryError> {
+    let mut random = [0; 16];
+    random[..8].copy_from_slice(&random::next_u64().to_le_bytes());
+    random[8..].copy_from_slice(&random::next_u64().to_le_bytes());
+    Ok(Value::Uuid(uuid::v4(random)))
+}
+
+/// Random real in `[0, 1)`, different on every call
+fn f_random(_values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(Value::Real(random::next_real()))
+}
+
+/// Random integer between the bounds, inclusive, different on every call
+fn f_random_int(values: Vec<Value>) -> Result<Value, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (integer_argument(&values[0])?, integer_argument(&values[1])?) {
+        (Some(low), Some(high)) if low <= high => Ok(Value::Signed(random::next_in(low, high))),
+        (Some(_), Some(_)) => Err(QueryError::OutOfRange),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Rows of the integers from the start to the stop, inclusive, going by the
+/// step, which defaults to one; no rows if any argument is null
+fn t_generate_series(values: Vec<Value>) -> Result<Box<Iterator<Item = Row>>, QueryError> {
+    let step = match values.get(2) {
+        Some(step) => integer_argument(step)?,
+        None => Some(1),
+    };
+    let (start, stop, step) = match (integer_argument(&values[0])?, integer_argument(&values[1])?, step) {
+        (_, _, Some(0)) => return Err(QueryError::OutOfRange),
+        (Some(start), Some(stop), Some(step)) => (start, stop, step),
+        _ => return Ok(Box::new(iter::empty())),
+    };
+    let within = move |n: &i128| if step > 0 { *n <= stop } else { *n >= stop };
+    let mut next = Some(start).filter(&within);
+    Ok(Box::new(iter::from_fn(move || {
+        let current = next?;
+        next = current.checked_add(step).filter(&within);
+        Some(Row::new(vec![Value::Signed(current)]))
+    })))
+}
+
+/// Rows of the parts of the text between occurrences of the separator, with
+/// their positions from zero; no rows if either is null
+///
+/// An empty separator splits the text into characters.
+fn t_split_text(values: Vec<Value>) -> Result<Box<Iterator<Item = Row>>, QueryError> {
+    let parts: Vec<String> = match (text_argument(&values[0])?, text_argument(&values[1])?) {
+        (Some(text), Some("")) => text.chars().map(String::from).collect(),
+        (Some(text), Some(separator)) => text.split(separator).map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    Ok(Box::new(parts.into_iter().enumerate().map(|(i, part)| {
+        Row::new(vec![Value::Unsigned(i as u128), Value::Text(part.into())])
+    })))
+}
+
+const NUMBER: &[ValueKind] = &[ValueKind::Unsigned, ValueKind::Signed, ValueKind::Real, ValueKind::Decimal];
+const INTEGER: &[ValueKind] = &[ValueKind::Unsigned, ValueKind::Signed];
+const TEXT: &[ValueKind] = &[ValueKind::Text];
+const BYTES: &[ValueKind] = &[ValueKind::Blob, ValueKind::Text];
+const TEMPORAL: &[ValueKind] = &[ValueKind::Date, ValueKind::Timestamp];
+const POINT: &[ValueKind] = &[ValueKind::GeoPoint];
+const VECTOR: &[ValueKind] = &[ValueKind::Vector];
+
+/// Signature of the functions of one number with a real result
+const REAL_FUNCTION: Signature = Signature::exactly(1).taking(&[NUMBER]).returning(ValueKind::Real);
+
+/// Regular expression functions, which come in addition to `functions`
+#[cfg(feature = "regex")]
+pub fn regex_functions() -> Vec<(&'static str, NativeFunction)> {
+    vec![
+        ("regex_match", NativeFunction::new(regex_functions::f_regex_match).with_signature(Signature::exactly(2).taking(&[TEXT]).returning(ValueKind::Boolean))),
+        ("regex_extract", NativeFunction::new(regex_functions::f_regex_extract).with_signature(Signature::between(2, 3).taking(&[TEXT, TEXT, &[ValueKind::Unsigned, ValueKind::Signed, ValueKind::Text]]).returning(ValueKind::Text))),
+    ]
+}
+
+/// Arithmetic functions in the mode, which come in addition to `functions`
+pub fn arithmetic_functions(mode: ArithmeticMode) -> Vec<(&'static str, NativeFunction)> {
+    vec![
+        ("add", NativeFunction::new(move |values| f_add(values, mode)).with_signature(Signature::at_least(1))),
+        ("sub", NativeFunction::new(move |values| f_sub(values, mode)).with_signature(Signature::exactly(2))),
+        ("mul", NativeFunction::new(move |values| f_mul(values, mode)).with_signature(Signature::at_least(1))),
+        ("div", NativeFunction::new(move |values| f_div(values, mode)).with_signature(Signature::exactly(2))),
+        ("neg", NativeFunction::new(move |values| f_neg(values, mode)).with_signature(Signature::exactly(1))),
+        ("abs", NativeFunction::new(move |values| f_abs(values, mode)).with_signature(Signature::exactly(1))),
+        ("pow", NativeFunction::new(move |values| f_pow(values, mode)).with_signature(Signature::exactly(2))),
+    ]
+}
+
+pub fn functions() -> Vec<(&'static str, NativeFunction)> {
+    vec![
+        ("strict_eq", NativeFunction::new(f_strict_eq).with_signature(Signature::at_least(2).returning(ValueKind::Boolean))),
+        ("lt", NativeFunction::new(f_lt).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("gt", NativeFunction::new(f_gt).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("lte", NativeFunction::new(f_lte).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("gte", NativeFunction::new(f_gte).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("neq", NativeFunction::new(f_neq).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("between", NativeFunction::new(f_between).with_signature(Signature::exactly(3).returning(ValueKind::Boolean))),
+        ("and", NativeFunction::new(f_and).with_signature(Signature::at_least(1).returning(ValueKind::Boolean))),
+        ("or", NativeFunction::new(f_or).with_signature(Signature::at_least(1).returning(ValueKind::Boolean))),
+        ("not", NativeFunction::new(f_not).with_signature(Signature::exactly(1).returning(ValueKind::Boolean))),
+        ("xor", NativeFunction::new(f_xor).with_signature(Signature::at_least(2).returning(ValueKind::Boolean))),
+        ("coalesce", NativeFunction::new(f_coalesce).with_signature(Signature::at_least(1))),
+        ("ifnull", NativeFunction::new(f_ifnull).with_signature(Signature::exactly(2))),
+        ("iif", NativeFunction::new(f_iif).with_signature(Signature::exactly(3))),
+        ("case", NativeFunction::new(f_case).with_signature(Signature::at_least(2))),
+        ("mod", NativeFunction::new(f_mod).with_signature(Signature::exactly(2))),
+        ("to_int", NativeFunction::new(f_to_int).with_signature(Signature::between(1, 2).taking(&[&[], &[ValueKind::Boolean]]).returning(ValueKind::Signed))),
+        ("to_unsigned", NativeFunction::new(f_to_unsigned).with_signature(Signature::between(1, 2).taking(&[&[], &[ValueKind::Boolean]]).returning(ValueKind::Unsigned))),
+        ("to_real", NativeFunction::new(f_to_real).with_signature(Signature::exactly(1).returning(ValueKind::Real))),
+        ("to_text", NativeFunction::new(f_to_text).with_signature(Signature::exactly(1).returning(ValueKind::Text))),
+        ("to_blob", NativeFunction::new(f_to_blob).with_signature(Signature::exactly(1).returning(ValueKind::Blob))),
+        ("sqrt", NativeFunction::new(f_sqrt).with_signature(REAL_FUNCTION)),
+        ("floor", NativeFunction::new(f_floor).with_signature(REAL_FUNCTION)),
+        ("ceil", NativeFunction::new(f_ceil).with_signature(REAL_FUNCTION)),
+        ("round", NativeFunction::new(f_round).with_signature(REAL_FUNCTION)),
+        ("ln", NativeFunction::new(f_ln).with_signature(REAL_FUNCTION)),
+        ("exp", NativeFunction::new(f_exp).with_signature(REAL_FUNCTION)),
+        ("gen_uuid", NativeFunction::new(f_gen_uuid_v4).with_signature(Signature::exactly(0).returning(ValueKind::Uuid))),
+        ("gen_uuid_v4", NativeFunction::new(f_gen_uuid_v4).with_signature(Signature::exactly(0).returning(ValueKind::Uuid))),
+        ("random", NativeFunction::new(f_random).with_signature(Signature::exactly(0).returning(ValueKind::Real))),
+        ("random_int", NativeFunction::new(f_random_int).with_signature(Signature::exactly(2).taking(&[INTEGER]).returning(ValueKind::Signed))),
+        ("now", NativeFunction::new(f_now).with_signature(Signature::exactly(0).returning(ValueKind::Timestamp))),
+        ("date_add", NativeFunction::new(f_date_add).with_signature(Signature::exactly(3).taking(&[TEXT, INTEGER, TEMPORAL]))),
+        ("date_diff", NativeFunction::new(f_date_diff).with_signature(Signature::exactly(3).taking(&[TEXT, TEMPORAL]).returning(ValueKind::Signed))),
+        ("date_trunc", NativeFunction::new(f_date_trunc).with_signature(Signature::exactly(2).taking(&[TEXT, TEMPORAL]))),
+        ("strftime", NativeFunction::new(f_strftime).with_signature(Signature::exactly(2).taking(&[TEXT, TEMPORAL]).returning(ValueKind::Text))),
+        ("strptime", NativeFunction::new(f_strptime).with_signature(Signature::exactly(2).taking(&[TEXT]).returning(ValueKind::Timestamp))),
+        ("array_contains", NativeFunction::new(f_array_contains).with_signature(Signature::exactly(2).returning(ValueKind::Boolean))),
+        ("array_length", NativeFunction::new(f_array_length).with_signature(Signature::exactly(1).returning(ValueKind::Unsigned))),
+        ("array_get", NativeFunction::new(f_array_get).with_signature(Signature::exactly(2))),
+        ("distance_m", NativeFunction::new(f_distance_m).with_signature(Signature::exactly(2).taking(&[POINT]).returning(ValueKind::Real))),
+        ("within_radius", NativeFunction::new(f_within_radius).with_signature(Signature::exactly(3).taking(&[POINT, POINT, NUMBER]).returning(ValueKind::Boolean))),
+        ("within_bbox", NativeFunction::new(f_within_bbox).with_signature(Signature::exactly(3).taking(&[POINT]).returning(ValueKind::Boolean))),
+        ("dot_product", NativeFunction::new(f_dot_product).with_signature(Signature::exactly(2).taking(&[VECTOR]).returning(ValueKind::Real))),
+        ("cosine_similarity", NativeFunction::new(f_cosine_similarity).with_signature(Signature::exactly(2).taking(&[VECTOR]).returning(ValueKind::Real))),
+        ("matches", NativeFunction::new(f_matches).with_signature(Signature::exactly(2).taking(&[TEXT]).returning(ValueKind::Boolean))),
+        ("upper", NativeFunction::new(f_upper).with_signature(Signature::exactly(1).taking(&[TEXT]).returning(ValueKind::Text))),
+        ("lower", NativeFunction::new(f_lower).with_signature(Signature::exactly(1).taking(&[TEXT]).returning(ValueKind::Text))),
+        ("length", NativeFunction::new(f_length).with_signature(Signature::exactly(1).taking(&[TEXT]).returning(ValueKind::Unsigned))),
+        ("length", NativeFunction::new(f_blob_length).with_signature(Signature::exactly(1).taking(&[&[ValueKind::Blob]]).returning(ValueKind::Unsigned))),
+        ("length", NativeFunction::new(f_array_length).with_signature(Signature::exactly(1).taking(&[&[ValueKind::Array]]).returning(ValueKind::Unsigned))),
+        ("substr", NativeFunction::new(f_substr).with_signature(Signature::between(2, 3).taking(&[TEXT, INTEGER]).returning(ValueKind::Text))),
+        ("trim", NativeFunction::new(f_trim).with_signature(Signature::exactly(1).taking(&[TEXT]).returning(ValueKind::Text))),
+        ("replace", NativeFunction::new(f_replace).with_signature(Signature::exactly(3).taking(&[TEXT]).returning(ValueKind::Text))),
+        ("concat", NativeFunction::new(f_concat).with_signature(Signature::ANY.taking(&[TEXT]).returning(ValueKind::Text))),
+        ("like", NativeFunction::new(f_like).with_signature(Signature::exactly(2).taking(&[TEXT]).returning(ValueKind::Boolean))),
+        ("collate", NativeFunction::new(f_collate).with_signature(Signature::exactly(2).taking(&[&[], TEXT]))),
+        ("glob", NativeFunction::new(f_glob).with_signature(Signature::exactly(2).taking(&[TEXT]).returning(ValueKind::Boolean))),
+        ("sha256", NativeFunction::new(f_sha256).with_signature(Signature::exactly(1).taking(&[BYTES]).returning(ValueKind::Blob))),
+        ("md5", NativeFunction::new(f_md5).with_signature(Signature::exactly(1).taking(&[BYTES]).returning(ValueKind::Blob))),
+        ("crc32", NativeFunction::new(f_crc32).with_signature(Signature::exactly(1).taking(&[BYTES]).returning(ValueKind::Unsigned))),
+        ("hex", NativeFunction::new(f_hex).with_signature(Signature::exactly(1).taking(&[BYTES]).returning(ValueKind::Text))),
+    ]
+}
+
+/// Functions returning rows, see `Query::FromTableFunction`
+pub fn table_functions() -> Vec<(&'static str, TableFunction)> {
+    vec![
+        ("generate_series", TableFunction::new(&["value"], t_generate_series).with_signature(Signature::between(2, 3).taking(&[INTEGER]))),
+        ("split_text", TableFunction::new(&["index", "value"], t_split_text).with_signature(Signature::exactly(2).taking(&[TEXT]))),
+    ]
+}
@@ -0,0 +1,79 @@
+//! Change-data-capture: stream every applied delta to external systems
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::mpsc::Sender;
+
+use {Delta, Revision};
+use codec::Encode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CdcFormat {
+    /// One object `{"revision":..,"delta":..}` per line, see `Delta::to_json`
+    Json,
+    /// Little-endian u64 revision followed by the delta, see `Delta::to_bytes`
+    Binary,
+}
+impl CdcFormat {
+    /// Serialize a change in this format
+    pub fn serialize(&self, revision: Revision, delta: &Delta) -> Vec<u8> {
+        match self {
+            CdcFormat::Json => format!("{{\"revision\":{},\"delta\":{}}}\n", revision, delta.to_json()).into_bytes(),
+            CdcFormat::Binary => {
+                let mut out = Vec::new();
+                revision.encode(&mut out);
+                delta.encode(&mut out);
+                out
+            },
+        }
+    }
+}
+
+/// Receives every delta applied to a database, see `SrimDB::with_cdc_sink`
+///
+/// Deltas are passed after they have been applied, in revision order.
+pub trait CdcSink: Send {
+    fn write(&mut self, revision: Revision, delta: &Delta) -> io::Result<()>;
+}
+
+/// Append changes to a file
+///
+/// In the binary format, each change is prefixed with its length as a
+/// little-endian u64.
+pub struct FileSink {
+    file: File,
+    format: CdcFormat,
+}
+impl FileSink {
+    pub fn new(file: File, format: CdcFormat) -> Self {
+        Self { file, format }
+    }
+}
+impl CdcSink for FileSink {
+    fn write(&mut self, revision: Revision, delta: &Delta) -> io::Result<()> {
+        let bytes = self.format.serialize(revision, delta);
+        if self.format == CdcFormat::Binary {
+            let mut len = Vec::new();
+            (bytes.len() as u64).encode(&mut len);
+            self.file.write_all(&len)?;
+        }
+        self.file.write_all(&bytes)
+    }
+}
+
+/// Send each serialized change as a message over a channel
+pub struct ChannelSink {
+    sender: Sender<Vec<u8>>,
+    format: CdcFormat,
+}
+impl ChannelSink {
+    pub fn new(sender: Sender<Vec<u8>>, format: CdcFormat) -> Self {
+        Self { sender, format }
+    }
+}
+impl CdcSink for ChannelSink {
+    fn write(&mut self, revision: Revision, delta: &Delta) -> io::Result<()> {
+        self.sender.send(self.format.serialize(revision, delta))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "CDC receiver hung up"))
+    }
+}
@@ -0,0 +1,1082 @@
+//! Compact binary encoding of deltas and their contents
+//!
+//! Integers are little-endian, lengths are u64 and enums start with a one-byte tag.
+
+use std::time::Duration;
+
+use {Delta, RowUpdate, Table, TableField, ReferenceAction, FieldDefault, IndexKind, Quota, Partitioning, Row, Value, FieldKind, IntSize, SharedText, Collation};
+use query::{Query, QueryField, Condition};
+use function::{FunctionCall, Argument};
+use procedure::ProcedureStep;
+use trigger::{Trigger, TriggerEvent, TriggerTiming, TriggerAction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    /// Unknown enum tag $1 for type $0
+    InvalidTag(&'static str, u8),
+    InvalidUtf8,
+    /// Decoded parts of a $0 don't fit together
+    Malformed(&'static str),
+    /// Input continues after the decoded value
+    TrailingBytes,
+}
+
+pub(crate) trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub(crate) trait Decode: Sized {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Decode a value that must span the whole input
+pub(crate) fn decode_exact<T: Decode>(mut input: &[u8]) -> Result<T, DecodeError> {
+    let value = T::decode(&mut input)?;
+    if !input.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+/// Decode a list that was added to the end of an encoding, empty if the input
+/// ends before it because it was encoded before the list existed
+fn decode_added<T: Decode>(input: &mut &[u8]) -> Result<Vec<T>, DecodeError> {
+    if input.is_empty() {
+        Ok(Vec::new())
+    }
+    else {
+        Vec::decode(input)
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], count: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < count {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (head, tail) = input.split_at(count);
+    *input = tail;
+    Ok(head)
+}
+
+fn take_array<'a>(input: &mut &'a [u8], out: &mut [u8]) -> Result<(), DecodeError> {
+    let len = out.len();
+    out.copy_from_slice(take(input, len)?);
+    Ok(())
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+impl Decode for u8 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(input, 1)?[0])
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag("bool", tag)),
+        }
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for u64 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 8];
+        take_array(input, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for u32 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 4];
+        take_array(input, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for i32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for i32 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 4];
+        take_array(input, &mut bytes)?;
+        Ok(i32::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for i64 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 8];
+        take_array(input, &mut bytes)?;
+        Ok(i64::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for u128 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for u128 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 16];
+        take_array(input, &mut bytes)?;
+        Ok(u128::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for i128 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl Decode for i128 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 16];
+        take_array(input, &mut bytes)?;
+        Ok(i128::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bits().encode(out);
+    }
+}
+impl Decode for f64 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(f64::from_bits(u64::decode(input)?))
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bits().encode(out);
+    }
+}
+impl Decode for f32 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(f32::from_bits(u32::decode(input)?))
+    }
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    (len as u64).encode(out);
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize, DecodeError> {
+    let len = u64::decode(input)?;
+    // Every element takes at least one byte, so longer lengths can't be valid
+    if len > input.len() as u64 {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    Ok(len as usize)
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl Encode for SharedText {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+impl Decode for SharedText {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(String::decode(input)?.into())
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            },
+        }
+    }
+}
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("Option", tag)),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+}
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?, C::decode(input)?))
+    }
+}
+
+impl Encode for IntSize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            IntSize::N8 => 0,
+            IntSize::N16 => 1,
+            IntSize::N32 => 2,
+            IntSize::N64 => 3,
+            IntSize::N128 => 4,
+        });
+    }
+}
+impl Decode for IntSize {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(IntSize::N8),
+            1 => Ok(IntSize::N16),
+            2 => Ok(IntSize::N32),
+            3 => Ok(IntSize::N64),
+            4 => Ok(IntSize::N128),
+            tag => Err(DecodeError::InvalidTag("IntSize", tag)),
+        }
+    }
+}
+
+impl Encode for FieldKind {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            FieldKind::Integer(size, signed) => {
+                out.push(0);
+                size.encode(out);
+                signed.encode(out);
+            },
+            FieldKind::Real => out.push(1),
+            FieldKind::Text => out.push(2),
+            FieldKind::Blob => out.push(3),
+            FieldKind::ForeignKey(table) => {
+                out.push(4);
+                table.encode(out);
+            },
+            FieldKind::Date => out.push(5),
+            FieldKind::Time => out.push(6),
+            FieldKind::Timestamp => out.push(7),
+            FieldKind::Decimal(precision, scale) => {
+                out.push(8);
+                precision.encode(out);
+                scale.encode(out);
+            },
+            FieldKind::Uuid => out.push(9),
+            FieldKind::Array(kind) => {
+                out.push(10);
+                kind.encode(out);
+            },
+            FieldKind::GeoPoint => out.push(11),
+            FieldKind::Vector(dimension) => {
+                out.push(12);
+                dimension.encode(out);
+            },
+            FieldKind::Boolean => out.push(13),
+        }
+    }
+}
+impl Decode for FieldKind {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(FieldKind::Integer(IntSize::decode(input)?, bool::decode(input)?)),
+            1 => Ok(FieldKind::Real),
+            2 => Ok(FieldKind::Text),
+            3 => Ok(FieldKind::Blob),
+            4 => Ok(FieldKind::ForeignKey(String::decode(input)?)),
+            5 => Ok(FieldKind::Date),
+            6 => Ok(FieldKind::Time),
+            7 => Ok(FieldKind::Timestamp),
+            8 => Ok(FieldKind::Decimal(u8::decode(input)?, u8::decode(input)?)),
+            9 => Ok(FieldKind::Uuid),
+            10 => Ok(FieldKind::Array(Box::new(FieldKind::decode(input)?))),
+            11 => Ok(FieldKind::GeoPoint),
+            12 => Ok(FieldKind::Vector(u32::decode(input)?)),
+            13 => Ok(FieldKind::Boolean),
+            tag => Err(DecodeError::InvalidTag("FieldKind", tag)),
+        }
+    }
+}
+
+impl Encode for Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Boolean(b) => { out.push(0); b.encode(out); },
+            Value::Unsigned(u) => { out.push(1); u.encode(out); },
+            Value::Signed(i) => { out.push(2); i.encode(out); },
+            Value::Real(r) => { out.push(3); r.encode(out); },
+            Value::Text(t) => { out.push(4); t.encode(out); },
+            Value::Blob(b) => { out.push(5); b.encode(out); },
+            Value::Null => out.push(6),
+            Value::Date(d) => { out.push(7); d.encode(out); },
+            Value::Time(t) => { out.push(8); t.encode(out); },
+            Value::Timestamp(t) => { out.push(9); t.encode(out); },
+            Value::Decimal(m, s) => { out.push(10); m.encode(out); s.encode(out); },
+            Value::Uuid(u) => { out.push(11); out.extend_from_slice(u); },
+            Value::Array(a) => { out.push(12); a.encode(out); },
+            Value::GeoPoint(lat, lon) => { out.push(13); lat.encode(out); lon.encode(out); },
+            Value::Vector(v) => { out.push(14); v.encode(out); },
+        }
+    }
+}
+impl Decode for Value {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Value::Boolean(bool::decode(input)?)),
+            1 => Ok(Value::Unsigned(u128::decode(input)?)),
+            2 => Ok(Value::Signed(i128::decode(input)?)),
+            3 => Ok(Value::Real(f64::decode(input)?)),
+            4 => Ok(Value::Text(SharedText::decode(input)?)),
+            5 => Ok(Value::Blob(Vec::decode(input)?)),
+            6 => Ok(Value::Null),
+            7 => Ok(Value::Date(i32::decode(input)?)),
+            8 => Ok(Value::Time(u64::decode(input)?)),
+            9 => Ok(Value::Timestamp(i64::decode(input)?)),
+            10 => Ok(Value::Decimal(i128::decode(input)?, u8::decode(input)?)),
+            11 => {
+                let mut bytes = [0; 16];
+                take_array(input, &mut bytes)?;
+                Ok(Value::Uuid(bytes))
+            },
+            12 => Ok(Value::Array(Vec::decode(input)?)),
+            13 => Ok(Value::GeoPoint(f64::decode(input)?, f64::decode(input)?)),
+            14 => Ok(Value::Vector(Vec::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("Value", tag)),
+        }
+    }
+}
+
+impl Encode for ReferenceAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            ReferenceAction::Restrict => 0,
+            ReferenceAction::Cascade => 1,
+        });
+    }
+}
+impl Decode for ReferenceAction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(ReferenceAction::Restrict),
+            1 => Ok(ReferenceAction::Cascade),
+            tag => Err(DecodeError::InvalidTag("ReferenceAction", tag)),
+        }
+    }
+}
+
+impl Encode for Collation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Collation::Binary => 0,
+            Collation::CaseInsensitive => 1,
+            Collation::Dictionary => 2,
+        });
+    }
+}
+impl Decode for Collation {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Collation::Binary),
+            1 => Ok(Collation::CaseInsensitive),
+            2 => Ok(Collation::Dictionary),
+            tag => Err(DecodeError::InvalidTag("Collation", tag)),
+        }
+    }
+}
+
+impl Encode for Quota {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.max_rows.map(|max| max as u64).encode(out);
+        self.max_bytes.map(|max| max as u64).encode(out);
+        self.evict.encode(out);
+    }
+}
+impl Decode for Quota {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Quota {
+            max_rows: Option::<u64>::decode(input)?.map(|max| max as usize),
+            max_bytes: Option::<u64>::decode(input)?.map(|max| max as usize),
+            evict: bool::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Duration {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_secs().encode(out);
+        self.subsec_nanos().encode(out);
+    }
+}
+impl Decode for Duration {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let secs = u64::decode(input)?;
+        let nanos = u32::decode(input)?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::Malformed("Duration"));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+impl Encode for Partitioning {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Partitioning::Range(bounds) => {
+                out.push(0);
+                bounds.encode(out);
+            },
+            Partitioning::Hash(count) => {
+                out.push(1);
+                (*count as u64).encode(out);
+            },
+        }
+    }
+}
+impl Decode for Partitioning {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Partitioning::Range(Vec::decode(input)?)),
+            1 => Ok(Partitioning::Hash(u64::decode(input)? as usize)),
+            tag => Err(DecodeError::InvalidTag("Partitioning", tag)),
+        }
+    }
+}
+
+impl Encode for IndexKind {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            IndexKind::Hash => 0,
+            IndexKind::BTree => 1,
+            IndexKind::Unique => 2,
+            IndexKind::FullText => 3,
+        });
+    }
+}
+impl Decode for IndexKind {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(IndexKind::Hash),
+            1 => Ok(IndexKind::BTree),
+            2 => Ok(IndexKind::Unique),
+            3 => Ok(IndexKind::FullText),
+            tag => Err(DecodeError::InvalidTag("IndexKind", tag)),
+        }
+    }
+}
+
+impl Encode for FieldDefault {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            FieldDefault::Value(v) => { out.push(0); v.encode(out); },
+            FieldDefault::FunctionCall(fc) => { out.push(1); fc.encode(out); },
+        }
+    }
+}
+impl Decode for FieldDefault {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(FieldDefault::Value(Value::decode(input)?)),
+            1 => Ok(FieldDefault::FunctionCall(FunctionCall::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("FieldDefault", tag)),
+        }
+    }
+}
+
+impl Encode for TableField {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name().encode(out);
+        self.kind().encode(out);
+        self.is_nullable().encode(out);
+        self.on_delete().encode(out);
+        self.default().encode(out);
+        self.generated().encode(out);
+        self.collation().encode(out);
+    }
+}
+impl Decode for TableField {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut field = TableField::new(String::decode(input)?, FieldKind::decode(input)?);
+        if bool::decode(input)? {
+            field = field.nullable();
+        }
+        field = field.with_on_delete(ReferenceAction::decode(input)?);
+        if let Some(default) = Option::decode(input)? {
+            field = field.with_default(default);
+        }
+        if let Some(expression) = Option::decode(input)? {
+            field = field.with_generated(expression);
+        }
+        Ok(field.with_collation(Collation::decode(input)?))
+    }
+}
+
+impl Encode for Table {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let fields = self.fields();
+        self.name().encode(out);
+        fields.encode(out);
+        let mask: Vec<bool> = (0..fields.len()).map(|i| self.is_key_field(i)).collect();
+        mask.encode(out);
+        self.has_unique_key().encode(out);
+        self.checks().encode(out);
+        self.unique_constraints().encode(out);
+        self.indexes().encode(out);
+        self.expression_indexes().encode(out);
+        self.partial_indexes().encode(out);
+        // Only tables with any of the options after the flag have them, so
+        // others encode as before they existed
+        let partitioned = self.partitioning().is_some();
+        let limited = self.quota().is_some() || self.ttl().is_some() || partitioned;
+        if self.is_temporary() || limited {
+            self.is_temporary().encode(out);
+        }
+        if limited {
+            self.quota().encode(out);
+            self.ttl().encode(out);
+        }
+        if let Some(partitioning) = self.partitioning() {
+            partitioning.encode(out);
+        }
+    }
+}
+impl Decode for Table {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let name = String::decode(input)?;
+        let fields = Vec::<TableField>::decode(input)?;
+        let mask = Vec::<bool>::decode(input)?;
+        let unique_key = bool::decode(input)?;
+        let checks = Vec::<(String, FunctionCall)>::decode(input)?;
+        let uniques = Vec::<(String, Vec<String>)>::decode(input)?;
+        // Tables encoded before these lists existed end here. Indexes themselves
+        // are never encoded, but built from the rows when a table is created.
+        let indexes = decode_added::<(IndexKind, Vec<String>)>(input)?;
+        let expression_indexes = decode_added::<(IndexKind, FunctionCall)>(input)?;
+        let partial_indexes = decode_added::<(IndexKind, Vec<String>, Condition)>(input)?;
+        let temporary = !input.is_empty() && bool::decode(input)?;
+        let (quota, ttl) = if input.is_empty() {
+            (None, None)
+        } else {
+            (Option::<Quota>::decode(input)?, Option::<(String, Duration)>::decode(input)?)
+        };
+        let partitioning = if input.is_empty() { None } else { Some(<(String, Partitioning)>::decode(input)?) };
+        if mask.len() != fields.len() {
+            return Err(DecodeError::Malformed("Table"));
+        }
+        let keys = fields.iter().zip(&mask).filter(|(_, k)| **k).map(|(f, _)| f.name()).collect();
+        let mut table = Table::new(&name, fields);
+        if !mask.iter().all(|k| *k) {
+            table = table.with_key_fields(keys);
+        }
+        if unique_key {
+            table = table.with_unique_key();
+        }
+        for (name, check) in checks {
+            table = table.with_check(&name, check);
+        }
+        for (name, field_names) in uniques {
+            if field_names.is_empty() || field_names.iter().any(|f| table.field_index(f.clone()).is_none()) {
+                return Err(DecodeError::Malformed("Table"));
+            }
+            table = table.with_unique(&name, field_names);
+        }
+        for (kind, field_names) in indexes {
+            if field_names.is_empty() || field_names.iter().any(|f| table.field_index(f.clone()).is_none()) {
+                return Err(DecodeError::Malformed("Table"));
+            }
+            table = table.with_index(kind, field_names);
+        }
+        for (kind, expression) in expression_indexes {
+            table = table.with_expression_index(kind, expression);
+        }
+        for (kind, field_names, predicate) in partial_indexes {
+            if field_names.is_empty() || field_names.iter().any(|f| table.field_index(f.clone()).is_none()) {
+                return Err(DecodeError::Malformed("Table"));
+            }
+            table = table.with_partial_index(kind, field_names, predicate);
+        }
+        if temporary {
+            table = table.temporary();
+        }
+        if let Some(quota) = quota {
+            table = table.with_quota(quota);
+        }
+        if let Some((field, retention)) = ttl {
+            if table.field_index(field.clone()).is_none() {
+                return Err(DecodeError::Malformed("Table"));
+            }
+            table = table.with_ttl(&field, retention);
+        }
+        if let Some((field, partitioning)) = partitioning {
+            if table.field_index(field.clone()).is_none() {
+                return Err(DecodeError::Malformed("Table"));
+            }
+            table = table.with_partitioning(&field, partitioning);
+        }
+        Ok(table)
+    }
+}
+
+impl Encode for Row {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.values().encode(out);
+    }
+}
+impl Decode for Row {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Row::new(Vec::decode(input)?))
+    }
+}
+
+impl Encode for QueryField {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.table.encode(out);
+        self.field.encode(out);
+    }
+}
+impl Decode for QueryField {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(QueryField { table: Option::decode(input)?, field: String::decode(input)? })
+    }
+}
+
+impl Encode for FunctionCall {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.target.encode(out);
+        self.arguments.encode(out);
+    }
+}
+impl Decode for FunctionCall {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(FunctionCall::new(String::decode(input)?, Vec::decode(input)?))
+    }
+}
+
+impl Encode for Argument {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Argument::FunctionCall(fc) => { out.push(0); fc.encode(out); },
+            Argument::Value(v) => { out.push(1); v.encode(out); },
+            Argument::QueryField(qf) => { out.push(2); qf.encode(out); },
+            Argument::Parameter(i) => { out.push(3); (*i as u64).encode(out); },
+        }
+    }
+}
+impl Decode for Argument {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Argument::FunctionCall(FunctionCall::decode(input)?)),
+            1 => Ok(Argument::Value(Value::decode(input)?)),
+            2 => Ok(Argument::QueryField(QueryField::decode(input)?)),
+            3 => Ok(Argument::Parameter(u64::decode(input)? as usize)),
+            tag => Err(DecodeError::InvalidTag("Argument", tag)),
+        }
+    }
+}
+
+impl Encode for Condition {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Condition::Value(v) => { out.push(0); v.encode(out); },
+            Condition::QueryField(qf) => { out.push(1); qf.encode(out); },
+            Condition::FunctionCall(fc) => { out.push(2); fc.encode(out); },
+        }
+    }
+}
+impl Decode for Condition {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Condition::Value(Value::decode(input)?)),
+            1 => Ok(Condition::QueryField(QueryField::decode(input)?)),
+            2 => Ok(Condition::FunctionCall(FunctionCall::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("Condition", tag)),
+        }
+    }
+}
+
+impl Encode for Query {
+    fn encode(&self, out: &mut Vec<u8>) {
+        use Query::*;
+        match self {
+            Empty(fields) => { out.push(0); fields.encode(out); },
+            Table(name) => { out.push(1); name.encode(out); },
+            FromValue(field, value) => { out.push(2); field.encode(out); value.encode(out); },
+            FromFunctionCall(field, fc) => { out.push(3); field.encode(out); fc.encode(out); },
+            Union(q1, q2) => { out.push(4); q1.encode(out); q2.encode(out); },
+            Intersection(q1, q2) => { out.push(5); q1.encode(out); q2.encode(out); },
+            Difference(q1, q2) => { out.push(6); q1.encode(out); q2.encode(out); },
+            Distinct(subquery) => { out.push(7); subquery.encode(out); },
+            Project(fields, subquery) => { out.push(8); fields.encode(out); subquery.encode(out); },
+            Filter(condition, subquery) => { out.push(9); condition.encode(out); subquery.encode(out); },
+            // Unqualified targets keep the encoding from before they could be qualified
+            Rename(from, QueryField { table: None, field }, subquery) => { out.push(10); from.encode(out); field.encode(out); subquery.encode(out); },
+            Rename(from, to, subquery) => { out.push(16); from.encode(out); to.encode(out); subquery.encode(out); },
+            JoinOn(condition, q1, q2) => { out.push(11); condition.encode(out); q1.encode(out); q2.encode(out); },
+            Unnest(field, subquery) => { out.push(12); field.encode(out); subquery.encode(out); },
+            NearestNeighbors(field, vector, k, subquery) => {
+                out.push(13);
+                field.encode(out);
+                vector.encode(out);
+                (*k as u64).encode(out);
+                subquery.encode(out);
+            },
+            OrderBy(field, subquery) => { out.push(14); field.encode(out); subquery.encode(out); },
+            FromTableFunction(fc) => { out.push(15); fc.encode(out); },
+        }
+    }
+}
+impl Decode for Query {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        use Query::*;
+        let boxed = |input: &mut &[u8]| Query::decode(input).map(Box::new);
+        Ok(match u8::decode(input)? {
+            0 => Empty(Vec::decode(input)?),
+            1 => Table(String::decode(input)?),
+            2 => FromValue(TableField::decode(input)?, Value::decode(input)?),
+            3 => FromFunctionCall(TableField::decode(input)?, FunctionCall::decode(input)?),
+            4 => Union(boxed(input)?, boxed(input)?),
+            5 => Intersection(boxed(input)?, boxed(input)?),
+            6 => Difference(boxed(input)?, boxed(input)?),
+            7 => Distinct(boxed(input)?),
+            8 => Project(Vec::decode(input)?, boxed(input)?),
+            9 => Filter(Condition::decode(input)?, boxed(input)?),
+            10 => Rename(QueryField::decode(input)?, QueryField::new(String::decode(input)?), boxed(input)?),
+            11 => JoinOn(Condition::decode(input)?, boxed(input)?, boxed(input)?),
+            12 => Unnest(QueryField::decode(input)?, boxed(input)?),
+            13 => NearestNeighbors(QueryField::decode(input)?, Vec::decode(input)?, u64::decode(input)? as usize, boxed(input)?),
+            14 => OrderBy(QueryField::decode(input)?, boxed(input)?),
+            15 => FromTableFunction(FunctionCall::decode(input)?),
+            16 => Rename(QueryField::decode(input)?, QueryField::decode(input)?, boxed(input)?),
+            tag => return Err(DecodeError::InvalidTag("Query", tag)),
+        })
+    }
+}
+
+impl Encode for RowUpdate {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            RowUpdate::Replace(row) => { out.push(0); row.encode(out); },
+            RowUpdate::Set(assignments) => { out.push(1); assignments.encode(out); },
+        }
+    }
+}
+impl Decode for RowUpdate {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(RowUpdate::Replace(Row::decode(input)?)),
+            1 => Ok(RowUpdate::Set(Vec::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("RowUpdate", tag)),
+        }
+    }
+}
+
+impl Encode for TriggerEvent {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            TriggerEvent::Insert => 0,
+            TriggerEvent::Update => 1,
+            TriggerEvent::Delete => 2,
+        });
+    }
+}
+impl Decode for TriggerEvent {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(TriggerEvent::Insert),
+            1 => Ok(TriggerEvent::Update),
+            2 => Ok(TriggerEvent::Delete),
+            tag => Err(DecodeError::InvalidTag("TriggerEvent", tag)),
+        }
+    }
+}
+
+impl Encode for TriggerTiming {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            TriggerTiming::Before => 0,
+            TriggerTiming::After => 1,
+        });
+    }
+}
+impl Decode for TriggerTiming {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(TriggerTiming::Before),
+            1 => Ok(TriggerTiming::After),
+            tag => Err(DecodeError::InvalidTag("TriggerTiming", tag)),
+        }
+    }
+}
+
+impl Encode for TriggerAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            TriggerAction::Check(condition) => { out.push(0); condition.encode(out); },
+            TriggerAction::Set(assignments) => { out.push(1); assignments.encode(out); },
+        }
+    }
+}
+impl Decode for TriggerAction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(TriggerAction::Check(Condition::decode(input)?)),
+            1 => Ok(TriggerAction::Set(Vec::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("TriggerAction", tag)),
+        }
+    }
+}
+
+impl Encode for Trigger {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.table.encode(out);
+        self.timing.encode(out);
+        self.events.encode(out);
+        self.action.encode(out);
+    }
+}
+impl Decode for Trigger {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Trigger {
+            name: String::decode(input)?,
+            table: String::decode(input)?,
+            timing: TriggerTiming::decode(input)?,
+            events: Vec::decode(input)?,
+            action: TriggerAction::decode(input)?,
+        })
+    }
+}
+
+impl Encode for ProcedureStep {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ProcedureStep::Apply(delta) => { out.push(0); delta.encode(out); },
+            ProcedureStep::Insert(name, values) => { out.push(1); name.encode(out); values.encode(out); },
+            ProcedureStep::Query(query) => { out.push(2); query.encode(out); },
+        }
+    }
+}
+impl Decode for ProcedureStep {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(ProcedureStep::Apply(Delta::decode(input)?)),
+            1 => Ok(ProcedureStep::Insert(String::decode(input)?, Vec::decode(input)?)),
+            2 => Ok(ProcedureStep::Query(Query::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag("ProcedureStep", tag)),
+        }
+    }
+}
+
+impl Encode for Delta {
+    fn encode(&self, out: &mut Vec<u8>) {
+        use Delta::*;
+        match self {
+            CreateTable(table) => { out.push(0); table.encode(out); },
+            DropTable(name) => { out.push(1); name.encode(out); },
+            Truncate(name) => { out.push(2); name.encode(out); },
+            AddColumn(name, field, default) => {
+                out.push(3);
+                name.encode(out);
+                field.encode(out);
+                default.encode(out);
+            },
+            RenameTable(from, to) => { out.push(4); from.encode(out); to.encode(out); },
+            RenameColumn(name, from, to) => {
+                out.push(5);
+                name.encode(out);
+                from.encode(out);
+                to.encode(out);
+            },
+            AlterColumnType(name, field, kind) => {
+                out.push(6);
+                name.encode(out);
+                field.encode(out);
+                kind.encode(out);
+            },
+            DropColumn(name, field) => { out.push(7); name.encode(out); field.encode(out); },
+            ForceDropColumn(name, field) => { out.push(8); name.encode(out); field.encode(out); },
+            AddRow(name, row) => { out.push(9); name.encode(out); row.encode(out); },
+            AddRows(name, rows) => { out.push(10); name.encode(out); rows.encode(out); },
+            AddNamedRow(name, values) => { out.push(20); name.encode(out); values.encode(out); },
+            CreateIndex(name, fields, kind) => { out.push(21); name.encode(out); fields.encode(out); kind.encode(out); },
+            CreateExpressionIndex(name, expression, kind) => {
+                out.push(22); name.encode(out); expression.encode(out); kind.encode(out);
+            },
+            CreatePartialIndex(name, fields, kind, predicate) => {
+                out.push(23); name.encode(out); fields.encode(out); kind.encode(out); predicate.encode(out);
+            },
+            RemoveRow(name, row) => { out.push(11); name.encode(out); row.encode(out); },
+            UpdateRow(name, row, update) => {
+                out.push(12);
+                name.encode(out);
+                row.encode(out);
+                update.encode(out);
+            },
+            RemoveWhere(name, condition) => { out.push(13); name.encode(out); condition.encode(out); },
+            UpdateWhere(name, condition, assignments) => {
+                out.push(14);
+                name.encode(out);
+                condition.encode(out);
+                assignments.encode(out);
+            },
+            Upsert(name, row) => { out.push(15); name.encode(out); row.encode(out); },
+            CreateTrigger(trigger) => { out.push(16); trigger.encode(out); },
+            DropTrigger(name, trigger) => { out.push(17); name.encode(out); trigger.encode(out); },
+            RemoveRowById(name, id) => { out.push(18); name.encode(out); id.encode(out); },
+            UpdateRowById(name, id, update) => {
+                out.push(19);
+                name.encode(out);
+                id.encode(out);
+                update.encode(out);
+            },
+            CreateFunction(name, body) => { out.push(24); name.encode(out); body.encode(out); },
+            DropFunction(name) => { out.push(25); name.encode(out); },
+            CreateView(name, query) => { out.push(26); name.encode(out); query.encode(out); },
+            DropView(name) => { out.push(27); name.encode(out); },
+            CreateMaterializedView(name, query) => { out.push(28); name.encode(out); query.encode(out); },
+            RefreshView(name) => { out.push(29); name.encode(out); },
+            CreateProcedure(name, steps) => { out.push(30); name.encode(out); steps.encode(out); },
+            DropProcedure(name) => { out.push(31); name.encode(out); },
+            CreatePolicy(name, condition) => { out.push(32); name.encode(out); condition.encode(out); },
+            DropPolicy(name) => { out.push(33); name.encode(out); },
+        }
+    }
+}
+impl Decode for Delta {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        use Delta::*;
+        Ok(match u8::decode(input)? {
+            0 => CreateTable(Table::decode(input)?),
+            1 => DropTable(String::decode(input)?),
+            2 => Truncate(String::decode(input)?),
+            3 => AddColumn(String::decode(input)?, TableField::decode(input)?, Value::decode(input)?),
+            4 => RenameTable(String::decode(input)?, String::decode(input)?),
+            5 => RenameColumn(String::decode(input)?, String::decode(input)?, String::decode(input)?),
+            6 => AlterColumnType(String::decode(input)?, String::decode(input)?, FieldKind::decode(input)?),
+            7 => DropColumn(String::decode(input)?, String::decode(input)?),
+            8 => ForceDropColumn(String::decode(input)?, String::decode(input)?),
+            9 => AddRow(String::decode(input)?, Row::decode(input)?),
+            10 => AddRows(String::decode(input)?, Vec::decode(input)?),
+            11 => RemoveRow(String::decode(input)?, Row::decode(input)?),
+            12 => UpdateRow(String::decode(input)?, Row::decode(input)?, RowUpdate::decode(input)?),
+            13 => RemoveWhere(String::decode(input)?, Condition::decode(input)?),
+            14 => UpdateWhere(String::decode(input)?, Condition::decode(input)?, Vec::decode(input)?),
+            15 => Upsert(String::decode(input)?, Row::decode(input)?),
+            16 => CreateTrigger(Trigger::decode(input)?),
+            17 => DropTrigger(String::decode(input)?, String::decode(input)?),
+            18 => RemoveRowById(String::decode(input)?, u64::decode(input)?),
+            19 => UpdateRowById(String::decode(input)?, u64::decode(input)?, RowUpdate::decode(input)?),
+            20 => AddNamedRow(String::decode(input)?, Vec::decode(input)?),
+            21 => CreateIndex(String::decode(input)?, Vec::decode(input)?, IndexKind::decode(input)?),
+            22 => CreateExpressionIndex(String::decode(input)?, FunctionCall::decode(input)?, IndexKind::decode(input)?),
+            23 => CreatePartialIndex(String::decode(input)?, Vec::decode(input)?, IndexKind::decode(input)?, Condition::decode(input)?),
+            24 => CreateFunction(String::decode(input)?, FunctionCall::decode(input)?),
+            25 => DropFunction(String::decode(input)?),
+            26 => CreateView(String::decode(input)?, Query::decode(input)?),
+            27 => DropView(String::decode(input)?),
+            28 => CreateMaterializedView(String::decode(input)?, Query::decode(input)?),
+            29 => RefreshView(String::decode(input)?),
+            30 => CreateProcedure(String::decode(input)?, Vec::decode(input)?),
+            31 => DropProcedure(String::decode(input)?),
+            32 => CreatePolicy(String::decode(input)?, Condition::decode(input)?),
+            33 => DropPolicy(String::decode(input)?),
+            tag => return Err(DecodeError::InvalidTag("Delta", tag)),
+        })
+    }
+}
+
+impl Delta {
+    /// Binary encoding of the delta, see `Delta::from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Delta, DecodeError> {
+        decode_exact(bytes)
+    }
+}
@@ -0,0 +1,146 @@
+//! Collations, the ways `Text` values are compared
+//!
+//! Each collation maps texts to keys, and texts are equal or ordered like
+//! their keys, so that comparisons, `Query::Distinct`, set operations,
+//! ordering and indexes all agree. A field gets its collation from the table,
+//! see `TableField::with_collation`, and comparisons of other texts can use
+//! one with the `collate` function.
+
+use std::borrow::Cow;
+
+use {Value, Condition, FunctionCall, Argument, QueryField};
+
+/// Functions comparing their arguments, which compare texts in the collation
+/// of the first field among them that has one
+const COMPARISONS: &[&str] = &["strict_eq", "neq", "lt", "gt", "lte", "gte", "between"];
+
+/// How texts are compared, binary by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collation {
+    /// By their Unicode scalar values
+    Binary,
+    /// As if lowercase
+    CaseInsensitive,
+    /// As if lowercase and without accents, so that dictionary order doesn't
+    /// depend on them; the same for all languages
+    Dictionary,
+}
+impl Default for Collation {
+    fn default() -> Self {
+        Collation::Binary
+    }
+}
+impl Collation {
+    /// Name of the collation for the `collate` function
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::CaseInsensitive => "case_insensitive",
+            Collation::Dictionary => "dictionary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Collation::Binary, Collation::CaseInsensitive, Collation::Dictionary].iter().cloned().find(|c| c.name() == name)
+    }
+
+    /// Text that other texts are compared with in place of `text`
+    pub fn key<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Collation::Binary => Cow::Borrowed(text),
+            Collation::CaseInsensitive => Cow::Owned(text.to_lowercase()),
+            Collation::Dictionary => {
+                let mut key = String::with_capacity(text.len());
+                for c in text.chars().flat_map(char::to_lowercase) {
+                    match unaccented(c) {
+                        Some(base) => key.push_str(base),
+                        None => key.push(c),
+                    }
+                }
+                Cow::Owned(key)
+            },
+        }
+    }
+
+    /// `value` with texts replaced by their keys, also within arrays; other values are kept
+    pub(crate) fn key_value<'a>(&self, value: &'a Value) -> Cow<'a, Value> {
+        match value {
+            _ if *self == Collation::Binary => Cow::Borrowed(value),
+            Value::Text(text) => Cow::Owned(Value::Text(self.key(text).into_owned().into())),
+            Value::Array(elements) => Cow::Owned(Value::Array(elements.iter().map(|e| self.key_value(e).into_owned()).collect())),
+            _ => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Values with each replaced by its key in the collation of its column
+pub(crate) fn key_values(collations: &[Collation], values: &[Value]) -> Vec<Value> {
+    values.iter().enumerate()
+        .map(|(i, value)| collations.get(i).cloned().unwrap_or_default().key_value(value).into_owned())
+        .collect()
+}
+
+/// Whether all of the columns are binary
+pub(crate) fn all_binary(collations: &[Collation]) -> bool {
+    collations.iter().all(|c| *c == Collation::Binary)
+}
+
+/// `condition` with the arguments of comparisons of fields with a collation
+/// other than binary wrapped in `collate`, to compare their keys
+pub(crate) fn collated(condition: &Condition, collation_of: &Fn(&QueryField) -> Collation) -> Condition {
+    match condition {
+        Condition::FunctionCall(fc) => Condition::FunctionCall(collated_call(fc, collation_of)),
+        _ => condition.clone(),
+    }
+}
+
+fn collated_call(call: &FunctionCall, collation_of: &Fn(&QueryField) -> Collation) -> FunctionCall {
+    let arguments: Vec<Argument> = call.arguments.iter().map(|argument| match argument {
+        Argument::FunctionCall(fc) => Argument::FunctionCall(collated_call(fc, collation_of)),
+        _ => argument.clone(),
+    }).collect();
+    let collation = call.arguments.iter().filter_map(|argument| match argument {
+        Argument::QueryField(qf) => Some(collation_of(qf)),
+        _ => None,
+    }).find(|c| *c != Collation::Binary);
+    match collation {
+        Some(collation) if COMPARISONS.contains(&call.target.as_str()) => {
+            let name = Argument::Value(Value::Text(collation.name().into()));
+            let arguments = arguments.into_iter()
+                .map(|argument| Argument::FunctionCall(FunctionCall::new("collate".to_owned(), vec![argument, name.clone()])))
+                .collect();
+            FunctionCall::new(call.target.clone(), arguments)
+        },
+        _ => FunctionCall::new(call.target.clone(), arguments),
+    }
+}
+
+/// Letters of a lowercase Latin letter without its accent
+fn unaccented(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
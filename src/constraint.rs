@@ -0,0 +1,164 @@
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use {DataDB, Table, TableName, FieldName, Row, Value, FieldKind, QueryField, QueryError, TypeError, ApplyError};
+use table::ReferenceAction;
+use trigger::table_fields;
+
+impl DataDB {
+    /// Check all constraints of `table` for a new version of the row at position `replacing`, or a new row
+    pub(crate) fn check_constraints(&self, table: &Table, row: &Row, replacing: Option<usize>) -> Result<(), ApplyError> {
+        self.check_unique(table, row, replacing)?;
+        self.check_row(table, row)
+    }
+
+    /// Check constraints that don't depend on other rows of the table
+    pub(crate) fn check_row(&self, table: &Table, row: &Row) -> Result<(), ApplyError> {
+        self.check_references(table, row)?;
+        self.check_checks(table, row)
+    }
+
+    /// Check the unique key and unique constraints against stored rows, other than the one at position `replacing`
+    fn check_unique(&self, table: &Table, row: &Row, replacing: Option<usize>) -> Result<(), ApplyError> {
+        let name = table.name();
+        let replacing = replacing.and_then(|position| self.table_rows[&name][position].id());
+        self.indexes[&name].check(table, row, replacing)
+    }
+
+    /// Evaluate check constraints of the table; unknown results pass, as in SQL
+    fn check_checks(&self, table: &Table, row: &Row) -> Result<(), ApplyError> {
+        let checks = table.checks();
+        if checks.is_empty() {
+            return Ok(());
+        }
+        let fields = table_fields(table);
+        for (name, check) in checks {
+            let value = check.resolve_args(&|qf: &QueryField| fields.resolve_field(qf, row))
+                .and_then(|fc| fc.apply(&self.functions))
+                .map_err(ApplyError::QueryError)?;
+            match value {
+                Value::Boolean(true) | Value::Null => {},
+                Value::Boolean(false) => return Err(ApplyError::CheckViolation { table: table.name(), constraint: name }),
+                _ => return Err(ApplyError::QueryError(QueryError::TypeError(TypeError::NotBoolean))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Some table has a foreign key to table `name`
+    pub(crate) fn is_referenced(&self, name: &TableName) -> bool {
+        self.tables.iter().any(|t| !t.references_to(name).is_empty())
+    }
+
+    /// Check that the foreign keys of `table`, and those to it, reference
+    /// tables with a single key field
+    pub(crate) fn check_reference_keys(&self, table: &Table) -> Result<(), ApplyError> {
+        let name = table.name();
+        let single_key = |target: &TableName| match self.tables.iter().find(|t| t.name() == *target) {
+            _ if *target == name => table.key_field().is_some(),
+            Some(target) => target.key_field().is_some(),
+            None => true,
+        };
+        let targets = table.fields().iter().all(|field| match field.kind() {
+            FieldKind::ForeignKey(ref target) => single_key(target),
+            _ => true,
+        });
+        let referenced = self.tables.iter().any(|t| t.name() != name && !t.references_to(&name).is_empty());
+        if targets && (!referenced || table.key_field().is_some()) {
+            Ok(())
+        }
+        else {
+            Err(ApplyError::InvalidSchema(name))
+        }
+    }
+
+    /// A row of table `target` has `value` as its only key field, in the collation of the field
+    fn reference_exists(&self, target: &TableName, value: &Value) -> bool {
+        let table = match self.table(target.clone()) {
+            Some(table) => table,
+            None => return false,
+        };
+        let field = match table.key_field() {
+            Some(field) => field,
+            None => return false,
+        };
+        let collation = table.fields()[field].collation();
+        let key = collation.key_value(value);
+        let matches = |r: &Arc<Row>| collation.key_value(r.value(field)) == key;
+        match self.key_lookup(target, &[value.clone()]) {
+            Some(rows) => rows.iter().any(matches),
+            None => self.table_rows[target].iter().any(matches),
+        }
+    }
+
+    /// Check that every foreign key of `row` references an existing row, or is null
+    fn check_references(&self, table: &Table, row: &Row) -> Result<(), ApplyError> {
+        for (i, field) in table.fields().iter().enumerate() {
+            if row.value(i).is_null() {
+                continue;
+            }
+            if let FieldKind::ForeignKey(ref target) = field.kind() {
+                self.check_reference(table, field.name(), target, row.value(i))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_reference(&self, table: &Table, field: FieldName, target: &TableName, value: &Value) -> Result<(), ApplyError> {
+        if self.reference_exists(target, value) {
+            Ok(())
+        }
+        else {
+            Err(ApplyError::InvalidReference(table.name(), field))
+        }
+    }
+
+    /// Enforce foreign keys to `removed`, which were just removed from `table`
+    /// or replaced by rows with another key
+    ///
+    /// Rows referencing a key no longer in the table are removed if their
+    /// foreign key cascades, otherwise this fails. Changes are not reverted
+    /// on failure, see `DataDB::apply`.
+    pub(crate) fn remove_referencing(&mut self, table: &Table, removed: &[Arc<Row>]) -> Result<(), ApplyError> {
+        let name = table.name();
+        if removed.is_empty() || !self.is_referenced(&name) {
+            return Ok(());
+        }
+
+        let collation = match table.key_field() {
+            Some(field) => table.fields()[field].collation(),
+            None => return Ok(()),
+        };
+        let keys: HashSet<Value> = removed.iter().map(|r| table.key_values(r).remove(0))
+            .filter(|key| !key.is_null() && !self.reference_exists(&name, key))
+            .map(|key| collation.key_value(&key).into_owned())
+            .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        for referencing in (*self.tables).clone() {
+            let referencing_name = referencing.name();
+            for i in referencing.references_to(&name) {
+                let (matching, rest): (Vec<_>, Vec<_>) = self.table_rows[&referencing_name].iter().cloned()
+                    .partition(|r| keys.contains(&*collation.key_value(r.value(i))));
+                if matching.is_empty() {
+                    continue;
+                }
+                match referencing.fields()[i].on_delete() {
+                    ReferenceAction::Restrict => return Err(ApplyError::Referenced(name, referencing_name)),
+                    ReferenceAction::Cascade => {
+                        for row in &matching {
+                            self.triggered_delete(&referencing_name, row)?;
+                        }
+                        if let Some(ref mut cascaded) = self.cascaded {
+                            cascaded.extend(matching.iter().map(|row| (referencing_name.clone(), row.clone())));
+                        }
+                        self.set_rows(&referencing, rest)?;
+                        self.remove_referencing(&referencing, &matching)?;
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+}
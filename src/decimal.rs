@@ -0,0 +1,150 @@
+//! Exact arithmetic on `Decimal` values, which are a mantissa scaled by a power of ten
+//!
+//! Operations fail instead of rounding or wrapping, so that no digits are lost silently.
+
+use std::cmp::Ordering;
+
+/// Most decimal digits a mantissa can always hold
+pub const MAX_PRECISION: u8 = 38;
+
+fn power_of_ten(exponent: u8) -> Option<i128> {
+    10i128.checked_pow(u32::from(exponent))
+}
+
+/// Mantissa of the same number with scale `to`, none if it doesn't fit or digits would be lost
+pub fn rescale(mantissa: i128, scale: u8, to: u8) -> Option<i128> {
+    if to >= scale {
+        mantissa.checked_mul(power_of_ten(to - scale)?)
+    }
+    else {
+        let divisor = power_of_ten(scale - to)?;
+        if mantissa % divisor != 0 {
+            return None;
+        }
+        Some(mantissa / divisor)
+    }
+}
+
+/// Number of decimal digits in the mantissa
+pub fn digits(mantissa: i128) -> u8 {
+    let mut value = mantissa.unsigned_abs();
+    let mut digits = 1;
+    while value >= 10 {
+        value /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+/// Sum with the larger of the two scales, none on overflow
+pub fn add(a: (i128, u8), b: (i128, u8)) -> Option<(i128, u8)> {
+    let scale = a.1.max(b.1);
+    let sum = rescale(a.0, a.1, scale)?.checked_add(rescale(b.0, b.1, scale)?)?;
+    Some((sum, scale))
+}
+
+/// Difference with the larger of the two scales, none on overflow
+pub fn sub(a: (i128, u8), b: (i128, u8)) -> Option<(i128, u8)> {
+    add(a, (b.0.checked_neg()?, b.1))
+}
+
+/// Product with the sum of the scales, none on overflow
+pub fn mul(a: (i128, u8), b: (i128, u8)) -> Option<(i128, u8)> {
+    let scale = a.1.checked_add(b.1).filter(|&scale| scale <= MAX_PRECISION)?;
+    Some((a.0.checked_mul(b.0)?, scale))
+}
+
+/// Exact quotient with the smallest scale at least that of `a`, none if
+/// the quotient has more than `MAX_PRECISION` fractional digits or on overflow
+///
+/// The divisor must not be zero.
+pub fn div(a: (i128, u8), b: (i128, u8)) -> Option<(i128, u8)> {
+    for scale in a.1..=MAX_PRECISION {
+        // a / b = (a.0 × 10^(scale + b.1 - a.1) / b.0) × 10^-scale
+        let dividend = rescale(a.0, a.1, scale.checked_add(b.1)?)?;
+        if dividend.checked_rem(b.0)? == 0 {
+            return Some((dividend.checked_div(b.0)?, scale));
+        }
+    }
+    None
+}
+
+/// Remainder of truncating division, with the larger of the two scales
+///
+/// The divisor must not be zero.
+pub fn rem(a: (i128, u8), b: (i128, u8)) -> Option<(i128, u8)> {
+    let scale = a.1.max(b.1);
+    Some((rescale(a.0, a.1, scale)?.wrapping_rem(rescale(b.0, b.1, scale)?), scale))
+}
+
+/// The same number with the smallest scale, without trailing fractional zeros
+pub fn normalize(mantissa: i128, scale: u8) -> (i128, u8) {
+    let (mut mantissa, mut scale) = (mantissa, scale);
+    while scale > 0 && mantissa % 10 == 0 {
+        mantissa /= 10;
+        scale -= 1;
+    }
+    (mantissa, scale)
+}
+
+pub fn compare(a: (i128, u8), b: (i128, u8)) -> Ordering {
+    let scale = a.1.max(b.1);
+    match (rescale(a.0, a.1, scale), rescale(b.0, b.1, scale)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        // Only a number too large for the common scale overflows, so its sign decides
+        (None, _) => a.0.cmp(&0),
+        (_, None) => 0.cmp(&b.0),
+    }
+}
+
+/// Parse an optionally signed number with an optional fractional part, like `-12.50`
+pub fn parse(text: &str) -> Option<(i128, u8)> {
+    let (negative, unsigned) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(i) if i + 1 < unsigned.len() => (&unsigned[..i], &unsigned[i + 1..]),
+        Some(_) => return None,
+        None => (unsigned, ""),
+    };
+    let digits = whole.bytes().chain(fraction.bytes());
+    if whole.is_empty() || fraction.len() > MAX_PRECISION as usize || !digits.clone().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut mantissa: i128 = 0;
+    for digit in digits {
+        mantissa = mantissa.checked_mul(10)?.checked_add(i128::from(digit - b'0'))?;
+    }
+    Some((if negative { -mantissa } else { mantissa }, fraction.len() as u8))
+}
+
+/// Format with exactly `scale` fractional digits
+pub fn format(mantissa: i128, scale: u8) -> String {
+    let digits = mantissa.unsigned_abs().to_string();
+    let digits = format!("{:0>width$}", digits, width = scale as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+    let sign = if mantissa < 0 { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    }
+    else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// Integer part, rounded towards zero
+pub fn truncate(mantissa: i128, scale: u8) -> i128 {
+    power_of_ten(scale).map_or(0, |divisor| mantissa / divisor)
+}
+
+/// Whether the number has no fractional part
+pub fn is_integral(mantissa: i128, scale: u8) -> bool {
+    power_of_ten(scale).map_or(mantissa == 0, |divisor| mantissa % divisor == 0)
+}
+
+/// Nearest floating-point number
+pub fn to_real(mantissa: i128, scale: u8) -> f64 {
+    mantissa as f64 / 10f64.powi(i32::from(scale))
+}
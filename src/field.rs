@@ -1,4 +1,6 @@
 use TableName;
+use {Value, ValueKind};
+use decimal::MAX_PRECISION;
 
 #[derive(Debug, Clone)]
 pub enum Field {
@@ -25,10 +27,125 @@ pub enum FieldKind {
     Text,
     /// Arbitrary binary data
     Blob,
-    /// Arbitrary binary data
+    /// Value of the key field of a row of table $0, which must have a single key field
     ForeignKey(TableName),
+    /// Calendar date, with a day precision
+    Date,
+    /// Time of day, with a microsecond precision
+    Time,
+    /// Point in time in UTC, with a microsecond precision
+    Timestamp,
+    /// Decimal(precision, scale), an exact number of at most `precision`
+    /// digits, `scale` of them after the decimal point
+    Decimal(u8, u8),
+    /// Universally unique identifier
+    Uuid,
+    /// Array of values of the kind, which can't be a foreign key
+    Array(Box<FieldKind>),
+    /// Latitude and longitude in degrees
+    GeoPoint,
+    /// Vector of single-precision numbers with the given nonzero dimension
+    Vector(u32),
+    /// True or false
+    Boolean,
 }
 impl FieldKind {
+    /// Kind of fields holding values like `value`, none for null or an array
+    /// without other elements; integers are 128-bit and decimals have the
+    /// maximum precision
+    pub(crate) fn of_value(value: &Value) -> Option<FieldKind> {
+        Some(match value {
+            Value::Null => return None,
+            Value::Boolean(_) => FieldKind::Boolean,
+            Value::Unsigned(_) => FieldKind::Integer(IntSize::N128, false),
+            Value::Signed(_) => FieldKind::Integer(IntSize::N128, true),
+            Value::Real(_) => FieldKind::Real,
+            Value::Text(_) => FieldKind::Text,
+            Value::Blob(_) => FieldKind::Blob,
+            Value::Date(_) => FieldKind::Date,
+            Value::Time(_) => FieldKind::Time,
+            Value::Timestamp(_) => FieldKind::Timestamp,
+            Value::Decimal(_, scale) => FieldKind::Decimal(MAX_PRECISION, *scale),
+            Value::Uuid(_) => FieldKind::Uuid,
+            Value::Array(elements) => {
+                let mut kind: Option<FieldKind> = None;
+                for element in elements.iter().filter_map(FieldKind::of_value) {
+                    kind = Some(match kind {
+                        Some(kind) => kind.more_generic(&element)?,
+                        None => element,
+                    });
+                }
+                FieldKind::Array(Box::new(kind?))
+            },
+            Value::GeoPoint(_, _) => FieldKind::GeoPoint,
+            Value::Vector(v) => FieldKind::Vector(v.len() as u32),
+        })
+    }
+
+    /// Kind of the values of the fields, none for foreign keys, whose
+    /// values are like those of the key they refer to
+    pub(crate) fn value_kind(&self) -> Option<ValueKind> {
+        Some(match self {
+            FieldKind::Integer(_, false) => ValueKind::Unsigned,
+            FieldKind::Integer(_, true) => ValueKind::Signed,
+            FieldKind::Real => ValueKind::Real,
+            FieldKind::Text => ValueKind::Text,
+            FieldKind::Blob => ValueKind::Blob,
+            FieldKind::ForeignKey(_) => return None,
+            FieldKind::Date => ValueKind::Date,
+            FieldKind::Time => ValueKind::Time,
+            FieldKind::Timestamp => ValueKind::Timestamp,
+            FieldKind::Decimal(_, _) => ValueKind::Decimal,
+            FieldKind::Uuid => ValueKind::Uuid,
+            FieldKind::Array(_) => ValueKind::Array,
+            FieldKind::GeoPoint => ValueKind::GeoPoint,
+            FieldKind::Vector(_) => ValueKind::Vector,
+            FieldKind::Boolean => ValueKind::Boolean,
+        })
+    }
+
+    /// Kind of fields holding values of both kinds, after casting them like
+    /// `ValueKind::more_generic` does, none if there is no such kind
+    pub(crate) fn more_generic(&self, other: &FieldKind) -> Option<FieldKind> {
+        use self::FieldKind::*;
+        Some(match (self, other) {
+            (a, b) if a == b => a.clone(),
+            (&Integer(a, signed), &Integer(b, other_signed)) if signed == other_signed => Integer(a.max(b), signed),
+            (&Integer(signed, true), &Integer(unsigned, false))
+            | (&Integer(unsigned, false), &Integer(signed, true)) => Integer(signed.max(unsigned.wider()), true),
+            (Integer(_, _), Real) | (Real, Integer(_, _)) | (Decimal(_, _), Real) | (Real, Decimal(_, _)) => Real,
+            (Integer(_, _), &Decimal(_, scale)) | (&Decimal(_, scale), Integer(_, _)) => Decimal(MAX_PRECISION, scale),
+            (&Decimal(p1, s1), &Decimal(p2, s2)) => {
+                let scale = s1.max(s2);
+                Decimal((p1 - s1).max(p2 - s2).saturating_add(scale).min(MAX_PRECISION), scale)
+            },
+            (Date, Timestamp) | (Timestamp, Date) => Timestamp,
+            (Array(a), Array(b)) => Array(Box::new(a.more_generic(b)?)),
+            _ => return None,
+        })
+    }
+
+    /// Whether the parameters of the kind are in range
+    pub fn is_valid(&self) -> bool {
+        match self {
+            FieldKind::Decimal(precision, scale) => *precision >= 1 && *precision <= MAX_PRECISION && scale <= precision,
+            FieldKind::Vector(dimension) => *dimension > 0,
+            FieldKind::Array(kind) => match **kind {
+                FieldKind::ForeignKey(_) => false,
+                ref kind => kind.is_valid(),
+            },
+            _ => true,
+        }
+    }
+
+    /// Whether stored values of the kind are ordered by `Value::compare`
+    pub fn is_ordered(&self) -> bool {
+        match self {
+            FieldKind::ForeignKey(_) | FieldKind::Array(_) | FieldKind::GeoPoint | FieldKind::Vector(_) => false,
+            _ => true,
+        }
+    }
+
     pub fn constant_size_bytes(self) -> Option<u8> {
         if let FieldKind::Integer(size, _) = self {
             Some(size.size_bytes())
@@ -39,7 +156,7 @@ impl FieldKind {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum IntSize {
     N8,   // [iu]8
     N16,  // [iu]16
@@ -58,4 +175,54 @@ impl IntSize {
             N128 => 16,
         }
     }
+
+    /// The next larger size, which can hold any unsigned integer of this
+    /// size as a signed one, except for 128 bits
+    fn wider(self) -> Self {
+        use self::IntSize::*;
+        match self {
+            N8   => N16,
+            N16  => N32,
+            N32  => N64,
+            N64 | N128 => N128,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        u32::from(self.size_bytes()) * 8
+    }
+
+    /// Whether an unsigned integer of this size can hold `value`
+    pub fn fits_unsigned(self, value: u128) -> bool {
+        self == IntSize::N128 || value >> self.bits() == 0
+    }
+
+    /// Whether a signed integer of this size can hold `value`
+    pub fn fits_signed(self, value: i128) -> bool {
+        self == IntSize::N128 || {
+            let limit = 1i128 << (self.bits() - 1);
+            -limit <= value && value < limit
+        }
+    }
+
+    /// `value` wrapped around to an unsigned integer of this size
+    pub(crate) fn wrap_unsigned(self, value: u128) -> u128 {
+        let shift = 128 - self.bits();
+        value << shift >> shift
+    }
+
+    /// `value` wrapped around to a signed integer of this size
+    pub(crate) fn wrap_signed(self, value: i128) -> i128 {
+        let shift = 128 - self.bits();
+        value << shift >> shift
+    }
+
+    /// `value` clamped to the limits of a signed integer of this size
+    pub(crate) fn saturate_signed(self, value: i128) -> i128 {
+        if self == IntSize::N128 {
+            return value;
+        }
+        let limit = 1i128 << (self.bits() - 1);
+        value.max(-limit).min(limit - 1)
+    }
 }
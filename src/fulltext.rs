@@ -0,0 +1,24 @@
+//! Tokenization of `Text` values for full-text search
+//!
+//! Text is split into words at every character that isn't alphanumeric, and
+//! words are compared case-insensitively.
+
+use std::collections::HashSet;
+
+/// Distinct lowercase words of the text, in order of first appearance
+pub fn tokens(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| seen.insert(word.clone()))
+        .collect()
+}
+
+/// Whether the text has all words of the query, in any order
+///
+/// A query without words matches any text.
+pub fn matches(text: &str, query: &str) -> bool {
+    let words: HashSet<String> = tokens(text).into_iter().collect();
+    tokens(query).iter().all(|word| words.contains(word))
+}
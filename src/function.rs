@@ -1,49 +1,320 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use DataDB;
+use ApplyError;
 use FunctionName;
+use TableName;
+use FieldName;
 use QueryField;
 use QueryError;
 use Value;
+use Row;
+use value::ValueKind;
+use builtin_functions;
 
 #[derive(Clone)]
 pub enum Function {
     Native(NativeFunction),
+    /// Native functions registered under the same name, the one called chosen
+    /// by the kinds of the arguments, see `resolve`
+    Overloaded(Vec<NativeFunction>),
+    /// Call of other functions, with `Argument::Parameter`s bound to the arguments
     Composed(FunctionCall),
 }
 impl Function {
-    pub fn call(&self, arguments: Vec<Value>) -> Result<Value, QueryError> {
+    /// Arguments the function accepts
+    ///
+    /// Composed functions take exactly as many arguments as their body has
+    /// parameters. Overloaded functions take any arity and kinds one of the
+    /// overloads takes.
+    pub fn signature(&self) -> Signature {
         match self {
-            Function::Native(nf) => nf.call(arguments),
-            Function::Composed(cf) => {
-                println!("{:?}", cf);
-                unimplemented!();
+            Function::Native(nf) => nf.signature,
+            Function::Overloaded(overloads) => {
+                let signatures: Vec<Signature> = overloads.iter().map(|o| o.signature).collect();
+                let returns = signatures[0].returns;
+                Signature {
+                    min_arity: signatures.iter().map(|s| s.min_arity).min().unwrap_or(0),
+                    max_arity: signatures.iter().map(|s| s.max_arity).max().unwrap_or(None)
+                        .filter(|_| signatures.iter().all(|s| s.max_arity.is_some())),
+                    returns: returns.filter(|_| signatures.iter().all(|s| s.returns == returns)),
+                    ..Signature::ANY
+                }
+            },
+            Function::Composed(body) => Signature::exactly(body.parameter_count()),
+        }
+    }
+
+    /// Check a call of the function as `name` with arguments of the kinds, none where it isn't known
+    pub(crate) fn check(&self, name: &str, kinds: &[Option<ValueKind>]) -> Result<(), QueryError> {
+        match self {
+            Function::Overloaded(overloads) => {
+                if overloads.iter().any(|o| o.signature.check(name, kinds).is_ok()) {
+                    Ok(())
+                }
+                else {
+                    Err(QueryError::NoMatchingOverload(name.to_owned(), known_kinds(kinds)))
+                }
+            },
+            _ => self.signature().check(name, kinds),
+        }
+    }
+
+    /// Call as function `name`, looking up the functions a composed function calls in `function_dict`
+    pub fn call(&self, name: &str, function_dict: &HashMap<FunctionName, Function>, arguments: Vec<Value>) -> Result<Value, QueryError> {
+        self.call_in(name, function_dict, arguments, &[])
+    }
+
+    /// Call while applying the composed functions `active`, see `FunctionCall::apply_in`
+    fn call_in(
+        &self,
+        name: &str,
+        function_dict: &HashMap<FunctionName, Function>,
+        arguments: Vec<Value>,
+        active: &[&str],
+    ) -> Result<Value, QueryError> {
+        let kinds: Vec<_> = arguments.iter().map(|v| Some(v.kind())).collect();
+        match self {
+            Function::Native(nf) => {
+                nf.signature.check(name, &kinds)?;
+                nf.call(arguments)
+            },
+            Function::Overloaded(overloads) => resolve(name, overloads, &kinds)?.call(arguments),
+            Function::Composed(body) => {
+                self.signature().check(name, &kinds)?;
+                if active.contains(&name) {
+                    return Err(QueryError::CyclicFunction(name.to_owned()));
+                }
+                let mut active = active.to_vec();
+                active.push(name);
+                body.apply_in(function_dict, &arguments, &active)
+            },
+        }
+    }
+}
+
+/// Function returning rows instead of a single value, see `Query::FromTableFunction`
+#[derive(Clone)]
+pub struct TableFunction {
+    function: Arc<Fn(Vec<Value>) -> Result<Box<Iterator<Item = Row>>, QueryError> + Send + Sync>,
+    /// Names of the fields of the rows
+    fields: Vec<FieldName>,
+    signature: Signature,
+}
+impl TableFunction {
+    pub fn new<F>(fields: &[&str], function: F) -> Self
+        where F: Fn(Vec<Value>) -> Result<Box<Iterator<Item = Row>>, QueryError> + Send + Sync + 'static
+    {
+        Self {
+            function: Arc::new(function),
+            fields: fields.iter().map(|f| (*f).to_owned()).collect(),
+            signature: Signature::ANY,
+        }
+    }
+
+    pub fn with_signature(self, signature: Signature) -> Self {
+        Self { signature, ..self }
+    }
+
+    pub fn fields(&self) -> Vec<FieldName> {
+        self.fields.clone()
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.signature
+    }
+
+    /// Rows for the arguments, produced as they are consumed
+    pub(crate) fn call(&self, name: &str, arguments: Vec<Value>) -> Result<Box<Iterator<Item = Row>>, QueryError> {
+        let kinds: Vec<_> = arguments.iter().map(|v| Some(v.kind())).collect();
+        self.signature.check(name, &kinds)?;
+        (self.function)(arguments)
+    }
+}
+
+/// Register native function `function` as `name`, as an overload if there
+/// already are native functions with the name
+pub(crate) fn register(functions: &mut HashMap<FunctionName, Function>, name: &str, function: NativeFunction) {
+    let registered = match functions.remove(name) {
+        Some(Function::Native(existing)) => Function::Overloaded(vec![existing, function]),
+        Some(Function::Overloaded(mut overloads)) => {
+            overloads.push(function);
+            Function::Overloaded(overloads)
+        },
+        _ => Function::Native(function),
+    };
+    functions.insert(name.to_owned(), registered);
+}
+
+/// Kinds of arguments, null where it isn't known
+fn known_kinds(kinds: &[Option<ValueKind>]) -> Vec<ValueKind> {
+    kinds.iter().map(|kind| kind.unwrap_or(ValueKind::Null)).collect()
+}
+
+/// The overload of function `name` to call with arguments of the kinds
+///
+/// Of the overloads accepting the arguments, the one whose parameters accept
+/// the fewest kinds for each non-null argument is chosen. If several are
+/// equally specific, the first registered is chosen when some arguments are
+/// null, since those can't tell them apart, and otherwise the call is ambiguous.
+fn resolve<'a>(name: &str, overloads: &'a [NativeFunction], kinds: &[Option<ValueKind>]) -> Result<&'a NativeFunction, QueryError> {
+    let known: Vec<usize> = (0..kinds.len()).filter(|i| kinds[*i].map_or(false, |k| k != ValueKind::Null)).collect();
+    let candidates: Vec<(&NativeFunction, Vec<usize>)> = overloads.iter()
+        .filter(|o| o.signature.check(name, kinds).is_ok())
+        .map(|o| (o, known.iter().map(|i| o.signature.parameter(*i).map_or(usize::max_value(), |k| k.len())).collect()))
+        .collect();
+    if candidates.is_empty() {
+        return Err(QueryError::NoMatchingOverload(name.to_owned(), known_kinds(kinds)));
+    }
+    let at_least_as_specific = |a: &[usize], b: &[usize]| a.iter().zip(b).all(|(x, y)| x <= y);
+    let best: Vec<&NativeFunction> = candidates.iter()
+        .filter(|(_, s)| candidates.iter().all(|(_, t)| at_least_as_specific(s, t)))
+        .map(|(o, _)| *o)
+        .collect();
+    match best.len() {
+        1 => Ok(best[0]),
+        n if n > 1 && known.len() < kinds.len() => Ok(best[0]),
+        _ => Err(QueryError::AmbiguousCall(name.to_owned(), known_kinds(kinds))),
+    }
+}
+
+/// Arity and argument kinds a function accepts, and the kind of its result
+///
+/// Calls are checked against the signature before the function is called,
+/// and also before a query executes if the arguments are known then.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signature {
+    pub min_arity: usize,
+    /// None if there can be any number of further arguments
+    pub max_arity: Option<usize>,
+    /// Kinds accepted by each argument besides null, the last ones also by
+    /// any further arguments; no kinds accept any kind
+    pub parameters: &'static [&'static [ValueKind]],
+    /// None if it depends on the arguments
+    pub returns: Option<ValueKind>,
+}
+impl Signature {
+    /// Any arguments, any result
+    pub const ANY: Signature = Signature { min_arity: 0, max_arity: None, parameters: &[], returns: None };
+
+    pub const fn exactly(arity: usize) -> Self {
+        Signature { min_arity: arity, max_arity: Some(arity), ..Signature::ANY }
+    }
+
+    pub const fn at_least(arity: usize) -> Self {
+        Signature { min_arity: arity, ..Signature::ANY }
+    }
+
+    pub const fn between(min_arity: usize, max_arity: usize) -> Self {
+        Signature { min_arity, max_arity: Some(max_arity), ..Signature::ANY }
+    }
+
+    pub const fn taking(self, parameters: &'static [&'static [ValueKind]]) -> Self {
+        Signature { parameters, ..self }
+    }
+
+    pub const fn returning(self, kind: ValueKind) -> Self {
+        Signature { returns: Some(kind), ..self }
+    }
+
+    /// Kinds accepted by argument `i`, none if any kind is
+    fn parameter(&self, i: usize) -> Option<&'static [ValueKind]> {
+        let kinds = *self.parameters.get(i).or(self.parameters.last())?;
+        if kinds.is_empty() { None } else { Some(kinds) }
+    }
+
+    /// Check a call of function `name` with arguments of the kinds, none where it isn't known
+    pub(crate) fn check(&self, name: &str, kinds: &[Option<ValueKind>]) -> Result<(), QueryError> {
+        if kinds.len() < self.min_arity {
+            return Err(QueryError::NotEnoughArguments(self.min_arity));
+        }
+        if let Some(max_arity) = self.max_arity {
+            if kinds.len() > max_arity {
+                return Err(QueryError::TooManyArguments(name.to_owned(), max_arity));
+            }
+        }
+        for (i, kind) in kinds.iter().enumerate() {
+            if let (Some(kind), Some(accepted)) = (kind, self.parameter(i)) {
+                if *kind != ValueKind::Null && !accepted.contains(kind) {
+                    return Err(QueryError::InvalidArgument(name.to_owned(), i, *kind));
+                }
             }
         }
+        Ok(())
     }
 }
 
 #[derive(Clone)]
 pub struct NativeFunction {
-    function: &'static Fn(Vec<Value>) -> Result<Value, QueryError>
+    /// Shared between the clones, so it may hold state like configuration
+    function: Arc<Fn(Vec<Value>) -> Result<Value, QueryError> + Send + Sync>,
+    signature: Signature,
 }
 impl NativeFunction {
-    pub(crate) const fn new(function: &'static Fn(Vec<Value>) -> Result<Value, QueryError>) -> Self {
-        Self { function }
+    pub fn new<F>(function: F) -> Self
+        where F: Fn(Vec<Value>) -> Result<Value, QueryError> + Send + Sync + 'static
+    {
+        Self { function: Arc::new(function), signature: Signature::ANY }
     }
+
+    pub fn with_signature(self, signature: Signature) -> Self {
+        Self { signature, ..self }
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.signature
+    }
+
     fn call(&self, arguments: Vec<Value>) -> Result<Value, QueryError> {
         (self.function)(arguments)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Argument {
     FunctionCall(FunctionCall),
     Value(Value),
     QueryField(QueryField),
+    /// Argument $0 of the call of the composed function this is in
+    Parameter(usize),
+}
+impl Argument {
+    pub(crate) fn evaluate(
+        &self,
+        function_dict: &HashMap<FunctionName, Function>,
+        resolve: &Fn(&QueryField) -> Result<Value, QueryError>
+    ) -> Result<Value, QueryError> {
+        match self {
+            Argument::Value(v) => Ok(v.clone()),
+            Argument::QueryField(qf) => resolve(qf),
+            Argument::FunctionCall(fc) => fc.resolve_args(resolve)?.apply(function_dict),
+            // Not in a composed function, so there are no arguments
+            Argument::Parameter(i) => Err(QueryError::NotEnoughArguments(i + 1)),
+        }
+    }
+
+    /// Number of parameters the argument refers to, see `FunctionCall::parameter_count`
+    pub(crate) fn parameter_count(&self) -> usize {
+        match self {
+            Argument::FunctionCall(fc) => fc.parameter_count(),
+            Argument::Parameter(i) => i + 1,
+            _ => 0,
+        }
+    }
+
+    /// The same argument with parameters bound to `parameters`, see `FunctionCall::bound`
+    pub(crate) fn bound(&self, parameters: &[Value]) -> Argument {
+        match self {
+            Argument::FunctionCall(fc) => Argument::FunctionCall(fc.bound(parameters)),
+            Argument::Parameter(i) if *i < parameters.len() => Argument::Value(parameters[*i].clone()),
+            arg => arg.clone(),
+        }
+    }
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FunctionCall {
     pub target: String,
     pub arguments: Vec<Argument>
@@ -66,22 +337,192 @@ impl FunctionCall {
                 Argument::FunctionCall(fc) => Argument::FunctionCall(fc.resolve_args(resolve)?),
                 Argument::Value(v) => Argument::Value(v.clone()),
                 Argument::QueryField(qf) => Argument::Value(resolve(&qf)?),
+                Argument::Parameter(i) => Argument::Parameter(i),
             });
         }
         Ok(FunctionCall::new(self.target.clone(), new_args))
     }
 
+    /// Fields referenced by the call and the calls nested in it
+    pub(crate) fn fields(&self) -> Vec<QueryField> {
+        let mut fields = Vec::new();
+        for arg in &self.arguments {
+            match arg {
+                Argument::FunctionCall(fc) => fields.extend(fc.fields()),
+                Argument::Value(_) | Argument::Parameter(_) => {},
+                Argument::QueryField(qf) => fields.push(qf.clone()),
+            }
+        }
+        fields
+    }
+
+    /// Number of parameters the call and the calls nested in it refer to,
+    /// that is the highest parameter plus one
+    pub(crate) fn parameter_count(&self) -> usize {
+        self.arguments.iter().map(Argument::parameter_count).max().unwrap_or(0)
+    }
+
+    /// The same call with `Argument::Parameter`s in it replaced by the values
+    /// of `parameters`, leaving those without a value
+    pub(crate) fn bound(&self, parameters: &[Value]) -> FunctionCall {
+        let arguments = self.arguments.iter().map(|arg| arg.bound(parameters)).collect();
+        FunctionCall::new(self.target.clone(), arguments)
+    }
+
+    /// Check that the functions the call and the calls nested in it call
+    /// exist, their arity, and the kinds of their arguments that are known
+    /// before evaluating them
+    pub(crate) fn check(&self, function_dict: &HashMap<FunctionName, Function>) -> Result<(), QueryError> {
+        let kinds = self.argument_kinds(function_dict)?;
+        match function_dict.get(&self.target) {
+            Some(function) => function.check(&self.target, &kinds),
+            None => Err(QueryError::NoSuchFunction(self.target.clone())),
+        }
+    }
+
+    /// Kinds of the arguments known before evaluating them, after checking the calls among them
+    pub(crate) fn argument_kinds(&self, function_dict: &HashMap<FunctionName, Function>) -> Result<Vec<Option<ValueKind>>, QueryError> {
+        let mut kinds = Vec::new();
+        for arg in &self.arguments {
+            kinds.push(match arg {
+                Argument::Value(v) => Some(v.kind()),
+                Argument::FunctionCall(fc) => {
+                    fc.check(function_dict)?;
+                    function_dict.get(&fc.target).and_then(|f| f.signature().returns)
+                },
+                Argument::QueryField(_) | Argument::Parameter(_) => None,
+            });
+        }
+        Ok(kinds)
+    }
+
+    /// Names of the functions the call and the calls nested in it call
+    pub(crate) fn targets(&self) -> Vec<&FunctionName> {
+        let mut targets = vec![&self.target];
+        for arg in &self.arguments {
+            if let Argument::FunctionCall(fc) = arg {
+                targets.extend(fc.targets());
+            }
+        }
+        targets
+    }
+
+    /// Replace references to field `from` with references to field `to`
+    pub(crate) fn rename_field(&mut self, from: &FieldName, to: &FieldName) {
+        for arg in self.arguments.iter_mut() {
+            match arg {
+                Argument::FunctionCall(fc) => fc.rename_field(from, to),
+                Argument::Value(_) | Argument::Parameter(_) => {},
+                Argument::QueryField(qf) => if qf.field == *from {
+                    qf.field = to.clone();
+                },
+            }
+        }
+    }
+
+    /// The same call with references to fields of table `table` unqualified
+    pub(crate) fn unqualified(&self, table: &TableName) -> FunctionCall {
+        let arguments = self.arguments.iter().map(|arg| match arg {
+            Argument::FunctionCall(fc) => Argument::FunctionCall(fc.unqualified(table)),
+            Argument::QueryField(qf) if qf.table.as_ref() == Some(table) => Argument::QueryField(QueryField::new(qf.field.clone())),
+            arg => arg.clone(),
+        }).collect();
+        FunctionCall::new(self.target.clone(), arguments)
+    }
+
     pub(crate) fn apply(&self, function_dict: &HashMap<FunctionName, Function>) -> Result<Value, QueryError> {
+        self.apply_in(function_dict, &[], &[])
+    }
+
+    /// Apply with `parameters` bound to the arguments of the innermost composed
+    /// function being applied, while applying the composed functions `active`
+    fn apply_in(
+        &self,
+        function_dict: &HashMap<FunctionName, Function>,
+        parameters: &[Value],
+        active: &[&str],
+    ) -> Result<Value, QueryError> {
+        if self.target == "iif" || self.target == "case" {
+            return self.apply_conditional(function_dict, parameters, active);
+        }
+        let function = function_dict.get(&self.target).ok_or_else(|| QueryError::NoSuchFunction(self.target.clone()))?;
         let mut args: Vec<Value> = Vec::new();
 
-        for arg in self.arguments.clone() {
-            args.push(match arg {
-                Argument::Value(v) => v.clone(),
-                Argument::FunctionCall(fc) => fc.apply(function_dict)?,
-                _ => panic!("Applying with unresolved query fields")
-            });
+        for i in 0..self.arguments.len() {
+            args.push(self.argument(i, function_dict, parameters, active)?);
         };
 
-        function_dict.get(&self.target).expect(&format!("No function named '{}'", self.target)).call(args)
+        function.call_in(&self.target, function_dict, args, active)
+    }
+
+    /// Value of argument `i`, see `apply_in`
+    fn argument(
+        &self,
+        i: usize,
+        function_dict: &HashMap<FunctionName, Function>,
+        parameters: &[Value],
+        active: &[&str],
+    ) -> Result<Value, QueryError> {
+        match &self.arguments[i] {
+            Argument::Value(v) => Ok(v.clone()),
+            Argument::FunctionCall(fc) => fc.apply_in(function_dict, parameters, active),
+            Argument::Parameter(p) => parameters.get(*p).cloned().ok_or(QueryError::NotEnoughArguments(p + 1)),
+            Argument::QueryField(qf) => Err(QueryError::UnboundField(qf.clone())),
+        }
+    }
+
+    /// Apply `iif` or `case`, evaluating only the conditions up to the one that
+    /// holds and the chosen value, so that other values can't fail the call
+    fn apply_conditional(
+        &self,
+        function_dict: &HashMap<FunctionName, Function>,
+        parameters: &[Value],
+        active: &[&str],
+    ) -> Result<Value, QueryError> {
+        let evaluate = |i: usize| self.argument(i, function_dict, parameters, active);
+        let kinds = vec![None; self.arguments.len()];
+        function_dict.get(&self.target).ok_or_else(|| QueryError::NoSuchFunction(self.target.clone()))?
+            .check(&self.target, &kinds)?;
+        let arguments = self.arguments.len();
+        match builtin_functions::case_branch(arguments, &evaluate)? {
+            Some(i) => evaluate(i),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+impl DataDB {
+    /// Define composed function `name`
+    ///
+    /// Since the body can only call functions that already exist, composed
+    /// functions defined this way can't call themselves.
+    pub(crate) fn create_function(&mut self, name: FunctionName, body: FunctionCall) -> Result<(), ApplyError> {
+        if self.functions.contains_key(&name) {
+            return Err(ApplyError::FunctionExists(name));
+        }
+        if !body.fields().is_empty() {
+            return Err(ApplyError::InvalidFunction(name));
+        }
+        if let Some(target) = body.targets().into_iter().find(|target| !self.functions.contains_key(*target)) {
+            return Err(ApplyError::NoSuchFunction(target.clone()));
+        }
+        Arc::make_mut(&mut self.functions).insert(name, Function::Composed(body));
+        Ok(())
+    }
+
+    pub(crate) fn drop_function(&mut self, name: FunctionName) -> Result<(), ApplyError> {
+        match self.functions.get(&name) {
+            Some(Function::Composed(_)) => {},
+            _ => return Err(ApplyError::NoSuchFunction(name)),
+        }
+        for (caller, function) in self.functions.iter() {
+            if let Function::Composed(body) = function {
+                if body.targets().contains(&&name) {
+                    return Err(ApplyError::FunctionInUse(name, caller.clone()));
+                }
+            }
+        }
+        Arc::make_mut(&mut self.functions).remove(&name);
+        Ok(())
     }
 }
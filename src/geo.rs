@@ -0,0 +1,50 @@
+//! Geodesy for `GeoPoint` values, which are WGS 84 latitude and longitude in degrees
+//!
+//! Distances are great-circle distances on a sphere with the mean radius of
+//! the Earth, so they can be off by up to about 0.5%.
+
+/// Mean radius of the Earth in meters
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// The point with -0.0 replaced by 0.0, none if it isn't on the globe
+pub fn normalize(lat: f64, lon: f64) -> Option<(f64, f64)> {
+    if !(lat >= -90.0 && lat <= 90.0 && lon >= -180.0 && lon <= 180.0) {
+        return None;
+    }
+    Some((lat + 0.0, lon + 0.0))
+}
+
+/// Parse `lat,lon`
+pub fn parse(text: &str) -> Option<(f64, f64)> {
+    let mut parts = text.splitn(2, ',');
+    let lat = parts.next()?.trim().parse().ok()?;
+    let lon = parts.next()?.trim().parse().ok()?;
+    normalize(lat, lon)
+}
+
+pub fn format(lat: f64, lon: f64) -> String {
+    format!("{},{}", lat, lon)
+}
+
+/// Great-circle distance in meters, using the haversine formula
+pub fn distance_m(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let half_dlat = (lat2 - lat1) / 2.0;
+    let half_dlon = (b.1 - a.1).to_radians() / 2.0;
+    let h = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlon.sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().min(1.0).asin()
+}
+
+/// Whether the point is in the box with the given south-west and north-east corners
+///
+/// A box whose west edge is east of its east edge crosses the antimeridian.
+pub fn in_box(point: (f64, f64), south_west: (f64, f64), north_east: (f64, f64)) -> bool {
+    let (lat, lon) = point;
+    let within_lon = if south_west.1 <= north_east.1 {
+        south_west.1 <= lon && lon <= north_east.1
+    }
+    else {
+        south_west.1 <= lon || lon <= north_east.1
+    };
+    south_west.0 <= lat && lat <= north_east.0 && within_lon
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+
+use {DataDB, Delta, RowUpdate, Table, Row, QueryResult};
+use function::Function;
+use trigger::TriggerEvent;
+
+/// An applied batch of deltas and the deltas that revert it
+#[derive(Debug, Clone)]
+pub(crate) struct HistoryEntry {
+    pub(crate) deltas: Vec<Delta>,
+    pub(crate) inverse: Vec<Delta>,
+}
+
+/// Undo and redo stacks, see `SrimDB::with_history`
+pub(crate) struct History {
+    depth: usize,
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+impl History {
+    pub(crate) fn new(depth: usize) -> Self {
+        Self { depth, undo: VecDeque::new(), redo: Vec::new() }
+    }
+
+    /// Record a new change, forgetting everything that could be redone
+    pub(crate) fn push(&mut self, entry: HistoryEntry) {
+        self.redo.clear();
+        self.push_undo(entry);
+    }
+
+    pub(crate) fn push_undo(&mut self, entry: HistoryEntry) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.undo.len() == self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(entry);
+    }
+
+    pub(crate) fn pop_undo(&mut self) -> Option<HistoryEntry> {
+        self.undo.pop_back()
+    }
+
+    pub(crate) fn push_redo(&mut self, entry: HistoryEntry) {
+        self.redo.push(entry);
+    }
+
+    pub(crate) fn pop_redo(&mut self) -> Option<HistoryEntry> {
+        self.redo.pop()
+    }
+}
+
+/// Deltas recreating a dropped table with its current schema, rows and triggers
+fn recreate_table(db: &DataDB, table: Table) -> Vec<Delta> {
+    let name = table.name();
+    let rows = rows_of(db, &name);
+    let mut deltas = vec![Delta::CreateTable(table), Delta::AddRows(name.clone(), rows)];
+    deltas.extend(db.triggers().iter().filter(|t| t.table == name).cloned().map(Delta::CreateTrigger));
+    deltas.extend(db.policy(&name).cloned().map(|policy| Delta::CreatePolicy(name.clone(), policy)));
+    deltas
+}
+
+fn rows_of(db: &DataDB, name: &str) -> Vec<Row> {
+    db.all_rows(name.to_owned()).unwrap_or_default().iter().map(|r| (**r).clone()).collect()
+}
+
+impl DataDB {
+    /// Deltas that revert `delta` when applied after it
+    ///
+    /// Must be called before applying `delta`. Reverted rows keep their
+    /// contents, but may end up in a different position within the table.
+    /// Returns no deltas if `delta` would fail or change nothing.
+    pub(crate) fn inverse(&self, delta: &Delta) -> Vec<Delta> {
+        use Delta::*;
+
+        // Restored rows are added with triggers disabled, so inverses of
+        // inserts and updates must match the rows as stored after triggers ran
+        let table = match delta.tables().first().and_then(|name| self.table(name.clone())) {
+            Some(table) => table,
+            None => return match delta {
+                CreateTable(table) => vec![DropTable(table.name())],
+                CreateFunction(name, _) if !self.function_dict().contains_key(name) => vec![DropFunction(name.clone())],
+                DropFunction(name) => match self.function_dict().get(name) {
+                    Some(Function::Composed(body)) => vec![CreateFunction(name.clone(), body.clone())],
+                    _ => Vec::new(),
+                },
+                CreateView(name, _) | CreateMaterializedView(name, _)
+                    if self.view(name).is_none() && self.materialized_view(name).is_none() && self.table(name.clone()).is_none() => {
+                    vec![DropView(name.clone())]
+                },
+                DropView(name) => match (self.view(name), self.materialized_view(name)) {
+                    (Some(query), _) => vec![CreateView(name.clone(), query.clone())],
+                    (None, Some(view)) => vec![CreateMaterializedView(name.clone(), view.query().clone())],
+                    (None, None) => Vec::new(),
+                },
+                CreateProcedure(name, _) if self.procedure(name).is_none() => vec![DropProcedure(name.clone())],
+                DropProcedure(name) => match self.procedure(name) {
+                    Some(steps) => vec![CreateProcedure(name.clone(), steps.to_vec())],
+                    None => Vec::new(),
+                },
+                _ => Vec::new(),
+            },
+        };
+        let name = table.name();
+        let referenced = self.is_referenced(&name);
+
+        let mut inverse = match delta {
+            CreateTable(_)
+            | CreateFunction(_, _)
+            | DropFunction(_)
+            | CreateView(_, _)
+            | CreateMaterializedView(_, _)
+            | RefreshView(_)
+            | DropView(_)
+            | CreateProcedure(_, _)
+            | DropProcedure(_) => Vec::new(),
+            DropTable(_) => recreate_table(self, table),
+            Truncate(_) => vec![AddRows(name.clone(), rows_of(self, &name))],
+            AddColumn(_, field, _) => vec![DropColumn(name, field.name())],
+            RenameTable(from, to) => vec![RenameTable(to.clone(), from.clone())],
+            RenameColumn(_, from, to) => vec![RenameColumn(name, to.clone(), from.clone())],
+            AlterColumnType(_, _, _)
+            | CreateIndex(_, _, _)
+            | CreateExpressionIndex(_, _, _)
+            | CreatePartialIndex(_, _, _, _)
+            | DropColumn(_, _)
+            | ForceDropColumn(_, _) => {
+                let mut inverse = vec![DropTable(name)];
+                inverse.extend(recreate_table(self, table));
+                inverse
+            },
+            AddRow(_, row) => match self.triggered_row(&table, TriggerEvent::Insert, row.clone()) {
+                Ok(row) => vec![RemoveRow(name, row)],
+                Err(_) => Vec::new(),
+            },
+            // Made concrete before applying, see `DataDB::concrete_deltas`
+            AddNamedRow(_, _) => Vec::new(),
+            AddRows(_, rows) => {
+                let mut inverse = Vec::new();
+                for row in rows.iter().rev() {
+                    match self.triggered_row(&table, TriggerEvent::Insert, row.clone()) {
+                        Ok(row) => inverse.push(RemoveRow(name.clone(), row)),
+                        Err(_) => return Vec::new(),
+                    }
+                }
+                inverse
+            },
+            RemoveRow(_, row) => vec![AddRow(name, row.clone())],
+            RemoveRowById(_, id) => match self.row_by_id(&name, *id) {
+                Some(row) => vec![AddRow(name, row)],
+                None => Vec::new(),
+            },
+            UpdateRowById(_, id, _) => match self.row_by_id(&name, *id) {
+                Some(row) => vec![UpdateRowById(name, *id, RowUpdate::Replace(row))],
+                None => Vec::new(),
+            },
+            UpdateRow(_, row, update) => match table.updated_row(row.clone(), update.clone())
+                .and_then(|new_row| self.triggered_row(&table, TriggerEvent::Update, new_row))
+            {
+                Ok(new_row) => vec![UpdateRow(name, new_row, RowUpdate::Replace(row.clone()))],
+                Err(_) => Vec::new(),
+            },
+            RemoveWhere(_, condition) => {
+                let current = match QueryResult::from_db_table(self, name.clone()) {
+                    Ok(current) => current,
+                    Err(_) => return Vec::new(),
+                };
+                let mut removed = Vec::new();
+                for row in current.shared_rows() {
+                    match current.test_row(&self.functions, condition, row) {
+                        Ok(true) => removed.push((**row).clone()),
+                        Ok(false) => {},
+                        Err(_) => return Vec::new(),
+                    }
+                }
+                vec![AddRows(name, removed)]
+            },
+            // Truncating a referenced table would remove or fail on referencing rows
+            UpdateWhere(_, _, _) if referenced => match self.applied(delta) {
+                Some(after) => self.table_rows[&name].iter()
+                    .filter_map(|row| {
+                        let id = row.id()?;
+                        let updated = after.row_by_id(&name, id)?;
+                        if updated == **row { None } else { Some(UpdateRowById(name.clone(), id, RowUpdate::Replace((**row).clone()))) }
+                    })
+                    .collect(),
+                None => Vec::new(),
+            },
+            UpdateWhere(_, _, _) => vec![Truncate(name.clone()), AddRows(name.clone(), rows_of(self, &name))],
+            Upsert(_, row) => {
+                let row = match table.validate_row(row.clone()).and_then(|row| self.generated_row(&table, row)) {
+                    Ok(row) => row,
+                    Err(_) => return Vec::new(),
+                };
+                let existing = self.all_rows(name.clone()).unwrap_or_default()
+                    .into_iter().find(|r| table.same_key(r, &row));
+                let event = if existing.is_some() { TriggerEvent::Update } else { TriggerEvent::Insert };
+                let row = match self.triggered_row(&table, event, row) {
+                    Ok(row) => row,
+                    Err(_) => return Vec::new(),
+                };
+                match existing {
+                    Some(old) => vec![UpdateRow(name, row, RowUpdate::Replace((*old).clone()))],
+                    None => vec![RemoveRow(name, row)],
+                }
+            },
+            CreateTrigger(trigger) => {
+                if self.triggers().iter().any(|t| t.table == trigger.table && t.name == trigger.name) {
+                    Vec::new()
+                }
+                else {
+                    vec![DropTrigger(name, trigger.name.clone())]
+                }
+            },
+            DropTrigger(_, trigger) => {
+                self.triggers().iter().find(|t| t.table == name && t.name == *trigger)
+                    .map(|t| vec![CreateTrigger(t.clone())]).unwrap_or_default()
+            },
+            CreatePolicy(_, _) if self.policy(&name).is_none() => vec![DropPolicy(name)],
+            CreatePolicy(_, _) => Vec::new(),
+            DropPolicy(_) => self.policy(&name).map(|policy| vec![CreatePolicy(name.clone(), policy.clone())]).unwrap_or_default(),
+        };
+
+        // Rows removed through cascading foreign keys are restored after the
+        // rows they reference, in the order they were removed
+        if !inverse.is_empty() && referenced {
+            if let Some(cascaded) = self.applied(delta).and_then(|after| after.cascaded) {
+                inverse.extend(cascaded.into_iter().map(|(table, row)| AddRow(table, (*row).clone())));
+            }
+        }
+        inverse
+    }
+
+    /// State after applying `delta`, recording rows removed by cascading foreign keys
+    fn applied(&self, delta: &Delta) -> Option<DataDB> {
+        let mut after = self.clone();
+        after.cascaded = Some(Vec::new());
+        after.apply(delta.clone()).ok()?;
+        Some(after)
+    }
+}
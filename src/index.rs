@@ -0,0 +1,1169 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::cmp::Ordering;
+use std::collections::{HashMap, BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+use {DataDB, Table, TableName, FieldName, FunctionName, Row, RowId, Value, QueryField, QueryResult, QueryError, ApplyError};
+use query::Condition;
+use table::IndexKind;
+use function::{Function, FunctionCall};
+use codec::Encode;
+use trigger::table_fields;
+use fulltext;
+use partition::Partitions;
+use collation::{self, Collation};
+
+/// Encoded values of the given columns, none if any of them is null
+///
+/// Nulls are never equal, so rows with them are never found by a lookup.
+fn row_key(row: &Row, columns: &[usize]) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+    for &i in columns {
+        match row.value(i) {
+            Value::Null => return None,
+            // Equal values must have equal encodings
+            Value::Real(r) if *r == 0.0 => Value::Real(0.0).encode(&mut key),
+            Value::Real(r) if r.is_nan() => Value::Real(::std::f64::NAN).encode(&mut key),
+            value => value.encode(&mut key),
+        }
+    }
+    Some(key)
+}
+
+/// `row` with its values replaced by their keys in the collations, see `Collation::key`
+fn keyed_row<'a>(collations: &[Collation], row: &'a Row) -> Cow<'a, Row> {
+    if collations.is_empty() {
+        Cow::Borrowed(row)
+    }
+    else {
+        Cow::Owned(Row::new(collation::key_values(collations, &row.values())).with_id(row.id()))
+    }
+}
+
+/// Key for looking up rows strictly equal to `values`, none if encodings can't be compared
+///
+/// No stored value equals a null, and values containing floating-point
+/// numbers may be equal despite different encodings.
+pub(crate) fn lookup_key(values: &[Value]) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+    for value in values {
+        match value {
+            Value::Null | Value::Array(_) | Value::Vector(_) | Value::GeoPoint(_, _) => return None,
+            Value::Real(r) if *r == 0.0 => Value::Real(0.0).encode(&mut key),
+            Value::Real(r) if r.is_nan() => Value::Real(::std::f64::NAN).encode(&mut key),
+            value => value.encode(&mut key),
+        }
+    }
+    Some(key)
+}
+
+/// Whether the value has a place in the order of `Value::compare`
+fn is_ordered(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Array(_) | Value::Vector(_) | Value::GeoPoint(_, _) => false,
+        _ => true,
+    }
+}
+
+/// Order of values of an ordered field like `Value::compare`, with nulls equal
+/// to each other and after all other values
+///
+/// Values of a single field have compatible kinds, so the order is total.
+fn index_order(a: &Value, b: &Value) -> Ordering {
+    match (is_ordered(a), is_ordered(b)) {
+        (true, true) => match a.compare(b) {
+            Ok(Some(order)) => order,
+            _ => unreachable!("Indexed values are ordered"),
+        },
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => Ordering::Equal,
+    }
+}
+
+/// Values of fields, ordered by the first one, then the second one and so on
+///
+/// A prefix of a key is ordered before all keys starting with it.
+#[derive(Debug, Clone)]
+struct OrderedKey(Vec<Value>);
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.0.iter().zip(&other.0) {
+            match index_order(a, b) {
+                Ordering::Equal => {},
+                order => return order,
+            }
+        }
+        self.0.len().cmp(&other.0.len())
+    }
+}
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for OrderedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for OrderedKey {}
+
+fn ordered_key(row: &Row, columns: &[usize]) -> OrderedKey {
+    OrderedKey(columns.iter().map(|&i| row.value(i).clone()).collect())
+}
+
+fn bound_value(bound: Bound<&Value>) -> Option<&Value> {
+    match bound {
+        Bound::Included(value) | Bound::Excluded(value) => Some(value),
+        Bound::Unbounded => None,
+    }
+}
+
+fn columns_of(mask: &[bool]) -> Vec<usize> {
+    mask.iter().enumerate().filter(|(_, m)| **m).map(|(i, _)| i).collect()
+}
+
+/// Number of times the planner used an index, shared by copies of it and
+/// carried over when it is rebuilt
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Usage(Arc<AtomicU64>);
+impl Usage {
+    pub(crate) fn record(&self) {
+        self.0.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// Size and usage of an index
+struct IndexStats {
+    entries: usize,
+    size_bytes: usize,
+    uses: u64,
+}
+
+/// Description of an index created with `Delta::CreateIndex`, `Delta::CreateExpressionIndex`
+/// or `Delta::CreatePartialIndex`, see `SrimDB::indexes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub table: TableName,
+    pub kind: IndexKind,
+    /// Indexed fields, empty for an expression index
+    pub fields: Vec<FieldName>,
+    /// Indexed expression of an expression index
+    pub expression: Option<FunctionCall>,
+    /// Condition the rows of a partial index satisfy
+    pub predicate: Option<Condition>,
+    /// Number of distinct keys, or words of a full-text index
+    pub entries: usize,
+    /// Approximate number of bytes used
+    pub size_bytes: usize,
+    /// Number of times the planner used the index to answer a query
+    pub uses: u64,
+}
+
+/// What requires the values of a `UniqueIndex` to be unique
+#[derive(Debug, Clone, PartialEq)]
+enum Uniqueness {
+    /// Unique key of the table
+    Key,
+    /// Unique constraint with the name
+    Constraint(String),
+    /// Index created with `IndexKind::Unique`
+    Index,
+    /// Expression index created with `IndexKind::Unique`
+    Expression(FunctionCall),
+}
+
+/// Hash index over fields with unique values, mapping their values to the row having them
+///
+/// Rows with a null in any of the fields are not indexed, as nulls are never equal.
+#[derive(Debug, Clone)]
+pub(crate) struct UniqueIndex {
+    uniqueness: Uniqueness,
+    columns: Vec<usize>,
+    entries: HashMap<Vec<u8>, RowId>,
+    uses: Usage,
+}
+impl UniqueIndex {
+    fn new(uniqueness: Uniqueness, columns: Vec<usize>) -> Self {
+        Self { uniqueness, columns, entries: HashMap::new(), uses: Usage::default() }
+    }
+
+    fn stats(&self) -> IndexStats {
+        let size_bytes = self.entries.keys().map(|key| key.len() + mem::size_of::<(Vec<u8>, RowId)>()).sum();
+        IndexStats { entries: self.entries.len(), size_bytes, uses: self.uses.count() }
+    }
+
+    fn violation(&self, table: &Table) -> ApplyError {
+        match self.uniqueness {
+            Uniqueness::Key => ApplyError::DuplicateKey(table.name()),
+            Uniqueness::Constraint(ref constraint) => ApplyError::UniqueViolation { table: table.name(), constraint: constraint.clone() },
+            Uniqueness::Index => {
+                let fields = table.fields();
+                ApplyError::DuplicateIndexKey(table.name(), self.columns.iter().map(|i| fields[*i].name()).collect())
+            },
+            Uniqueness::Expression(ref expression) => ApplyError::DuplicateIndexValue(table.name(), expression.clone()),
+        }
+    }
+
+    /// Fails if a row other than `replacing` has the same values as `row`
+    fn check(&self, table: &Table, row: &Row, replacing: Option<RowId>) -> Result<(), ApplyError> {
+        match row_key(row, &self.columns).and_then(|key| self.entries.get(&key).cloned()) {
+            Some(id) if Some(id) != replacing => Err(self.violation(table)),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, row: &Row) {
+        if let Some(key) = row_key(row, &self.columns) {
+            self.entries.insert(key, row.id().expect("Indexed rows are stored"));
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        if let Some(key) = row_key(row, &self.columns) {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// Index created with `Delta::CreateIndex`, mapping values of its fields to the rows having them
+#[derive(Debug, Clone)]
+pub(crate) struct HashIndex {
+    columns: Vec<usize>,
+    entries: HashMap<Vec<u8>, Vec<RowId>>,
+    uses: Usage,
+}
+impl HashIndex {
+    fn new(columns: Vec<usize>) -> Self {
+        Self { columns, entries: HashMap::new(), uses: Usage::default() }
+    }
+
+    fn stats(&self) -> IndexStats {
+        let size_bytes = self.entries.iter()
+            .map(|(key, ids)| key.len() + ids.len() * mem::size_of::<RowId>() + mem::size_of::<(Vec<u8>, Vec<RowId>)>())
+            .sum();
+        IndexStats { entries: self.entries.len(), size_bytes, uses: self.uses.count() }
+    }
+
+    fn insert(&mut self, row: &Row) {
+        if let Some(key) = row_key(row, &self.columns) {
+            self.entries.entry(key).or_insert_with(Vec::new).push(row.id().expect("Indexed rows are stored"));
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        let id = row.id().expect("Indexed rows are stored");
+        if let Some(key) = row_key(row, &self.columns) {
+            let now_empty = match self.entries.get_mut(&key) {
+                Some(ids) => {
+                    ids.retain(|i| *i != id);
+                    ids.is_empty()
+                },
+                None => false,
+            };
+            if now_empty {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Index created with `Delta::CreateIndex`, keeping rows sorted by the values of its fields
+///
+/// Rows with nulls are indexed too, so that lookups by a prefix of the fields find them.
+#[derive(Debug, Clone)]
+pub(crate) struct BTreeIndex {
+    columns: Vec<usize>,
+    /// Ids of rows with equal values, ascending
+    entries: BTreeMap<OrderedKey, Vec<RowId>>,
+    /// An ordered value of each field, if any was indexed, for checking that
+    /// looked up values can be compared with them
+    samples: Vec<Option<Value>>,
+    uses: Usage,
+}
+impl BTreeIndex {
+    fn new(columns: Vec<usize>) -> Self {
+        let samples = vec![None; columns.len()];
+        Self { columns, entries: BTreeMap::new(), samples, uses: Usage::default() }
+    }
+
+    fn stats(&self) -> IndexStats {
+        let size_bytes = self.entries.iter()
+            .map(|(key, ids)| {
+                key.0.iter().map(Value::approx_size_bytes).sum::<usize>() + ids.len() * mem::size_of::<RowId>()
+                    + mem::size_of::<(OrderedKey, Vec<RowId>)>()
+            })
+            .sum();
+        IndexStats { entries: self.entries.len(), size_bytes, uses: self.uses.count() }
+    }
+
+    fn insert(&mut self, row: &Row) {
+        let id = row.id().expect("Indexed rows are stored");
+        let key = ordered_key(row, &self.columns);
+        for (sample, value) in self.samples.iter_mut().zip(&key.0) {
+            if sample.is_none() && is_ordered(value) {
+                *sample = Some(value.clone());
+            }
+        }
+        let ids = self.entries.entry(key).or_insert_with(Vec::new);
+        if let Err(position) = ids.binary_search(&id) {
+            ids.insert(position, id);
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        let id = row.id().expect("Indexed rows are stored");
+        let key = ordered_key(row, &self.columns);
+        let now_empty = match self.entries.get_mut(&key) {
+            Some(ids) => {
+                ids.retain(|i| *i != id);
+                ids.is_empty()
+            },
+            None => false,
+        };
+        if now_empty {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Whether `value` can be stored in the field at `position`, which it can't
+    /// if it is ordered, but can't be compared with the other values
+    fn admits(&self, position: usize, value: &Value) -> bool {
+        !is_ordered(value) || self.accepts(position, value)
+    }
+
+    /// Whether `value` can be compared with the values of the field at `position`
+    fn accepts(&self, position: usize, value: &Value) -> bool {
+        if !is_ordered(value) {
+            return false;
+        }
+        match self.samples[position] {
+            Some(ref sample) => value.compare(sample).map_or(false, |order| order.is_some()),
+            None => true,
+        }
+    }
+
+    /// Ids of rows equal to `prefix` in the leading fields, with the next field
+    /// within the bounds, ordered by their values
+    ///
+    /// Rows with a null in the next field are only found if it is unbounded.
+    /// None if the bounds have no field or a value can't be compared with the field.
+    fn range(&self, prefix: &[Value], lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<RowId>> {
+        let n = prefix.len();
+        let bounded: Vec<&Value> = bound_value(lower).into_iter().chain(bound_value(upper)).collect();
+        if n > self.columns.len() || (n == self.columns.len() && !bounded.is_empty()) {
+            return None;
+        }
+        if !prefix.iter().enumerate().all(|(i, value)| self.accepts(i, value)) || !bounded.iter().all(|value| self.accepts(n, value)) {
+            return None;
+        }
+
+        let mut start = prefix.to_vec();
+        start.extend(bound_value(lower).cloned());
+        let ids = self.entries.range((Bound::Included(OrderedKey(start)), Bound::Unbounded))
+            .skip_while(|(key, _)| match lower {
+                Bound::Excluded(value) => index_order(&key.0[n], value) == Ordering::Equal,
+                _ => false,
+            })
+            .take_while(|(key, _)| {
+                prefix.iter().zip(&key.0).all(|(value, k)| index_order(k, value) == Ordering::Equal) && match upper {
+                    Bound::Included(value) => index_order(&key.0[n], value) != Ordering::Greater,
+                    Bound::Excluded(value) => index_order(&key.0[n], value) == Ordering::Less,
+                    Bound::Unbounded => bound_value(lower).is_none() || is_ordered(&key.0[n]),
+                }
+            })
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect();
+        Some(ids)
+    }
+
+    /// Ids of all rows, ordered by the first field and then by id
+    fn ids_in_order(&self) -> Vec<RowId> {
+        let mut ids = Vec::new();
+        let mut group: Option<(&Value, usize)> = None;
+        for (key, key_ids) in &self.entries {
+            match group {
+                Some((value, _)) if index_order(value, &key.0[0]) == Ordering::Equal => {},
+                _ => {
+                    if let Some((_, start)) = group {
+                        ids[start..].sort();
+                    }
+                    group = Some((&key.0[0], ids.len()));
+                },
+            }
+            ids.extend(key_ids);
+        }
+        if let Some((_, start)) = group {
+            ids[start..].sort();
+        }
+        ids
+    }
+}
+
+/// Index created with `Delta::CreateIndex`, mapping the words of a text field to the rows having them
+///
+/// Values of other kinds are not indexed.
+#[derive(Debug, Clone)]
+pub(crate) struct FullTextIndex {
+    column: usize,
+    postings: HashMap<String, BTreeSet<RowId>>,
+    uses: Usage,
+}
+impl FullTextIndex {
+    fn new(column: usize) -> Self {
+        Self { column, postings: HashMap::new(), uses: Usage::default() }
+    }
+
+    fn stats(&self) -> IndexStats {
+        let size_bytes = self.postings.iter()
+            .map(|(word, ids)| word.len() + ids.len() * mem::size_of::<RowId>() + mem::size_of::<(String, BTreeSet<RowId>)>())
+            .sum();
+        IndexStats { entries: self.postings.len(), size_bytes, uses: self.uses.count() }
+    }
+
+    fn words(&self, row: &Row) -> Vec<String> {
+        match row.value(self.column) {
+            Value::Text(text) => fulltext::tokens(text),
+            _ => Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, row: &Row) {
+        let id = row.id().expect("Indexed rows are stored");
+        for word in self.words(row) {
+            self.postings.entry(word).or_insert_with(BTreeSet::new).insert(id);
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        let id = row.id().expect("Indexed rows are stored");
+        for word in self.words(row) {
+            let now_empty = match self.postings.get_mut(&word) {
+                Some(ids) => {
+                    ids.remove(&id);
+                    ids.is_empty()
+                },
+                None => false,
+            };
+            if now_empty {
+                self.postings.remove(&word);
+            }
+        }
+    }
+
+    /// Ids of rows having all words of the query, ascending, none if it has no words
+    fn search(&self, query: &str) -> Option<Vec<RowId>> {
+        let mut postings = Vec::new();
+        for word in fulltext::tokens(query) {
+            match self.postings.get(&word) {
+                Some(ids) => postings.push(ids),
+                None => return Some(Vec::new()),
+            }
+        }
+        postings.sort_by_key(|ids| ids.len());
+        let (rarest, others) = postings.split_first()?;
+        Some(rarest.iter().filter(|id| others.iter().all(|ids| ids.contains(id))).cloned().collect())
+    }
+}
+
+/// Index created with `Delta::CreateExpressionIndex`, over the value of its expression for each row
+#[derive(Clone)]
+pub(crate) struct ExpressionIndex {
+    expression: FunctionCall,
+    /// Fields of the table, for resolving references of the expression
+    fields: QueryResult,
+    functions: Arc<HashMap<FunctionName, Function>>,
+    /// Index of rows with the value of the expression as their only field
+    values: TableIndexes,
+}
+impl fmt::Debug for ExpressionIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExpressionIndex")
+            .field("expression", &self.expression)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+impl ExpressionIndex {
+    fn new(table: &Table, kind: IndexKind, expression: FunctionCall, functions: &Arc<HashMap<FunctionName, Function>>) -> Self {
+        let mut values = TableIndexes::default();
+        match kind {
+            IndexKind::Hash => values.hash.push(HashIndex::new(vec![0])),
+            IndexKind::BTree => values.btree.push(BTreeIndex::new(vec![0])),
+            IndexKind::Unique => values.unique.push(UniqueIndex::new(Uniqueness::Expression(expression.clone()), vec![0])),
+            IndexKind::FullText => values.fulltext.push(FullTextIndex::new(0)),
+        }
+        Self { expression, fields: table_fields(table), functions: functions.clone(), values }
+    }
+
+    /// Row with the value of the expression for `row` as its only field
+    fn value_row(&self, row: &Row) -> Result<Row, QueryError> {
+        let value = self.expression.resolve_args(&|qf: &QueryField| self.fields.resolve_field(qf, row))?
+            .apply(&self.functions)?;
+        Ok(Row::new(vec![value]).with_id(row.id()))
+    }
+
+    /// Fails if the expression fails for `row`, or its value violates uniqueness
+    /// with a stored row other than `replacing` or can't be ordered with the stored values
+    fn check(&self, table: &Table, row: &Row, replacing: Option<RowId>) -> Result<(), ApplyError> {
+        let value_row = self.value_row(row).map_err(ApplyError::QueryError)?;
+        if self.values.btree.iter().any(|index| !index.admits(0, value_row.value(0))) {
+            return Err(ApplyError::QueryError(QueryError::IncompatibleTypes));
+        }
+        self.values.check(table, &value_row, replacing)
+    }
+
+    /// Rows failing `check` are not indexed
+    fn insert(&mut self, row: &Row) {
+        if let Ok(value_row) = self.value_row(row) {
+            if self.values.btree.iter().all(|index| index.admits(0, value_row.value(0))) {
+                self.values.insert(&value_row);
+            }
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        if let Ok(value_row) = self.value_row(row) {
+            self.values.remove(&value_row);
+        }
+    }
+}
+
+/// Index created with `Delta::CreatePartialIndex`, over the rows satisfying its predicate
+#[derive(Clone)]
+pub(crate) struct PartialIndex {
+    predicate: Condition,
+    /// Fields of the table, for resolving references of the predicate
+    fields: QueryResult,
+    functions: Arc<HashMap<FunctionName, Function>>,
+    /// Ids of the rows satisfying the predicate
+    ids: BTreeSet<RowId>,
+    /// Index of the rows satisfying the predicate
+    rows: TableIndexes,
+}
+impl fmt::Debug for PartialIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartialIndex")
+            .field("predicate", &self.predicate)
+            .field("rows", &self.rows)
+            .finish()
+    }
+}
+impl PartialIndex {
+    fn new(table: &Table, kind: IndexKind, columns: Vec<usize>, predicate: Condition, functions: &Arc<HashMap<FunctionName, Function>>) -> Self {
+        let mut rows = TableIndexes::default();
+        let collations = table.collations();
+        if !collation::all_binary(&collations) {
+            rows.collations = collations;
+        }
+        match kind {
+            IndexKind::Hash => rows.hash.push(HashIndex::new(columns)),
+            IndexKind::BTree => rows.btree.push(BTreeIndex::new(columns)),
+            IndexKind::Unique => rows.unique.push(UniqueIndex::new(Uniqueness::Index, columns)),
+            IndexKind::FullText => rows.fulltext.push(FullTextIndex::new(columns[0])),
+        }
+        Self { predicate, fields: table_fields(table), functions: functions.clone(), ids: BTreeSet::new(), rows }
+    }
+
+    fn matches(&self, row: &Row) -> Result<bool, QueryError> {
+        self.predicate.test(&self.functions, &|qf: &QueryField| self.fields.resolve_field(qf, row))
+    }
+
+    /// Fails if the predicate fails for `row`, or `row` satisfies it and violates
+    /// uniqueness with a stored row other than `replacing`
+    fn check(&self, table: &Table, row: &Row, replacing: Option<RowId>) -> Result<(), ApplyError> {
+        if self.matches(row).map_err(ApplyError::QueryError)? {
+            self.rows.check(table, row, replacing)?;
+        }
+        Ok(())
+    }
+
+    /// Rows failing the predicate or `check` are not indexed
+    fn insert(&mut self, row: &Row) {
+        if let Ok(true) = self.matches(row) {
+            self.ids.insert(row.id().expect("Indexed rows are stored"));
+            self.rows.insert(row);
+        }
+    }
+
+    fn remove(&mut self, row: &Row) {
+        if self.ids.remove(&row.id().expect("Indexed rows are stored")) {
+            self.rows.remove(row);
+        }
+    }
+}
+
+/// Lookup of values in an index
+enum Probe<'a> {
+    Unique(&'a UniqueIndex, Vec<Value>),
+    Hash(&'a HashIndex, Vec<Value>),
+    /// Values of a prefix of the fields
+    Ordered(&'a BTreeIndex, Vec<Value>),
+}
+impl<'a> Probe<'a> {
+    /// Number of fields the looked up values are in
+    fn covered(&self) -> usize {
+        match self {
+            Probe::Unique(_, values) | Probe::Hash(_, values) | Probe::Ordered(_, values) => values.len(),
+        }
+    }
+
+    fn ids(&self) -> Option<Vec<RowId>> {
+        match self {
+            Probe::Unique(index, values) => Some(index.entries.get(&lookup_key(values)?).into_iter().cloned().collect()),
+            Probe::Hash(index, values) => Some(index.entries.get(&lookup_key(values)?).cloned().unwrap_or_default()),
+            Probe::Ordered(index, prefix) => index.range(prefix, Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+
+    fn uses(&self) -> &Usage {
+        match self {
+            Probe::Unique(index, _) => &index.uses,
+            Probe::Hash(index, _) => &index.uses,
+            Probe::Ordered(index, _) => &index.uses,
+        }
+    }
+}
+
+/// Indexes of a table
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TableIndexes {
+    unique: Vec<UniqueIndex>,
+    hash: Vec<HashIndex>,
+    btree: Vec<BTreeIndex>,
+    fulltext: Vec<FullTextIndex>,
+    expressions: Vec<ExpressionIndex>,
+    partials: Vec<PartialIndex>,
+    partitions: Option<Partitions>,
+    /// Collation of each field, by which unique, hash and ordered indexes
+    /// store their values; empty if all are binary
+    collations: Vec<Collation>,
+}
+impl TableIndexes {
+    /// Index `rows` for the table's key, if unique, its unique constraints and its
+    /// created indexes, and assign them to its partitions
+    ///
+    /// Fails if any two rows violate a constraint, or a row fails an expression
+    /// index or the predicate of a partial index.
+    pub(crate) fn build(table: &Table, rows: &[Arc<Row>], functions: &Arc<HashMap<FunctionName, Function>>) -> Result<Self, ApplyError> {
+        let mut unique = Vec::new();
+        if table.has_unique_key() {
+            let mask: Vec<bool> = (0..table.fields().len()).map(|i| table.is_key_field(i)).collect();
+            unique.push(UniqueIndex::new(Uniqueness::Key, columns_of(&mask)));
+        }
+        for (name, mask) in table.unique_masks() {
+            unique.push(UniqueIndex::new(Uniqueness::Constraint(name), columns_of(&mask)));
+        }
+        let (mut hash, mut btree, mut fulltext) = (Vec::new(), Vec::new(), Vec::new());
+        let collations = table.collations();
+        let collations = if collation::all_binary(&collations) { Vec::new() } else { collations };
+        let keyed: Vec<Cow<Row>> = rows.iter().map(|row| keyed_row(&collations, row)).collect();
+        for (kind, columns) in table.index_columns() {
+            match kind {
+                IndexKind::Hash => hash.push(HashIndex::new(columns)),
+                IndexKind::BTree => btree.push(BTreeIndex::new(columns)),
+                IndexKind::Unique => unique.push(UniqueIndex::new(Uniqueness::Index, columns)),
+                IndexKind::FullText => fulltext.push(FullTextIndex::new(columns[0])),
+            }
+        }
+
+        for index in unique.iter_mut() {
+            for row in &keyed {
+                index.check(table, row, None)?;
+                index.insert(row);
+            }
+        }
+        for index in hash.iter_mut() {
+            for row in &keyed {
+                index.insert(row);
+            }
+        }
+        for index in btree.iter_mut() {
+            for row in &keyed {
+                index.insert(row);
+            }
+        }
+        for index in fulltext.iter_mut() {
+            for row in rows {
+                index.insert(row);
+            }
+        }
+        let mut expressions = Vec::new();
+        for (kind, expression) in table.expression_indexes() {
+            let mut index = ExpressionIndex::new(table, kind, expression, functions);
+            for row in rows {
+                index.check(table, row, None)?;
+                index.insert(row);
+            }
+            expressions.push(index);
+        }
+        let mut partials = Vec::new();
+        for (kind, columns, predicate) in table.partial_index_columns() {
+            let mut index = PartialIndex::new(table, kind, columns, predicate, functions);
+            for row in rows {
+                index.check(table, row, None)?;
+                index.insert(row);
+            }
+            partials.push(index);
+        }
+        let mut partitions = Partitions::new(table);
+        if let Some(ref mut partitions) = partitions {
+            for row in rows {
+                partitions.insert(row);
+            }
+        }
+        Ok(TableIndexes { unique, hash, btree, fulltext, expressions, partials, partitions, collations })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.unique.is_empty() && self.hash.is_empty() && self.btree.is_empty() && self.fulltext.is_empty()
+            && self.expressions.is_empty() && self.partials.is_empty() && self.partitions.is_none()
+    }
+
+    /// Whether `check` can reject rows
+    pub(crate) fn has_checks(&self) -> bool {
+        !self.unique.is_empty() || !self.expressions.is_empty() || !self.partials.is_empty()
+    }
+
+    /// Fails if `row` violates a constraint with a stored row other than `replacing`,
+    /// or fails an expression index or a partial index
+    pub(crate) fn check(&self, table: &Table, row: &Row, replacing: Option<RowId>) -> Result<(), ApplyError> {
+        let keyed = keyed_row(&self.collations, row);
+        for index in &self.unique {
+            index.check(table, &keyed, replacing)?;
+        }
+        for index in &self.expressions {
+            index.check(table, row, replacing)?;
+        }
+        for index in &self.partials {
+            index.check(table, row, replacing)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert(&mut self, row: &Row) {
+        let keyed = keyed_row(&self.collations, row);
+        for index in self.unique.iter_mut() {
+            index.insert(&keyed);
+        }
+        for index in self.hash.iter_mut() {
+            index.insert(&keyed);
+        }
+        for index in self.btree.iter_mut() {
+            index.insert(&keyed);
+        }
+        for index in self.fulltext.iter_mut() {
+            index.insert(row);
+        }
+        for index in self.expressions.iter_mut() {
+            index.insert(row);
+        }
+        for index in self.partials.iter_mut() {
+            index.insert(row);
+        }
+        if let Some(ref mut partitions) = self.partitions {
+            partitions.insert(row);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, row: &Row) {
+        let keyed = keyed_row(&self.collations, row);
+        for index in self.unique.iter_mut() {
+            index.remove(&keyed);
+        }
+        for index in self.hash.iter_mut() {
+            index.remove(&keyed);
+        }
+        for index in self.btree.iter_mut() {
+            index.remove(&keyed);
+        }
+        for index in self.fulltext.iter_mut() {
+            index.remove(row);
+        }
+        for index in self.expressions.iter_mut() {
+            index.remove(row);
+        }
+        for index in self.partials.iter_mut() {
+            index.remove(row);
+        }
+        if let Some(ref mut partitions) = self.partitions {
+            partitions.remove(row);
+        }
+    }
+
+    /// Ids of rows that may be strictly equal to `values` in `columns`, none if no index covers them
+    ///
+    /// Hash indexes, including those of the key and unique constraints, must
+    /// cover all of their fields, and ordered indexes a prefix of them. The
+    /// index covering the most of `columns` is used.
+    fn lookup(&self, columns: &[usize], values: &[Value]) -> Option<Vec<RowId>> {
+        let value_of = |column: &usize| columns.iter().position(|c| c == column).map(|i| self.key_value(*column, &values[i]).into_owned());
+        let key_of = |index_columns: &[usize]| -> Option<Vec<Value>> { index_columns.iter().map(&value_of).collect() };
+
+        let mut probes = Vec::new();
+        for index in &self.unique {
+            probes.extend(key_of(&index.columns).map(|key| Probe::Unique(index, key)));
+        }
+        for index in &self.hash {
+            probes.extend(key_of(&index.columns).map(|key| Probe::Hash(index, key)));
+        }
+        for index in &self.btree {
+            let prefix: Vec<Value> = index.columns.iter().map(&value_of).take_while(Option::is_some).map(Option::unwrap).collect();
+            if !prefix.is_empty() {
+                probes.push(Probe::Ordered(index, prefix));
+            }
+        }
+        // The first of equally good probes
+        let best = probes.into_iter().rev().max_by_key(Probe::covered)?;
+        let ids = best.ids()?;
+        best.uses().record();
+        Some(ids)
+    }
+
+    /// Stats of the only index, for the indexes of an expression or a partial index
+    /// `value` as stored for the column, see `Collation::key`
+    fn key_value<'a>(&self, column: usize, value: &'a Value) -> Cow<'a, Value> {
+        match self.collations.get(column) {
+            Some(collation) => collation.key_value(value),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// Whether `row` has values equal to `values` in `columns`, in their collations
+    fn matches_values(&self, row: &Row, columns: &[usize], values: &[Value]) -> bool {
+        columns.iter().zip(values).all(|(&i, value)| self.key_value(i, row.value(i)) == self.key_value(i, value))
+    }
+
+    fn only_stats(&self) -> IndexStats {
+        let stats = self.unique.iter().map(UniqueIndex::stats)
+            .chain(self.hash.iter().map(HashIndex::stats))
+            .chain(self.btree.iter().map(BTreeIndex::stats))
+            .chain(self.fulltext.iter().map(FullTextIndex::stats))
+            .next();
+        stats.expect("Expression and partial indexes have an index")
+    }
+
+    /// Usage counter of the only index, like `only_stats`
+    fn only_usage(&self) -> &Usage {
+        let usage = self.unique.iter().map(|index| &index.uses)
+            .chain(self.hash.iter().map(|index| &index.uses))
+            .chain(self.btree.iter().map(|index| &index.uses))
+            .chain(self.fulltext.iter().map(|index| &index.uses))
+            .next();
+        usage.expect("Expression and partial indexes have an index")
+    }
+
+    /// Stats of the index of the kind over the columns, created with `Delta::CreateIndex`
+    fn created_stats(&self, kind: IndexKind, columns: &[usize]) -> Option<IndexStats> {
+        match kind {
+            IndexKind::Hash => self.hash.iter().find(|index| index.columns == columns).map(HashIndex::stats),
+            IndexKind::BTree => self.btree.iter().find(|index| index.columns == columns).map(BTreeIndex::stats),
+            IndexKind::Unique => self.unique.iter()
+                .find(|index| index.uniqueness == Uniqueness::Index && index.columns == columns)
+                .map(UniqueIndex::stats),
+            IndexKind::FullText => self.fulltext.iter().find(|index| index.column == columns[0]).map(FullTextIndex::stats),
+        }
+    }
+
+    /// Share the usage counters of the same indexes in `old`, which this replaces
+    pub(crate) fn keep_usage(&mut self, old: &TableIndexes) {
+        for index in self.unique.iter_mut() {
+            if let Some(o) = old.unique.iter().find(|o| o.uniqueness == index.uniqueness && o.columns == index.columns) {
+                index.uses = o.uses.clone();
+            }
+        }
+        for index in self.hash.iter_mut() {
+            if let Some(o) = old.hash.iter().find(|o| o.columns == index.columns) {
+                index.uses = o.uses.clone();
+            }
+        }
+        for index in self.btree.iter_mut() {
+            if let Some(o) = old.btree.iter().find(|o| o.columns == index.columns) {
+                index.uses = o.uses.clone();
+            }
+        }
+        for index in self.fulltext.iter_mut() {
+            if let Some(o) = old.fulltext.iter().find(|o| o.column == index.column) {
+                index.uses = o.uses.clone();
+            }
+        }
+        for index in self.expressions.iter_mut() {
+            let expression = &index.expression;
+            for o in old.expressions.iter().filter(|o| o.expression == *expression) {
+                index.values.keep_usage(&o.values);
+            }
+        }
+        for index in self.partials.iter_mut() {
+            let predicate = &index.predicate;
+            for o in old.partials.iter().filter(|o| o.predicate == *predicate) {
+                index.rows.keep_usage(&o.rows);
+            }
+        }
+        if let (Some(partitions), Some(old)) = (self.partitions.as_mut(), old.partitions.as_ref()) {
+            partitions.keep_usage(old);
+        }
+    }
+
+    pub(crate) fn partitions(&self) -> Option<&Partitions> {
+        self.partitions.as_ref()
+    }
+
+    /// Index over the expression, ignoring qualifiers of references to fields of the table
+    fn expression(&self, table: &TableName, expression: &FunctionCall) -> Option<&TableIndexes> {
+        let expression = expression.unqualified(table);
+        self.expressions.iter().find(|index| index.expression.unqualified(table) == expression).map(|index| &index.values)
+    }
+
+    /// Partial indexes whose predicate is one of the conditions, ignoring
+    /// qualifiers of references to fields of the table
+    fn partial(&self, table: &TableName, conditions: &[&Condition]) -> Vec<&PartialIndex> {
+        let conditions: Vec<Condition> = conditions.iter().map(|c| c.unqualified(table)).collect();
+        self.partials.iter().filter(|index| conditions.contains(&index.predicate.unqualified(table))).collect()
+    }
+
+    /// Ids of rows having all words of the query in the column, none if no
+    /// full-text index is over it or the query has no words
+    fn search(&self, column: usize, query: &str) -> Option<Vec<RowId>> {
+        let index = self.fulltext.iter().find(|index| index.column == column)?;
+        let ids = index.search(query)?;
+        index.uses.record();
+        Some(ids)
+    }
+
+    /// Rows within the bounds of an ordered index starting with the column, see `BTreeIndex::range`
+    fn range(&self, column: usize, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<RowId>> {
+        let index = self.ordered(column)?;
+        let key = |bound: Bound<&Value>| bound.map(|value| self.key_value(column, value).into_owned());
+        let (lower, upper) = (key(lower), key(upper));
+        let ids = index.range(&[], lower.as_ref(), upper.as_ref())?;
+        index.uses.record();
+        Some(ids)
+    }
+
+    /// Ordered index whose first field is the column, preferring one over only it
+    fn ordered(&self, column: usize) -> Option<&BTreeIndex> {
+        self.btree.iter().filter(|index| index.columns[0] == column).min_by_key(|index| index.columns.len())
+    }
+
+    /// Ids of rows with `values` in the key fields, in their collations, none if the key isn't unique
+    ///
+    /// Not counted as a use of the index, as it only serves constraints.
+    fn key(&self, values: &[Value]) -> Option<Vec<RowId>> {
+        let index = self.unique.iter().find(|index| index.uniqueness == Uniqueness::Key)?;
+        if index.columns.len() != values.len() {
+            return Some(Vec::new());
+        }
+        let key: Vec<Value> = index.columns.iter().zip(values).map(|(&column, value)| self.key_value(column, value).into_owned()).collect();
+        Some(lookup_key(&key).and_then(|key| index.entries.get(&key).cloned()).into_iter().collect())
+    }
+}
+
+impl DataDB {
+    pub(crate) fn create_index(&mut self, name: TableName, field_names: Vec<FieldName>, kind: IndexKind) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        if field_names.is_empty() {
+            return Err(ApplyError::InvalidSchema(name));
+        }
+        for field_name in &field_names {
+            if table.field_index(field_name.clone()).is_none() {
+                return Err(ApplyError::NoSuchField(name, field_name.clone()));
+            }
+        }
+        if table.indexes().contains(&(kind, field_names.clone())) {
+            return Err(ApplyError::IndexExists(name, field_names));
+        }
+
+        let table = table.with_index(kind, field_names);
+        table.check_schema()?;
+        let rows = (*self.table_rows[&name]).clone();
+        self.set_rows(&table, rows)?;
+        *self.table_mut(&name).unwrap() = table;
+        Ok(())
+    }
+
+    pub(crate) fn create_expression_index(&mut self, name: TableName, expression: FunctionCall, kind: IndexKind) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        for qf in expression.fields() {
+            if qf.table.as_ref().map_or(false, |t| *t != name) || table.field_index(qf.field.clone()).is_none() {
+                return Err(ApplyError::NoSuchField(name, qf.field));
+            }
+        }
+        let unqualified = expression.unqualified(&name);
+        if table.expression_indexes().iter().any(|(k, e)| *k == kind && e.unqualified(&name) == unqualified) {
+            return Err(ApplyError::ExpressionIndexExists(name, expression));
+        }
+
+        let table = table.with_expression_index(kind, expression);
+        let rows = (*self.table_rows[&name]).clone();
+        self.set_rows(&table, rows)?;
+        *self.table_mut(&name).unwrap() = table;
+        Ok(())
+    }
+
+    pub(crate) fn create_partial_index(&mut self, name: TableName, field_names: Vec<FieldName>, kind: IndexKind, predicate: Condition) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        if field_names.is_empty() {
+            return Err(ApplyError::InvalidSchema(name));
+        }
+        for field_name in &field_names {
+            if table.field_index(field_name.clone()).is_none() {
+                return Err(ApplyError::NoSuchField(name, field_name.clone()));
+            }
+        }
+        for qf in predicate.fields() {
+            if qf.table.as_ref().map_or(false, |t| *t != name) || table.field_index(qf.field.clone()).is_none() {
+                return Err(ApplyError::NoSuchField(name, qf.field));
+            }
+        }
+        let unqualified = predicate.unqualified(&name);
+        if table.partial_indexes().iter().any(|(k, f, p)| *k == kind && *f == field_names && p.unqualified(&name) == unqualified) {
+            return Err(ApplyError::IndexExists(name, field_names));
+        }
+
+        let table = table.with_partial_index(kind, field_names, predicate);
+        table.check_schema()?;
+        let rows = (*self.table_rows[&name]).clone();
+        self.set_rows(&table, rows)?;
+        *self.table_mut(&name).unwrap() = table;
+        Ok(())
+    }
+
+    /// Indexes of all tables, in table order and then in creation order for each kind of index
+    pub(crate) fn index_infos(&self) -> Vec<IndexInfo> {
+        let mut infos = Vec::new();
+        for table in self.tables.iter() {
+            let name = table.name();
+            let indexes = &self.indexes[&name];
+            let info = |kind, fields, expression, predicate, stats: IndexStats| IndexInfo {
+                table: name.clone(), kind, fields, expression, predicate,
+                entries: stats.entries, size_bytes: stats.size_bytes, uses: stats.uses,
+            };
+            for ((kind, fields), (_, columns)) in table.indexes().into_iter().zip(table.index_columns()) {
+                let stats = indexes.created_stats(kind, &columns).expect("Created indexes are maintained");
+                infos.push(info(kind, fields, None, None, stats));
+            }
+            for ((kind, expression), index) in table.expression_indexes().into_iter().zip(indexes.expressions.iter()) {
+                infos.push(info(kind, Vec::new(), Some(expression), None, index.values.only_stats()));
+            }
+            for ((kind, fields, predicate), index) in table.partial_indexes().into_iter().zip(indexes.partials.iter()) {
+                infos.push(info(kind, fields, None, Some(predicate), index.rows.only_stats()));
+            }
+        }
+        infos
+    }
+
+    /// Rows of the table strictly equal to `values` in `columns`, in table order,
+    /// none if no index can answer the lookup
+    pub(crate) fn index_lookup(&self, name: &TableName, columns: &[usize], values: &[Value]) -> Option<Vec<Arc<Row>>> {
+        let indexes = self.indexes.get(name)?;
+        let mut ids = indexes.lookup(columns, values)?;
+        ids.sort();
+        let mut rows = self.rows_by_id(name, ids);
+        rows.retain(|row| indexes.matches_values(row, columns, values));
+        Some(rows)
+    }
+
+    /// Rows of the table with `values` in its key fields, in their collations,
+    /// none if the table has no unique key
+    pub(crate) fn key_lookup(&self, name: &TableName, values: &[Value]) -> Option<Vec<Arc<Row>>> {
+        let ids = self.indexes.get(name)?.key(values)?;
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table for which the expression may be strictly equal to `value`,
+    /// in table order, none if no index over the expression can answer the lookup
+    pub(crate) fn expression_lookup(&self, name: &TableName, expression: &FunctionCall, value: &Value) -> Option<Vec<Arc<Row>>> {
+        let mut ids = self.indexes.get(name)?.expression(name, expression)?.lookup(&[0], &[value.clone()])?;
+        ids.sort();
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table with the value of the expression within the bounds, in table order,
+    /// none if no ordered index is over the expression or a bound can't be compared with its values
+    pub(crate) fn expression_range(&self, name: &TableName, expression: &FunctionCall, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<Arc<Row>>> {
+        let mut ids = self.indexes.get(name)?.expression(name, expression)?.range(0, lower, upper)?;
+        ids.sort();
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table strictly equal to `values` in `columns`, in table order, none
+    /// if no partial index with one of the conditions as its predicate can answer the lookup
+    pub(crate) fn partial_lookup(&self, name: &TableName, conditions: &[&Condition], columns: &[usize], values: &[Value]) -> Option<Vec<Arc<Row>>> {
+        let partials = self.indexes.get(name)?.partial(name, conditions);
+        let (index, mut ids) = partials.iter().filter_map(|index| index.rows.lookup(columns, values).map(|ids| (index, ids))).next()?;
+        ids.sort();
+        let mut rows = self.rows_by_id(name, ids);
+        rows.retain(|row| index.rows.matches_values(row, columns, values));
+        Some(rows)
+    }
+
+    /// Rows of the table with the column within the bounds, in table order, none if no
+    /// ordered partial index with one of the conditions as its predicate starts with the column
+    pub(crate) fn partial_range(&self, name: &TableName, conditions: &[&Condition], column: usize, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<Arc<Row>>> {
+        let partials = self.indexes.get(name)?.partial(name, conditions);
+        let mut ids = partials.iter().filter_map(|index| index.rows.range(column, lower, upper)).next()?;
+        ids.sort();
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table satisfying one of the conditions, in table order, none
+    /// if no partial index has one of them as its predicate
+    pub(crate) fn partial_rows(&self, name: &TableName, conditions: &[&Condition]) -> Option<Vec<Arc<Row>>> {
+        let index = *self.indexes.get(name)?.partial(name, conditions).first()?;
+        index.rows.only_usage().record();
+        Some(self.rows_by_id(name, index.ids.iter().cloned().collect()))
+    }
+
+    /// Rows of the table that may have all words of the query in the column, in
+    /// table order, none if no full-text index can answer the search
+    ///
+    /// Partial indexes are used if their predicate is one of the conditions.
+    pub(crate) fn text_search(&self, name: &TableName, conditions: &[&Condition], column: usize, query: &str) -> Option<Vec<Arc<Row>>> {
+        let indexes = self.indexes.get(name)?;
+        let ids = indexes.search(column, query)
+            .or_else(|| indexes.partial(name, conditions).iter().filter_map(|index| index.rows.search(column, query)).next())?;
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table for which the expression may have all words of the query,
+    /// in table order, none if no full-text index over the expression can answer the search
+    pub(crate) fn expression_search(&self, name: &TableName, expression: &FunctionCall, query: &str) -> Option<Vec<Arc<Row>>> {
+        let ids = self.indexes.get(name)?.expression(name, expression)?.search(0, query)?;
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table with the column within the bounds, in table order, none if
+    /// no ordered index starts with the column or a bound can't be compared with its values
+    pub(crate) fn index_range(&self, name: &TableName, column: usize, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<Arc<Row>>> {
+        let mut ids = self.indexes.get(name)?.range(column, lower, upper)?;
+        ids.sort();
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table sorted by the column, with rows having a null
+    /// in it last and equal rows in table order, none if no ordered index starts with the column
+    pub(crate) fn index_order(&self, name: &TableName, column: usize) -> Option<Vec<Arc<Row>>> {
+        let index = self.indexes.get(name)?.ordered(column)?;
+        index.uses.record();
+        let ids = index.ids_in_order();
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Rows of the table with the ids, in the same order
+    pub(crate) fn rows_by_id(&self, name: &TableName, ids: Vec<RowId>) -> Vec<Arc<Row>> {
+        let rows = &self.table_rows[name];
+        ids.into_iter().map(|id| {
+            let position = rows.binary_search_by_key(&Some(id), |r| r.id()).expect("Indexed rows are stored");
+            rows[position].clone()
+        }).collect()
+    }
+}
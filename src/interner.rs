@@ -0,0 +1,126 @@
+use std::fmt;
+use std::ops::Deref;
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use Row;
+use Value;
+
+/// Immutable UTF-8 text with a shared allocation
+///
+/// Clones are pointer copies, and comparing two clones of the same
+/// allocation does not look at the contents.
+#[derive(Clone, PartialOrd, Ord)]
+pub struct SharedText(Arc<str>);
+impl SharedText {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Both values point to the same allocation
+    pub fn ptr_eq(&self, other: &SharedText) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Deref for SharedText {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+impl Borrow<str> for SharedText {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+impl PartialEq for SharedText {
+    fn eq(&self, other: &SharedText) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+impl Eq for SharedText {}
+impl Hash for SharedText {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+impl<'a> PartialEq<&'a str> for SharedText {
+    fn eq(&self, other: &&'a str) -> bool {
+        &*self.0 == *other
+    }
+}
+impl<'a> From<&'a str> for SharedText {
+    fn from(text: &'a str) -> Self {
+        SharedText(Arc::from(text))
+    }
+}
+impl From<String> for SharedText {
+    fn from(text: String) -> Self {
+        SharedText(Arc::from(text))
+    }
+}
+impl fmt::Debug for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+impl fmt::Display for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+/// Number of texts below which the interner is never pruned automatically
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// Deduplicates stored text, so that equal texts share one allocation
+///
+/// Texts of removed or replaced rows are forgotten once the number of texts
+/// doubles since the last pruning, so at most about half of them are unused.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interner {
+    strings: HashSet<SharedText>,
+    /// Number of texts left by the last pruning
+    pruned_len: usize,
+}
+impl Interner {
+    pub(crate) fn new() -> Self {
+        Self { strings: HashSet::new(), pruned_len: 0 }
+    }
+
+    /// Number of texts, including those no longer used
+    pub(crate) fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub(crate) fn intern(&mut self, text: SharedText) -> SharedText {
+        if let Some(existing) = self.strings.get(text.as_str()) {
+            return existing.clone();
+        }
+        if self.len() >= MIN_PRUNE_LEN.max(2 * self.pruned_len) {
+            self.prune();
+        }
+        self.strings.insert(text.clone());
+        text
+    }
+
+    pub(crate) fn intern_value(&mut self, value: Value) -> Value {
+        match value {
+            Value::Text(text) => Value::Text(self.intern(text)),
+            other => other,
+        }
+    }
+
+    pub(crate) fn intern_row(&mut self, row: Row) -> Row {
+        let id = row.id();
+        Row::new(row.into_values().into_iter().map(|v| self.intern_value(v)).collect()).with_id(id)
+    }
+
+    /// Forget texts that are no longer referenced outside of the interner
+    pub(crate) fn prune(&mut self) {
+        self.strings.retain(|text| Arc::strong_count(&text.0) > 1);
+        self.pruned_len = self.strings.len();
+    }
+}
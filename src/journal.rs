@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+use {Delta, Revision};
+use query_log::json_escape;
+
+/// A delta together with the revision it produced
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub revision: Revision,
+    pub delta: Delta,
+    /// Triggers ran when the delta was applied; false for deltas reverting a change with `undo`
+    pub fires_triggers: bool,
+}
+
+/// History of applied deltas, see `SrimDB::with_journal`
+pub(crate) struct Journal {
+    entries: Vec<JournalEntry>,
+    /// Append one JSON object per entry, for auditing
+    file: Option<File>,
+}
+impl Journal {
+    pub(crate) fn new(file: Option<File>) -> Self {
+        Self { entries: Vec::new(), file }
+    }
+
+    pub(crate) fn record(&mut self, revision: Revision, delta: Delta, fires_triggers: bool) -> io::Result<()> {
+        let entry = JournalEntry { revision, delta, fires_triggers };
+        let result = match self.file {
+            Some(ref mut file) => {
+                let line = format!(
+                    "{{\"revision\":{},\"delta\":\"{}\",\"fires_triggers\":{}}}\n",
+                    entry.revision, json_escape(&format!("{:?}", entry.delta)), entry.fires_triggers
+                );
+                file.write_all(line.as_bytes())
+            },
+            None => Ok(()),
+        };
+        self.entries.push(entry);
+        result
+    }
+
+    pub(crate) fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Entries that produced revisions after `revision`
+    pub(crate) fn since(&self, revision: Revision) -> &[JournalEntry] {
+        let start = self.entries.iter().position(|e| e.revision > revision).unwrap_or(self.entries.len());
+        &self.entries[start..]
+    }
+}
@@ -0,0 +1,605 @@
+//! JSON encoding of deltas and their contents, for consumption by external systems
+//!
+//! Enums are objects with the variant name as the only key, except deltas and
+//! queries, which are objects with a "type" key and one key per parameter.
+
+use std::time::Duration;
+
+use {Delta, RowUpdate, Table, TableField, FieldDefault, IndexKind, Quota, Partitioning, Row, Value, FieldKind, FieldName, TableName, SharedText, Collation};
+use query::{Query, QueryField, Condition};
+use function::{FunctionCall, Argument};
+use procedure::ProcedureStep;
+use trigger::{Trigger, TriggerEvent, TriggerTiming, TriggerAction};
+use query_log::json_escape;
+use temporal;
+use decimal;
+use uuid;
+
+pub(crate) trait ToJson {
+    fn write_json(&self, out: &mut String);
+}
+
+fn object(out: &mut String, fields: &[(&str, &ToJson)]) {
+    out.push('{');
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        key.write_json(out);
+        out.push(':');
+        value.write_json(out);
+    }
+    out.push('}');
+}
+
+/// JSON encoding of a value, as a single line
+pub(crate) fn to_json<T: ToJson>(value: &T) -> String {
+    let mut out = String::new();
+    value.write_json(&mut out);
+    out
+}
+
+fn variant(out: &mut String, name: &str, value: &ToJson) {
+    object(out, &[(name, value)]);
+}
+
+impl ToJson for str {
+    fn write_json(&self, out: &mut String) {
+        out.push('"');
+        out.push_str(&json_escape(self));
+        out.push('"');
+    }
+}
+
+impl ToJson for String {
+    fn write_json(&self, out: &mut String) {
+        self.as_str().write_json(out);
+    }
+}
+
+impl ToJson for SharedText {
+    fn write_json(&self, out: &mut String) {
+        self.as_str().write_json(out);
+    }
+}
+
+impl ToJson for bool {
+    fn write_json(&self, out: &mut String) {
+        out.push_str(if *self { "true" } else { "false" });
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn write_json(&self, out: &mut String) {
+        out.push('[');
+        for (i, item) in self.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            item.write_json(out);
+        }
+        out.push(']');
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Some(value) => value.write_json(out),
+            None => out.push_str("null"),
+        }
+    }
+}
+
+/// Number literal, written as is
+struct Number(String);
+impl ToJson for Number {
+    fn write_json(&self, out: &mut String) {
+        out.push_str(&self.0);
+    }
+}
+
+impl ToJson for Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => "Null".write_json(out),
+            Value::Boolean(b) => variant(out, "Boolean", b),
+            Value::Unsigned(u) => variant(out, "Unsigned", &Number(u.to_string())),
+            Value::Signed(i) => variant(out, "Signed", &Number(i.to_string())),
+            // JSON has no literals for non-finite numbers
+            Value::Real(r) if r.is_finite() => variant(out, "Real", &Number(format!("{:?}", r))),
+            Value::Real(r) => variant(out, "Real", &r.to_string()),
+            Value::Text(t) => variant(out, "Text", t),
+            Value::Blob(b) => {
+                let hex: String = b.iter().map(|byte| format!("{:02x}", byte)).collect();
+                variant(out, "Blob", &hex)
+            },
+            Value::Date(d) => variant(out, "Date", &temporal::format_date(*d)),
+            Value::Time(t) => variant(out, "Time", &temporal::format_time(*t)),
+            Value::Timestamp(t) => variant(out, "Timestamp", &temporal::format_timestamp(*t)),
+            // As text, since JSON numbers are commonly read as doubles
+            Value::Decimal(m, s) => variant(out, "Decimal", &decimal::format(*m, *s)),
+            Value::Uuid(u) => variant(out, "Uuid", &uuid::format(u)),
+            Value::Array(a) => variant(out, "Array", a),
+            Value::GeoPoint(lat, lon) => variant(out, "GeoPoint", &GeoPoint(*lat, *lon)),
+            Value::Vector(v) => {
+                let elements: Vec<Number> = v.iter().map(|x| Number(format!("{:?}", x))).collect();
+                variant(out, "Vector", &elements)
+            },
+        }
+    }
+}
+
+impl ToJson for FieldKind {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            FieldKind::Integer(size, signed) => variant(out, "Integer", &IntegerKind(size.size_bytes() * 8, *signed)),
+            FieldKind::Real => "Real".write_json(out),
+            FieldKind::Text => "Text".write_json(out),
+            FieldKind::Blob => "Blob".write_json(out),
+            FieldKind::ForeignKey(table) => variant(out, "ForeignKey", table),
+            FieldKind::Date => "Date".write_json(out),
+            FieldKind::Time => "Time".write_json(out),
+            FieldKind::Timestamp => "Timestamp".write_json(out),
+            FieldKind::Decimal(precision, scale) => variant(out, "Decimal", &DecimalKind(*precision, *scale)),
+            FieldKind::Uuid => "Uuid".write_json(out),
+            FieldKind::Array(kind) => variant(out, "Array", &**kind),
+            FieldKind::GeoPoint => "GeoPoint".write_json(out),
+            FieldKind::Vector(dimension) => variant(out, "Vector", &Number(dimension.to_string())),
+            FieldKind::Boolean => "Boolean".write_json(out),
+        }
+    }
+}
+
+struct GeoPoint(f64, f64);
+impl ToJson for GeoPoint {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("lat", &Number(format!("{:?}", self.0))), ("lon", &Number(format!("{:?}", self.1)))]);
+    }
+}
+
+struct DecimalKind(u8, u8);
+impl ToJson for DecimalKind {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("precision", &Number(self.0.to_string())), ("scale", &Number(self.1.to_string()))]);
+    }
+}
+
+struct IntegerKind(u8, bool);
+impl ToJson for IntegerKind {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("bits", &Number(self.0.to_string())), ("signed", &self.1)]);
+    }
+}
+
+impl ToJson for FieldDefault {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            FieldDefault::Value(v) => variant(out, "Value", v),
+            FieldDefault::FunctionCall(fc) => variant(out, "FunctionCall", fc),
+        }
+    }
+}
+
+impl ToJson for TableField {
+    fn write_json(&self, out: &mut String) {
+        let (name, kind, nullable) = (self.name(), self.kind(), self.is_nullable());
+        let on_delete = format!("{:?}", self.on_delete());
+        let default = self.default();
+        let mut fields: Vec<(&str, &ToJson)> = vec![("name", &name), ("kind", &kind), ("nullable", &nullable)];
+        // Only foreign keys are affected by removals
+        if let FieldKind::ForeignKey(_) = kind {
+            fields.push(("on_delete", &on_delete));
+        }
+        if let Some(ref default) = default {
+            fields.push(("default", default));
+        }
+        let generated = self.generated();
+        if let Some(ref generated) = generated {
+            fields.push(("generated", generated));
+        }
+        let collation = self.collation().name().to_owned();
+        if self.collation() != Collation::Binary {
+            fields.push(("collation", &collation));
+        }
+        object(out, &fields);
+    }
+}
+
+impl ToJson for Table {
+    fn write_json(&self, out: &mut String) {
+        let fields = self.fields();
+        let keys: Vec<String> = fields.iter().enumerate()
+            .filter(|(i, _)| self.is_key_field(*i))
+            .map(|(_, f)| f.name())
+            .collect();
+        let (name, unique_key) = (self.name(), self.has_unique_key());
+        let checks: Vec<NamedCheck> = self.checks().into_iter().map(|(n, c)| NamedCheck(n, c)).collect();
+        let mut members: Vec<(&str, &ToJson)> = vec![
+            ("name", &name),
+            ("fields", &fields),
+            ("key_fields", &keys),
+            ("unique_key", &unique_key),
+        ];
+        let uniques: Vec<NamedUnique> = self.unique_constraints().into_iter().map(|(n, f)| NamedUnique(n, f)).collect();
+        if !checks.is_empty() {
+            members.push(("checks", &checks));
+        }
+        if !uniques.is_empty() {
+            members.push(("uniques", &uniques));
+        }
+        let indexes: Vec<TableIndex> = self.indexes().into_iter().map(|(k, f)| TableIndex(k, f)).collect();
+        if !indexes.is_empty() {
+            members.push(("indexes", &indexes));
+        }
+        let expression_indexes: Vec<ExpressionIndex> = self.expression_indexes().into_iter()
+            .map(|(k, e)| ExpressionIndex(k, e))
+            .collect();
+        if !expression_indexes.is_empty() {
+            members.push(("expression_indexes", &expression_indexes));
+        }
+        let partial_indexes: Vec<PartialIndex> = self.partial_indexes().into_iter()
+            .map(|(k, f, p)| PartialIndex(k, f, p))
+            .collect();
+        if !partial_indexes.is_empty() {
+            members.push(("partial_indexes", &partial_indexes));
+        }
+        let temporary = self.is_temporary();
+        if temporary {
+            members.push(("temporary", &temporary));
+        }
+        let quota = self.quota();
+        if let Some(ref quota) = quota {
+            members.push(("quota", quota));
+        }
+        let ttl = self.ttl().map(|(field, retention)| Ttl(field, retention));
+        if let Some(ref ttl) = ttl {
+            members.push(("ttl", ttl));
+        }
+        let partitioning = self.partitioning().map(|(field, by)| TablePartitioning(field, by));
+        if let Some(ref partitioning) = partitioning {
+            members.push(("partitioning", partitioning));
+        }
+        object(out, &members);
+    }
+}
+
+impl ToJson for Quota {
+    fn write_json(&self, out: &mut String) {
+        let max_rows = self.max_rows.map(|max| Number(max.to_string()));
+        let max_bytes = self.max_bytes.map(|max| Number(max.to_string()));
+        object(out, &[("max_rows", &max_rows), ("max_bytes", &max_bytes), ("evict", &self.evict)]);
+    }
+}
+
+struct Ttl(FieldName, Duration);
+impl ToJson for Ttl {
+    fn write_json(&self, out: &mut String) {
+        let seconds = match self.1.subsec_nanos() {
+            0 => Number(self.1.as_secs().to_string()),
+            nanos => Number(format!("{}.{:09}", self.1.as_secs(), nanos)),
+        };
+        object(out, &[("field", &self.0), ("seconds", &seconds)]);
+    }
+}
+
+struct TablePartitioning(FieldName, Partitioning);
+impl ToJson for TablePartitioning {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("field", &self.0), ("by", &self.1)]);
+    }
+}
+
+impl ToJson for Partitioning {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Partitioning::Range(bounds) => variant(out, "Range", bounds),
+            Partitioning::Hash(count) => variant(out, "Hash", &Number(count.to_string())),
+        }
+    }
+}
+
+struct TableIndex(IndexKind, Vec<FieldName>);
+impl ToJson for TableIndex {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("kind", &self.0), ("fields", &self.1)]);
+    }
+}
+
+struct ExpressionIndex(IndexKind, FunctionCall);
+impl ToJson for ExpressionIndex {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("kind", &self.0), ("expression", &self.1)]);
+    }
+}
+
+struct PartialIndex(IndexKind, Vec<FieldName>, Condition);
+impl ToJson for PartialIndex {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("kind", &self.0), ("fields", &self.1), ("predicate", &self.2)]);
+    }
+}
+
+impl ToJson for IndexKind {
+    fn write_json(&self, out: &mut String) {
+        format!("{:?}", self).write_json(out);
+    }
+}
+
+struct NamedCheck(String, FunctionCall);
+impl ToJson for NamedCheck {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("name", &self.0), ("check", &self.1)]);
+    }
+}
+
+struct NamedUnique(String, Vec<FieldName>);
+impl ToJson for NamedUnique {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("name", &self.0), ("fields", &self.1)]);
+    }
+}
+
+impl ToJson for Row {
+    fn write_json(&self, out: &mut String) {
+        self.values().write_json(out);
+    }
+}
+
+impl ToJson for QueryField {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("table", &self.table), ("field", &self.field)]);
+    }
+}
+
+impl ToJson for FunctionCall {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("target", &self.target), ("arguments", &self.arguments)]);
+    }
+}
+
+impl ToJson for Argument {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Argument::FunctionCall(fc) => variant(out, "FunctionCall", fc),
+            Argument::Value(v) => variant(out, "Value", v),
+            Argument::QueryField(qf) => variant(out, "QueryField", qf),
+            Argument::Parameter(i) => variant(out, "Parameter", &Number(i.to_string())),
+        }
+    }
+}
+
+impl ToJson for Condition {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Condition::Value(v) => variant(out, "Value", v),
+            Condition::QueryField(qf) => variant(out, "QueryField", qf),
+            Condition::FunctionCall(fc) => variant(out, "FunctionCall", fc),
+        }
+    }
+}
+
+impl ToJson for Query {
+    fn write_json(&self, out: &mut String) {
+        use Query::*;
+        let kind = match self {
+            Empty(_) => "Empty",
+            Table(_) => "Table",
+            FromValue(_, _) => "FromValue",
+            FromFunctionCall(_, _) => "FromFunctionCall",
+            Union(_, _) => "Union",
+            Intersection(_, _) => "Intersection",
+            Difference(_, _) => "Difference",
+            Distinct(_) => "Distinct",
+            Project(_, _) => "Project",
+            Filter(_, _) => "Filter",
+            Rename(_, _, _) => "Rename",
+            JoinOn(_, _, _) => "JoinOn",
+            Unnest(_, _) => "Unnest",
+            NearestNeighbors(_, _, _, _) => "NearestNeighbors",
+            OrderBy(_, _) => "OrderBy",
+            FromTableFunction(_) => "FromTableFunction",
+        };
+        let kind = kind.to_owned();
+        match self {
+            Empty(fields) => object(out, &[("type", &kind), ("fields", fields)]),
+            Table(name) => object(out, &[("type", &kind), ("table", name)]),
+            FromValue(field, value) => object(out, &[("type", &kind), ("field", field), ("value", value)]),
+            FromFunctionCall(field, fc) => object(out, &[("type", &kind), ("field", field), ("call", fc)]),
+            Union(q1, q2)
+            | Intersection(q1, q2)
+            | Difference(q1, q2) => object(out, &[("type", &kind), ("left", &**q1), ("right", &**q2)]),
+            Distinct(subquery) => object(out, &[("type", &kind), ("query", &**subquery)]),
+            Project(fields, subquery) => object(out, &[("type", &kind), ("fields", fields), ("query", &**subquery)]),
+            Filter(condition, subquery) => object(out, &[("type", &kind), ("condition", condition), ("query", &**subquery)]),
+            Rename(from, to, subquery) => object(out, &[("type", &kind), ("from", from), ("to", to), ("query", &**subquery)]),
+            JoinOn(condition, q1, q2) => object(out, &[
+                ("type", &kind), ("condition", condition), ("left", &**q1), ("right", &**q2),
+            ]),
+            Unnest(field, subquery)
+            | OrderBy(field, subquery) => object(out, &[("type", &kind), ("field", field), ("query", &**subquery)]),
+            NearestNeighbors(field, vector, k, subquery) => {
+                let vector: Vec<Number> = vector.iter().map(|x| Number(format!("{:?}", x))).collect();
+                object(out, &[
+                    ("type", &kind), ("field", field), ("vector", &vector), ("count", &Number(k.to_string())), ("query", &**subquery),
+                ])
+            },
+            FromTableFunction(fc) => object(out, &[("type", &kind), ("call", fc)]),
+        }
+    }
+}
+
+/// Assignment of a value or argument to a field
+struct Assignment<'a, T: 'a>(&'a FieldName, &'a T);
+impl<'a, T: ToJson> ToJson for Assignment<'a, T> {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("field", self.0), ("value", self.1)]);
+    }
+}
+
+fn assignments<T: ToJson>(list: &[(FieldName, T)]) -> Vec<Assignment<T>> {
+    list.iter().map(|(f, v)| Assignment(f, v)).collect()
+}
+
+impl ToJson for RowUpdate {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            RowUpdate::Replace(row) => variant(out, "Replace", row),
+            RowUpdate::Set(list) => variant(out, "Set", &assignments(list)),
+        }
+    }
+}
+
+impl ToJson for TriggerEvent {
+    fn write_json(&self, out: &mut String) {
+        format!("{:?}", self).write_json(out);
+    }
+}
+
+impl ToJson for TriggerTiming {
+    fn write_json(&self, out: &mut String) {
+        format!("{:?}", self).write_json(out);
+    }
+}
+
+impl ToJson for TriggerAction {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            TriggerAction::Check(condition) => variant(out, "Check", condition),
+            TriggerAction::Set(list) => variant(out, "Set", &assignments(list)),
+        }
+    }
+}
+
+impl ToJson for Trigger {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[
+            ("name", &self.name),
+            ("table", &self.table),
+            ("timing", &self.timing),
+            ("events", &self.events),
+            ("action", &self.action),
+        ]);
+    }
+}
+
+struct Insertion<'a>(&'a TableName, &'a [(FieldName, Argument)]);
+impl<'a> ToJson for Insertion<'a> {
+    fn write_json(&self, out: &mut String) {
+        object(out, &[("table", self.0), ("values", &assignments(self.1))]);
+    }
+}
+
+impl ToJson for ProcedureStep {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            ProcedureStep::Apply(delta) => variant(out, "Apply", delta),
+            ProcedureStep::Insert(name, values) => variant(out, "Insert", &Insertion(name, values)),
+            ProcedureStep::Query(query) => variant(out, "Query", query),
+        }
+    }
+}
+
+impl ToJson for Delta {
+    fn write_json(&self, out: &mut String) {
+        use Delta::*;
+        let kind = match self {
+            CreateTable(_) => "CreateTable",
+            DropTable(_) => "DropTable",
+            Truncate(_) => "Truncate",
+            AddColumn(_, _, _) => "AddColumn",
+            RenameTable(_, _) => "RenameTable",
+            RenameColumn(_, _, _) => "RenameColumn",
+            AlterColumnType(_, _, _) => "AlterColumnType",
+            DropColumn(_, _) => "DropColumn",
+            ForceDropColumn(_, _) => "ForceDropColumn",
+            AddRow(_, _) => "AddRow",
+            AddRows(_, _) => "AddRows",
+            AddNamedRow(_, _) => "AddNamedRow",
+            RemoveRow(_, _) => "RemoveRow",
+            UpdateRow(_, _, _) => "UpdateRow",
+            RemoveWhere(_, _) => "RemoveWhere",
+            UpdateWhere(_, _, _) => "UpdateWhere",
+            Upsert(_, _) => "Upsert",
+            CreateTrigger(_) => "CreateTrigger",
+            DropTrigger(_, _) => "DropTrigger",
+            RemoveRowById(_, _) => "RemoveRowById",
+            UpdateRowById(_, _, _) => "UpdateRowById",
+            CreateIndex(_, _, _) => "CreateIndex",
+            CreateExpressionIndex(_, _, _) => "CreateExpressionIndex",
+            CreatePartialIndex(_, _, _, _) => "CreatePartialIndex",
+            CreateFunction(_, _) => "CreateFunction",
+            DropFunction(_) => "DropFunction",
+            CreateView(_, _) => "CreateView",
+            DropView(_) => "DropView",
+            CreateMaterializedView(_, _) => "CreateMaterializedView",
+            RefreshView(_) => "RefreshView",
+            CreateProcedure(_, _) => "CreateProcedure",
+            DropProcedure(_) => "DropProcedure",
+            CreatePolicy(_, _) => "CreatePolicy",
+            DropPolicy(_) => "DropPolicy",
+        };
+        let kind = kind.to_owned();
+        match self {
+            CreateTable(table) => object(out, &[("type", &kind), ("table", table)]),
+            DropTable(name)
+            | Truncate(name) => object(out, &[("type", &kind), ("table", name)]),
+            AddColumn(name, field, default) => object(out, &[
+                ("type", &kind), ("table", name), ("field", field), ("default", default),
+            ]),
+            RenameTable(from, to) => object(out, &[("type", &kind), ("from", from), ("to", to)]),
+            RenameColumn(name, from, to) => object(out, &[
+                ("type", &kind), ("table", name), ("from", from), ("to", to),
+            ]),
+            AlterColumnType(name, field, field_kind) => object(out, &[
+                ("type", &kind), ("table", name), ("field", field), ("kind", field_kind),
+            ]),
+            DropColumn(name, field)
+            | ForceDropColumn(name, field) => object(out, &[("type", &kind), ("table", name), ("field", field)]),
+            AddRow(name, row)
+            | RemoveRow(name, row)
+            | Upsert(name, row) => object(out, &[("type", &kind), ("table", name), ("row", row)]),
+            AddRows(name, rows) => object(out, &[("type", &kind), ("table", name), ("rows", rows)]),
+            AddNamedRow(name, values) => object(out, &[("type", &kind), ("table", name), ("values", &assignments(values))]),
+            CreateIndex(name, fields, index_kind) => object(out, &[
+                ("type", &kind), ("table", name), ("fields", fields), ("kind", index_kind),
+            ]),
+            CreateExpressionIndex(name, expression, index_kind) => object(out, &[
+                ("type", &kind), ("table", name), ("expression", expression), ("kind", index_kind),
+            ]),
+            CreatePartialIndex(name, fields, index_kind, predicate) => object(out, &[
+                ("type", &kind), ("table", name), ("fields", fields), ("kind", index_kind), ("predicate", predicate),
+            ]),
+            UpdateRow(name, row, update) => object(out, &[
+                ("type", &kind), ("table", name), ("row", row), ("update", update),
+            ]),
+            RemoveWhere(name, condition) => object(out, &[("type", &kind), ("table", name), ("condition", condition)]),
+            UpdateWhere(name, condition, list) => object(out, &[
+                ("type", &kind), ("table", name), ("condition", condition), ("assignments", &assignments(list)),
+            ]),
+            CreateTrigger(trigger) => object(out, &[("type", &kind), ("trigger", trigger)]),
+            DropTrigger(name, trigger) => object(out, &[("type", &kind), ("table", name), ("trigger", trigger)]),
+            RemoveRowById(name, id) => object(out, &[("type", &kind), ("table", name), ("id", &Number(id.to_string()))]),
+            UpdateRowById(name, id, update) => object(out, &[
+                ("type", &kind), ("table", name), ("id", &Number(id.to_string())), ("update", update),
+            ]),
+            CreateFunction(name, body) => object(out, &[("type", &kind), ("function", name), ("body", body)]),
+            DropFunction(name) => object(out, &[("type", &kind), ("function", name)]),
+            CreateView(name, query)
+            | CreateMaterializedView(name, query) => object(out, &[("type", &kind), ("view", name), ("query", query)]),
+            RefreshView(name)
+            | DropView(name) => object(out, &[("type", &kind), ("view", name)]),
+            CreateProcedure(name, steps) => object(out, &[("type", &kind), ("procedure", name), ("steps", steps)]),
+            DropProcedure(name) => object(out, &[("type", &kind), ("procedure", name)]),
+            CreatePolicy(name, condition) => object(out, &[("type", &kind), ("table", name), ("condition", condition)]),
+            DropPolicy(name) => object(out, &[("type", &kind), ("table", name)]),
+        }
+    }
+}
+
+impl Delta {
+    /// JSON encoding of the delta, as a single line
+    pub fn to_json(&self) -> String {
+        to_json(self)
+    }
+}
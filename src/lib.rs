@@ -6,9 +6,18 @@
 #![feature(decl_macro)]
 
 extern crate reduce;
+#[cfg(feature = "regex")]
+extern crate regex;
 
 use std::path::{Path, PathBuf};
 use std::io;
+use std::mem;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use std::thread;
 
 use std::collections::HashMap;
 
@@ -17,24 +26,87 @@ pub mod field;
 pub mod value;
 pub mod query;
 pub mod function;
+pub mod query_log;
+pub mod interner;
+pub mod transaction;
+pub mod journal;
+pub mod subscription;
+pub mod trigger;
+pub mod codec;
+pub mod replication;
+pub mod json;
+pub mod cdc;
+pub mod temporal;
+pub mod decimal;
+pub mod uuid;
+pub mod geo;
+pub mod vector;
+pub mod fulltext;
+pub mod pattern;
+pub mod digest;
+pub mod procedure;
+pub mod access;
+pub mod collation;
+mod history;
+mod constraint;
+mod index;
+mod bloom;
+mod random;
+mod view;
+mod policy;
+mod partition;
+
+use view::MaterializedView;
 
 pub mod builtin_functions;
+#[cfg(feature = "regex")]
+mod regex_functions;
+
+#[cfg(feature = "bench")]
+pub mod bench;
 
-pub use table::{Table, TableField, Row};
+pub use table::{Table, TableField, Row, ReferenceAction, FieldDefault, IndexKind, Quota, Partitioning};
 pub use field::{Field, FieldKind, IntSize};
-pub use value::Value;
-pub use query::{Query, QueryField, QueryResult};
-pub use function::{FunctionCall, Argument};
+pub use value::{Value, ValueKind, ArithmeticMode, CastMode};
+pub use query::{Query, QueryField, QueryResult, QueryOptions, CancelToken, Condition, ROWID_FIELD};
+pub use query::{INDEXES_TABLE, TABLES_TABLE, FIELDS_TABLE, FUNCTIONS_TABLE};
+pub use function::{FunctionCall, Argument, Signature, NativeFunction, TableFunction};
+pub use query_log::{QueryLog, LogEvent, LogEventKind};
+pub use interner::SharedText;
+pub use transaction::Transaction;
+pub use journal::JournalEntry;
+pub use subscription::{DeltaEvent, SubscriptionFilter};
+pub use trigger::{Trigger, TriggerEvent, TriggerTiming, TriggerAction};
+pub use codec::DecodeError;
+pub use collation::Collation;
+pub use replication::{Leader, Follower, Transport, ReplicationError};
+pub use cdc::{CdcSink, CdcFormat, FileSink, ChannelSink};
+pub use procedure::ProcedureStep;
+pub use access::{Role, Permission};
 
+use query::ExecutionContext;
 use function::Function;
+use interner::Interner;
+use journal::Journal;
+use history::{History, HistoryEntry};
+use index::TableIndexes;
+pub use index::IndexInfo;
+pub use partition::PartitionInfo;
+use subscription::Subscribers;
+use procedure::BoundStep;
 
 pub type TableName = String;
 pub type FieldName = String;
 pub type FunctionName = String;
+/// Number of deltas applied to a database
+pub type Revision = u64;
+/// Identifies a stored row, see `Row::id`
+pub type RowId = u64;
 
 #[derive(Debug, Clone)]
 pub enum TypeError {
     NotBoolean,
+    NotArray,
 }
 
 #[derive(Debug, Clone)]
@@ -46,44 +118,406 @@ pub enum QueryError {
     NoSuchTable(TableName),
     NoSuchField(QueryField),
     AmbiguousField(QueryField),
+    MemoryLimitExceeded,
+    Cancelled,
+    /// Integer doesn't fit the type of `Row::get`, the row or value asked for
+    /// doesn't exist, or arithmetic overflowed in `ArithmeticMode::Checked`
+    OutOfRange,
+    /// Divisor of a division or remainder is zero
+    DivisionByZero,
+    /// Regular expression or date format is invalid, with the reason
+    InvalidPattern(String),
+    /// The state at this revision is not available
+    NoSuchRevision(Revision),
+    /// No function named $0
+    NoSuchFunction(FunctionName),
+    /// Field $0 is referenced where there is no row, like in `Query::FromFunctionCall`
+    UnboundField(QueryField),
+    /// Composed function $0 calls itself, directly or through other functions
+    CyclicFunction(FunctionName),
+    /// Function $0 takes at most $1 arguments
+    TooManyArguments(FunctionName, usize),
+    /// Argument $1 of function $0, counting from zero, can't be of kind $2
+    InvalidArgument(FunctionName, usize, ValueKind),
+    /// No overload of function $0 accepts arguments of kinds $1
+    NoMatchingOverload(FunctionName, Vec<ValueKind>),
+    /// Several overloads of function $0 accept arguments of kinds $1 equally well
+    AmbiguousCall(FunctionName, Vec<ValueKind>),
+    /// Table $0 has a row-level security policy, but the query has no `QueryOptions::context`
+    NoContext(TableName),
+    /// Role of the handle can't read table or view $0
+    AccessDenied(TableName),
+    /// No collation named $0, see `Collation::name`
+    NoSuchCollation(String),
+    /// Number doesn't fit kind $0 exactly, see `CastMode`
+    CastOverflow(ValueKind),
+    /// Field $0 of a set operation has values of kinds $1 and $2, which have no common kind
+    IncompatibleColumns(FieldName, ValueKind, ValueKind),
 }
 
 #[derive(Debug, Clone)]
 pub enum ApplyError {
     NoSuchTable(TableName),
+    TableExists(TableName),
     AddCannotModify(TableName),
+    NoSuchRow(TableName),
+    NoSuchField(TableName, FieldName),
+    WrongFieldCount { table: TableName, expected: usize, found: usize },
+    TypeMismatch { table: TableName, field: FieldName },
+    /// Integer doesn't fit the declared size of the field
+    OutOfRange { table: TableName, field: FieldName },
+    /// Null value for a field that isn't nullable
+    NotNull { table: TableName, field: FieldName },
+    FieldExists(TableName, FieldName),
+    /// Table $0 has no fields, a field with an empty or duplicate name or an invalid kind,
+    /// an index without fields or over fields of kinds it doesn't support, or a
+    /// foreign key to or from a table without a single key field
+    InvalidSchema(TableName),
+    /// Table $0 already has an index of the same kind over fields $1
+    IndexExists(TableName, Vec<FieldName>),
+    /// Table $0 already has an index of the same kind over expression $1
+    ExpressionIndexExists(TableName, FunctionCall),
+    /// Operation would modify a key field
+    KeyField(TableName, FieldName),
+    /// Row would have the same key fields as another row of a table with a unique key
+    DuplicateKey(TableName),
+    /// Row would have the same values as another row in the fields of unique constraint `constraint`
+    UniqueViolation { table: TableName, constraint: String },
+    /// Row would have the same values as another row in fields $1 of a unique index
+    DuplicateIndexKey(TableName, Vec<FieldName>),
+    /// Row would have the same value of expression $1 as another row in a unique index
+    DuplicateIndexValue(TableName, FunctionCall),
+    /// Row doesn't satisfy check constraint `constraint` of the table
+    CheckViolation { table: TableName, constraint: String },
+    /// Foreign key $1 of table $0 doesn't match the key of any row in the referenced table
+    InvalidReference(TableName, FieldName),
+    /// Removed rows of table $0 are still referenced by rows of table $1
+    Referenced(TableName, TableName),
+    QueryError(QueryError),
+    NoSuchSavepoint(String),
+    /// Table was modified by someone else after the transaction started
+    Conflict(TableName),
+    /// Database is no longer at the expected revision
+    RevisionConflict { expected: Revision, current: Revision },
+    /// Table $0 already has a trigger named $1
+    TriggerExists(TableName, String),
+    NoSuchTrigger(TableName, String),
+    /// Trigger $1 of table $0 can't be run at its timing and events
+    InvalidTrigger(TableName, String),
+    /// Trigger $1 of table $0 rejected a row
+    TriggerRejected(TableName, String),
+    /// A function named $0 already exists
+    FunctionExists(FunctionName),
+    /// No function named $0, or when dropping, no composed function named $0
+    NoSuchFunction(FunctionName),
+    /// Body of composed function $0 refers to fields
+    InvalidFunction(FunctionName),
+    /// Composed function $0 is called by composed function $1
+    FunctionInUse(FunctionName, FunctionName),
+    NoSuchView(TableName),
+    /// Table or view $0 is read by view $1
+    TableInUse(TableName, TableName),
+    /// Table $0 is temporary, so views and foreign keys of other tables can't refer to it
+    TemporaryTable(TableName),
+    /// A procedure named $0 already exists
+    ProcedureExists(FunctionName),
+    NoSuchProcedure(FunctionName),
+    /// Table $0 already has a row-level security policy
+    PolicyExists(TableName),
+    NoSuchPolicy(TableName),
+    /// Field $1 of table $0 is read by the row-level security policy of the table
+    PolicyField(TableName, FieldName),
+    /// Table $0 has a row-level security policy, so materialized views can't read it
+    SecuredTable(TableName),
+    /// Rows of table $0 would exceed its quota, which doesn't evict rows
+    QuotaExceeded(TableName),
+    /// Role of the handle lacks permission $1 on table, view, function or procedure $0
+    AccessDenied(String, Permission),
 }
 
+#[derive(Debug, Clone)]
 pub enum Delta {
     CreateTable(Table),
     DropTable(TableName),
+    /// Remove all rows, keeping the table
+    Truncate(TableName),
+    /// Add field $1 to table $0, setting it to $2 in existing rows
+    AddColumn(TableName, TableField, Value),
+    /// Rename table $0 to $1
+    RenameTable(TableName, TableName),
+    /// Rename field $1 of table $0 to $2
+    RenameColumn(TableName, FieldName, FieldName),
+    /// Change the kind of a field, converting existing values; fails without changes if any value can't be converted
+    AlterColumnType(TableName, FieldName, FieldKind),
+    /// Remove a non-key field from the table and its rows
+    DropColumn(TableName, FieldName),
+    /// Remove a field from the table and its rows, even if it is a key field
+    ForceDropColumn(TableName, FieldName),
     AddRow(TableName, Row),
+    /// Add a row from values of the named fields; omitted fields get their default, or null
+    ///
+    /// Defaults are evaluated each time the delta is applied, including replays.
+    AddNamedRow(TableName, Vec<(FieldName, Value)>),
+    /// Add a batch of rows; nothing is added if any of them is invalid
+    AddRows(TableName, Vec<Row>),
+    /// Remove the first row equal to $1; duplicates of it are kept
     RemoveRow(TableName, Row),
+    /// Update the first row equal to $1
+    UpdateRow(TableName, Row, RowUpdate),
+    /// Remove the row with id $1
+    ///
+    /// Rows restored by `SrimDB::undo` get new ids, so redoing this fails.
+    RemoveRowById(TableName, RowId),
+    /// Update the row with id $1, keeping its id
+    UpdateRowById(TableName, RowId, RowUpdate),
+    /// Remove all rows matching the condition
+    RemoveWhere(TableName, Condition),
+    /// Set fields of all rows matching the condition, evaluating arguments against the old row
+    UpdateWhere(TableName, Condition, Vec<(FieldName, Argument)>),
+    /// Replace the row with the same key fields, or add the row if there is none
+    Upsert(TableName, Row),
+    CreateTrigger(Trigger),
+    /// Remove trigger $1 of table $0
+    DropTrigger(TableName, String),
+    /// Index fields $1 of table $0, so that queries filtering on them don't scan the table
+    CreateIndex(TableName, Vec<FieldName>, IndexKind),
+    /// Index the value of expression $1 over fields of table $0, so that queries
+    /// filtering on the same expression don't scan the table
+    CreateExpressionIndex(TableName, FunctionCall, IndexKind),
+    /// Index fields $1 of the rows of table $0 satisfying condition $3, so that
+    /// queries filtering on the same condition don't scan the table
+    CreatePartialIndex(TableName, Vec<FieldName>, IndexKind, Condition),
+    /// Define composed function $0, which can then be called by name like the builtins
+    ///
+    /// Its body can call only functions that already exist, and refers to the
+    /// call arguments with `Argument::Parameter` instead of fields.
+    CreateFunction(FunctionName, FunctionCall),
+    /// Remove composed function $0, unless another composed function calls it
+    DropFunction(FunctionName),
+    /// Store query $1 as view $0, which `Query::Table` then reads like a table
+    ///
+    /// The query can read only tables and views that already exist, and those
+    /// can't be dropped or renamed while the view exists.
+    CreateView(TableName, Query),
+    /// Store the rows of query $1 as materialized view $0, which `Query::Table`
+    /// then reads like a table
+    ///
+    /// If the query is a union of filters, projections and renames of tables,
+    /// the rows are updated as the tables change. Otherwise they are only
+    /// updated by `RefreshView`. Dependencies are tracked like for `CreateView`.
+    CreateMaterializedView(TableName, Query),
+    /// Recompute the rows of materialized view $0
+    RefreshView(TableName),
+    /// Remove view or materialized view $0, unless another view reads it
+    DropView(TableName),
+    /// Define procedure $0, which `SrimDB::call_procedure` runs with arguments
+    /// bound to the `Argument::Parameter`s in its steps
+    CreateProcedure(FunctionName, Vec<ProcedureStep>),
+    DropProcedure(FunctionName),
+    /// Attach row-level security policy $1 to table $0: queries only see the
+    /// rows of the table satisfying it, with `Argument::Parameter(0)` bound to
+    /// `QueryOptions::context`
+    CreatePolicy(TableName, Condition),
+    /// Remove the row-level security policy of table $0
+    DropPolicy(TableName),
+}
+
+impl Delta {
+    /// Names of the tables this delta modifies
+    pub fn tables(&self) -> Vec<TableName> {
+        use Delta::*;
+        match self {
+            CreateTable(table) => vec![table.name()],
+            CreateTrigger(trigger) => vec![trigger.table.clone()],
+            RenameTable(from, to) => vec![from.clone(), to.clone()],
+            DropTable(name)
+            | Truncate(name)
+            | AddColumn(name, _, _)
+            | RenameColumn(name, _, _)
+            | AlterColumnType(name, _, _)
+            | DropColumn(name, _)
+            | ForceDropColumn(name, _)
+            | AddRow(name, _)
+            | AddNamedRow(name, _)
+            | AddRows(name, _)
+            | RemoveRow(name, _)
+            | UpdateRow(name, _, _)
+            | RemoveRowById(name, _)
+            | UpdateRowById(name, _, _)
+            | RemoveWhere(name, _)
+            | UpdateWhere(name, _, _)
+            | Upsert(name, _)
+            | DropTrigger(name, _)
+            | CreateIndex(name, _, _)
+            | CreateExpressionIndex(name, _, _)
+            | CreatePartialIndex(name, _, _, _)
+            | CreatePolicy(name, _)
+            | DropPolicy(name) => vec![name.clone()],
+            CreateFunction(_, _)
+            | DropFunction(_)
+            | CreateView(_, _)
+            | CreateMaterializedView(_, _)
+            | RefreshView(_)
+            | DropView(_)
+            | CreateProcedure(_, _)
+            | DropProcedure(_) => Vec::new(),
+        }
+    }
+}
+
+/// Rows changed by applying deltas
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyReport {
+    pub inserted: usize,
+    /// Rows with changed values, including rows changed by schema changes
+    pub updated: usize,
+    pub removed: usize,
+    /// Rows removed from each table by cascading foreign keys, not included in `removed`
+    pub cascaded: HashMap<TableName, usize>,
+}
+impl ApplyReport {
+    fn inserts(count: usize) -> Self {
+        Self { inserted: count, ..Self::default() }
+    }
+
+    fn updates(count: usize) -> Self {
+        Self { updated: count, ..Self::default() }
+    }
+
+    fn removals(count: usize) -> Self {
+        Self { removed: count, ..Self::default() }
+    }
+
+    /// Total number of rows affected, including those removed by cascading
+    pub fn rows(&self) -> usize {
+        self.inserted + self.updated + self.removed + self.cascaded.values().sum::<usize>()
+    }
+
+    fn merge(&mut self, other: ApplyReport) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.removed += other.removed;
+        for (table, count) in other.cascaded {
+            *self.cascaded.entry(table).or_insert(0) += count;
+        }
+    }
+}
+
+/// New contents for an updated row
+#[derive(Debug, Clone)]
+pub enum RowUpdate {
+    /// Replace all values of the row
+    Replace(Row),
+    /// Replace values of the named fields, keeping the others
+    Set(Vec<(FieldName, Value)>),
 }
 
+/// Database contents
+///
+/// Cloning is cheap: tables are shared until modified, so a clone can be
+/// used as a stable snapshot while the original keeps changing.
+///
+/// Inserted rows get ids from a counter shared by all tables, and are always
+/// appended, so the rows of each table are sorted by id.
 #[derive(Clone)]
 struct DataDB {
-    tables: Vec<Table>,
-    table_rows: HashMap<TableName, Vec<Row>>,
-    // indexes: Vec<(TableName, TableIndex)>,
-    functions: HashMap<FunctionName, Function>
+    tables: Arc<Vec<Table>>,
+    table_rows: HashMap<TableName, Arc<Vec<Arc<Row>>>>,
+    /// Indexes for the unique key, unique constraints and created indexes of each table
+    indexes: HashMap<TableName, Arc<TableIndexes>>,
+    functions: Arc<HashMap<FunctionName, Function>>,
+    table_functions: Arc<HashMap<FunctionName, TableFunction>>,
+    views: Arc<HashMap<TableName, Query>>,
+    materialized: Arc<HashMap<TableName, MaterializedView>>,
+    procedures: Arc<HashMap<FunctionName, Vec<ProcedureStep>>>,
+    /// Row-level security policies by table
+    policies: Arc<HashMap<TableName, Condition>>,
+    /// Mode of the arithmetic functions in `functions`
+    arithmetic_mode: ArithmeticMode,
+    triggers: Arc<Vec<Trigger>>,
+    /// Cleared while reverting changes, which must restore rows exactly
+    fire_triggers: bool,
+    /// Rows removed by cascading foreign keys, in order, while recorded by `apply`
+    cascaded: Option<Vec<(TableName, Arc<Row>)>>,
+    next_row_id: RowId,
+    /// Shared by all clones
+    interner: Arc<Mutex<Interner>>,
 }
 impl DataDB {
     pub(crate) fn new() -> Self {
         let mut functions: HashMap<FunctionName, Function> = HashMap::new();
-        for (name, function) in builtin_functions::FUNCTIONS.iter() {
-            functions.insert(name.to_owned().to_owned(), Function::Native(function.clone()));
+        for (name, function) in builtin_functions::functions() {
+            function::register(&mut functions, name, function);
+        }
+        #[cfg(feature = "regex")]
+        for (name, function) in builtin_functions::regex_functions() {
+            function::register(&mut functions, name, function);
+        }
+        let table_functions = builtin_functions::table_functions().into_iter()
+            .map(|(name, function)| (name.to_owned(), function))
+            .collect();
+        let arithmetic_mode = ArithmeticMode::default();
+        for (name, function) in builtin_functions::arithmetic_functions(arithmetic_mode) {
+            functions.insert(name.to_owned(), Function::Native(function));
         }
 
         Self {
-            tables: Vec::new(),
+            tables: Arc::new(Vec::new()),
             table_rows: HashMap::new(),
-            functions,
+            indexes: HashMap::new(),
+            functions: Arc::new(functions),
+            table_functions: Arc::new(table_functions),
+            views: Arc::new(HashMap::new()),
+            materialized: Arc::new(HashMap::new()),
+            procedures: Arc::new(HashMap::new()),
+            policies: Arc::new(HashMap::new()),
+            arithmetic_mode,
+            triggers: Arc::new(Vec::new()),
+            fire_triggers: true,
+            cascaded: None,
+            next_row_id: 1,
+            interner: Arc::new(Mutex::new(Interner::new())),
+        }
+    }
+
+    /// Replace the arithmetic functions with those handling integer overflow according to `mode`
+    ///
+    /// Overloads registered for them are kept.
+    fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        let functions = Arc::make_mut(&mut self.functions);
+        for (name, function) in builtin_functions::arithmetic_functions(mode) {
+            match functions.get_mut(name) {
+                Some(Function::Overloaded(overloads)) => overloads[0] = function,
+                _ => {
+                    functions.insert(name.to_owned(), Function::Native(function));
+                },
+            }
+        }
+        self.arithmetic_mode = mode;
+    }
+
+    /// Empty database with the native functions, table functions and
+    /// arithmetic mode of this one, but none of its composed functions
+    pub(crate) fn configuration(&self) -> DataDB {
+        let mut functions = (*self.functions).clone();
+        functions.retain(|_, function| match function {
+            Function::Composed(_) => false,
+            _ => true,
+        });
+        DataDB {
+            functions: Arc::new(functions),
+            table_functions: self.table_functions.clone(),
+            arithmetic_mode: self.arithmetic_mode,
+            ..DataDB::new()
         }
     }
 
-    pub(crate) fn function_dict(&self) -> HashMap<FunctionName, Function> {
-        self.functions.clone()
+    pub(crate) fn function_dict(&self) -> &HashMap<FunctionName, Function> {
+        &self.functions
+    }
+
+    pub(crate) fn table_function_dict(&self) -> &HashMap<FunctionName, TableFunction> {
+        &self.table_functions
     }
 
     pub(crate) fn table_index(&self, name: TableName) -> Option<usize> {
@@ -103,27 +537,196 @@ impl DataDB {
         Some(self.table_by_index(self.table_index(name)?))
     }
 
-    pub(crate) fn all_rows(&self, name: TableName) -> Option<Vec<Row>> {
-        self.table_rows.get(&name).map(|x| x.clone())
+    fn table_mut(&mut self, name: &TableName) -> Option<&mut Table> {
+        Arc::make_mut(&mut self.tables).iter_mut().find(|t| t.name() == *name)
+    }
+
+    pub(crate) fn all_rows(&self, name: TableName) -> Option<Vec<Arc<Row>>> {
+        self.table_rows.get(&name).map(|x| (**x).clone())
+    }
+
+    /// Deltas removing the rows expired at time `now` from each table that has any
+    pub(crate) fn expired_removals(&self, now: i64) -> Result<Vec<Delta>, QueryError> {
+        let mut deltas = Vec::new();
+        for table in self.tables.iter() {
+            let condition = match table.expired_condition(now) {
+                Some(condition) => condition,
+                None => continue,
+            };
+            let current = QueryResult::from_db_table(self, table.name())?;
+            let mut expired = false;
+            for row in current.shared_rows() {
+                if current.test_row(&self.functions, &condition, row)? {
+                    expired = true;
+                    break;
+                }
+            }
+            if expired {
+                deltas.push(Delta::RemoveWhere(table.name(), condition));
+            }
+        }
+        Ok(deltas)
+    }
+
+    pub(crate) fn row_by_id(&self, name: &TableName, id: RowId) -> Option<Row> {
+        let position = self.row_id_position(name, id).ok()?;
+        Some((*self.table_rows[name][position]).clone())
+    }
+
+    /// Apply a change, returning the rows it affected and the tables it
+    /// modified, including those modified by cascading foreign keys
+    pub(crate) fn apply(&mut self, delta: Delta) -> Result<(ApplyReport, Vec<TableName>), ApplyError> {
+        // Removals cascade to other tables after changing the table itself,
+        // quotas are checked after changing the table and materialized views
+        // are updated after changing the tables they read
+        let mut tables = delta.tables();
+        let quotas = tables.iter().any(|t| self.table(t.clone()).map_or(false, |t| t.quota().is_some()));
+        if tables.iter().any(|t| self.is_referenced(t)) || quotas || !self.materialized.is_empty() {
+            let mut working = self.clone();
+            working.cascaded = Some(Vec::new());
+            let mut report = working.apply_change(delta)?;
+            if quotas {
+                report.merge(working.enforce_quotas(&tables)?);
+            }
+            working.maintain_views(self)?;
+            let cascaded = working.cascaded.take().unwrap_or_default();
+            for (name, _) in &cascaded {
+                *report.cascaded.entry(name.clone()).or_insert(0) += 1;
+                if !tables.contains(name) {
+                    tables.push(name.clone());
+                }
+            }
+            working.cascaded = self.cascaded.take().map(|mut recorded| {
+                recorded.extend(cascaded);
+                recorded
+            });
+            *self = working;
+            return Ok((report, tables));
+        }
+        self.apply_change(delta).map(|report| (report, tables))
+    }
+
+    fn apply_change(&mut self, delta: Delta) -> Result<ApplyReport, ApplyError> {
+        use Delta::*;
+        match delta {
+            CreateTable(table)      => self.create_table(table).map(|_| ApplyReport::default()),
+            DropTable(name)         => self.drop_table(name).map(|_| ApplyReport::default()),
+            Truncate(name)          => self.truncate(name).map(ApplyReport::removals),
+            AddColumn(name, field, default) => self.add_column(name, field, default).map(ApplyReport::updates),
+            RenameTable(from, to)   => self.rename_table(from, to).map(|_| ApplyReport::default()),
+            RenameColumn(name, from, to) => self.rename_column(name, from, to).map(|_| ApplyReport::default()),
+            AlterColumnType(name, field, kind) => self.alter_column_type(name, field, kind).map(ApplyReport::updates),
+            DropColumn(name, field) => self.drop_column(name, field, false).map(ApplyReport::updates),
+            ForceDropColumn(name, field) => self.drop_column(name, field, true).map(ApplyReport::updates),
+            AddRow(name, row)       => self.add_row(name, row).map(|_| ApplyReport::inserts(1)),
+            AddNamedRow(name, values) => self.add_named_row(name, values).map(|_| ApplyReport::inserts(1)),
+            AddRows(name, rows)     => self.add_rows(name, rows).map(ApplyReport::inserts),
+            RemoveRow(name, row)    => self.remove_row(name, row).map(|_| ApplyReport::removals(1)),
+            UpdateRow(name, row, u) => self.update_row(name, row, u).map(|_| ApplyReport::updates(1)),
+            RemoveRowById(name, id) => self.remove_row_by_id(name, id).map(|_| ApplyReport::removals(1)),
+            UpdateRowById(name, id, u) => self.update_row_by_id(name, id, u).map(|_| ApplyReport::updates(1)),
+            RemoveWhere(name, cond) => self.remove_where(name, cond).map(ApplyReport::removals),
+            UpdateWhere(name, cond, assignments) => self.update_where(name, cond, assignments).map(ApplyReport::updates),
+            Upsert(name, row)       => self.upsert(name, row).map(|inserted| {
+                if inserted { ApplyReport::inserts(1) } else { ApplyReport::updates(1) }
+            }),
+            CreateTrigger(trigger)  => self.create_trigger(trigger).map(|_| ApplyReport::default()),
+            DropTrigger(name, trigger) => self.drop_trigger(name, trigger).map(|_| ApplyReport::default()),
+            CreateIndex(name, fields, kind) => self.create_index(name, fields, kind).map(|_| ApplyReport::default()),
+            CreateExpressionIndex(name, expression, kind) => {
+                self.create_expression_index(name, expression, kind).map(|_| ApplyReport::default())
+            },
+            CreatePartialIndex(name, fields, kind, predicate) => {
+                self.create_partial_index(name, fields, kind, predicate).map(|_| ApplyReport::default())
+            },
+            CreateFunction(name, body) => self.create_function(name, body).map(|_| ApplyReport::default()),
+            DropFunction(name)      => self.drop_function(name).map(|_| ApplyReport::default()),
+            CreateView(name, query) => self.create_view(name, query).map(|_| ApplyReport::default()),
+            CreateMaterializedView(name, query) => self.create_materialized_view(name, query).map(|_| ApplyReport::default()),
+            RefreshView(name)       => self.refresh_view(name).map(|_| ApplyReport::default()),
+            DropView(name)          => self.drop_view(name).map(|_| ApplyReport::default()),
+            CreateProcedure(name, steps) => self.create_procedure(name, steps).map(|_| ApplyReport::default()),
+            DropProcedure(name)     => self.drop_procedure(name).map(|_| ApplyReport::default()),
+            CreatePolicy(name, condition) => self.create_policy(name, condition).map(|_| ApplyReport::default()),
+            DropPolicy(name)        => self.drop_policy(name).map(|_| ApplyReport::default()),
+        }
+    }
+
+    /// Apply all changes, or none of them if any fails, returning the rows
+    /// they affected and the tables each one modified, see `apply`
+    ///
+    /// If `reversible` is set, also returns deltas that revert the whole batch.
+    pub(crate) fn apply_all(&mut self, deltas: Vec<Delta>, reversible: bool) -> Result<(ApplyReport, Vec<Vec<TableName>>, Vec<Delta>), ApplyError> {
+        let mut working = self.clone();
+        let mut report = ApplyReport::default();
+        let mut tables = Vec::with_capacity(deltas.len());
+        let mut inverse = Vec::new();
+        for delta in deltas {
+            if reversible {
+                let mut reverting = working.inverse(&delta);
+                reverting.extend(inverse);
+                inverse = reverting;
+            }
+            let (delta_report, delta_tables) = working.apply(delta)?;
+            report.merge(delta_report);
+            tables.push(delta_tables);
+        }
+        *self = working;
+        Ok((report, tables, inverse))
     }
 
     pub(crate) fn create_table(&mut self, table: Table) -> Result<(), ApplyError> {
+        table.check_schema()?;
+        self.check_reference_keys(&table)?;
+        for (_, check) in table.checks() {
+            check.check(self.function_dict()).map_err(ApplyError::QueryError)?;
+        }
+        if self.view(&table.name()).is_some() || self.materialized_view(&table.name()).is_some() {
+            return Err(ApplyError::TableExists(table.name()));
+        }
+        if !table.is_temporary() {
+            for field in table.fields() {
+                self.check_not_temporary_reference(&field)?;
+            }
+        }
         if let Some(i) = self.table_index(table.name()) {
             if self.tables[i] != table {
                 return Err(ApplyError::AddCannotModify(table.name()));
             }
         }
         else {
-            self.tables.push(table.clone());
-            self.table_rows.insert(table.name(), Vec::new());
+            Arc::make_mut(&mut self.tables).push(table.clone());
+            self.set_rows(&table, Vec::new())?;
         }
         Ok(())
     }
 
+    /// Fail if `field` is a foreign key to a temporary table
+    fn check_not_temporary_reference(&self, field: &TableField) -> Result<(), ApplyError> {
+        match field.kind() {
+            FieldKind::ForeignKey(ref target) if self.is_temporary(target) => Err(ApplyError::TemporaryTable(target.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn is_temporary(&self, name: &TableName) -> bool {
+        self.tables.iter().any(|t| t.name() == *name && t.is_temporary())
+    }
+
     pub(crate) fn drop_table(&mut self, name: TableName) -> Result<(), ApplyError> {
         if let Some(i) = self.table_index(name.clone()) {
-            self.tables.remove(i);
-            self.table_rows.remove(&name);
+            self.check_not_viewed(&name)?;
+            let table = Arc::make_mut(&mut self.tables).remove(i);
+            let rows = self.table_rows.remove(&name).unwrap();
+            self.indexes.remove(&name);
+            self.remove_referencing(&table, &rows)?;
+            if self.triggers.iter().any(|t| t.table == name) {
+                Arc::make_mut(&mut self.triggers).retain(|t| t.table != name);
+            }
+            if self.policies.contains_key(&name) {
+                Arc::make_mut(&mut self.policies).remove(&name);
+            }
+            self.interner.lock().unwrap().prune();
             Ok(())
         }
         else {
@@ -131,24 +734,562 @@ impl DataDB {
         }
     }
 
-    pub(crate) fn add_row(&mut self, name: TableName, row: Row) -> Result<(), ApplyError> {
+    pub(crate) fn add_column(&mut self, name: TableName, field: TableField, default: Value) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        if table.field_index(field.name()).is_some() {
+            return Err(ApplyError::FieldExists(name, field.name()));
+        }
+        if field.name().is_empty() || !field.kind().is_valid() {
+            return Err(ApplyError::InvalidSchema(name));
+        }
+        if !table.is_temporary() {
+            self.check_not_temporary_reference(&field)?;
+        }
+        let mut new_table = table.clone();
+        new_table.add_field(field.clone());
+        new_table.check_schema()?;
+        self.check_reference_keys(&new_table)?;
+        let default = field.coerce(default).map_err(|_| ApplyError::TypeMismatch {
+            table: name.clone(),
+            field: field.name(),
+        })?;
+        let default = self.interner.lock().unwrap().intern_value(default);
+        if let FieldKind::ForeignKey(ref target) = field.kind() {
+            if !self.table_rows[&name].is_empty() && !default.is_null() {
+                self.check_reference(&table, field.name(), target, &default)?;
+            }
+        }
+
+        let mut rows: Vec<_> = self.table_rows[&name].iter().map(|row| Arc::new(row.with_value(default.clone()))).collect();
+        if field.generated().is_some() {
+            for row in rows.iter_mut() {
+                let generated = self.generated_row(&new_table, (**row).clone())?;
+                *row = Arc::new(self.interner.lock().unwrap().intern_row(generated));
+            }
+        }
+
+        let count = rows.len();
+        self.set_rows(&new_table, rows)?;
+        *self.table_mut(&name).unwrap() = new_table;
+        Ok(count)
+    }
+
+    pub(crate) fn rename_table(&mut self, from: TableName, to: TableName) -> Result<(), ApplyError> {
+        if self.table_index(from.clone()).is_none() {
+            return Err(ApplyError::NoSuchTable(from));
+        }
+        if from == to {
+            return Ok(());
+        }
+        if self.table_index(to.clone()).is_some() || self.view(&to).is_some() || self.materialized_view(&to).is_some() {
+            return Err(ApplyError::TableExists(to));
+        }
+        self.check_not_viewed(&from)?;
+
+        // Expression and partial indexes resolve fields by their qualified
+        // names, and rebuilding them may fail, so they are built before any change
+        let mut renamed = self.table(from.clone()).unwrap();
+        renamed.rename(to.clone());
+        renamed.rename_references(&from, &to);
+        let mut indexes = TableIndexes::build(&renamed, &self.table_rows[&from], &self.functions)?;
+        indexes.keep_usage(&self.indexes[&from]);
+
+        for table in Arc::make_mut(&mut self.tables).iter_mut() {
+            if table.name() == from {
+                table.rename(to.clone());
+            }
+            table.rename_references(&from, &to);
+        }
+        for trigger in Arc::make_mut(&mut self.triggers).iter_mut().filter(|t| t.table == from) {
+            trigger.table = to.clone();
+        }
+        self.rename_policy(&from, &to);
+        let rows = self.table_rows.remove(&from).unwrap();
+        self.indexes.remove(&from);
+        self.table_rows.insert(to.clone(), rows);
+        self.indexes.insert(to, Arc::new(indexes));
+        Ok(())
+    }
+
+    pub(crate) fn rename_column(&mut self, name: TableName, from: FieldName, to: FieldName) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let index = table.field_index(from.clone()).ok_or(ApplyError::NoSuchField(name.clone(), from.clone()))?;
+        if from == to {
+            return Ok(());
+        }
+        if table.field_index(to.clone()).is_some() {
+            return Err(ApplyError::FieldExists(name, to));
+        }
+
+        let mut new_table = table.clone();
+        new_table.rename_field(index, to.clone());
+        new_table.check_schema()?;
+        // Expression and partial indexes resolve fields by name
+        let rows = (*self.table_rows[&name]).clone();
+        self.set_rows(&new_table, rows)?;
+        *self.table_mut(&name).unwrap() = new_table;
+        if self.policies.contains_key(&name) {
+            Arc::make_mut(&mut self.policies).get_mut(&name).unwrap().rename_field(&from, &to);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn alter_column_type(&mut self, name: TableName, field_name: FieldName, kind: FieldKind) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let index = table.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(name.clone(), field_name.clone()))?;
+        let mut altered = table.clone();
+        altered.set_field_kind(index, kind.clone());
+        altered.check_schema()?;
+        let field = table.fields()[index].with_kind(kind);
+
+        let rows = self.table_rows.get(&name).unwrap();
+        let mut interner = self.interner.lock().unwrap();
+        let mut converted = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let value = field.coerce(row.value(index).clone()).map_err(|_| ApplyError::TypeMismatch {
+                table: name.clone(),
+                field: field_name.clone(),
+            })?;
+            converted.push(Arc::new(row.with_value_at(index, interner.intern_value(value))));
+        }
+        drop(interner);
+        if altered.has_generated_fields() {
+            for row in converted.iter_mut() {
+                let generated = self.generated_row(&altered, (**row).clone())?;
+                *row = Arc::new(self.interner.lock().unwrap().intern_row(generated));
+            }
+        }
+
+        for row in &converted {
+            self.check_row(&altered, row)?;
+        }
+
+        let count = converted.len();
+        self.set_rows(&altered, converted)?;
+        *self.table_mut(&name).unwrap() = altered;
+        Ok(count)
+    }
+
+    pub(crate) fn drop_column(&mut self, name: TableName, field_name: FieldName, force: bool) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let index = table.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(name.clone(), field_name.clone()))?;
+        if table.is_key_field(index) && !force {
+            return Err(ApplyError::KeyField(name, field_name));
+        }
+        self.check_not_policy_field(&name, &field_name)?;
+
+        let mut new_table = table.clone();
+        new_table.remove_field(index);
+        new_table.check_schema()?;
+        self.check_reference_keys(&new_table)?;
+        let rows: Vec<_> = self.table_rows[&name].iter().map(|row| Arc::new(row.without_value(index))).collect();
+
+        let count = rows.len();
+        self.set_rows(&new_table, rows)?;
+        *self.table_mut(&name).unwrap() = new_table;
+        self.interner.lock().unwrap().prune();
+        Ok(count)
+    }
+
+    pub(crate) fn truncate(&mut self, name: TableName) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let removed = self.table_rows[&name].clone();
+        self.set_rows(&table, Vec::new())?;
+        self.remove_referencing(&table, &removed)?;
+        self.interner.lock().unwrap().prune();
+        Ok(removed.len())
+    }
+
+    /// Remove the oldest rows of the tables over their quotas that evict rows,
+    /// failing if any other table is over its quota
+    fn enforce_quotas(&mut self, tables: &[TableName]) -> Result<ApplyReport, ApplyError> {
+        let mut report = ApplyReport::default();
+        for name in tables {
+            let quota = match self.table(name.clone()).and_then(|t| t.quota()) {
+                Some(quota) => quota,
+                None => continue,
+            };
+            let excess = quota.excess(&self.table_rows[name]);
+            if excess > 0 && !quota.evict {
+                return Err(ApplyError::QuotaExceeded(name.clone()));
+            }
+            if excess > 0 {
+                self.remove_oldest(name, excess)?;
+            }
+            report.merge(ApplyReport::removals(excess));
+        }
+        Ok(report)
+    }
+
+    /// Remove the first `count` rows of the table, which are the oldest
+    fn remove_oldest(&mut self, name: &TableName, count: usize) -> Result<(), ApplyError> {
+        let removed = self.table_rows[name][..count].to_vec();
+        for row in &removed {
+            self.triggered_delete(name, row)?;
+        }
+        Arc::make_mut(self.table_rows.get_mut(name).unwrap()).drain(..count);
+        if let Some(indexes) = self.indexes_mut(name) {
+            for row in &removed {
+                indexes.remove(row);
+            }
+        }
+        let table = self.table(name.clone()).unwrap();
+        self.remove_referencing(&table, &removed)
+    }
+
+    /// Replace all rows of the table, failing without changes if they violate a unique constraint
+    pub(crate) fn set_rows(&mut self, table: &Table, rows: Vec<Arc<Row>>) -> Result<(), ApplyError> {
+        let mut indexes = TableIndexes::build(table, &rows, &self.functions)?;
+        if let Some(old) = self.indexes.get(&table.name()) {
+            indexes.keep_usage(old);
+        }
+        self.table_rows.insert(table.name(), Arc::new(rows));
+        self.indexes.insert(table.name(), Arc::new(indexes));
+        Ok(())
+    }
+
+    /// Indexes of the table for modification, unless it has none
+    fn indexes_mut(&mut self, name: &TableName) -> Option<&mut TableIndexes> {
+        let indexes = self.indexes.get_mut(name)?;
+        if indexes.is_empty() {
+            None
+        }
+        else {
+            Some(Arc::make_mut(indexes))
+        }
+    }
+
+    fn allocate_row_id(&mut self) -> RowId {
+        let id = self.next_row_id;
+        self.next_row_id += 1;
+        id
+    }
+
+    /// Position of the row with the given id
+    fn row_id_position(&self, name: &TableName, id: RowId) -> Result<usize, ApplyError> {
         self.table_rows
-            .get_mut(&name)
+            .get(name)
             .ok_or(ApplyError::NoSuchTable(name.clone()))?
-            .push(row);
+            .binary_search_by_key(&Some(id), |r| r.id())
+            .map_err(|_| ApplyError::NoSuchRow(name.clone()))
+    }
+
+    pub(crate) fn add_row(&mut self, name: TableName, row: Row) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let row = self.triggered_row(&table, TriggerEvent::Insert, row)?;
+        self.insert_row(&table, row)
+    }
+
+    /// Store a new row that triggers already ran for
+    fn insert_row(&mut self, table: &Table, row: Row) -> Result<(), ApplyError> {
+        self.check_constraints(table, &row, None)?;
+        let id = self.allocate_row_id();
+        let row = self.interner.lock().unwrap().intern_row(row).with_id(Some(id));
+        if let Some(indexes) = self.indexes_mut(&table.name()) {
+            indexes.insert(&row);
+        }
+        Arc::make_mut(self.table_rows.get_mut(&table.name()).unwrap()).push(Arc::new(row));
+        Ok(())
+    }
+
+    /// Row with the given values, and defaults for the other fields
+    pub(crate) fn named_row(&self, table: &Table, values: Vec<(FieldName, Value)>) -> Result<Row, ApplyError> {
+        let fields = table.fields();
+        let mut row = vec![None; fields.len()];
+        for (field_name, value) in values {
+            let i = table.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(table.name(), field_name))?;
+            row[i] = Some(value);
+        }
+
+        let mut complete = Vec::with_capacity(fields.len());
+        for (value, field) in row.into_iter().zip(fields) {
+            complete.push(match (value, field.default()) {
+                (Some(value), _) => value,
+                (None, Some(FieldDefault::Value(value))) => value,
+                (None, Some(FieldDefault::FunctionCall(fc))) => {
+                    fc.resolve_args(&|qf: &QueryField| Err(QueryError::NoSuchField(qf.clone())))
+                        .and_then(|fc| fc.apply(&self.functions))
+                        .map_err(ApplyError::QueryError)?
+                },
+                (None, None) => Value::Null,
+            });
+        }
+        Ok(Row::new(complete))
+    }
+
+    /// Validated `row` with the values of its generated fields computed, in field order
+    pub(crate) fn generated_row(&self, table: &Table, row: Row) -> Result<Row, ApplyError> {
+        if !table.has_generated_fields() {
+            return Ok(row);
+        }
+        let fields = trigger::table_fields(table);
+        let mut row = row;
+        for (i, field) in table.fields().iter().enumerate() {
+            if let Some(expression) = field.generated() {
+                let value = expression.resolve_args(&|qf: &QueryField| fields.resolve_field(qf, &row))
+                    .and_then(|fc| fc.apply(&self.functions))
+                    .map_err(ApplyError::QueryError)?;
+                row = row.with_value_at(i, table.coerced(field, value)?);
+            }
+        }
+        Ok(row)
+    }
+
+    /// Replace deltas adding named rows by deltas adding the rows, with their
+    /// defaults and generated fields, so that applying them again adds the same rows
+    pub(crate) fn concrete_deltas(&self, deltas: &mut [Delta]) -> Result<(), ApplyError> {
+        let last = match deltas.iter().rposition(|d| match d { Delta::AddNamedRow(_, _) => true, _ => false }) {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        // Defaults are evaluated against the changes before them
+        let mut working = self.clone();
+        for (i, delta) in deltas[..=last].iter_mut().enumerate() {
+            if let Delta::AddNamedRow(ref name, ref values) = *delta {
+                let table = working.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+                let row = working.named_row(&table, values.clone())?;
+                let row = working.generated_row(&table, table.validate_row(row)?)?;
+                *delta = Delta::AddRow(name.clone(), row);
+            }
+            if i < last {
+                working.apply(delta.clone())?;
+            }
+        }
         Ok(())
     }
+
+    pub(crate) fn add_named_row(&mut self, name: TableName, values: Vec<(FieldName, Value)>) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let row = self.named_row(&table, values)?;
+        let row = self.triggered_row(&table, TriggerEvent::Insert, row)?;
+        self.insert_row(&table, row)
+    }
+
+    pub(crate) fn add_rows(&mut self, name: TableName, rows: Vec<Row>) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let mut valid = Vec::with_capacity(rows.len());
+        for row in rows {
+            let row = self.triggered_row(&table, TriggerEvent::Insert, row)?;
+            self.check_row(&table, &row)?;
+            valid.push(row);
+        }
+
+        let count = valid.len();
+        let first_id = self.next_row_id;
+        let new_rows: Vec<Arc<Row>> = {
+            let mut interner = self.interner.lock().unwrap();
+            valid.into_iter().zip(first_id..).map(|(row, id)| {
+                Arc::new(interner.intern_row(row).with_id(Some(id)))
+            }).collect()
+        };
+        if !self.indexes[&name].has_checks() {
+            if let Some(indexes) = self.indexes_mut(&name) {
+                for row in &new_rows {
+                    indexes.insert(row);
+                }
+            }
+            let stored = Arc::make_mut(self.table_rows.get_mut(&name).unwrap());
+            stored.reserve(count);
+            stored.extend(new_rows);
+        }
+        else {
+            // Rebuilding the indexes also catches duplicates within the batch
+            let mut rows = (*self.table_rows[&name]).clone();
+            rows.extend(new_rows);
+            self.set_rows(&table, rows)?;
+        }
+        self.next_row_id += count as RowId;
+        Ok(count)
+    }
+
+    /// Returns true if the row was inserted rather than replaced
+    pub(crate) fn upsert(&mut self, name: TableName, row: Row) -> Result<bool, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let row = self.generated_row(&table, table.validate_row(row)?)?;
+        let position = self.table_rows[&name].iter().position(|r| table.same_key(r, &row));
+        let event = if position.is_some() { TriggerEvent::Update } else { TriggerEvent::Insert };
+        let row = self.triggered_row(&table, event, row)?;
+        // Triggers may have changed the key
+        self.check_constraints(&table, &row, position)?;
+        let id = match position {
+            Some(position) => self.table_rows[&name][position].id(),
+            None => Some(self.allocate_row_id()),
+        };
+        let row = Arc::new(self.interner.lock().unwrap().intern_row(row).with_id(id));
+
+        let old = position.map(|position| self.table_rows[&name][position].clone());
+        if let Some(indexes) = self.indexes_mut(&name) {
+            if let Some(ref old) = old {
+                indexes.remove(old);
+            }
+            indexes.insert(&row);
+        }
+        let rows = Arc::make_mut(self.table_rows.get_mut(&name).unwrap());
+        match position {
+            Some(position) => rows[position] = row,
+            None => rows.push(row),
+        }
+        if let Some(old) = old {
+            self.remove_referencing(&table, &[old])?;
+        }
+        Ok(position.is_none())
+    }
+
+    pub(crate) fn remove_row(&mut self, name: TableName, row: Row) -> Result<(), ApplyError> {
+        let position = self.table_rows
+            .get(&name)
+            .ok_or(ApplyError::NoSuchTable(name.clone()))?
+            .iter().position(|r| **r == row).ok_or(ApplyError::NoSuchRow(name.clone()))?;
+        self.remove_row_at(name, position)
+    }
+
+    pub(crate) fn remove_row_by_id(&mut self, name: TableName, id: RowId) -> Result<(), ApplyError> {
+        let position = self.row_id_position(&name, id)?;
+        self.remove_row_at(name, position)
+    }
+
+    fn remove_row_at(&mut self, name: TableName, position: usize) -> Result<(), ApplyError> {
+        let row = self.table_rows[&name][position].clone();
+        self.triggered_delete(&name, &row)?;
+        if let Some(indexes) = self.indexes_mut(&name) {
+            indexes.remove(&row);
+        }
+        Arc::make_mut(self.table_rows.get_mut(&name).unwrap()).remove(position);
+        let table = self.table(name).unwrap();
+        self.remove_referencing(&table, &[row])
+    }
+
+    pub(crate) fn update_row(&mut self, name: TableName, row: Row, update: RowUpdate) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let position = self.table_rows[&name].iter().position(|r| **r == row).ok_or(ApplyError::NoSuchRow(name.clone()))?;
+        self.update_row_at(&table, position, update)
+    }
+
+    pub(crate) fn update_row_by_id(&mut self, name: TableName, id: RowId, update: RowUpdate) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let position = self.row_id_position(&name, id)?;
+        self.update_row_at(&table, position, update)
+    }
+
+    fn update_row_at(&mut self, table: &Table, position: usize, update: RowUpdate) -> Result<(), ApplyError> {
+        let name = table.name();
+        let old = self.table_rows[&name][position].clone();
+        let new_row = table.updated_row((*old).clone(), update)?;
+        let new_row = self.triggered_row(table, TriggerEvent::Update, new_row)?;
+        self.check_constraints(table, &new_row, Some(position))?;
+        let new_row = self.interner.lock().unwrap().intern_row(new_row).with_id(old.id());
+        if let Some(indexes) = self.indexes_mut(&name) {
+            indexes.remove(&old);
+            indexes.insert(&new_row);
+        }
+        Arc::make_mut(self.table_rows.get_mut(&name).unwrap())[position] = Arc::new(new_row);
+        self.remove_referencing(table, &[old])
+    }
+
+    pub(crate) fn remove_where(&mut self, name: TableName, condition: Condition) -> Result<usize, ApplyError> {
+        let current = QueryResult::from_db_table(self, name.clone()).map_err(|_| ApplyError::NoSuchTable(name.clone()))?;
+
+        let mut keep = Vec::with_capacity(current.shared_rows().len());
+        let mut removed = Vec::new();
+        for row in current.shared_rows() {
+            let matches = current.test_row(&self.functions, &condition, row).map_err(ApplyError::QueryError)?;
+            if matches {
+                self.triggered_delete(&name, row)?;
+                removed.push(row.clone());
+            }
+            keep.push(!matches);
+        }
+
+        if !removed.is_empty() {
+            let mut flags = keep.into_iter();
+            Arc::make_mut(self.table_rows.get_mut(&name).unwrap()).retain(|_| flags.next().unwrap());
+            if let Some(indexes) = self.indexes_mut(&name) {
+                for row in &removed {
+                    indexes.remove(row);
+                }
+            }
+            let table = self.table(name).unwrap();
+            self.remove_referencing(&table, &removed)?;
+        }
+        Ok(removed.len())
+    }
+
+    pub(crate) fn update_where(&mut self, name: TableName, condition: Condition, assignments: Vec<(FieldName, Argument)>) -> Result<usize, ApplyError> {
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let current = QueryResult::from_db_table(self, name.clone()).map_err(ApplyError::QueryError)?;
+
+        let mut targets = Vec::new();
+        for (field_name, _) in &assignments {
+            targets.push(table.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(name.clone(), field_name.clone()))?);
+        }
+
+        // Compute all new rows before modifying anything
+        let mut updates = Vec::new();
+        for (position, row) in current.shared_rows().iter().enumerate() {
+            if !current.test_row(&self.functions, &condition, row).map_err(ApplyError::QueryError)? {
+                continue;
+            }
+            let mut values = row.values();
+            for (&i, (_, argument)) in targets.iter().zip(&assignments) {
+                values[i] = argument.evaluate(&self.functions, &|qf: &QueryField| current.resolve_field(qf, row))
+                    .map_err(ApplyError::QueryError)?;
+            }
+            let new_row = self.triggered_row(&table, TriggerEvent::Update, Row::new(values))?;
+            self.check_row(&table, &new_row)?;
+            updates.push((position, new_row));
+        }
+
+        let count = updates.len();
+        if count > 0 {
+            let mut interner = self.interner.lock().unwrap();
+            let mut rows = (*self.table_rows[&name]).clone();
+            let mut old = Vec::with_capacity(count);
+            for (position, row) in updates {
+                let id = rows[position].id();
+                old.push(mem::replace(&mut rows[position], Arc::new(interner.intern_row(row).with_id(id))));
+            }
+            drop(interner);
+            self.set_rows(&table, rows)?;
+            self.remove_referencing(&table, &old)?;
+        }
+        Ok(count)
+    }
 }
 
 pub struct SrimDB {
     filepath: Option<PathBuf>,
-    data_db: DataDB
+    data_db: DataDB,
+    log: Option<QueryLog>,
+    next_event_id: AtomicU64,
+    memory_limit: Option<usize>,
+    revision: Revision,
+    /// Number of deltas applied, including those that don't advance the revision
+    changes: u64,
+    /// Value of `changes` after the last delta that modified each table
+    table_changes: HashMap<TableName, u64>,
+    journal: Option<Journal>,
+    history: Option<History>,
+    subscribers: Subscribers,
+    cdc_sinks: Vec<Box<CdcSink>>,
+    /// Permissions of the users of the handle, none if everything is allowed
+    role: Option<Role>,
 }
 impl SrimDB {
     pub fn new() -> Self {
         Self {
             filepath: None,
-            data_db: DataDB::new()
+            data_db: DataDB::new(),
+            log: None,
+            next_event_id: AtomicU64::new(0),
+            memory_limit: None,
+            revision: 0,
+            changes: 0,
+            table_changes: HashMap::new(),
+            journal: None,
+            history: None,
+            subscribers: Subscribers::new(),
+            cdc_sinks: Vec::new(),
+            role: None,
         }
     }
 
@@ -163,6 +1304,77 @@ impl SrimDB {
         Self { filepath: Some(filepath.as_ref().to_path_buf()), ..self }
     }
 
+    /// Abort queries whose intermediate results hold more than `bytes` at once
+    pub fn with_query_memory_limit(self, bytes: usize) -> Self {
+        Self { memory_limit: Some(bytes), ..self }
+    }
+
+    /// Record every applied delta in memory, see `journal` and `replay`
+    pub fn with_journal(self) -> Self {
+        Self { journal: Some(Journal::new(None)), ..self }
+    }
+
+    /// Record every applied delta in memory, and append it to `file` as a JSON line
+    pub fn with_journal_file(self, file: File) -> Self {
+        Self { journal: Some(Journal::new(Some(file))), ..self }
+    }
+
+    /// Remember up to `depth` latest changes, so that they can be reverted with `undo`
+    pub fn with_history(self, depth: usize) -> Self {
+        Self { history: Some(History::new(depth)), ..self }
+    }
+
+    /// Pass every applied delta to `sink`, in addition to any sinks added before
+    pub fn with_cdc_sink<S: CdcSink + 'static>(mut self, sink: S) -> Self {
+        self.cdc_sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Emit a structured event for every executed query and applied delta
+    pub fn with_query_log(self, log: QueryLog) -> Self {
+        Self { log: Some(log), ..self }
+    }
+
+    /// Make native function `function` callable as `name`
+    ///
+    /// If native functions with the name exist, including builtins, it becomes
+    /// one of their overloads, and calls choose between them by the kinds of
+    /// the arguments. Otherwise it replaces any function with the name.
+    pub fn with_function(mut self, name: &str, function: NativeFunction) -> Self {
+        function::register(Arc::make_mut(&mut self.data_db.functions), name, function);
+        self
+    }
+
+    /// Make table function `function` usable as `name` in `Query::FromTableFunction`,
+    /// replacing any table function with the name
+    pub fn with_table_function(mut self, name: &str, function: TableFunction) -> Self {
+        Arc::make_mut(&mut self.data_db.table_functions).insert(name.to_owned(), function);
+        self
+    }
+
+    /// Handle integer overflow in arithmetic functions according to `mode`
+    /// instead of saturating
+    ///
+    /// The mode applies to queries as well as to defaults, checks and triggers.
+    pub fn with_arithmetic_mode(mut self, mode: ArithmeticMode) -> Self {
+        self.data_db.set_arithmetic_mode(mode);
+        self
+    }
+
+
+    /// Check queries and deltas against the permissions of `role`, see `access`
+    pub fn with_role(self, role: Role) -> Self {
+        Self { role: Some(role), ..self }
+    }
+
+    /// Replace the role queries and deltas are checked against, or allow everything without one
+    pub fn set_role(&mut self, role: Option<Role>) {
+        self.role = role;
+    }
+
+    pub fn role(&self) -> Option<&Role> {
+        self.role.as_ref()
+    }
 
     pub fn load_overwrite(&mut self) -> io::Result<()> {
         unimplemented!();
@@ -174,38 +1386,437 @@ impl SrimDB {
     }
 
     pub fn query(&self, query: Query) -> Result<QueryResult, QueryError> {
-        query.execute(&self.data_db)
+        self.query_with(query, QueryOptions::default())
     }
 
-    pub fn apply(&mut self, delta: Delta) -> Result<(), ApplyError> {
-        use Delta::*;
-        match delta {
-            CreateTable(table)      => self.data_db.create_table(table),
-            DropTable(name)         => self.data_db.drop_table(name),
-            AddRow(name, row)       => self.data_db.add_row(name, row),
-            _ =>  unimplemented!()
-            // DropRow(TableName, Row),
+    /// Check the arity and argument kinds of the function calls in the query
+    /// without executing it; kinds of fields are only checked when executing
+    pub fn check_query(&self, query: &Query) -> Result<(), QueryError> {
+        query.check(&self.data_db)
+    }
+
+    pub fn query_with(&self, query: Query, options: QueryOptions) -> Result<QueryResult, QueryError> {
+        let timestamp = SystemTime::now();
+        let start = Instant::now();
+        let ctx = ExecutionContext::new(&self.data_db)
+            .with_memory_limit(self.memory_limit)
+            .with_options(options);
+        let result = self.check_access(&query)
+            .and_then(|_| query.check(&self.data_db))
+            .and_then(|_| query.execute(&ctx));
+
+        if self.log.is_some() {
+            self.log_event(LogEvent {
+                id: 0,
+                kind: LogEventKind::Query,
+                timestamp,
+                statement: format!("{:?}", query),
+                duration: start.elapsed(),
+                rows: result.as_ref().ok().map(|r| r.shared_rows().len()),
+                error: result.as_ref().err().map(|e| format!("{:?}", e)),
+            });
         }
+        result
     }
-}
 
+    /// Receive an event for every applied delta that matches `filter`
+    ///
+    /// Events are sent after the delta is applied, in revision order. Dropping
+    /// the receiver ends the subscription.
+    pub fn subscribe(&mut self, filter: SubscriptionFilter) -> Receiver<DeltaEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.add(filter, sender);
+        receiver
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Run a query against the state right after revision `revision`
+    ///
+    /// Past states are reconstructed by replaying the journal, so journaling
+    /// must have been enabled since revision 0. This takes time proportional
+    /// to `revision`.
+    pub fn query_at(&self, revision: Revision, query: Query) -> Result<QueryResult, QueryError> {
+        if revision == self.revision {
+            return self.query(query);
+        }
+        let data_db = self.state_at(revision).ok_or(QueryError::NoSuchRevision(revision))?;
+        Snapshot { data_db, memory_limit: self.memory_limit, role: self.role.clone() }.query(query)
+    }
 
-    /// Companies (INT id, TEXT name, TEXT city)
-    /// Employees (INT id, TEXT name, TEXT company)
-    fn setup_simple_company_employee_scenario() -> SrimDB {
+    fn state_at(&self, revision: Revision) -> Option<DataDB> {
+        let entries = self.journal.as_ref()?.entries();
+        if revision > self.revision || entries.first().map_or(true, |e| e.revision != 1) {
+            return None;
+        }
+        let mut state = self.data_db.configuration();
+        for entry in entries.iter().take_while(|e| e.revision <= revision) {
+            state.fire_triggers = entry.fires_triggers;
+            state.apply(entry.delta.clone()).ok()?;
+        }
+        state.fire_triggers = true;
+        Some(state)
+    }
 
-        let mut db = SrimDB::new();
+    /// Indexes of all tables with their sizes and how many times queries used them
+    ///
+    /// The same information can be queried from the `__indexes` system table.
+    pub fn indexes(&self) -> Vec<IndexInfo> {
+        self.data_db.index_infos()
+    }
 
-        db.apply(Delta::CreateTable(
+    /// Partitions of table `name` in order with their sizes and how many times
+    /// queries read them alone, empty if it isn't partitioned
+    pub fn partitions(&self, name: &str) -> Vec<PartitionInfo> {
+        self.data_db.partition_infos(&name.to_owned())
+    }
+
+    /// Start a transaction based on the current state
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self)
+    }
+
+    /// Run procedure `name` with `arguments`, see `Delta::CreateProcedure`
+    ///
+    /// The changes of all its steps are applied atomically, like a committed
+    /// transaction. Returns the results of its queries in order.
+    pub fn call_procedure(&mut self, name: &str, arguments: Vec<Value>) -> Result<Vec<QueryResult>, ApplyError> {
+        let steps = self.data_db.bound_procedure(name, &arguments)?;
+        let mut transaction = self.begin();
+        let mut results = Vec::new();
+        for step in steps {
+            match step {
+                BoundStep::Apply(delta) => { transaction.apply(delta)?; },
+                BoundStep::Query(query) => results.push(transaction.query(query).map_err(ApplyError::QueryError)?),
+            }
+        }
+        transaction.commit(self)?;
+        Ok(results)
+    }
+
+    /// Remove the expired rows of the tables with a TTL, see `Table::with_ttl`
+    ///
+    /// The removals are applied like `apply_all`, so they are journaled and
+    /// can be undone. Returns the number of rows removed.
+    pub fn vacuum_expired(&mut self) -> Result<usize, ApplyError> {
+        let deltas = self.data_db.expired_removals(temporal::now()).map_err(ApplyError::QueryError)?;
+        if deltas.is_empty() {
+            return Ok(0);
+        }
+        self.apply_all(deltas)
+    }
+
+    /// Call `vacuum_expired` on `db` every `interval` from a new thread, until
+    /// the database is dropped
+    ///
+    /// Failed passes are retried at the next interval.
+    pub fn vacuum_in_background(db: &Arc<Mutex<SrimDB>>, interval: Duration) -> thread::JoinHandle<()> {
+        let db = Arc::downgrade(db);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match db.upgrade() {
+                Some(db) => { let _ = db.lock().unwrap().vacuum_expired(); },
+                None => return,
+            }
+        })
+    }
+
+    /// Stable read-only view of the current state, unaffected by later deltas
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            data_db: self.data_db.clone(),
+            memory_limit: self.memory_limit,
+            role: self.role.clone(),
+        }
+    }
+
+    /// Apply a change, returning the number of rows it affected
+    pub fn apply(&mut self, delta: Delta) -> Result<usize, ApplyError> {
+        self.apply_reporting(delta).map(|report| report.rows())
+    }
+
+    /// Apply a change, returning how many rows it inserted, updated and removed
+    pub fn apply_reporting(&mut self, delta: Delta) -> Result<ApplyReport, ApplyError> {
+        let statement = if self.log.is_some() { Some(format!("{:?}", delta)) } else { None };
+        let timestamp = SystemTime::now();
+        let start = Instant::now();
+
+        let result = self.apply_recorded(vec![delta]);
+
+        self.log_apply(statement, timestamp, start, &result);
+        result
+    }
+
+    /// Check that `delta` would apply successfully, without changing anything
+    ///
+    /// Performs the same validation as `apply`, including triggers, against
+    /// a copy of the current state.
+    pub fn check_delta(&self, delta: &Delta) -> Result<(), ApplyError> {
+        let mut copy = self.data_db.clone();
+        // Strings interned by the check must not outlive it
+        copy.interner = Arc::new(Mutex::new(Interner::new()));
+        copy.apply(delta.clone()).map(|_| ())
+    }
+
+    /// Apply a batch of changes atomically: if any of them fails, none take effect
+    ///
+    /// Returns the total number of rows affected.
+    pub fn apply_all(&mut self, deltas: Vec<Delta>) -> Result<usize, ApplyError> {
+        self.apply_all_reporting(deltas).map(|report| report.rows())
+    }
+
+    /// Apply a batch of changes atomically, returning the rows affected by all of them
+    pub fn apply_all_reporting(&mut self, deltas: Vec<Delta>) -> Result<ApplyReport, ApplyError> {
+        let statement = if self.log.is_some() { Some(format!("{:?}", deltas)) } else { None };
+        let timestamp = SystemTime::now();
+        let start = Instant::now();
+
+        let result = self.apply_recorded(deltas);
+
+        self.log_apply(statement, timestamp, start, &result);
+        result
+    }
+
+    /// Apply a batch of changes atomically, only if the database is still at revision `expected`
+    ///
+    /// Returns the new revision, to be used as `expected` in the next call.
+    pub fn apply_if(&mut self, expected: Revision, deltas: Vec<Delta>) -> Result<Revision, ApplyError> {
+        if self.revision != expected {
+            return Err(ApplyError::RevisionConflict { expected, current: self.revision });
+        }
+        self.apply_all(deltas)?;
+        Ok(self.revision)
+    }
+
+    /// Current revision, advanced by every applied delta that changes a
+    /// table that isn't temporary
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Journaled deltas in order, empty if journaling is not enabled
+    pub fn journal(&self) -> &[JournalEntry] {
+        self.journal.as_ref().map_or(&[], |j| j.entries())
+    }
+
+    /// Apply journaled deltas after revision `from_revision` to `target`
+    ///
+    /// Replaying from revision 0 into an empty database reconstructs the
+    /// current state. Returns the revision of the last replayed delta.
+    pub fn replay(&self, from_revision: Revision, target: &mut SrimDB) -> Result<Revision, ApplyError> {
+        let mut last = from_revision;
+        if let Some(ref journal) = self.journal {
+            for entry in journal.since(from_revision) {
+                target.data_db.fire_triggers = entry.fires_triggers;
+                let result = target.apply(entry.delta.clone());
+                target.data_db.fire_triggers = true;
+                result?;
+                last = entry.revision;
+            }
+        }
+        Ok(last)
+    }
+
+    /// Revert the latest `steps` changes, returning the number actually reverted
+    ///
+    /// Each change applied with `apply` or `apply_all`, including committed
+    /// transactions, is one step. Reverting advances the revision like any
+    /// other change. Restored rows may be in a different order than before,
+    /// and triggers don't fire for them. Requires `with_history`, otherwise nothing can be undone.
+    pub fn undo(&mut self, steps: usize) -> Result<usize, ApplyError> {
+        for done in 0..steps {
+            let entry = match self.history.as_mut().and_then(|h| h.pop_undo()) {
+                Some(entry) => entry,
+                None => return Ok(done),
+            };
+            self.data_db.fire_triggers = false;
+            let result = self.apply_deltas(entry.inverse.clone(), false);
+            self.data_db.fire_triggers = true;
+            let history = self.history.as_mut().unwrap();
+            match result {
+                Ok(_) => history.push_redo(entry),
+                Err(error) => {
+                    history.push_undo(entry);
+                    return Err(error);
+                },
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Reapply the latest `steps` undone changes, returning the number actually reapplied
+    ///
+    /// Applying any new change forgets all undone changes.
+    pub fn redo(&mut self, steps: usize) -> Result<usize, ApplyError> {
+        for done in 0..steps {
+            let entry = match self.history.as_mut().and_then(|h| h.pop_redo()) {
+                Some(entry) => entry,
+                None => return Ok(done),
+            };
+            let result = self.apply_deltas(entry.deltas.clone(), true);
+            let history = self.history.as_mut().unwrap();
+            match result {
+                Ok((_, applied)) => history.push_undo(applied),
+                Err(error) => {
+                    history.push_redo(entry);
+                    return Err(error);
+                },
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Apply changes atomically, remembering them in the history if enabled
+    fn apply_recorded(&mut self, deltas: Vec<Delta>) -> Result<ApplyReport, ApplyError> {
+        let reversible = self.history.is_some();
+        let (report, applied) = self.apply_deltas(deltas, reversible)?;
+        if let Some(history) = self.history.as_mut() {
+            history.push(applied);
+        }
+        Ok(report)
+    }
+
+    /// Apply changes atomically, advancing the revision and journaling them
+    ///
+    /// Deltas adding named rows are journaled as the rows they added, see
+    /// `DataDB::concrete_deltas`. If `reversible` is set, also returns the
+    /// deltas as applied, and deltas that revert them.
+    fn apply_deltas(&mut self, mut deltas: Vec<Delta>, reversible: bool) -> Result<(ApplyReport, HistoryEntry), ApplyError> {
+        if let Some(ref role) = self.role {
+            for delta in &deltas {
+                role.check_delta(delta)?;
+            }
+        }
+        self.data_db.concrete_deltas(&mut deltas)?;
+        let applied = if reversible { deltas.clone() } else { Vec::new() };
+        let keep = self.journal.is_some() || !self.subscribers.is_empty() || !self.cdc_sinks.is_empty();
+        let copies: Vec<_> = deltas.iter().map(|d| if keep { Some(d.clone()) } else { None }).collect();
+        let temporary_before: Vec<TableName> = if keep { self.temporary_tables() } else { Vec::new() };
+
+        let (report, tables, inverse) = if deltas.len() == 1 {
+            // A single delta is atomic by itself, no need for a working copy
+            let delta = deltas.pop().unwrap();
+            let inverse = if reversible { self.data_db.inverse(&delta) } else { Vec::new() };
+            self.data_db.apply(delta).map(|(report, tables)| (report, vec![tables], inverse))?
+        }
+        else {
+            self.data_db.apply_all(deltas, reversible)?
+        };
+
+        // Tables temporary before or after the changes, as they may be created or dropped by them
+        let mut temporary = temporary_before;
+        if keep {
+            temporary.extend(self.temporary_tables());
+        }
+        for (t, copy) in tables.into_iter().zip(copies) {
+            let persistent = t.is_empty() || !t.iter().all(|name| temporary.contains(name));
+            self.record_change(t, copy, persistent);
+        }
+        Ok((report, HistoryEntry { deltas: applied, inverse }))
+    }
+
+    fn temporary_tables(&self) -> Vec<TableName> {
+        self.data_db.tables.iter().filter(|t| t.is_temporary()).map(|t| t.name()).collect()
+    }
+
+    /// Record the change made by `delta` to `tables`, advancing the revision
+    /// and journaling it and passing it to change data capture sinks only if
+    /// it is `persistent`, so that followers see no gaps between revisions
+    fn record_change(&mut self, tables: Vec<TableName>, delta: Option<Delta>, persistent: bool) {
+        self.changes += 1;
+        if persistent {
+            self.revision += 1;
+        }
+        for table in &tables {
+            self.table_changes.insert(table.clone(), self.changes);
+        }
+        if let Some(ref delta) = delta {
+            self.subscribers.notify(self.revision, delta, &tables);
+        }
+        let delta = delta.filter(|_| persistent);
+        if let Some(ref delta) = delta {
+            for sink in self.cdc_sinks.iter_mut() {
+                // As with the journal, a failing sink can't undo the delta
+                let _ = sink.write(self.revision, delta);
+            }
+        }
+        if let (Some(journal), Some(delta)) = (self.journal.as_mut(), delta) {
+            // The delta is already applied, a failing journal file can't undo it
+            let _ = journal.record(self.revision, delta, self.data_db.fire_triggers);
+        }
+    }
+
+    /// Value of `changes` after the last delta that modified the table, if any did
+    pub(crate) fn table_change(&self, name: &TableName) -> Option<u64> {
+        self.table_changes.get(name).cloned()
+    }
+
+    /// Check that the role of the handle can read what `query` reads
+    fn check_access(&self, query: &Query) -> Result<(), QueryError> {
+        self.role.as_ref().map_or(Ok(()), |role| role.check_query(query))
+    }
+
+    fn log_apply(&self, statement: Option<String>, timestamp: SystemTime, start: Instant, result: &Result<ApplyReport, ApplyError>) {
+        if let Some(statement) = statement {
+            self.log_event(LogEvent {
+                id: 0,
+                kind: LogEventKind::Delta,
+                timestamp,
+                statement,
+                duration: start.elapsed(),
+                rows: result.as_ref().ok().map(|r| r.rows()),
+                error: result.as_ref().err().map(|e| format!("{:?}", e)),
+            });
+        }
+    }
+
+    fn log_event(&self, mut event: LogEvent) {
+        if let Some(ref log) = self.log {
+            event.id = self.next_event_id.fetch_add(1, Ordering::Relaxed);
+            // A failing log destination must not fail the logged operation
+            let _ = log.emit(&event);
+        }
+    }
+}
+
+/// Point-in-time view of a database, see `SrimDB::snapshot`
+#[derive(Clone)]
+pub struct Snapshot {
+    data_db: DataDB,
+    memory_limit: Option<usize>,
+    role: Option<Role>,
+}
+impl Snapshot {
+    pub fn query(&self, query: Query) -> Result<QueryResult, QueryError> {
+        self.query_with(query, QueryOptions::default())
+    }
+
+    pub fn query_with(&self, query: Query, options: QueryOptions) -> Result<QueryResult, QueryError> {
+        if let Some(ref role) = self.role {
+            role.check_query(&query)?;
+        }
+        query.check(&self.data_db)?;
+        let ctx = ExecutionContext::new(&self.data_db)
+            .with_memory_limit(self.memory_limit)
+            .with_options(options);
+        query.execute(&ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Companies (INT id, TEXT name, TEXT city)
+    /// Employees (INT id, TEXT name, TEXT company)
+    fn setup_simple_company_employee_scenario() -> SrimDB {
+
+        let mut db = SrimDB::new();
+
+        db.apply(Delta::CreateTable(
             Table::new("Companies", vec![
                 TableField::new("id".to_owned(),   FieldKind::Integer(IntSize::N64, false)),
                 TableField::new("name".to_owned(), FieldKind::Text),
                 TableField::new("city".to_owned(), FieldKind::Text),
-            ])
+            ]).with_key_fields(vec!["id".to_owned()])
         )).unwrap();
 
         db.apply(Delta::CreateTable(
@@ -225,8 +1836,8 @@ mod tests {
                 "Companies".to_owned(),
                 Row::new(vec![
                     Value::Unsigned(i as u128),
-                    Value::Text(format!("Company {}", i).to_owned()),
-                    Value::Text(format!("City {}", i % CITY_COUNT).to_owned()),
+                    Value::Text(format!("Company {}", i).into()),
+                    Value::Text(format!("City {}", i % CITY_COUNT).into()),
                 ])
             )).unwrap();
         }
@@ -236,8 +1847,8 @@ mod tests {
                 "Employees".to_owned(),
                 Row::new(vec![
                     Value::Unsigned(i as u128),
-                    Value::Text(format!("Person {}", i).to_owned()),
-                    Value::Text(format!("Company {}", i % COMPANY_COUNT).to_owned()),
+                    Value::Text(format!("Person {}", i).into()),
+                    Value::Text(format!("Company {}", i % COMPANY_COUNT).into()),
                 ])
             )).unwrap();
         }
@@ -260,7 +1871,7 @@ mod tests {
             "Users".to_owned(),
             Row::new(vec![
                 Value::Unsigned(0),
-                Value::Text("Test User 1".to_owned())
+                Value::Text("Test User 1".into())
             ])
         )).unwrap();
 
@@ -268,7 +1879,7 @@ mod tests {
             "Users".to_owned(),
             Row::new(vec![
                 Value::Unsigned(1),
-                Value::Text("Test User 2".to_owned())
+                Value::Text("Test User 2".into())
             ])
         )).unwrap();
 
@@ -283,8 +1894,8 @@ mod tests {
 
         assert_eq!(result.field_names(), vec!["name".to_owned()]);
         assert_eq!(result.rows(), vec![
-            Row::new(vec![Value::Text("Test User 1".to_owned())]),
-            Row::new(vec![Value::Text("Test User 2".to_owned())]),
+            Row::new(vec![Value::Text("Test User 1".into())]),
+            Row::new(vec![Value::Text("Test User 2".into())]),
         ]);
 
         db.save().unwrap();
@@ -394,7 +2005,7 @@ mod tests {
                 query::Condition::FunctionCall(
                     FunctionCall::new("strict_eq".to_owned(), vec![
                         Argument::QueryField(QueryField::new("city".to_owned())),
-                        Argument::Value(Value::Text("City 2".to_owned()))
+                        Argument::Value(Value::Text("City 2".into()))
                     ])
                 ),
                 Box::new(company_names_and_cities)
@@ -418,7 +2029,7 @@ mod tests {
             ))
         );
 
-        let result = db.query(Query::Rename(QueryField::new("name".to_owned()), "company".to_owned(), Box::new(company_names_and_cities))).unwrap();
+        let result = db.query(Query::Rename(QueryField::new("name".to_owned()), QueryField::new("company".to_owned()), Box::new(company_names_and_cities))).unwrap();
         assert_eq!(result.field_names(), vec!["company", "city"]);
     }
 
@@ -473,7 +2084,7 @@ mod tests {
                     query::Condition::FunctionCall(
                         FunctionCall::new("strict_eq".to_owned(), vec![
                             Argument::QueryField(QueryField::new("city".to_owned()).from_table("Companies".to_owned())),
-                            Argument::Value(Value::Text("City 2".to_string())),
+                            Argument::Value(Value::Text("City 2".into())),
                         ])
                     ),
                     Box::new(Query::Filter(
@@ -502,4 +2113,3895 @@ mod tests {
             };
         }
     }
+
+    #[test]
+    fn test_shared_rows() {
+        let db = setup_simple_company_employee_scenario();
+
+        let table = Query::Table("Companies".to_owned());
+        let filtered = Query::Filter(query::Condition::Value(Value::Boolean(true)), Box::new(table.clone()));
+
+        let r1 = db.query(table).unwrap();
+        let r2 = db.query(filtered).unwrap();
+
+        assert_eq!(r1.shared_rows().len(), 100);
+        for (a, b) in r1.shared_rows().iter().zip(r2.shared_rows()) {
+            assert!(Arc::ptr_eq(a, b));
+        }
+    }
+
+    #[test]
+    fn test_query_log() {
+        use std::sync::Mutex;
+
+        let events: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+
+        let mut db = SrimDB::new().with_query_log(QueryLog::Callback(Box::new(move |event: &LogEvent| {
+            sink.lock().unwrap().push(event.to_json());
+        })));
+
+        db.apply(Delta::CreateTable(
+            Table::new("Users", vec![TableField::new("name".to_owned(), FieldKind::Text)])
+        )).unwrap();
+        db.query(Query::Table("Users".to_owned())).unwrap();
+        assert!(db.query(Query::Table("Missing".to_owned())).is_err());
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].starts_with("{\"id\":0,"));
+        assert!(events[0].contains("\"type\":\"delta\""));
+        assert!(events[1].contains("\"type\":\"query\""));
+        assert!(events[1].contains("\"rows\":0,\"outcome\":\"ok\""));
+        assert!(events[2].contains("\"outcome\":\"error\",\"error\":\"NoSuchTable(\\\"Missing\\\")\""));
+    }
+
+    #[test]
+    fn test_text_interning() {
+        let db = setup_simple_company_employee_scenario();
+
+        let employees = db.query(Query::Table("Employees".to_owned())).unwrap();
+        let companies = db.query(Query::Table("Companies".to_owned())).unwrap();
+
+        let text = |row: &Row, i: usize| match row.value(i) {
+            Value::Text(t) => t.clone(),
+            v => panic!("Expected text, found {:?}", v)
+        };
+
+        // Employees 0 and 100 both work for "Company 0"
+        let a = text(&employees.shared_rows()[0], 2);
+        let b = text(&employees.shared_rows()[100], 2);
+        let c = text(&companies.shared_rows()[0], 1);
+        assert_eq!(a, "Company 0");
+        assert!(a.ptr_eq(&b));
+        assert!(a.ptr_eq(&c));
+
+        // Texts of replaced rows are forgotten, so the interner doesn't grow without bound
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Notes", vec![TableField::new("text".to_owned(), FieldKind::Text)]))).unwrap();
+        let note = |i: usize| Row::new(vec![Value::Text(format!("Note {}", i).into())]);
+        db.apply(Delta::AddRow("Notes".to_owned(), note(0))).unwrap();
+        for i in 1..5000 {
+            db.apply(Delta::UpdateRow("Notes".to_owned(), note(i - 1), RowUpdate::Replace(note(i)))).unwrap();
+        }
+        assert!(db.data_db.interner.lock().unwrap().len() <= 2048);
+    }
+
+    #[test]
+    fn test_query_memory_limit() {
+        let db = setup_simple_company_employee_scenario().with_query_memory_limit(1 << 20);
+
+        // Table scans and filters share rows with the table
+        db.query(Query::Filter(
+            query::Condition::Value(Value::Boolean(true)),
+            Box::new(Query::Table("Employees".to_owned()))
+        )).unwrap();
+
+        // Cross product of 100 * 500 rows does not fit in 1 MiB
+        let result = db.query(Query::JoinOn(
+            query::Condition::Value(Value::Boolean(false)),
+            Box::new(Query::Table("Companies".to_owned())),
+            Box::new(Query::Table("Employees".to_owned()))
+        ));
+        match result {
+            Err(QueryError::MemoryLimitExceeded) => {},
+            other => panic!("Expected MemoryLimitExceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_query_cancellation() {
+        use std::time::Duration;
+
+        let db = setup_simple_company_employee_scenario();
+        let query = Query::Table("Companies".to_owned());
+
+        let token = CancelToken::new();
+        let options = QueryOptions { timeout: None, cancel_token: Some(token.clone()), seed: None, context: None };
+        assert!(db.query_with(query.clone(), options.clone()).is_ok());
+
+        token.cancel();
+        match db.query_with(query.clone(), options) {
+            Err(QueryError::Cancelled) => {},
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+
+        let options = QueryOptions { timeout: Some(Duration::from_secs(0)), cancel_token: None, seed: None, context: None };
+        match db.query_with(query, options) {
+            Err(QueryError::Cancelled) => {},
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    #[test]
+    fn test_bench_workloads() {
+        let config = bench::BenchConfig {
+            rows: 100, groups: 10, lookups: 5, scans: 2, join_rows: 10, mixed_operations: 4
+        };
+        let reports = bench::run_all(&config);
+        assert_eq!(reports.iter().map(|r| r.name).collect::<Vec<_>>(),
+            vec!["bulk_load", "point_lookups", "filtered_scans", "joins", "mixed"]);
+        assert_eq!(reports[0].operations(), 100);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut db = setup_simple_company_employee_scenario();
+        let snapshot = db.snapshot();
+
+        for i in 0..10 {
+            db.apply(Delta::AddRow(
+                "Companies".to_owned(),
+                Row::new(vec![
+                    Value::Unsigned(1000 + i),
+                    Value::Text(format!("Company {}", 1000 + i).into()),
+                    Value::Text("City 0".into()),
+                ])
+            )).unwrap();
+        }
+        db.apply(Delta::DropTable("Employees".to_owned())).unwrap();
+
+        let handle = ::std::thread::spawn(move || {
+            let companies = snapshot.query(Query::Table("Companies".to_owned())).unwrap();
+            let employees = snapshot.query(Query::Table("Employees".to_owned())).unwrap();
+            (companies.shared_rows().len(), employees.shared_rows().len())
+        });
+        assert_eq!(handle.join().unwrap(), (100, 500));
+
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().shared_rows().len(), 110);
+        assert!(db.query(Query::Table("Employees".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_remove_row() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+
+        for v in &[1, 2, 1] {
+            db.apply(Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(*v)]))).unwrap();
+        }
+
+        // Only the first matching row is removed
+        db.apply(Delta::RemoveRow("Values".to_owned(), Row::new(vec![Value::Signed(1)]))).unwrap();
+        let result = db.query(Query::Table("Values".to_owned())).unwrap();
+        assert_eq!(result.rows(), vec![Row::new(vec![Value::Signed(2)]), Row::new(vec![Value::Signed(1)])]);
+
+        match db.apply(Delta::RemoveRow("Values".to_owned(), Row::new(vec![Value::Signed(3)]))) {
+            Err(ApplyError::NoSuchRow(ref name)) if name == "Values" => {},
+            other => panic!("Expected NoSuchRow, got {:?}", other),
+        }
+        match db.apply(Delta::RemoveRow("Missing".to_owned(), Row::new(vec![Value::Signed(1)]))) {
+            Err(ApplyError::NoSuchTable(_)) => {},
+            other => panic!("Expected NoSuchTable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_update_row() {
+        let mut db = setup_simple_company_employee_scenario();
+        let company = |id: u128, name: &str, city: &str| Row::new(vec![
+            Value::Unsigned(id),
+            Value::Text(name.into()),
+            Value::Text(city.into()),
+        ]);
+
+        db.apply(Delta::UpdateRow(
+            "Companies".to_owned(),
+            company(3, "Company 3", "City 3"),
+            RowUpdate::Set(vec![("city".to_owned(), Value::Text("City 9".into()))])
+        )).unwrap();
+
+        db.apply(Delta::UpdateRow(
+            "Companies".to_owned(),
+            company(4, "Company 4", "City 4"),
+            RowUpdate::Replace(company(4, "Renamed", "City 4"))
+        )).unwrap();
+
+        let rows = db.query(Query::Table("Companies".to_owned())).unwrap().rows();
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[3], company(3, "Company 3", "City 9"));
+        assert_eq!(rows[4], company(4, "Renamed", "City 4"));
+
+        // New values are checked against the schema
+        match db.apply(Delta::UpdateRow(
+            "Companies".to_owned(),
+            company(5, "Company 5", "City 5"),
+            RowUpdate::Set(vec![("city".to_owned(), Value::Blob(vec![1, 2]))])
+        )) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "city" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        match db.apply(Delta::UpdateRow(
+            "Companies".to_owned(),
+            company(5, "Company 5", "City 5"),
+            RowUpdate::Replace(Row::new(vec![Value::Unsigned(5)]))
+        )) {
+            Err(ApplyError::WrongFieldCount { expected: 3, found: 1, .. }) => {},
+            other => panic!("Expected WrongFieldCount, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows()[5], company(5, "Company 5", "City 5"));
+    }
+
+    #[test]
+    fn test_remove_where() {
+        let mut db = setup_simple_company_employee_scenario();
+
+        let removed = db.apply(Delta::RemoveWhere(
+            "Companies".to_owned(),
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new("city".to_owned())),
+                Argument::Value(Value::Text("City 2".into())),
+            ]))
+        )).unwrap();
+        assert_eq!(removed, 10);
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 90);
+
+        let removed = db.apply(Delta::RemoveWhere("Companies".to_owned(), Condition::Value(Value::Boolean(false)))).unwrap();
+        assert_eq!(removed, 0);
+
+        match db.apply(Delta::RemoveWhere("Companies".to_owned(), Condition::QueryField(QueryField::new("missing".to_owned())))) {
+            Err(ApplyError::QueryError(QueryError::NoSuchField(_))) => {},
+            other => panic!("Expected NoSuchField, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_update_where() {
+        let mut db = setup_simple_company_employee_scenario();
+
+        // UPDATE Companies SET name = name + " (moved)", city = "City 0" WHERE city == "City 2"
+        let updated = db.apply(Delta::UpdateWhere(
+            "Companies".to_owned(),
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new("city".to_owned())),
+                Argument::Value(Value::Text("City 2".into())),
+            ])),
+            vec![
+                ("name".to_owned(), Argument::FunctionCall(FunctionCall::new("add".to_owned(), vec![
+                    Argument::QueryField(QueryField::new("name".to_owned())),
+                    Argument::Value(Value::Text(" (moved)".into())),
+                ]))),
+                ("city".to_owned(), Argument::Value(Value::Text("City 0".into()))),
+            ]
+        )).unwrap();
+        assert_eq!(updated, 10);
+
+        let rows = db.query(Query::Table("Companies".to_owned())).unwrap().rows();
+        assert_eq!(rows[12], Row::new(vec![
+            Value::Unsigned(12),
+            Value::Text("Company 12 (moved)".into()),
+            Value::Text("City 0".into()),
+        ]));
+        assert_eq!(rows[13].value(2), &Value::Text("City 3".into()));
+
+        // A failing assignment leaves the table untouched
+        match db.apply(Delta::UpdateWhere(
+            "Companies".to_owned(),
+            Condition::Value(Value::Boolean(true)),
+            vec![("id".to_owned(), Argument::Value(Value::Text("x".into())))]
+        )) {
+            Err(ApplyError::TypeMismatch { .. }) => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows(), rows);
+    }
+
+    #[test]
+    fn test_upsert() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Users", vec![
+                TableField::new("id".to_owned(),   FieldKind::Integer(IntSize::N64, false)),
+                TableField::new("name".to_owned(), FieldKind::Text),
+            ]).with_key_fields(vec!["id".to_owned()])
+        )).unwrap();
+
+        let user = |id: u128, name: &str| Row::new(vec![Value::Unsigned(id), Value::Text(name.into())]);
+
+        db.apply(Delta::Upsert("Users".to_owned(), user(1, "Alice"))).unwrap();
+        db.apply(Delta::Upsert("Users".to_owned(), user(2, "Bob"))).unwrap();
+        db.apply(Delta::Upsert("Users".to_owned(), user(1, "Alicia"))).unwrap();
+
+        let result = db.query(Query::Table("Users".to_owned())).unwrap();
+        assert_eq!(result.rows(), vec![user(1, "Alicia"), user(2, "Bob")]);
+    }
+
+    #[test]
+    fn test_unique_key() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Users", vec![
+                TableField::new("id".to_owned(),   FieldKind::Integer(IntSize::N64, false)),
+                TableField::new("name".to_owned(), FieldKind::Text),
+            ]).with_key_fields(vec!["id".to_owned()]).with_unique_key()
+        )).unwrap();
+        let user = |id: u128, name: &str| Row::new(vec![Value::Unsigned(id), Value::Text(name.into())]);
+        let users = |db: &SrimDB| db.query(Query::Table("Users".to_owned())).unwrap().rows();
+
+        db.apply(Delta::AddRows("Users".to_owned(), vec![user(1, "Alice"), user(2, "Bob")])).unwrap();
+        match db.apply(Delta::AddRow("Users".to_owned(), user(1, "Alicia"))) {
+            Err(ApplyError::DuplicateKey(ref table)) if table == "Users" => {},
+            other => panic!("Expected DuplicateKey, got {:?}", other),
+        }
+        assert!(db.apply(Delta::AddRows("Users".to_owned(), vec![user(3, "Carol"), user(3, "Dave")])).is_err());
+        assert!(db.apply(Delta::UpdateRow("Users".to_owned(), user(2, "Bob"), RowUpdate::Set(vec![
+            ("id".to_owned(), Value::Unsigned(1)),
+        ]))).is_err());
+        assert!(db.apply(Delta::UpdateWhere("Users".to_owned(), Condition::Value(Value::Boolean(true)), vec![
+            ("id".to_owned(), Argument::Value(Value::Unsigned(5))),
+        ])).is_err());
+        assert_eq!(users(&db), vec![user(1, "Alice"), user(2, "Bob")]);
+
+        // Updating a row without changing its key is fine
+        db.apply(Delta::UpdateRow("Users".to_owned(), user(2, "Bob"), RowUpdate::Set(vec![
+            ("name".to_owned(), Value::Text("Robert".into())),
+        ]))).unwrap();
+        assert_eq!(users(&db), vec![user(1, "Alice"), user(2, "Robert")]);
+    }
+
+    #[test]
+    fn test_foreign_keys() {
+        let mut db = SrimDB::new();
+        let id = || TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N64, false));
+        db.apply(Delta::CreateTable(Table::new("Authors", vec![id()]))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Books", vec![
+            id(),
+            TableField::new("author".to_owned(), FieldKind::ForeignKey("Authors".to_owned()))
+                .with_on_delete(ReferenceAction::Cascade),
+        ]).with_key_fields(vec!["id".to_owned()]))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Reviews", vec![
+            TableField::new("book".to_owned(), FieldKind::ForeignKey("Books".to_owned())),
+        ]))).unwrap();
+        let row = |values: &[u128]| Row::new(values.iter().map(|v| Value::Unsigned(*v)).collect());
+        let rows = |db: &SrimDB, name: &str| db.query(Query::Table(name.to_owned())).unwrap().rows().len();
+
+        db.apply(Delta::AddRows("Authors".to_owned(), vec![row(&[1]), row(&[2])])).unwrap();
+        db.apply(Delta::AddRows("Books".to_owned(), vec![row(&[10, 1]), row(&[11, 1]), row(&[12, 2])])).unwrap();
+        db.apply(Delta::AddRow("Reviews".to_owned(), row(&[12]))).unwrap();
+        match db.apply(Delta::AddRow("Books".to_owned(), row(&[13, 3]))) {
+            Err(ApplyError::InvalidReference(ref table, ref field)) if table == "Books" && field == "author" => {},
+            other => panic!("Expected InvalidReference, got {:?}", other),
+        }
+
+        // Removing an author cascades to their books
+        db.apply(Delta::RemoveRow("Authors".to_owned(), row(&[1]))).unwrap();
+        assert_eq!(rows(&db, "Books"), 1);
+
+        // Reviewed books can't be removed, not even by cascading
+        match db.apply(Delta::RemoveRow("Authors".to_owned(), row(&[2]))) {
+            Err(ApplyError::Referenced(ref table, ref by)) if table == "Books" && by == "Reviews" => {},
+            other => panic!("Expected Referenced, got {:?}", other),
+        }
+        assert!(db.apply(Delta::DropTable("Books".to_owned())).is_err());
+        assert_eq!((rows(&db, "Authors"), rows(&db, "Books")), (1, 1));
+
+        db.apply(Delta::Truncate("Reviews".to_owned())).unwrap();
+        db.apply(Delta::Truncate("Authors".to_owned())).unwrap();
+        assert_eq!(rows(&db, "Books"), 0);
+    }
+
+    #[test]
+    fn test_foreign_key_updates() {
+        let mut db = SrimDB::new().with_history(10);
+        let id = || TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N64, false));
+        db.apply(Delta::CreateTable(Table::new("Authors", vec![id()]))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Books", vec![
+            id(),
+            TableField::new("author".to_owned(), FieldKind::ForeignKey("Authors".to_owned()))
+                .with_on_delete(ReferenceAction::Cascade),
+        ]).with_key_fields(vec!["id".to_owned()]))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Reviews", vec![
+            TableField::new("book".to_owned(), FieldKind::ForeignKey("Books".to_owned())),
+        ]))).unwrap();
+        let row = |values: &[u128]| Row::new(values.iter().map(|v| Value::Unsigned(*v)).collect());
+        let rows = |db: &SrimDB, name: &str| db.query(Query::Table(name.to_owned())).unwrap().rows();
+
+        db.apply(Delta::AddRows("Authors".to_owned(), vec![row(&[1]), row(&[2])])).unwrap();
+        db.apply(Delta::AddRows("Books".to_owned(), vec![row(&[10, 1]), row(&[11, 1]), row(&[12, 2])])).unwrap();
+        db.apply(Delta::AddRow("Reviews".to_owned(), row(&[12]))).unwrap();
+
+        // Changing a referenced key restricts or cascades like removing the row
+        match db.apply(Delta::UpdateRow("Books".to_owned(), row(&[12, 2]), RowUpdate::Replace(row(&[13, 2])))) {
+            Err(ApplyError::Referenced(ref table, ref by)) if table == "Books" && by == "Reviews" => {},
+            other => panic!("Expected Referenced, got {:?}", other),
+        }
+        let is_second = Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("id".to_owned())),
+            Argument::Value(Value::Unsigned(2)),
+        ]));
+        assert!(db.apply(Delta::UpdateWhere("Authors".to_owned(), is_second, vec![
+            ("id".to_owned(), Argument::Value(Value::Unsigned(5))),
+        ])).is_err());
+        db.apply(Delta::UpdateRow("Authors".to_owned(), row(&[1]), RowUpdate::Replace(row(&[3])))).unwrap();
+        assert_eq!(rows(&db, "Books"), vec![row(&[12, 2])]);
+
+        // Undo restores the rows removed by cascading
+        db.undo(1).unwrap();
+        assert_eq!(rows(&db, "Authors"), vec![row(&[1]), row(&[2])]);
+        assert_eq!(rows(&db, "Books"), vec![row(&[12, 2]), row(&[10, 1]), row(&[11, 1])]);
+        db.apply(Delta::RemoveRow("Authors".to_owned(), row(&[1]))).unwrap();
+        db.undo(1).unwrap();
+        assert_eq!(rows(&db, "Books").len(), 3);
+
+        // Keys are compared in their collations, and must be a single field
+        db.apply(Delta::CreateTable(Table::new("Tags", vec![
+            TableField::new("name".to_owned(), FieldKind::Text).with_collation(Collation::CaseInsensitive),
+        ]))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Labels", vec![
+            TableField::new("tag".to_owned(), FieldKind::ForeignKey("Tags".to_owned())),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Tags".to_owned(), Row::new(vec![Value::Text("Rust".into())]))).unwrap();
+        db.apply(Delta::AddRow("Labels".to_owned(), Row::new(vec![Value::Text("rust".into())]))).unwrap();
+        match db.apply(Delta::RemoveRow("Tags".to_owned(), Row::new(vec![Value::Text("Rust".into())]))) {
+            Err(ApplyError::Referenced(ref table, ref by)) if table == "Tags" && by == "Labels" => {},
+            other => panic!("Expected Referenced, got {:?}", other),
+        }
+        db.apply(Delta::CreateTable(Table::new("Pairs", vec![id(), TableField::new("name".to_owned(), FieldKind::Text)]))).unwrap();
+        let to_pairs = || TableField::new("pair".to_owned(), FieldKind::ForeignKey("Pairs".to_owned())).nullable();
+        for (table, delta) in vec![
+            ("Quotes", Delta::CreateTable(Table::new("Quotes", vec![to_pairs()]))),
+            ("Labels", Delta::AddColumn("Labels".to_owned(), to_pairs(), Value::Null)),
+        ] {
+            match db.apply(delta) {
+                Err(ApplyError::InvalidSchema(ref name)) if name == table => {},
+                other => panic!("Expected InvalidSchema, got {:?}", other),
+            }
+        }
+
+        // Rows removed by cascading change the referencing table too
+        let events = db.subscribe(SubscriptionFilter::Tables(vec!["Books".to_owned()]));
+        let mut transaction = db.begin();
+        transaction.apply(Delta::AddRow("Books".to_owned(), row(&[14, 2]))).unwrap();
+        db.apply(Delta::RemoveRow("Authors".to_owned(), row(&[1]))).unwrap();
+        assert_eq!(events.try_recv().unwrap().tables(), vec!["Authors".to_owned(), "Books".to_owned()]);
+        match transaction.commit(&mut db) {
+            Err(ApplyError::Conflict(ref table)) if table == "Books" => {},
+            other => panic!("Expected Conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nulls() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("People", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("age".to_owned(), FieldKind::Integer(IntSize::N8, false)).nullable(),
+        ]))).unwrap();
+        let person = |name: &str, age: Value| Row::new(vec![Value::Text(name.into()), age]);
+        let people = || Box::new(Query::Table("People".to_owned()));
+        let age_is = |age: Value| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("age".to_owned())),
+            Argument::Value(age),
+        ]));
+
+        db.apply(Delta::AddRows("People".to_owned(), vec![
+            person("Alice", Value::Unsigned(30)),
+            person("Bob", Value::Null),
+        ])).unwrap();
+        match db.apply(Delta::AddRows("People".to_owned(), vec![Row::new(vec![Value::Null, Value::Null])])) {
+            Err(ApplyError::NotNull { ref field, .. }) if field == "name" => {},
+            other => panic!("Expected NotNull, got {:?}", other),
+        }
+
+        // Comparing with null is unknown, so neither the condition nor its negation holds
+        let result = db.query(Query::Filter(age_is(Value::Unsigned(30)), people())).unwrap();
+        assert_eq!(result.rows(), vec![person("Alice", Value::Unsigned(30))]);
+        assert_eq!(db.query(Query::Filter(age_is(Value::Null), people())).unwrap().rows().len(), 0);
+
+        // Set operations treat nulls as equal
+        let both = db.query(Query::Distinct(Box::new(Query::Union(people(), people())))).unwrap();
+        assert_eq!(both.rows().len(), 2);
+        let none = db.query(Query::Difference(people(), people())).unwrap();
+        assert_eq!(none.rows().len(), 0);
+    }
+
+    #[test]
+    fn test_field_defaults() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Accounts", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("balance".to_owned(), FieldKind::Integer(IntSize::N64, true))
+                .with_default(FieldDefault::Value(Value::Signed(0))),
+            TableField::new("limit".to_owned(), FieldKind::Integer(IntSize::N64, true))
+                .with_default(FieldDefault::FunctionCall(FunctionCall::new("add".to_owned(), vec![
+                    Argument::Value(Value::Signed(100)),
+                    Argument::Value(Value::Signed(50)),
+                ]))),
+            TableField::new("note".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let named = |values: Vec<(&str, Value)>| Delta::AddNamedRow(
+            "Accounts".to_owned(),
+            values.into_iter().map(|(f, v)| (f.to_owned(), v)).collect(),
+        );
+
+        db.apply(named(vec![("name", Value::Text("a".into()))])).unwrap();
+        db.apply(named(vec![("balance", Value::Signed(5)), ("name", Value::Text("b".into()))])).unwrap();
+        let result = db.query(Query::Table("Accounts".to_owned())).unwrap();
+        assert_eq!(result.rows(), vec![
+            Row::new(vec![Value::Text("a".into()), Value::Signed(0), Value::Signed(150), Value::Null]),
+            Row::new(vec![Value::Text("b".into()), Value::Signed(5), Value::Signed(150), Value::Null]),
+        ]);
+
+        // Fields without a default must be given unless nullable
+        match db.apply(named(vec![("note", Value::Text("x".into()))])) {
+            Err(ApplyError::NotNull { ref field, .. }) if field == "name" => {},
+            other => panic!("Expected NotNull, got {:?}", other),
+        }
+        assert!(db.apply(named(vec![("missing", Value::Signed(1))])).is_err());
+    }
+
+    #[test]
+    fn test_check_constraints() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Prices", vec![
+            TableField::new("amount".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("currency".to_owned(), FieldKind::Text).nullable(),
+        ]).with_check("euros", FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("currency".to_owned())),
+            Argument::Value(Value::Text("EUR".into())),
+        ])))).unwrap();
+        let price = |amount: u128, currency: Value| Row::new(vec![Value::Unsigned(amount), currency]);
+
+        db.apply(Delta::AddRow("Prices".to_owned(), price(1, Value::Text("EUR".into())))).unwrap();
+        // Unknown passes the check
+        db.apply(Delta::AddRow("Prices".to_owned(), price(2, Value::Null))).unwrap();
+        match db.apply(Delta::AddRow("Prices".to_owned(), price(3, Value::Text("USD".into())))) {
+            Err(ApplyError::CheckViolation { ref table, ref constraint }) if table == "Prices" && constraint == "euros" => {},
+            other => panic!("Expected CheckViolation, got {:?}", other),
+        }
+        assert!(db.apply(Delta::UpdateWhere("Prices".to_owned(), Condition::Value(Value::Boolean(true)), vec![
+            ("currency".to_owned(), Argument::Value(Value::Text("SEK".into()))),
+        ])).is_err());
+        assert_eq!(db.query(Query::Table("Prices".to_owned())).unwrap().rows(), vec![
+            price(1, Value::Text("EUR".into())),
+            price(2, Value::Null),
+        ]);
+
+        // Checks follow renamed fields, and are removed with the fields they read
+        db.apply(Delta::RenameColumn("Prices".to_owned(), "currency".to_owned(), "unit".to_owned())).unwrap();
+        match db.apply(Delta::AddRow("Prices".to_owned(), price(3, Value::Text("USD".into())))) {
+            Err(ApplyError::CheckViolation { ref constraint, .. }) if constraint == "euros" => {},
+            other => panic!("Expected CheckViolation, got {:?}", other),
+        }
+        db.apply(Delta::ForceDropColumn("Prices".to_owned(), "unit".to_owned())).unwrap();
+        db.apply(Delta::AddRow("Prices".to_owned(), Row::new(vec![Value::Unsigned(3)]))).unwrap();
+
+        // Checks can only read fields of the table and call existing functions
+        let with_check = |check: FunctionCall| Delta::CreateTable(Table::new("Invalid", vec![
+            TableField::new("amount".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+        ]).with_check("invalid", check));
+        match db.apply(with_check(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("currency".to_owned())),
+            Argument::Value(Value::Text("EUR".into())),
+        ]))) {
+            Err(ApplyError::InvalidSchema(_)) => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+        match db.apply(with_check(FunctionCall::new("no_such".to_owned(), vec![]))) {
+            Err(ApplyError::QueryError(QueryError::NoSuchFunction(_))) => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unique_constraints() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Users", vec![
+            TableField::new("id".to_owned(),    FieldKind::Integer(IntSize::N64, false)),
+            TableField::new("email".to_owned(), FieldKind::Text).nullable(),
+        ]).with_key_fields(vec!["id".to_owned()]).with_unique("unique_email", vec!["email".to_owned()]))).unwrap();
+        let user = |id: u128, email: Value| Row::new(vec![Value::Unsigned(id), email]);
+        let email = |e: &str| Value::Text(e.into());
+        let users = |db: &SrimDB| db.query(Query::Table("Users".to_owned())).unwrap().rows();
+
+        db.apply(Delta::AddRows("Users".to_owned(), vec![user(1, email("a@x")), user(2, email("b@x"))])).unwrap();
+        match db.apply(Delta::AddRow("Users".to_owned(), user(3, email("a@x")))) {
+            Err(ApplyError::UniqueViolation { ref constraint, .. }) if constraint == "unique_email" => {},
+            other => panic!("Expected UniqueViolation, got {:?}", other),
+        }
+        assert!(db.apply(Delta::AddRows("Users".to_owned(), vec![user(3, email("c@x")), user(4, email("c@x"))])).is_err());
+        assert!(db.apply(Delta::UpdateRow("Users".to_owned(), user(2, email("b@x")), RowUpdate::Set(vec![
+            ("email".to_owned(), email("a@x")),
+        ]))).is_err());
+
+        // Nulls never conflict, and removed or updated values can be reused
+        db.apply(Delta::AddRows("Users".to_owned(), vec![user(3, Value::Null), user(4, Value::Null)])).unwrap();
+        db.apply(Delta::RemoveRow("Users".to_owned(), user(1, email("a@x")))).unwrap();
+        db.apply(Delta::UpdateRow("Users".to_owned(), user(2, email("b@x")), RowUpdate::Set(vec![
+            ("email".to_owned(), email("a@x")),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Users".to_owned(), user(5, email("b@x")))).unwrap();
+        assert_eq!(users(&db), vec![
+            user(2, email("a@x")), user(3, Value::Null), user(4, Value::Null), user(5, email("b@x")),
+        ]);
+
+        db.apply(Delta::DropColumn("Users".to_owned(), "email".to_owned())).unwrap();
+        assert_eq!(db.data_db.table("Users".to_owned()).unwrap().unique_constraints(), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let mut db = SrimDB::new();
+        let field = |name: &str| TableField::new(name.to_owned(), FieldKind::Text);
+
+        for fields in vec![vec![], vec![field("")], vec![field("id"), field("name"), field("id")]] {
+            match db.apply(Delta::CreateTable(Table::new("Users", fields))) {
+                Err(ApplyError::InvalidSchema(ref table)) if table == "Users" => {},
+                other => panic!("Expected InvalidSchema, got {:?}", other),
+            }
+        }
+        assert!(db.data_db.table("Users".to_owned()).is_none());
+        db.apply(Delta::CreateTable(Table::new("Users", vec![field("id"), field("name")]))).unwrap();
+
+        // Altering the table can't make it invalid either
+        db.apply(Delta::ForceDropColumn("Users".to_owned(), "name".to_owned())).unwrap();
+        for delta in vec![
+            Delta::RenameColumn("Users".to_owned(), "id".to_owned(), "".to_owned()),
+            Delta::ForceDropColumn("Users".to_owned(), "id".to_owned()),
+        ] {
+            match db.apply(delta) {
+                Err(ApplyError::InvalidSchema(ref table)) if table == "Users" => {},
+                other => panic!("Expected InvalidSchema, got {:?}", other),
+            }
+        }
+        assert_eq!(db.data_db.table("Users".to_owned()).unwrap().field_index("id".to_owned()), Some(0));
+    }
+
+    #[test]
+    fn test_add_row_validation() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Values", vec![
+            TableField::new("small".to_owned(), FieldKind::Integer(IntSize::N8, true)),
+            TableField::new("count".to_owned(), FieldKind::Integer(IntSize::N16, false)),
+        ]))).unwrap();
+        let add = |small: Value, count: Value| Delta::AddRow("Values".to_owned(), Row::new(vec![small, count]));
+
+        // Values are converted to the field kinds
+        db.apply(add(Value::Unsigned(127), Value::Signed(65535))).unwrap();
+        let result = db.query(Query::Table("Values".to_owned())).unwrap();
+        assert_eq!(result.rows()[0].values(), vec![Value::Signed(127), Value::Unsigned(65535)]);
+
+        match db.apply(Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(1)]))) {
+            Err(ApplyError::WrongFieldCount { expected: 2, found: 1, .. }) => {},
+            other => panic!("Expected WrongFieldCount, got {:?}", other),
+        }
+        match db.apply(add(Value::Text("1".into()), Value::Unsigned(1))) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "small" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        match db.apply(add(Value::Signed(-129), Value::Unsigned(1))) {
+            Err(ApplyError::OutOfRange { ref field, .. }) if field == "small" => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        match db.apply(add(Value::Signed(1), Value::Signed(-1))) {
+            Err(ApplyError::OutOfRange { ref field, .. }) if field == "count" => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Values".to_owned())).unwrap().rows().len(), 1);
+    }
+
+    #[test]
+    fn test_temporal_values() {
+        use std::cmp::Ordering;
+
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Events", vec![
+            TableField::new("day".to_owned(), FieldKind::Date),
+            TableField::new("at".to_owned(), FieldKind::Time),
+            TableField::new("logged".to_owned(), FieldKind::Timestamp),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+
+        // Text is parsed and offsets are converted to UTC
+        db.apply(Delta::AddRow("Events".to_owned(), Row::new(vec![
+            text("2024-02-29"), text("12:30:00.25"), text("2024-02-29T23:30:00-01:00"),
+        ]))).unwrap();
+        let row = db.query(Query::Table("Events".to_owned())).unwrap().rows()[0].values();
+        assert_eq!(row[0], Value::Date(temporal::date(2024, 2, 29).unwrap()));
+        assert_eq!(row[1], Value::Time(45_000_250_000));
+        assert_eq!(row[2].cast_to(value::ValueKind::Text).unwrap(), text("2024-03-01T00:30:00Z"));
+        assert_eq!(row[2].cast_to(value::ValueKind::Date).unwrap(), Value::Date(temporal::date(2024, 3, 1).unwrap()));
+
+        match db.apply(Delta::AddRow("Events".to_owned(), Row::new(vec![
+            text("2023-02-29"), text("12:00:00"), text("2023-01-01T00:00:00Z"),
+        ]))) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "day" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        // A timestamp only fits a date field at midnight
+        match db.apply(Delta::AddRow("Events".to_owned(), Row::new(vec![row[2].clone(), row[1].clone(), row[2].clone()]))) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "day" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+
+        assert_eq!(row[0].compare(&row[2]).unwrap(), Some(Ordering::Less));
+        assert_eq!(Value::Date(1).compare(&Value::Timestamp(temporal::MICROS_PER_DAY)).unwrap(), Some(Ordering::Equal));
+        assert_eq!(row[1].compare(&Value::Null).unwrap(), None);
+        assert!(row[0].compare(&row[1]).is_err());
+
+        // Out of range years and non-ASCII offsets are rejected like other malformed text
+        for (invalid, kind) in &[
+            ("999999999999999999-01-01", value::ValueKind::Date),
+            ("2024-02-29T12:00:00+1é00", value::ValueKind::Timestamp),
+        ] {
+            match text(invalid).cast_to(*kind) {
+                Err(QueryError::IncompatibleTypes) => {},
+                other => panic!("Expected IncompatibleTypes, got {:?}", other),
+            }
+        }
+
+        let delta = Delta::AddRow("Events".to_owned(), Row::new(row.clone()));
+        assert_eq!(Delta::from_bytes(&delta.to_bytes()).unwrap().to_bytes(), delta.to_bytes());
+    }
+
+    #[test]
+    fn test_decimal_values() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Ledger", vec![
+            TableField::new("amount".to_owned(), FieldKind::Decimal(6, 2)),
+        ]))).unwrap();
+        let add = |v: Value| Delta::AddRow("Ledger".to_owned(), Row::new(vec![v]));
+
+        // Values are stored with the scale of the field
+        db.apply(add(Value::Text("12.5".into()))).unwrap();
+        db.apply(add(Value::Real(0.1))).unwrap();
+        db.apply(add(Value::Signed(-3))).unwrap();
+        let amounts: Vec<Value> = db.query(Query::Table("Ledger".to_owned())).unwrap().rows()
+            .iter().map(|row| row.value(0).clone()).collect();
+        assert_eq!(amounts, vec![Value::Decimal(1250, 2), Value::Decimal(10, 2), Value::Decimal(-300, 2)]);
+
+        // Addition is exact, unlike with reals
+        let sum = amounts.iter().fold(Value::Decimal(0, 0), |sum, v| sum.binop_add(v.clone()).unwrap());
+        assert_eq!(sum, Value::Decimal(960, 2));
+        assert_eq!(Value::Decimal(1, 1).binop_add(Value::Decimal(2, 1)).unwrap(), Value::Decimal(3, 1));
+        assert_eq!(sum.cast_to(value::ValueKind::Text).unwrap(), Value::Text("9.60".into()));
+        assert_eq!(Value::Decimal(96, 1).compare(&sum).unwrap(), Some(std::cmp::Ordering::Equal));
+        match Value::Decimal(i128::max_value(), 0).binop_add(Value::Unsigned(1)) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        match Value::Decimal(i128::min_value(), 0).binop_div(Value::Decimal(-1, 0)) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        // Equal numbers are equal whatever their scales
+        assert_eq!(Value::Decimal(150, 2), Value::Decimal(15, 1));
+        let distinct: std::collections::HashSet<Value> = vec![Value::Decimal(150, 2), Value::Decimal(15, 1)].into_iter().collect();
+        assert_eq!(distinct.len(), 1);
+
+        match db.apply(add(Value::Text("0.125".into()))) {
+            Err(ApplyError::TypeMismatch { .. }) => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        match db.apply(add(Value::Unsigned(10_000))) {
+            Err(ApplyError::OutOfRange { .. }) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        match db.apply(Delta::CreateTable(Table::new("Invalid", vec![
+            TableField::new("amount".to_owned(), FieldKind::Decimal(2, 3)),
+        ]))) {
+            Err(ApplyError::InvalidSchema(_)) => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_uuid_values() {
+        let mut db = SrimDB::new().with_journal().with_history(10);
+        db.apply(Delta::CreateTable(Table::new("Sessions", vec![
+            TableField::new("id".to_owned(), FieldKind::Uuid)
+                .with_default(FieldDefault::FunctionCall(FunctionCall::new("gen_uuid_v4".to_owned(), vec![]))),
+            TableField::new("user".to_owned(), FieldKind::Text),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+
+        db.apply(Delta::AddRow("Sessions".to_owned(), Row::new(vec![
+            text("123E4567-E89B-42D3-A456-426614174000"), text("a"),
+        ]))).unwrap();
+        for _ in 0..2 {
+            db.apply(Delta::AddNamedRow("Sessions".to_owned(), vec![("user".to_owned(), text("b"))])).unwrap();
+        }
+        let ids: Vec<Value> = db.query(Query::Table("Sessions".to_owned())).unwrap().rows()
+            .iter().map(|row| row.value(0).clone()).collect();
+        assert_eq!(ids[0].cast_to(value::ValueKind::Text).unwrap(), text("123e4567-e89b-42d3-a456-426614174000"));
+        assert_ne!(ids[1], ids[2]);
+        for id in &ids[1..] {
+            match id {
+                Value::Uuid(bytes) => assert_eq!((bytes[6] >> 4, bytes[8] >> 6), (4, 2)),
+                other => panic!("Expected Uuid, got {:?}", other),
+            }
+        }
+
+        for invalid in &["123e4567-e89b-42d3-a456", "123e4567e89b42d3a456426614174000", "+23e4567-e89b-42d3-a456-426614174000"] {
+            match db.apply(Delta::AddRow("Sessions".to_owned(), Row::new(vec![text(invalid), text("c")]))) {
+                Err(ApplyError::TypeMismatch { ref field, .. }) if field == "id" => {},
+                other => panic!("Expected TypeMismatch, got {:?}", other),
+            }
+        }
+
+        // Named rows are journaled and undone with the defaults they got
+        let sessions = || Query::Table("Sessions".to_owned());
+        let mut copy = SrimDB::new();
+        db.replay(0, &mut copy).unwrap();
+        assert_eq!(copy.query(sessions()).unwrap().rows(), db.query(sessions()).unwrap().rows());
+        db.undo(1).unwrap();
+        assert_eq!(db.query(sessions()).unwrap().rows().len(), 2);
+    }
+
+    #[test]
+    fn test_array_values() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Posts", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("tags".to_owned(), FieldKind::Array(Box::new(FieldKind::Text))).nullable(),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        let post = |id: u128, tags: Value| Delta::AddRow("Posts".to_owned(), Row::new(vec![Value::Unsigned(id), tags]));
+        let posts = || Box::new(Query::Table("Posts".to_owned()));
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let tags = || Argument::QueryField(QueryField::new("tags".to_owned()));
+
+        db.apply(post(1, Value::Array(vec![text("rust"), text("db")]))).unwrap();
+        db.apply(post(2, Value::Array(vec![text("db")]))).unwrap();
+        db.apply(post(3, Value::Array(vec![]))).unwrap();
+        db.apply(post(4, Value::Null)).unwrap();
+        match db.apply(post(5, Value::Array(vec![text("a"), Value::Blob(vec![1])]))) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "tags" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+
+        let about_rust = db.query(Query::Filter(
+            Condition::FunctionCall(call("array_contains", vec![tags(), Argument::Value(text("rust"))])),
+            posts(),
+        )).unwrap();
+        assert_eq!(about_rust.rows().len(), 1);
+
+        let unnested = db.query(Query::Unnest(QueryField::new("tags".to_owned()), posts())).unwrap();
+        assert_eq!(unnested.rows(), vec![
+            Row::new(vec![Value::Unsigned(1), text("rust")]),
+            Row::new(vec![Value::Unsigned(1), text("db")]),
+            Row::new(vec![Value::Unsigned(2), text("db")]),
+        ]);
+        match db.query(Query::Unnest(QueryField::new("id".to_owned()), posts())) {
+            Err(QueryError::TypeError(TypeError::NotArray)) => {},
+            other => panic!("Expected NotArray, got {:?}", other),
+        }
+
+        let functions = db.data_db.function_dict();
+        let array = Argument::Value(Value::Array(vec![text("a"), text("b")]));
+        let apply = |name: &str, arguments: Vec<Argument>| call(name, arguments).apply(&functions).unwrap();
+        assert_eq!(apply("array_length", vec![array.clone()]), Value::Unsigned(2));
+        assert_eq!(apply("array_get", vec![array.clone(), Argument::Value(Value::Unsigned(1))]), text("b"));
+        assert_eq!(apply("array_get", vec![array.clone(), Argument::Value(Value::Signed(2))]), Value::Null);
+        assert_eq!(apply("array_length", vec![Argument::Value(Value::Null)]), Value::Null);
+
+        match db.apply(Delta::CreateTable(Table::new("Invalid", vec![
+            TableField::new("posts".to_owned(), FieldKind::Array(Box::new(FieldKind::ForeignKey("Posts".to_owned())))),
+        ]))) {
+            Err(ApplyError::InvalidSchema(_)) => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_geo_points() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Cities", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("location".to_owned(), FieldKind::GeoPoint),
+        ]))).unwrap();
+        let city = |name: &str, location: Value| Delta::AddRow("Cities".to_owned(), Row::new(vec![Value::Text(name.into()), location]));
+        let point = |lat: f64, lon: f64| Argument::Value(Value::GeoPoint(lat, lon));
+        let location = || Argument::QueryField(QueryField::new("location".to_owned()));
+        let names = |db: &SrimDB, condition: FunctionCall| -> Vec<Value> {
+            db.query(Query::Filter(Condition::FunctionCall(condition), Box::new(Query::Table("Cities".to_owned()))))
+                .unwrap().rows().iter().map(|row| row.value(0).clone()).collect()
+        };
+
+        db.apply(city("Helsinki", Value::GeoPoint(60.1699, 24.9384))).unwrap();
+        db.apply(city("Tallinn", Value::Text("59.4370,24.7536".into()))).unwrap();
+        db.apply(city("Suva", Value::GeoPoint(-18.1416, 178.4419))).unwrap();
+        match db.apply(city("Nowhere", Value::GeoPoint(91.0, 0.0))) {
+            Err(ApplyError::OutOfRange { ref field, .. }) if field == "location" => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+
+        let distance = FunctionCall::new("distance_m".to_owned(), vec![point(60.1699, 24.9384), point(59.4370, 24.7536)])
+            .apply(db.data_db.function_dict()).unwrap();
+        match distance {
+            Value::Real(meters) => assert!((meters - 82_150.0).abs() < 100.0),
+            other => panic!("Expected Real, got {:?}", other),
+        }
+
+        let near_helsinki = FunctionCall::new("within_radius".to_owned(), vec![
+            location(), point(60.1699, 24.9384), Argument::Value(Value::Unsigned(100_000)),
+        ]);
+        assert_eq!(names(&db, near_helsinki), vec![Value::Text("Helsinki".into()), Value::Text("Tallinn".into())]);
+
+        // Boxes may cross the antimeridian
+        let pacific = FunctionCall::new("within_bbox".to_owned(), vec![location(), point(-30.0, 170.0), point(0.0, -170.0)]);
+        assert_eq!(names(&db, pacific), vec![Value::Text("Suva".into())]);
+    }
+
+    #[test]
+    fn test_vectors() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Documents", vec![
+            TableField::new("title".to_owned(), FieldKind::Text),
+            TableField::new("embedding".to_owned(), FieldKind::Vector(2)).nullable(),
+        ]))).unwrap();
+        let document = |title: &str, embedding: Value| Delta::AddRow("Documents".to_owned(), Row::new(vec![Value::Text(title.into()), embedding]));
+        let titles = |result: QueryResult| -> Vec<Value> { result.rows().iter().map(|row| row.value(0).clone()).collect() };
+
+        db.apply(document("east", Value::Vector(vec![1.0, 0.0]))).unwrap();
+        db.apply(document("north", Value::Vector(vec![0.0, 1.0]))).unwrap();
+        db.apply(document("north-east", Value::Array(vec![Value::Signed(1), Value::Real(1.0)]))).unwrap();
+        db.apply(document("zero", Value::Vector(vec![0.0, 0.0]))).unwrap();
+        db.apply(document("none", Value::Null)).unwrap();
+        db.apply(document("nan", Value::Vector(vec![::std::f32::NAN, 1.0]))).unwrap();
+        match db.apply(document("3d", Value::Vector(vec![1.0, 0.0, 0.0]))) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "embedding" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+
+        let nearest = db.query(Query::NearestNeighbors(
+            QueryField::new("embedding".to_owned()), vec![1.0, 0.2], 2, Box::new(Query::Table("Documents".to_owned())),
+        )).unwrap();
+        assert_eq!(titles(nearest), vec![Value::Text("east".into()), Value::Text("north-east".into())]);
+        let nearest = db.query(Query::NearestNeighbors(
+            QueryField::new("embedding".to_owned()), vec![::std::f32::NAN, 0.0], 2, Box::new(Query::Table("Documents".to_owned())),
+        )).unwrap();
+        assert!(nearest.rows().is_empty());
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, a: Vec<f32>, b: Vec<f32>| FunctionCall::new(name.to_owned(), vec![
+            Argument::Value(Value::Vector(a)), Argument::Value(Value::Vector(b)),
+        ]).apply(functions);
+        assert_eq!(apply("dot_product", vec![1.0, 2.0], vec![3.0, 4.0]).unwrap(), Value::Real(11.0));
+        assert_eq!(apply("cosine_similarity", vec![1.0, 0.0], vec![2.0, 0.0]).unwrap(), Value::Real(1.0));
+        assert_eq!(apply("cosine_similarity", vec![1.0, 0.0], vec![0.0, 0.0]).unwrap(), Value::Null);
+        assert!(apply("dot_product", vec![1.0], vec![1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn test_hash_indexes() {
+        let mut db = SrimDB::new().with_history(10);
+        db.apply(Delta::CreateTable(Table::new("Users", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("city".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let user = |name: &str, city: Value| Row::new(vec![Value::Text(name.into()), city]);
+        let city = |c: &str| Value::Text(c.into());
+        db.apply(Delta::AddRows("Users".to_owned(), vec![
+            user("alice", city("Oslo")), user("bob", city("Paris")), user("carol", city("Oslo")), user("dave", Value::Null),
+        ])).unwrap();
+
+        let in_city = |db: &SrimDB, c: Value| -> Vec<Row> {
+            db.query(Query::Filter(
+                Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                    Argument::QueryField(QueryField::new("city".to_owned())), Argument::Value(c),
+                ])),
+                Box::new(Query::Table("Users".to_owned())),
+            )).unwrap().rows()
+        };
+        let scanned = in_city(&db, city("Oslo"));
+
+        let create = Delta::CreateIndex("Users".to_owned(), vec!["city".to_owned()], IndexKind::Hash);
+        assert_eq!(Delta::from_bytes(&create.to_bytes()).unwrap().to_bytes(), create.to_bytes());
+        db.apply(create.clone()).unwrap();
+        assert_eq!(db.data_db.table("Users".to_owned()).unwrap().indexes(), vec![(IndexKind::Hash, vec!["city".to_owned()])]);
+        assert!(db.data_db.index_lookup(&"Users".to_owned(), &[1], &[city("Oslo")]).is_some());
+        assert_eq!(in_city(&db, city("Oslo")), scanned);
+        assert_eq!(in_city(&db, city("Rome")), vec![]);
+        assert_eq!(in_city(&db, Value::Null), vec![]);
+
+        // The index follows changes to the rows
+        db.apply(Delta::UpdateRow("Users".to_owned(), user("bob", city("Paris")), RowUpdate::Replace(user("bob", city("Oslo"))))).unwrap();
+        db.apply(Delta::RemoveRow("Users".to_owned(), user("alice", city("Oslo")))).unwrap();
+        db.apply(Delta::AddRow("Users".to_owned(), user("erin", city("Oslo")))).unwrap();
+        assert_eq!(in_city(&db, city("Oslo")), vec![user("bob", city("Oslo")), user("carol", city("Oslo")), user("erin", city("Oslo"))]);
+        assert_eq!(in_city(&db, city("Paris")), vec![]);
+
+        match db.apply(create) {
+            Err(ApplyError::IndexExists(ref table, _)) if table == "Users" => {},
+            other => panic!("Expected IndexExists, got {:?}", other),
+        }
+        match db.apply(Delta::CreateIndex("Users".to_owned(), vec!["age".to_owned()], IndexKind::Hash)) {
+            Err(ApplyError::NoSuchField(_, ref field)) if field == "age" => {},
+            other => panic!("Expected NoSuchField, got {:?}", other),
+        }
+
+        // Dropping an indexed field drops the index
+        db.apply(Delta::ForceDropColumn("Users".to_owned(), "city".to_owned())).unwrap();
+        assert!(db.data_db.table("Users".to_owned()).unwrap().indexes().is_empty());
+        db.undo(1).unwrap();
+        assert_eq!(db.data_db.table("Users".to_owned()).unwrap().indexes().len(), 1);
+    }
+
+    #[test]
+    fn test_ordered_indexes() {
+        use std::ops::Bound;
+
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Employees", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("salary".to_owned(), FieldKind::Integer(IntSize::N32, true)).nullable(),
+            TableField::new("location".to_owned(), FieldKind::GeoPoint).nullable(),
+        ]))).unwrap();
+        let employee = |name: &str, salary: Value| Row::new(vec![Value::Text(name.into()), salary, Value::Null]);
+        db.apply(Delta::AddRows("Employees".to_owned(), vec![
+            employee("a", Value::Signed(300)), employee("b", Value::Null), employee("c", Value::Signed(100)),
+            employee("d", Value::Signed(200)), employee("e", Value::Signed(100)), employee("f", Value::Signed(400)),
+        ])).unwrap();
+
+        let salary = || Argument::QueryField(QueryField::new("salary".to_owned()));
+        let signed = |v: i128| Argument::Value(Value::Signed(v));
+        let names = |db: &SrimDB, query: Query| -> Vec<Value> {
+            db.query(query).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()
+        };
+        let filter = |name: &str, arguments: Vec<Argument>| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new(name.to_owned(), arguments)),
+            Box::new(Query::Table("Employees".to_owned())),
+        );
+        let queries = || vec![
+            filter("lt", vec![salary(), signed(300)]),
+            filter("gt", vec![salary(), signed(100)]),
+            filter("lt", vec![signed(100), salary()]),
+            filter("between", vec![salary(), signed(100), signed(300)]),
+            filter("between", vec![salary(), signed(300), signed(100)]),
+            filter("strict_eq", vec![salary(), signed(100)]),
+            filter("strict_eq", vec![salary(), Argument::Value(Value::Unsigned(100))]),
+            Query::OrderBy(QueryField::new("salary".to_owned()), Box::new(Query::Table("Employees".to_owned()))),
+        ];
+        let scanned: Vec<Vec<Value>> = queries().into_iter().map(|q| names(&db, q)).collect();
+        let text = |names: &[&str]| -> Vec<Value> { names.iter().map(|n| Value::Text((*n).into())).collect() };
+        assert_eq!(scanned[0], text(&["c", "d", "e"]));
+        assert_eq!(scanned[3], text(&["a", "c", "d", "e"]));
+        assert_eq!(scanned[4], text(&[]));
+        assert_eq!(scanned[7], text(&["c", "e", "d", "a", "f", "b"]));
+
+        db.apply(Delta::CreateIndex("Employees".to_owned(), vec!["salary".to_owned()], IndexKind::BTree)).unwrap();
+        assert!(db.data_db.index_range(&"Employees".to_owned(), 1, Bound::Unbounded, Bound::Excluded(&Value::Signed(300))).is_some());
+        assert!(db.data_db.index_order(&"Employees".to_owned(), 1).is_some());
+        let indexed: Vec<Vec<Value>> = queries().into_iter().map(|q| names(&db, q)).collect();
+        assert_eq!(indexed, scanned);
+
+        // The index follows changes to the rows
+        db.apply(Delta::UpdateRow("Employees".to_owned(), employee("f", Value::Signed(400)), RowUpdate::Replace(employee("f", Value::Signed(50))))).unwrap();
+        assert_eq!(names(&db, queries().remove(7)), text(&["f", "c", "e", "d", "a", "b"]));
+
+        match db.apply(Delta::CreateIndex("Employees".to_owned(), vec!["location".to_owned()], IndexKind::BTree)) {
+            Err(ApplyError::InvalidSchema(ref table)) if table == "Employees" => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+        match db.apply(Delta::AlterColumnType("Employees".to_owned(), "salary".to_owned(), FieldKind::Vector(1))) {
+            Err(ApplyError::InvalidSchema(ref table)) if table == "Employees" => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("lt", vec![Value::Signed(1), Value::Real(1.5)]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("gt", vec![Value::Text("b".into()), Value::Text("a".into())]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("lt", vec![Value::Null, Value::Signed(1)]).unwrap(), Value::Null);
+        assert_eq!(apply("between", vec![Value::Signed(5), Value::Null, Value::Signed(1)]).unwrap(), Value::Boolean(false));
+        assert_eq!(apply("between", vec![Value::Signed(5), Value::Null, Value::Signed(9)]).unwrap(), Value::Null);
+        assert!(apply("lt", vec![Value::Text("a".into()), Value::Signed(1)]).is_err());
+    }
+
+    #[test]
+    fn test_comparison_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Employees", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("salary".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+        ]))).unwrap();
+        let employee = |name: &str, salary: i128| Row::new(vec![Value::Text(name.into()), Value::Signed(salary)]);
+        db.apply(Delta::AddRows("Employees".to_owned(), vec![
+            employee("a", 40000), employee("b", 50000), employee("c", 60000),
+        ])).unwrap();
+
+        let filter = |name: &str, salary: Value| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new(name.to_owned(), vec![
+                Argument::QueryField(QueryField::new("salary".to_owned())), Argument::Value(salary),
+            ])),
+            Box::new(Query::Table("Employees".to_owned())),
+        );
+        let queries = || vec![
+            filter("gte", Value::Unsigned(50000)),
+            filter("lte", Value::Real(50000.0)),
+            filter("neq", Value::Unsigned(50000)),
+        ];
+        let names = |db: &SrimDB| -> Vec<Vec<Value>> {
+            queries().into_iter().map(|q| db.query(q).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()).collect()
+        };
+        let text = |names: &[&str]| -> Vec<Value> { names.iter().map(|n| Value::Text((*n).into())).collect() };
+        let scanned = names(&db);
+        assert_eq!(scanned, vec![text(&["b", "c"]), text(&["a", "b"]), text(&["a", "c"])]);
+        db.apply(Delta::CreateIndex("Employees".to_owned(), vec!["salary".to_owned()], IndexKind::BTree)).unwrap();
+        assert_eq!(names(&db), scanned);
+        assert_eq!(db.indexes()[0].uses, 2);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("lte", vec![Value::Text("a".into()), Value::Text("a".into())]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("gte", vec![Value::Blob(vec![1]), Value::Blob(vec![1, 0])]).unwrap(), Value::Boolean(false));
+        assert_eq!(apply("neq", vec![Value::Signed(1), Value::Unsigned(1)]).unwrap(), Value::Boolean(false));
+        assert_eq!(apply("neq", vec![Value::GeoPoint(1.0, 2.0), Value::GeoPoint(1.0, 3.0)]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("neq", vec![Value::Null, Value::Signed(1)]).unwrap(), Value::Null);
+        assert!(apply("gte", vec![Value::Text("a".into()), Value::Signed(1)]).is_err());
+    }
+
+    #[test]
+    fn test_boolean_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Employees", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("salary".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+        ]))).unwrap();
+        let employee = |name: &str, salary: i128| Row::new(vec![Value::Text(name.into()), Value::Signed(salary)]);
+        db.apply(Delta::AddRows("Employees".to_owned(), vec![
+            employee("a", 40000), employee("b", 50000), employee("c", 60000),
+        ])).unwrap();
+
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let salary = || Argument::QueryField(QueryField::new("salary".to_owned()));
+        let above = |v: i128| Argument::FunctionCall(call("gt", vec![salary(), Argument::Value(Value::Signed(v))]));
+        let query = || Query::Filter(
+            Condition::FunctionCall(call("and", vec![above(40000), Argument::FunctionCall(call("not", vec![above(50000)]))])),
+            Box::new(Query::Table("Employees".to_owned())),
+        );
+        let names = |db: &SrimDB| -> Vec<Value> { db.query(query()).unwrap().rows().iter().map(|row| row.value(0).clone()).collect() };
+        assert_eq!(names(&db), vec![Value::Text("b".into())]);
+        // Terms of `and` can be answered with indexes
+        db.apply(Delta::CreateIndex("Employees".to_owned(), vec!["salary".to_owned()], IndexKind::BTree)).unwrap();
+        assert_eq!(names(&db), vec![Value::Text("b".into())]);
+        assert_eq!(db.indexes()[0].uses, 1);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let (t, f, null) = (Value::Boolean(true), Value::Boolean(false), Value::Null);
+        assert_eq!(apply("and", vec![t.clone(), null.clone()]).unwrap(), null);
+        assert_eq!(apply("and", vec![null.clone(), f.clone()]).unwrap(), f);
+        assert_eq!(apply("or", vec![null.clone(), t.clone()]).unwrap(), t);
+        assert_eq!(apply("or", vec![f.clone(), null.clone()]).unwrap(), null);
+        assert_eq!(apply("not", vec![f.clone()]).unwrap(), t);
+        assert_eq!(apply("xor", vec![t.clone(), t.clone(), t.clone()]).unwrap(), t);
+        assert_eq!(apply("xor", vec![t.clone(), null.clone()]).unwrap(), null);
+        match apply("or", vec![f.clone(), Value::Signed(1)]) {
+            Err(QueryError::TypeError(TypeError::NotBoolean)) => {},
+            other => panic!("Expected NotBoolean, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("sub", vec![Value::Signed(5), Value::Unsigned(7)]).unwrap(), Value::Signed(-2));
+        assert_eq!(apply("mul", vec![Value::Unsigned(3), Value::Real(0.5), Value::Signed(-2)]).unwrap(), Value::Real(-3.0));
+        assert_eq!(apply("div", vec![Value::Signed(-7), Value::Unsigned(2)]).unwrap(), Value::Signed(-3));
+        assert_eq!(apply("mod", vec![Value::Signed(-7), Value::Unsigned(2)]).unwrap(), Value::Signed(-1));
+        assert_eq!(apply("div", vec![Value::Decimal(100, 2), Value::Unsigned(8)]).unwrap(), Value::Decimal(125, 3));
+        assert_eq!(apply("mul", vec![Value::Decimal(150, 2), Value::Decimal(3, 1)]).unwrap(), Value::Decimal(450, 3));
+        assert_eq!(apply("neg", vec![Value::Unsigned(3)]).unwrap(), Value::Signed(-3));
+        assert_eq!(apply("abs", vec![Value::Decimal(-25, 1)]).unwrap(), Value::Decimal(25, 1));
+        assert_eq!(apply("pow", vec![Value::Signed(-2), Value::Unsigned(3)]).unwrap(), Value::Signed(-8));
+        assert_eq!(apply("pow", vec![Value::Unsigned(4), Value::Real(0.5)]).unwrap(), Value::Real(2.0));
+        assert_eq!(apply("sub", vec![Value::Null, Value::Signed(1)]).unwrap(), Value::Null);
+        for divide in &["div", "mod"] {
+            match apply(divide, vec![Value::Signed(1), Value::Decimal(0, 2)]) {
+                Err(QueryError::DivisionByZero) => {},
+                other => panic!("Expected DivisionByZero, got {:?}", other),
+            }
+        }
+        match apply("div", vec![Value::Decimal(1, 0), Value::Decimal(3, 0)]) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert!(apply("mul", vec![Value::Text("a".into()), Value::Signed(1)]).is_err());
+    }
+
+    #[test]
+    fn test_null_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Users", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("nickname".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        db.apply(Delta::AddRows("Users".to_owned(), vec![
+            Row::new(vec![text("Alice"), text("Al")]), Row::new(vec![text("Robert"), Value::Null]),
+        ])).unwrap();
+        let field = |name: &str| Argument::QueryField(QueryField::new(name.to_owned()));
+        let called = |name: &str| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::FunctionCall(FunctionCall::new("coalesce".to_owned(), vec![field("nickname"), field("name")])),
+                Argument::Value(text(name)),
+            ])),
+            Box::new(Query::Table("Users".to_owned())),
+        );
+        assert_eq!(db.query(called("Robert")).unwrap().rows().len(), 1);
+        assert_eq!(db.query(called("Alice")).unwrap().rows().len(), 0);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("coalesce", vec![Value::Null, Value::Null, Value::Signed(3), Value::Signed(4)]).unwrap(), Value::Signed(3));
+        assert_eq!(apply("coalesce", vec![Value::Null]).unwrap(), Value::Null);
+        assert_eq!(apply("ifnull", vec![Value::Null, text("-")]).unwrap(), text("-"));
+        assert_eq!(apply("ifnull", vec![text("a"), text("-")]).unwrap(), text("a"));
+        assert!(apply("ifnull", vec![Value::Null]).is_err());
+    }
+
+    #[test]
+    fn test_conditional_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Scores", vec![
+            TableField::new("points".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("games".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+        ]))).unwrap();
+        let score = |points: u128, games: u128| Row::new(vec![Value::Unsigned(points), Value::Unsigned(games)]);
+        db.apply(Delta::AddRows("Scores".to_owned(), vec![score(10, 5), score(0, 0), score(3, 3)])).unwrap();
+
+        let field = |name: &str| Argument::QueryField(QueryField::new(name.to_owned()));
+        let call = |name: &str, arguments: Vec<Argument>| Argument::FunctionCall(FunctionCall::new(name.to_owned(), arguments));
+        let unsigned = |v: u128| Argument::Value(Value::Unsigned(v));
+        // Dividing by zero in the branch that isn't chosen doesn't fail the query
+        let average = call("iif", vec![
+            call("strict_eq", vec![field("games"), unsigned(0)]), unsigned(0), call("div", vec![field("points"), field("games")]),
+        ]);
+        let at_least = |v: u128| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("gte".to_owned(), vec![average.clone(), unsigned(v)])),
+            Box::new(Query::Table("Scores".to_owned())),
+        );
+        assert_eq!(db.query(at_least(1)).unwrap().rows().len(), 2);
+        assert_eq!(db.query(at_least(0)).unwrap().rows().len(), 3);
+
+        let functions = db.data_db.function_dict();
+        let apply = |arguments: Vec<Argument>| FunctionCall::new("case".to_owned(), arguments).apply(functions);
+        let boolean = |b: bool| Argument::Value(Value::Boolean(b));
+        let text = |t: &str| Argument::Value(Value::Text(t.into()));
+        assert_eq!(apply(vec![boolean(false), text("a"), Argument::Value(Value::Null), text("b"), boolean(true), text("c")]).unwrap(), Value::Text("c".into()));
+        assert_eq!(apply(vec![boolean(false), text("a"), text("else")]).unwrap(), Value::Text("else".into()));
+        assert_eq!(apply(vec![boolean(false), text("a")]).unwrap(), Value::Null);
+        match apply(vec![unsigned(1), text("a")]) {
+            Err(QueryError::TypeError(TypeError::NotBoolean)) => {},
+            other => panic!("Expected NotBoolean, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cast_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Orders", vec![TableField::new("id".to_owned(), FieldKind::Text)]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        db.apply(Delta::AddRows("Orders".to_owned(), vec![Row::new(vec![text("0042")]), Row::new(vec![text("x7")])])).unwrap();
+        let by_id = Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::FunctionCall(FunctionCall::new("to_int".to_owned(), vec![
+                    Argument::FunctionCall(FunctionCall::new("iif".to_owned(), vec![
+                        Argument::FunctionCall(FunctionCall::new("like".to_owned(), vec![
+                            Argument::QueryField(QueryField::new("id".to_owned())), Argument::Value(text("x%")),
+                        ])),
+                        Argument::Value(Value::Null),
+                        Argument::QueryField(QueryField::new("id".to_owned())),
+                    ])),
+                ])),
+                Argument::Value(Value::Signed(42)),
+            ])),
+            Box::new(Query::Table("Orders".to_owned())),
+        );
+        assert_eq!(db.query(by_id).unwrap().rows().len(), 1);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("to_int", vec![text(" -7 ")]).unwrap(), Value::Signed(-7));
+        assert_eq!(apply("to_int", vec![Value::Decimal(-259, 2), Value::Boolean(true)]).unwrap(), Value::Signed(-2));
+        assert_eq!(apply("to_unsigned", vec![Value::Real(2.9), Value::Boolean(true)]).unwrap(), Value::Unsigned(2));
+        assert_eq!(apply("to_real", vec![text("1.5")]).unwrap(), Value::Real(1.5));
+        assert_eq!(apply("to_text", vec![Value::Unsigned(12)]).unwrap(), text("12"));
+        assert_eq!(apply("to_text", vec![Value::Blob(b"hi".to_vec())]).unwrap(), text("hi"));
+        assert_eq!(apply("to_blob", vec![text("hi")]).unwrap(), Value::Blob(b"hi".to_vec()));
+        assert_eq!(apply("to_int", vec![Value::Null]).unwrap(), Value::Null);
+        for (name, value) in vec![("to_int", text("x7")), ("to_text", Value::Blob(vec![0xff]))] {
+            match apply(name, vec![value]) {
+                Err(QueryError::IncompatibleTypes) => {},
+                other => panic!("Expected IncompatibleTypes, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let hex = |name: &str, value: Value| apply("hex", vec![apply(name, vec![value]).unwrap()]).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        assert_eq!(hex("sha256", text("abc")), text("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(hex("sha256", Value::Blob(Vec::new())), text("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        assert_eq!(hex("md5", text("The quick brown fox jumps over the lazy dog")), text("9e107d9d372bb6826bd81d3542a419d6"));
+        assert_eq!(hex("md5", text("")), text("d41d8cd98f00b204e9800998ecf8427e"));
+        // Longer than one block
+        assert_eq!(hex("sha256", text(&"a".repeat(100))), text("2816597888e4a0d3a36b82b83316ab32680eb8f00f8cd3b904d681246d285a0e"));
+        assert_eq!(apply("crc32", vec![text("123456789")]).unwrap(), Value::Unsigned(0xcbf43926));
+        assert_eq!(apply("hex", vec![Value::Blob(vec![0, 0xab])]).unwrap(), text("00ab"));
+        assert_eq!(apply("md5", vec![Value::Null]).unwrap(), Value::Null);
+        assert!(apply("sha256", vec![Value::Signed(1)]).is_err());
+    }
+
+    #[test]
+    fn test_temporal_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let text = |t: &str| Value::Text(t.into());
+        let date = |t: &str| Value::Date(temporal::parse_date(t).unwrap());
+        let timestamp = |t: &str| Value::Timestamp(temporal::parse_timestamp(t).unwrap());
+
+        assert_eq!(apply("date_add", vec![text("month"), Value::Unsigned(1), date("2024-01-31")]).unwrap(), date("2024-02-29"));
+        assert_eq!(apply("date_add", vec![text("years"), Value::Signed(-1), date("2024-02-29")]).unwrap(), date("2023-02-28"));
+        assert_eq!(apply("date_add", vec![text("hour"), Value::Unsigned(36), date("2024-01-01")]).unwrap(), timestamp("2024-01-02T12:00:00Z"));
+        assert_eq!(apply("date_diff", vec![text("day"), date("2024-03-01"), timestamp("2024-02-01T12:00:00Z")]).unwrap(), Value::Signed(-28));
+        assert_eq!(apply("date_diff", vec![text("month"), date("2024-01-31"), date("2024-02-29")]).unwrap(), Value::Signed(0));
+        assert_eq!(apply("date_diff", vec![text("year"), date("2020-02-29"), date("2024-03-01")]).unwrap(), Value::Signed(4));
+        assert_eq!(apply("date_trunc", vec![text("week"), timestamp("2024-03-07T10:30:00Z")]).unwrap(), timestamp("2024-03-04T00:00:00Z"));
+        assert_eq!(apply("date_trunc", vec![text("month"), date("2024-03-07")]).unwrap(), date("2024-03-01"));
+        assert_eq!(apply("date_trunc", vec![text("minute"), timestamp("2024-03-07T10:30:59.5Z")]).unwrap(), timestamp("2024-03-07T10:30:00Z"));
+
+        let moment = timestamp("2024-03-07T09:05:01.000002Z");
+        assert_eq!(apply("strftime", vec![text("%d/%m/%Y %H:%M:%S.%f day %j %%"), moment.clone()]).unwrap(), text("07/03/2024 09:05:01.000002 day 067 %"));
+        assert_eq!(apply("strptime", vec![text("07/03/2024 09:05:01.000002"), text("%d/%m/%Y %H:%M:%S.%f")]).unwrap(), moment);
+        assert_eq!(apply("strptime", vec![text("2024-03"), text("%Y-%m")]).unwrap(), timestamp("2024-03-01T00:00:00Z"));
+        assert!(apply("strptime", vec![text("2024-13"), text("%Y-%m")]).is_err());
+        match apply("strftime", vec![text("%Q"), moment]) {
+            Err(QueryError::InvalidPattern(_)) => {},
+            other => panic!("Expected InvalidPattern, got {:?}", other),
+        }
+        // Dates and truncated timestamps beyond the range of timestamps
+        let calls = vec![
+            ("strftime", vec![text("%Y"), Value::Date(i32::max_value())]),
+            ("date_diff", vec![text("day"), Value::Date(i32::min_value()), date("2024-03-01")]),
+            ("date_trunc", vec![text("week"), Value::Timestamp(i64::min_value())]),
+        ];
+        for (name, values) in calls {
+            match apply(name, values) {
+                Err(QueryError::OutOfRange) => {},
+                other => panic!("Expected OutOfRange from {}, got {:?}", name, other),
+            }
+        }
+        match apply("now", Vec::new()).unwrap() {
+            Value::Timestamp(micros) => assert!(micros > temporal::parse_timestamp("2020-01-01T00:00:00Z").unwrap()),
+            other => panic!("Expected a timestamp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_random_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+
+        for _ in 0..100 {
+            match apply("random", Vec::new()).unwrap() {
+                Value::Real(r) => assert!(0.0 <= r && r < 1.0),
+                other => panic!("Expected a real, got {:?}", other),
+            }
+            match apply("random_int", vec![Value::Signed(-2), Value::Unsigned(2)]).unwrap() {
+                Value::Signed(i) => assert!(-2 <= i && i <= 2),
+                other => panic!("Expected a signed integer, got {:?}", other),
+            }
+        }
+        assert_eq!(apply("random_int", vec![Value::Signed(7), Value::Signed(7)]).unwrap(), Value::Signed(7));
+        assert_eq!(apply("random_int", vec![Value::Null, Value::Signed(7)]).unwrap(), Value::Null);
+        match apply("random_int", vec![Value::Signed(3), Value::Signed(1)]) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_ne!(apply("gen_uuid", Vec::new()).unwrap(), apply("gen_uuid", Vec::new()).unwrap());
+
+        // The same seed repeats the results, also of `gen_uuid`
+        let draw = |name: &str, arguments: Vec<Value>, kind: FieldKind| Box::new(Query::FromFunctionCall(
+            TableField::new("value".to_owned(), kind),
+            FunctionCall::new(name.to_owned(), arguments.into_iter().map(Argument::Value).collect()),
+        ));
+        let query = Query::Union(
+            Box::new(Query::Union(
+                draw("random_int", vec![Value::Signed(0), Value::Signed(1_000_000_000)], FieldKind::Integer(IntSize::N64, true)),
+                draw("random_int", vec![Value::Signed(0), Value::Signed(1_000_000_000)], FieldKind::Integer(IntSize::N64, true)),
+            )),
+            draw("random_int", vec![Value::Signed(0), Value::Signed(1_000_000_000)], FieldKind::Integer(IntSize::N64, true)),
+        );
+        let seeded = |seed| db.query_with(query.clone(), QueryOptions { seed: Some(seed), ..QueryOptions::default() }).unwrap().rows();
+        assert_eq!(seeded(42), seeded(42));
+        assert_ne!(seeded(42), seeded(43));
+        let uuid = Query::FromFunctionCall(TableField::new("id".to_owned(), FieldKind::Uuid), FunctionCall::new("gen_uuid".to_owned(), vec![]));
+        let seeded = |seed| db.query_with(uuid.clone(), QueryOptions { seed: Some(seed), ..QueryOptions::default() }).unwrap().rows();
+        assert_eq!(seeded(7), seeded(7));
+        assert_ne!(db.query(uuid.clone()).unwrap().rows(), db.query(uuid.clone()).unwrap().rows());
+    }
+
+    #[test]
+    fn test_composed_functions() {
+        let mut db = setup_simple_company_employee_scenario();
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let define = |db: &mut SrimDB, name: &str, body: FunctionCall| {
+            Arc::make_mut(&mut db.data_db.functions).insert(name.to_owned(), Function::Composed(body));
+        };
+        define(&mut db, "double", call("mul", vec![Argument::Parameter(0), Argument::Value(Value::Signed(2))]));
+        define(&mut db, "quadruple", call("double", vec![Argument::FunctionCall(call("double", vec![Argument::Parameter(0)]))]));
+        // Only the chosen branch is evaluated, so the recursion can end
+        define(&mut db, "safe_div", call("iif", vec![
+            Argument::FunctionCall(call("strict_eq", vec![Argument::Parameter(1), Argument::Value(Value::Signed(0))])),
+            Argument::Value(Value::Null),
+            Argument::FunctionCall(call("div", vec![Argument::Parameter(0), Argument::Parameter(1)])),
+        ]));
+        define(&mut db, "ping", call("pong", vec![Argument::Parameter(0)]));
+        define(&mut db, "pong", call("ping", vec![Argument::Parameter(0)]));
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| call(name, values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("quadruple", vec![Value::Signed(5)]).unwrap(), Value::Signed(20));
+        assert_eq!(apply("safe_div", vec![Value::Signed(7), Value::Signed(2)]).unwrap(), Value::Signed(3));
+        assert_eq!(apply("safe_div", vec![Value::Signed(7), Value::Signed(0)]).unwrap(), Value::Null);
+        assert_eq!(functions["double"].call("double", functions, vec![Value::Signed(4)]).unwrap(), Value::Signed(8));
+        match apply("double", Vec::new()) {
+            Err(QueryError::NotEnoughArguments(1)) => {},
+            other => panic!("Expected NotEnoughArguments, got {:?}", other),
+        }
+        match apply("ping", vec![Value::Signed(1)]) {
+            Err(QueryError::CyclicFunction(ref name)) if name == "ping" => {},
+            other => panic!("Expected CyclicFunction, got {:?}", other),
+        }
+
+        // Composed functions are called with field values in queries
+        let result = db.query(Query::Filter(
+            Condition::FunctionCall(call("lt", vec![
+                Argument::FunctionCall(call("quadruple", vec![Argument::QueryField(QueryField::new("id".to_owned()))])),
+                Argument::Value(Value::Signed(8)),
+            ])),
+            Box::new(Query::Table("Companies".to_owned())),
+        )).unwrap();
+        assert_eq!(result.rows().len(), 2);
+    }
+
+    #[test]
+    fn test_create_function() {
+        let mut db = SrimDB::new().with_journal().with_history(10);
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let double = call("mul", vec![Argument::Parameter(0), Argument::Value(Value::Signed(2))]);
+        let quadruple = call("double", vec![Argument::FunctionCall(call("double", vec![Argument::Parameter(0)]))]);
+        db.apply(Delta::CreateFunction("double".to_owned(), double.clone())).unwrap();
+        db.apply(Delta::CreateFunction("quadruple".to_owned(), quadruple.clone())).unwrap();
+
+        let query = Query::FromFunctionCall(
+            TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N64, true)),
+            call("quadruple", vec![Argument::Value(Value::Signed(3))]),
+        );
+        assert_eq!(db.query(query.clone()).unwrap().rows(), vec![Row::new(vec![Value::Signed(12)])]);
+
+        match db.apply(Delta::CreateFunction("add".to_owned(), double.clone())) {
+            Err(ApplyError::FunctionExists(ref name)) if name == "add" => {},
+            other => panic!("Expected FunctionExists, got {:?}", other),
+        }
+        match db.apply(Delta::CreateFunction("recursive".to_owned(), call("recursive", vec![Argument::Parameter(0)]))) {
+            Err(ApplyError::NoSuchFunction(ref name)) if name == "recursive" => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+        match db.apply(Delta::CreateFunction("field".to_owned(), call("neg", vec![Argument::QueryField(QueryField::new("id".to_owned()))]))) {
+            Err(ApplyError::InvalidFunction(ref name)) if name == "field" => {},
+            other => panic!("Expected InvalidFunction, got {:?}", other),
+        }
+        match db.apply(Delta::DropFunction("double".to_owned())) {
+            Err(ApplyError::FunctionInUse(ref name, ref caller)) if name == "double" && caller == "quadruple" => {},
+            other => panic!("Expected FunctionInUse, got {:?}", other),
+        }
+        match db.apply(Delta::DropFunction("mul".to_owned())) {
+            Err(ApplyError::NoSuchFunction(ref name)) if name == "mul" => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+
+        // Definitions survive encoding, replay and undo
+        let create = Delta::CreateFunction("quadruple".to_owned(), quadruple);
+        assert_eq!(Delta::from_bytes(&create.to_bytes()).unwrap().to_bytes(), create.to_bytes());
+        assert!(create.to_json().contains("\"Parameter\""));
+        let mut replica = SrimDB::new();
+        db.replay(0, &mut replica).unwrap();
+        assert_eq!(replica.query(query.clone()).unwrap().rows(), vec![Row::new(vec![Value::Signed(12)])]);
+
+        db.apply(Delta::DropFunction("quadruple".to_owned())).unwrap();
+        assert!(!db.data_db.function_dict().contains_key("quadruple"));
+        db.undo(1).unwrap();
+        assert_eq!(db.query(query).unwrap().rows(), vec![Row::new(vec![Value::Signed(12)])]);
+    }
+
+    #[test]
+    fn test_function_signatures() {
+        let db = setup_simple_company_employee_scenario();
+        let functions = db.data_db.function_dict();
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let value = |v: Value| Argument::Value(v);
+        let field = |name: &str| Argument::QueryField(QueryField::new(name.to_owned()));
+        let filter = |fc: FunctionCall| Query::Filter(Condition::FunctionCall(fc), Box::new(Query::Table("Companies".to_owned())));
+
+        assert_eq!(functions["upper"].signature().returns, Some(ValueKind::Text));
+        assert_eq!(functions["substr"].signature().max_arity, Some(3));
+
+        match call("upper", vec![value(Value::Text("a".into())), value(Value::Text("b".into()))]).apply(functions) {
+            Err(QueryError::TooManyArguments(ref name, 1)) if name == "upper" => {},
+            other => panic!("Expected TooManyArguments, got {:?}", other),
+        }
+        match call("between", vec![value(Value::Signed(1))]).apply(functions) {
+            Err(QueryError::NotEnoughArguments(3)) => {},
+            other => panic!("Expected NotEnoughArguments, got {:?}", other),
+        }
+        assert_eq!(call("upper", vec![value(Value::Null)]).apply(functions).unwrap(), Value::Null);
+
+        // Literal arguments and results of nested calls are checked before any row is read
+        let bad_literal = filter(call("like", vec![field("name"), value(Value::Signed(1))]));
+        match db.check_query(&bad_literal) {
+            Err(QueryError::InvalidArgument(ref name, 1, ValueKind::Signed)) if name == "like" => {},
+            other => panic!("Expected InvalidArgument, got {:?}", other),
+        }
+        let bad_nested = filter(call("like", vec![field("name"), Argument::FunctionCall(call("length", vec![field("city")]))]));
+        match db.query(bad_nested) {
+            Err(QueryError::InvalidArgument(ref name, 1, ValueKind::Unsigned)) if name == "like" => {},
+            other => panic!("Expected InvalidArgument, got {:?}", other),
+        }
+        let bad_arity = filter(call("and", vec![Argument::FunctionCall(call("not", vec![]))]));
+        assert!(db.check_query(&bad_arity).is_err());
+
+        // Kinds of fields are checked against the values when executing
+        let bad_field = filter(call("strict_eq", vec![Argument::FunctionCall(call("upper", vec![field("id")])), value(Value::Text("1".into()))]));
+        assert!(db.check_query(&bad_field).is_ok());
+        match db.query(bad_field) {
+            Err(QueryError::InvalidArgument(ref name, 0, ValueKind::Unsigned)) if name == "upper" => {},
+            other => panic!("Expected InvalidArgument, got {:?}", other),
+        }
+        let good = filter(call("like", vec![field("name"), value(Value::Text("Company 1_".into()))]));
+        assert_eq!(db.query(good).unwrap().rows().len(), 10);
+    }
+
+    #[test]
+    fn test_function_overloading() {
+        fn describe_number(_: Vec<Value>) -> Result<Value, QueryError> { Ok(Value::Text("number".into())) }
+        fn describe_integer(_: Vec<Value>) -> Result<Value, QueryError> { Ok(Value::Text("integer".into())) }
+        fn describe_any(_: Vec<Value>) -> Result<Value, QueryError> { Ok(Value::Text("any".into())) }
+        fn add_texts(values: Vec<Value>) -> Result<Value, QueryError> {
+            match (&values[0], &values[1]) {
+                (Value::Text(a), Value::Text(b)) => Ok(Value::Text(format!("{}{}", a, b).into())),
+                _ => Ok(Value::Null),
+            }
+        }
+        const NUMBER: &[ValueKind] = &[ValueKind::Unsigned, ValueKind::Signed, ValueKind::Real];
+        const INTEGER: &[ValueKind] = &[ValueKind::Unsigned, ValueKind::Signed];
+
+        let db = SrimDB::new()
+            .with_function("describe", NativeFunction::new(describe_number).with_signature(Signature::exactly(1).taking(&[NUMBER])))
+            .with_function("describe", NativeFunction::new(describe_integer).with_signature(Signature::exactly(1).taking(&[INTEGER])))
+            .with_function("describe", NativeFunction::new(describe_any).with_signature(Signature::exactly(1)))
+            .with_function("pair", NativeFunction::new(describe_integer).with_signature(Signature::exactly(2).taking(&[INTEGER, &[]])))
+            .with_function("pair", NativeFunction::new(describe_any).with_signature(Signature::exactly(2).taking(&[&[], INTEGER])))
+            .with_function("add", NativeFunction::new(add_texts).with_signature(Signature::exactly(2).taking(&[&[ValueKind::Text]])))
+            .with_arithmetic_mode(ArithmeticMode::Checked);
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let text = |t: &str| Value::Text(t.into());
+
+        // The most specific overload is chosen, or the first one if a null argument can't tell them apart
+        assert_eq!(apply("describe", vec![Value::Signed(1)]).unwrap(), text("integer"));
+        assert_eq!(apply("describe", vec![Value::Real(1.5)]).unwrap(), text("number"));
+        assert_eq!(apply("describe", vec![text("1")]).unwrap(), text("any"));
+        assert_eq!(apply("describe", vec![Value::Null]).unwrap(), text("number"));
+        assert_eq!(apply("pair", vec![Value::Signed(1), text("1")]).unwrap(), text("integer"));
+        assert_eq!(apply("pair", vec![Value::Null, Value::Signed(1)]).unwrap(), text("any"));
+        match apply("pair", vec![Value::Signed(1), Value::Signed(1)]) {
+            Err(QueryError::AmbiguousCall(ref name, ref kinds)) if name == "pair" && *kinds == vec![ValueKind::Signed, ValueKind::Signed] => {},
+            other => panic!("Expected AmbiguousCall, got {:?}", other),
+        }
+
+        // Builtins can be overloaded, also the arithmetic ones whose mode changes
+        assert_eq!(apply("add", vec![text("a"), text("b")]).unwrap(), text("ab"));
+        match apply("add", vec![Value::Signed(i128::max_value()), Value::Signed(1)]) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_eq!(apply("length", vec![text("äö")]).unwrap(), Value::Unsigned(2));
+        assert_eq!(apply("length", vec![Value::Blob("äö".as_bytes().to_vec())]).unwrap(), Value::Unsigned(4));
+        assert_eq!(apply("length", vec![Value::Array(vec![Value::Null; 3])]).unwrap(), Value::Unsigned(3));
+        match apply("length", vec![Value::Boolean(true)]) {
+            Err(QueryError::NoMatchingOverload(ref name, _)) if name == "length" => {},
+            other => panic!("Expected NoMatchingOverload, got {:?}", other),
+        }
+        match db.check_query(&Query::FromFunctionCall(
+            TableField::new("length".to_owned(), FieldKind::Integer(IntSize::N64, false)),
+            FunctionCall::new("length".to_owned(), vec![Argument::Value(Value::Signed(1))]),
+        )) {
+            Err(QueryError::NoMatchingOverload(_, ref kinds)) if *kinds == vec![ValueKind::Signed] => {},
+            other => panic!("Expected NoMatchingOverload, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_function_call_errors() {
+        let db = setup_simple_company_employee_scenario();
+        let functions = db.data_db.function_dict();
+        let call = |name: &str, arguments: Vec<Argument>| FunctionCall::new(name.to_owned(), arguments);
+        let field = Argument::QueryField(QueryField::new("id".to_owned()));
+
+        match call("no_such", vec![]).apply(functions) {
+            Err(QueryError::NoSuchFunction(ref name)) if name == "no_such" => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+        match call("neg", vec![field.clone()]).apply(functions) {
+            Err(QueryError::UnboundField(ref qf)) if qf.field == "id" => {},
+            other => panic!("Expected UnboundField, got {:?}", other),
+        }
+
+        let unknown = Query::Filter(
+            Condition::FunctionCall(call("not", vec![Argument::FunctionCall(call("no_such", vec![field.clone()]))])),
+            Box::new(Query::Table("Companies".to_owned())),
+        );
+        match db.query(unknown) {
+            Err(QueryError::NoSuchFunction(ref name)) if name == "no_such" => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+        let unbound = Query::FromFunctionCall(
+            TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N64, true)),
+            call("neg", vec![field]),
+        );
+        match db.query(unbound) {
+            Err(QueryError::UnboundField(ref qf)) if qf.field == "id" => {},
+            other => panic!("Expected UnboundField, got {:?}", other),
+        }
+        // Also when applying deltas
+        let mut db = db;
+        match db.apply(Delta::RemoveWhere("Companies".to_owned(), Condition::FunctionCall(call("no_such", vec![])))) {
+            Err(ApplyError::QueryError(QueryError::NoSuchFunction(_))) => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_table_functions() {
+        let db = SrimDB::new();
+        let call = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect());
+        let series = |values: Vec<Value>| db.query(Query::FromTableFunction(call("generate_series", values)));
+        let values = |result: QueryResult| result.rows().into_iter().map(|row| row.value(0).clone()).collect::<Vec<_>>();
+
+        assert_eq!(values(series(vec![Value::Signed(1), Value::Unsigned(3)]).unwrap()), vec![Value::Signed(1), Value::Signed(2), Value::Signed(3)]);
+        assert_eq!(values(series(vec![Value::Signed(5), Value::Signed(0), Value::Signed(-2)]).unwrap()), vec![Value::Signed(5), Value::Signed(3), Value::Signed(1)]);
+        assert!(series(vec![Value::Signed(3), Value::Signed(1)]).unwrap().rows().is_empty());
+        assert!(series(vec![Value::Null, Value::Signed(1)]).unwrap().rows().is_empty());
+        match series(vec![Value::Signed(1), Value::Signed(3), Value::Signed(0)]) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+
+        // Table functions are leaves like tables
+        let even = db.query(Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::FunctionCall(FunctionCall::new("mod".to_owned(), vec![
+                    Argument::QueryField(QueryField::new("value".to_owned())),
+                    Argument::Value(Value::Signed(2)),
+                ])),
+                Argument::Value(Value::Signed(0)),
+            ])),
+            Box::new(Query::FromTableFunction(call("generate_series", vec![Value::Signed(1), Value::Signed(1000)]))),
+        )).unwrap();
+        assert_eq!(even.rows().len(), 500);
+
+        let lines = db.query(Query::FromTableFunction(call("split_text", vec![Value::Text("a\nbc\n".into()), Value::Text("\n".into())]))).unwrap();
+        assert_eq!(lines.field_names(), vec!["index".to_owned(), "value".to_owned()]);
+        assert_eq!(lines.rows(), vec![
+            Row::new(vec![Value::Unsigned(0), Value::Text("a".into())]),
+            Row::new(vec![Value::Unsigned(1), Value::Text("bc".into())]),
+            Row::new(vec![Value::Unsigned(2), Value::Text("".into())]),
+        ]);
+
+        // Huge series are stopped by the memory limit as they are generated
+        let limited = SrimDB::new().with_query_memory_limit(1 << 20);
+        match limited.query(Query::FromTableFunction(call("generate_series", vec![Value::Signed(0), Value::Signed(i128::max_value())]))) {
+            Err(QueryError::MemoryLimitExceeded) => {},
+            other => panic!("Expected MemoryLimitExceeded, got {:?}", other.map(|r| r.rows().len())),
+        }
+        match db.query(Query::FromTableFunction(call("no_such", vec![]))) {
+            Err(QueryError::NoSuchFunction(_)) => {},
+            other => panic!("Expected NoSuchFunction, got {:?}", other),
+        }
+        match db.query(Query::FromTableFunction(FunctionCall::new("split_text".to_owned(), vec![Argument::QueryField(QueryField::new("doc".to_owned()))]))) {
+            Err(QueryError::UnboundField(_)) => {},
+            other => panic!("Expected UnboundField, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_closure_functions() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let prefix = "id-".to_owned();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let step = 10;
+        let db = SrimDB::new()
+            .with_function("tag", NativeFunction::new(move |values: Vec<Value>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                match &values[0] {
+                    Value::Text(text) => Ok(Value::Text(format!("{}{}", prefix, text).into())),
+                    _ => Ok(Value::Null),
+                }
+            }).with_signature(Signature::exactly(1)))
+            .with_table_function("tens", TableFunction::new(&["n"], move |values: Vec<Value>| {
+                let count = match values[0] {
+                    Value::Unsigned(count) => count,
+                    _ => 0,
+                };
+                Ok(Box::new((0..count).map(move |i| Row::new(vec![Value::Unsigned(i * step)]))) as Box<Iterator<Item = Row>>)
+            }));
+
+        let call = |name: &str, value: Value| FunctionCall::new(name.to_owned(), vec![Argument::Value(value)]);
+        let functions = db.data_db.function_dict();
+        assert_eq!(call("tag", Value::Text("7".into())).apply(functions).unwrap(), Value::Text("id-7".into()));
+        assert_eq!(call("tag", Value::Signed(7)).apply(functions).unwrap(), Value::Null);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let result = db.query(Query::FromTableFunction(call("tens", Value::Unsigned(3)))).unwrap();
+        assert_eq!(result.rows(), vec![
+            Row::new(vec![Value::Unsigned(0)]),
+            Row::new(vec![Value::Unsigned(10)]),
+            Row::new(vec![Value::Unsigned(20)]),
+        ]);
+    }
+
+    #[test]
+    fn test_views() {
+        let mut db = setup_simple_company_employee_scenario().with_history(10);
+        let field = |table: &str, name: &str| QueryField::new(name.to_owned()).from_table(table.to_owned());
+        let eq = |qf: QueryField, value: Value| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(qf),
+            Argument::Value(value),
+        ]));
+        let local = Query::Filter(eq(field("Companies", "city"), Value::Text("City 3".into())), Box::new(Query::Table("Companies".to_owned())));
+        let names = Query::Project(vec![field("Local", "name")], Box::new(Query::Table("Local".to_owned())));
+        db.apply(Delta::CreateView("Local".to_owned(), local.clone())).unwrap();
+        db.apply(Delta::CreateView("LocalNames".to_owned(), names)).unwrap();
+
+        // Views are read like tables, with their fields qualified with the view name
+        assert_eq!(db.query(Query::Table("Local".to_owned())).unwrap().rows().len(), 10);
+        let result = db.query(Query::Filter(eq(field("LocalNames", "name"), Value::Text("Company 13".into())), Box::new(Query::Table("LocalNames".to_owned())))).unwrap();
+        assert_eq!(result.rows(), vec![Row::new(vec![Value::Text("Company 13".into())])]);
+        db.apply(Delta::AddRow("Companies".to_owned(), Row::new(vec![
+            Value::Unsigned(100),
+            Value::Text("Company 100".into()),
+            Value::Text("City 3".into()),
+        ]))).unwrap();
+        assert_eq!(db.query(Query::Table("LocalNames".to_owned())).unwrap().rows().len(), 11);
+
+        match db.apply(Delta::DropTable("Companies".to_owned())) {
+            Err(ApplyError::TableInUse(ref table, ref view)) if table == "Companies" && view == "Local" => {},
+            other => panic!("Expected TableInUse, got {:?}", other),
+        }
+        match db.apply(Delta::RenameTable("Companies".to_owned(), "Firms".to_owned())) {
+            Err(ApplyError::TableInUse(_, _)) => {},
+            other => panic!("Expected TableInUse, got {:?}", other),
+        }
+        match db.apply(Delta::DropView("Local".to_owned())) {
+            Err(ApplyError::TableInUse(ref table, ref view)) if table == "Local" && view == "LocalNames" => {},
+            other => panic!("Expected TableInUse, got {:?}", other),
+        }
+        match db.apply(Delta::CreateView("Employees".to_owned(), local.clone())) {
+            Err(ApplyError::TableExists(ref name)) if name == "Employees" => {},
+            other => panic!("Expected TableExists, got {:?}", other),
+        }
+        match db.apply(Delta::CreateView("Missing".to_owned(), Query::Table("Missing".to_owned()))) {
+            Err(ApplyError::NoSuchTable(ref name)) if name == "Missing" => {},
+            other => panic!("Expected NoSuchTable, got {:?}", other),
+        }
+
+        let create = Delta::CreateView("Local".to_owned(), local);
+        assert_eq!(Delta::from_bytes(&create.to_bytes()).unwrap().to_bytes(), create.to_bytes());
+        assert!(create.to_json().contains("\"type\":\"Filter\""));
+
+        // Dropping views in order frees the tables, and undoing restores them
+        db.apply(Delta::DropView("LocalNames".to_owned())).unwrap();
+        db.apply(Delta::DropView("Local".to_owned())).unwrap();
+        match db.query(Query::Table("Local".to_owned())) {
+            Err(QueryError::NoSuchTable(_)) => {},
+            other => panic!("Expected NoSuchTable, got {:?}", other),
+        }
+        db.undo(1).unwrap();
+        assert_eq!(db.query(Query::Table("Local".to_owned())).unwrap().rows().len(), 11);
+        db.apply(Delta::DropView("Local".to_owned())).unwrap();
+        db.apply(Delta::DropTable("Companies".to_owned())).unwrap();
+    }
+
+    #[test]
+    fn test_materialized_views() {
+        let mut db = setup_simple_company_employee_scenario();
+        let field = |table: &str, name: &str| QueryField::new(name.to_owned()).from_table(table.to_owned());
+        let city = |c: &str| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(field("Companies", "city")),
+            Argument::Value(Value::Text(c.into())),
+        ]));
+        let companies = || Box::new(Query::Table("Companies".to_owned()));
+        let local = Query::Union(
+            Box::new(Query::Project(vec![field("Companies", "name")], Box::new(Query::Filter(city("City 3"), companies())))),
+            Box::new(Query::Project(vec![field("Companies", "name")], Box::new(Query::Filter(city("City 4"), companies())))),
+        );
+        let cities = Query::Distinct(Box::new(Query::Project(vec![field("Companies", "city")], companies())));
+        db.apply(Delta::CreateMaterializedView("Local".to_owned(), local)).unwrap();
+        db.apply(Delta::CreateMaterializedView("Cities".to_owned(), cities)).unwrap();
+        let count = |db: &SrimDB, name: &str| db.query(Query::Table(name.to_owned())).unwrap().rows().len();
+        assert_eq!(count(&db, "Local"), 20);
+        assert_eq!(count(&db, "Cities"), 10);
+
+        // Unions of filters and projections follow inserts, updates and removals
+        db.apply(Delta::AddRow("Companies".to_owned(), Row::new(vec![
+            Value::Unsigned(100),
+            Value::Text("Company 100".into()),
+            Value::Text("City 10".into()),
+        ]))).unwrap();
+        db.apply(Delta::UpdateWhere(
+            "Companies".to_owned(),
+            city("City 10"),
+            vec![("city".to_owned(), Argument::Value(Value::Text("City 4".into())))],
+        )).unwrap();
+        db.apply(Delta::RemoveWhere("Companies".to_owned(), city("City 3"))).unwrap();
+        let result = db.query(Query::Table("Local".to_owned())).unwrap();
+        assert_eq!(result.rows().len(), 11);
+        assert_eq!(result.rows()[10], Row::new(vec![Value::Text("Company 100".into())]));
+        let fresh = db.query(Query::Project(vec![field("Companies", "name")], Box::new(Query::Filter(city("City 4"), companies())))).unwrap();
+        assert_eq!(result.rows(), fresh.rows());
+
+        // Other queries keep their rows until refreshed
+        assert_eq!(count(&db, "Cities"), 10);
+        db.apply(Delta::RefreshView("Cities".to_owned())).unwrap();
+        assert_eq!(count(&db, "Cities"), 9);
+
+        match db.apply(Delta::DropTable("Companies".to_owned())) {
+            Err(ApplyError::TableInUse(ref table, _)) if table == "Companies" => {},
+            other => panic!("Expected TableInUse, got {:?}", other),
+        }
+        match db.apply(Delta::RefreshView("Companies".to_owned())) {
+            Err(ApplyError::NoSuchView(ref name)) if name == "Companies" => {},
+            other => panic!("Expected NoSuchView, got {:?}", other),
+        }
+        db.apply(Delta::DropView("Local".to_owned())).unwrap();
+        db.apply(Delta::DropView("Cities".to_owned())).unwrap();
+        db.apply(Delta::DropTable("Companies".to_owned())).unwrap();
+    }
+
+    #[test]
+    fn test_temporary_tables() {
+        let mut db = SrimDB::new().with_journal();
+        let staging = Table::new("Staging", vec![TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N64, false))]).temporary();
+        db.apply(Delta::CreateTable(Table::new("Kept", vec![TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N64, false))]))).unwrap();
+        db.apply_all(vec![
+            Delta::CreateTable(staging.clone()),
+            Delta::AddRows("Staging".to_owned(), (0..5).map(|i| Row::new(vec![Value::Unsigned(i)])).collect()),
+        ]).unwrap();
+        db.apply(Delta::RemoveRow("Staging".to_owned(), Row::new(vec![Value::Unsigned(0)]))).unwrap();
+        db.apply(Delta::AddRow("Kept".to_owned(), Row::new(vec![Value::Unsigned(1)]))).unwrap();
+
+        // Temporary tables are used like others, but their changes aren't journaled
+        assert_eq!(db.query(Query::Table("Staging".to_owned())).unwrap().rows().len(), 4);
+        assert_eq!(db.revision(), 2);
+        assert_eq!(db.journal().iter().map(|e| e.revision).collect::<Vec<_>>(), vec![1, 2]);
+        let mut replica = SrimDB::new();
+        db.replay(0, &mut replica).unwrap();
+        assert_eq!(replica.query(Query::Table("Kept".to_owned())).unwrap().rows().len(), 1);
+        match replica.query(Query::Table("Staging".to_owned())) {
+            Err(QueryError::NoSuchTable(_)) => {},
+            other => panic!("Expected NoSuchTable, got {:?}", other),
+        }
+
+        let reference = TableField::new("staged".to_owned(), FieldKind::ForeignKey("Staging".to_owned())).nullable();
+        match db.apply(Delta::AddColumn("Kept".to_owned(), reference, Value::Null)) {
+            Err(ApplyError::TemporaryTable(ref name)) if name == "Staging" => {},
+            other => panic!("Expected TemporaryTable, got {:?}", other),
+        }
+        match db.apply(Delta::CreateView("Staged".to_owned(), Query::Table("Staging".to_owned()))) {
+            Err(ApplyError::TemporaryTable(ref name)) if name == "Staging" => {},
+            other => panic!("Expected TemporaryTable, got {:?}", other),
+        }
+
+        let create = Delta::CreateTable(staging);
+        match Delta::from_bytes(&create.to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert!(table.is_temporary()),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(create.to_json().contains("\"temporary\":true"));
+        db.apply(Delta::DropTable("Staging".to_owned())).unwrap();
+        assert_eq!(db.journal().len(), 2);
+    }
+
+    #[test]
+    fn test_system_catalog() {
+        let mut db = setup_simple_company_employee_scenario();
+        db.apply(Delta::CreateView("AllCompanies".to_owned(), Query::Table("Companies".to_owned()))).unwrap();
+        db.apply(Delta::CreateFunction("double".to_owned(), FunctionCall::new("mul".to_owned(), vec![
+            Argument::Parameter(0),
+            Argument::Value(Value::Signed(2)),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        let eq = |table: &str, field: &str, value: Value| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new(field.to_owned()).from_table(table.to_owned())),
+            Argument::Value(value),
+        ]));
+        let select = |table: &str, fields: &[&str], condition: Condition| db.query(Query::Project(
+            fields.iter().map(|f| QueryField::new((*f).to_owned())).collect(),
+            Box::new(Query::Filter(condition, Box::new(Query::Table(table.to_owned())))),
+        )).unwrap().rows();
+
+        assert_eq!(select(TABLES_TABLE, &["name", "kind", "rows"], eq(TABLES_TABLE, "temporary", Value::Boolean(false))), vec![
+            Row::new(vec![text("Companies"), text("table"), Value::Unsigned(100)]),
+            Row::new(vec![text("Employees"), text("table"), Value::Unsigned(500)]),
+            Row::new(vec![text("AllCompanies"), text("view"), Value::Null]),
+        ]);
+        assert_eq!(select(FIELDS_TABLE, &["name", "position", "kind"], eq(FIELDS_TABLE, "table", text("Companies"))), vec![
+            Row::new(vec![text("id"), Value::Unsigned(0), text("Integer(N64, false)")]),
+            Row::new(vec![text("name"), Value::Unsigned(1), text("Text")]),
+            Row::new(vec![text("city"), Value::Unsigned(2), text("Text")]),
+        ]);
+        assert_eq!(select(FUNCTIONS_TABLE, &["kind", "min_arity", "max_arity", "returns"], eq(FUNCTIONS_TABLE, "name", text("upper"))), vec![
+            Row::new(vec![text("native"), Value::Unsigned(1), Value::Unsigned(1), text("Text")]),
+        ]);
+        assert_eq!(select(FUNCTIONS_TABLE, &["kind", "min_arity", "max_arity"], eq(FUNCTIONS_TABLE, "name", text("double"))), vec![
+            Row::new(vec![text("composed"), Value::Unsigned(1), Value::Unsigned(1)]),
+        ]);
+        assert_eq!(select(FUNCTIONS_TABLE, &["kind", "max_arity"], eq(FUNCTIONS_TABLE, "name", text("generate_series"))), vec![
+            Row::new(vec![text("table"), Value::Unsigned(3)]),
+        ]);
+
+        // Catalog tables combine with others like any table
+        let keyed = db.query(Query::JoinOn(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new("table".to_owned()).from_table(FIELDS_TABLE.to_owned())),
+                Argument::QueryField(QueryField::new("name".to_owned()).from_table(TABLES_TABLE.to_owned())),
+            ])),
+            Box::new(Query::Table(FIELDS_TABLE.to_owned())),
+            Box::new(Query::Table(TABLES_TABLE.to_owned())),
+        )).unwrap();
+        assert_eq!(keyed.rows().len(), 6);
+    }
+
+    #[test]
+    fn test_procedures() {
+        let mut db = setup_simple_company_employee_scenario().with_history(10);
+        let city_is = |argument: Argument| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("city".to_owned())),
+            argument,
+        ]));
+        let relocate = vec![
+            ProcedureStep::Apply(Delta::UpdateWhere(
+                "Companies".to_owned(),
+                city_is(Argument::Parameter(0)),
+                vec![("city".to_owned(), Argument::Parameter(1))],
+            )),
+            ProcedureStep::Insert("Companies".to_owned(), vec![
+                ("id".to_owned(), Argument::Parameter(2)),
+                ("name".to_owned(), Argument::FunctionCall(FunctionCall::new("upper".to_owned(), vec![
+                    Argument::Value(Value::Text("new".into())),
+                ]))),
+                ("city".to_owned(), Argument::Parameter(1)),
+            ]),
+            ProcedureStep::Query(Query::Filter(city_is(Argument::Parameter(1)), Box::new(Query::Table("Companies".to_owned())))),
+        ];
+        let create = Delta::CreateProcedure("relocate".to_owned(), relocate);
+        db.apply(create.clone()).unwrap();
+        match Delta::from_bytes(&create.to_bytes()).unwrap() {
+            Delta::CreateProcedure(name, steps) => assert_eq!((name.as_str(), steps.len()), ("relocate", 3)),
+            other => panic!("Expected CreateProcedure, got {:?}", other),
+        }
+
+        // Later steps see the changes of earlier ones
+        let arguments = vec![Value::Text("City 1".into()), Value::Text("City 4".into()), Value::Unsigned(100)];
+        let results = db.call_procedure("relocate", arguments).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].rows().len(), 21);
+        assert!(results[0].rows().contains(&Row::new(vec![Value::Unsigned(100), Value::Text("NEW".into()), Value::Text("City 4".into())])));
+
+        // A failing step discards the changes of all steps
+        let arguments = vec![Value::Text("City 4".into()), Value::Text("City 5".into()), Value::Signed(-1)];
+        match db.call_procedure("relocate", arguments) {
+            Err(ApplyError::OutOfRange { ref field, .. }) if field == "id" => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 101);
+        match db.call_procedure("relocate", vec![Value::Null]) {
+            Err(ApplyError::QueryError(QueryError::NotEnoughArguments(3))) => {},
+            other => panic!("Expected NotEnoughArguments, got {:?}", other),
+        }
+
+        // A call is one step for undo
+        assert_eq!(db.undo(1).unwrap(), 1);
+        assert_eq!(db.query(Query::Filter(city_is(Argument::Value(Value::Text("City 1".into()))), Box::new(Query::Table("Companies".to_owned())))).unwrap().rows().len(), 10);
+
+        db.apply(Delta::DropProcedure("relocate".to_owned())).unwrap();
+        match db.call_procedure("relocate", Vec::new()) {
+            Err(ApplyError::NoSuchProcedure(ref name)) if name == "relocate" => {},
+            other => panic!("Expected NoSuchProcedure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_row_level_security() {
+        let mut db = setup_simple_company_employee_scenario();
+        let eq = |field: &str, argument: Argument| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new(field.to_owned())),
+            argument,
+        ]));
+        let create = Delta::CreatePolicy("Companies".to_owned(), eq("city", Argument::Parameter(0)));
+        db.apply(create.clone()).unwrap();
+        db.apply(Delta::CreateIndex("Companies".to_owned(), vec!["id".to_owned()], IndexKind::Hash)).unwrap();
+        db.apply(Delta::CreateView("AllCompanies".to_owned(), Query::Table("Companies".to_owned()))).unwrap();
+        let count = |db: &SrimDB, query: Query, city: &str| db.query_with(query, QueryOptions {
+            context: Some(Value::Text(city.into())),
+            ..QueryOptions::default()
+        }).unwrap().rows().len();
+
+        // Table scans, index lookups and views all see only the rows of the context
+        assert_eq!(count(&db, Query::Table("Companies".to_owned()), "City 3"), 10);
+        assert_eq!(count(&db, Query::Table("AllCompanies".to_owned()), "City 3"), 10);
+        let company_5 = Query::Filter(eq("id", Argument::Value(Value::Unsigned(5))), Box::new(Query::Table("Companies".to_owned())));
+        assert_eq!(count(&db, company_5.clone(), "City 3"), 0);
+        assert_eq!(count(&db, company_5.clone(), "City 5"), 1);
+        match db.query(Query::Table("Companies".to_owned())) {
+            Err(QueryError::NoContext(ref name)) if name == "Companies" => {},
+            other => panic!("Expected NoContext, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Employees".to_owned())).unwrap().rows().len(), 500);
+
+        match db.apply(Delta::CreateMaterializedView("Cached".to_owned(), Query::Table("AllCompanies".to_owned()))) {
+            Err(ApplyError::SecuredTable(ref name)) if name == "Companies" => {},
+            other => panic!("Expected SecuredTable, got {:?}", other),
+        }
+        match db.apply(Delta::ForceDropColumn("Companies".to_owned(), "city".to_owned())) {
+            Err(ApplyError::PolicyField(_, ref field)) if field == "city" => {},
+            other => panic!("Expected PolicyField, got {:?}", other),
+        }
+        match db.apply(create.clone()) {
+            Err(ApplyError::PolicyExists(_)) => {},
+            other => panic!("Expected PolicyExists, got {:?}", other),
+        }
+        match Delta::from_bytes(&create.to_bytes()).unwrap() {
+            Delta::CreatePolicy(name, condition) => assert_eq!((name, condition), ("Companies".to_owned(), eq("city", Argument::Parameter(0)))),
+            other => panic!("Expected CreatePolicy, got {:?}", other),
+        }
+
+        // The policy follows renamed fields
+        db.apply(Delta::RenameColumn("Companies".to_owned(), "city".to_owned(), "town".to_owned())).unwrap();
+        assert_eq!(count(&db, company_5, "City 5"), 1);
+
+        db.apply(Delta::DropPolicy("Companies".to_owned())).unwrap();
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 100);
+    }
+
+    #[test]
+    fn test_access_control() {
+        let mut db = setup_simple_company_employee_scenario();
+        db.apply(Delta::CreateView("AllCompanies".to_owned(), Query::Table("Companies".to_owned()))).unwrap();
+        let mut db = db.with_role(Role::new()
+            .grant("AllCompanies", Permission::Read)
+            .grant("Employees", Permission::Read)
+            .grant("Employees", Permission::Write));
+        let hire = Delta::AddRow("Employees".to_owned(), Row::new(vec![
+            Value::Unsigned(500),
+            Value::Text("Employee 500".into()),
+            Value::Text("Company 0".into()),
+        ]));
+
+        assert_eq!(db.query(Query::Table("AllCompanies".to_owned())).unwrap().rows().len(), 100);
+        match db.query(Query::Table("Companies".to_owned())) {
+            Err(QueryError::AccessDenied(ref name)) if name == "Companies" => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        match db.snapshot().query(Query::Table("Companies".to_owned())) {
+            Err(QueryError::AccessDenied(_)) => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        db.apply(hire.clone()).unwrap();
+        match db.apply(Delta::Truncate("Companies".to_owned())) {
+            Err(ApplyError::AccessDenied(ref name, Permission::Write)) if name == "Companies" => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        match db.apply(Delta::DropTable("Employees".to_owned())) {
+            Err(ApplyError::AccessDenied(ref name, Permission::Schema)) if name == "Employees" => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        match db.apply(Delta::CreateView("Copy".to_owned(), Query::Table("Companies".to_owned()))) {
+            Err(ApplyError::AccessDenied(ref name, Permission::Schema)) if name == "Copy" => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+
+        // Transactions check each change, and nothing is applied if any is denied
+        let mut transaction = db.begin();
+        transaction.apply(Delta::RemoveRow("Employees".to_owned(), Row::new(vec![
+            Value::Unsigned(500),
+            Value::Text("Employee 500".into()),
+            Value::Text("Company 0".into()),
+        ]))).unwrap();
+        match transaction.apply(Delta::Truncate("Companies".to_owned())) {
+            Err(ApplyError::AccessDenied(_, Permission::Write)) => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        match db.apply_all(vec![hire.clone(), Delta::Truncate("Companies".to_owned())]) {
+            Err(ApplyError::AccessDenied(_, Permission::Write)) => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Employees".to_owned())).unwrap().rows().len(), 501);
+
+        // Functions and procedures need the schema permission on all tables
+        let double = FunctionCall::new("mul".to_owned(), vec![Argument::Parameter(0), Argument::Value(Value::Signed(2))]);
+        match db.apply(Delta::CreateFunction("double".to_owned(), double.clone())) {
+            Err(ApplyError::AccessDenied(ref name, Permission::Schema)) if name == "double" => {},
+            other => panic!("Expected AccessDenied, got {:?}", other),
+        }
+        db.set_role(Some(Role::new().grant_all(Permission::Schema)));
+        db.apply(Delta::CreateFunction("double".to_owned(), double)).unwrap();
+        db.set_role(None);
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 100);
+    }
+
+    #[test]
+    fn test_table_quotas() {
+        let mut db = SrimDB::new();
+        let fields = || vec![TableField::new("message".to_owned(), FieldKind::Text)];
+        let row = |i: usize| Row::new(vec![Value::Text(format!("Message {}", i).into())]);
+        let log = Table::new("Log", fields()).with_quota(Quota { max_rows: Some(3), evict: true, ..Quota::default() });
+        db.apply(Delta::CreateTable(log.clone())).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Cache", fields()).with_quota(Quota {
+            max_bytes: Some(3 * row(0).approx_size_bytes()),
+            ..Quota::default()
+        }))).unwrap();
+
+        // Evicting tables keep the newest rows
+        for i in 0..4 {
+            db.apply(Delta::AddRow("Log".to_owned(), row(i))).unwrap();
+        }
+        let report = db.apply_reporting(Delta::AddRows("Log".to_owned(), vec![row(4), row(5)])).unwrap();
+        assert_eq!((report.inserted, report.removed), (2, 2));
+        assert_eq!(db.query(Query::Table("Log".to_owned())).unwrap().rows(), vec![row(3), row(4), row(5)]);
+
+        // Others reject changes over the limits
+        db.apply(Delta::AddRows("Cache".to_owned(), vec![row(0), row(1), row(2)])).unwrap();
+        match db.apply(Delta::AddRow("Cache".to_owned(), row(3))) {
+            Err(ApplyError::QuotaExceeded(ref name)) if name == "Cache" => {},
+            other => panic!("Expected QuotaExceeded, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Cache".to_owned())).unwrap().rows().len(), 3);
+
+        match Delta::from_bytes(&Delta::CreateTable(log.clone()).to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert_eq!(table, log),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(Delta::CreateTable(log).to_json().contains("\"quota\":{\"max_rows\":3,\"max_bytes\":null,\"evict\":true}"));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut db = SrimDB::new();
+        let hour = Duration::from_secs(3600);
+        let sessions = Table::new("Sessions", vec![
+            TableField::new("user".to_owned(), FieldKind::Text),
+            TableField::new("seen".to_owned(), FieldKind::Timestamp).nullable(),
+        ]).with_key_fields(vec!["user".to_owned()]).with_ttl("seen", hour);
+        db.apply(Delta::CreateTable(sessions.clone())).unwrap();
+        let now = temporal::now();
+        let row = |user: &str, seen: Value| Row::new(vec![Value::Text(user.into()), seen]);
+        let rows = vec![
+            row("expired", Value::Timestamp(now - 2 * 3600 * temporal::MICROS_PER_SECOND)),
+            row("recent", Value::Timestamp(now - 60 * temporal::MICROS_PER_SECOND)),
+            row("forever", Value::Null),
+        ];
+        db.apply(Delta::AddRows("Sessions".to_owned(), rows.clone())).unwrap();
+
+        // Expired rows are skipped until they are vacuumed
+        let all = || Query::Table("Sessions".to_owned());
+        assert_eq!(db.query(all()).unwrap().rows(), rows[1..].to_vec());
+        let anyone = Query::Filter(Condition::Value(Value::Boolean(true)), Box::new(all()));
+        assert_eq!(db.query(anyone).unwrap().rows(), rows[1..].to_vec());
+        assert_eq!(db.vacuum_expired().unwrap(), 1);
+        assert_eq!(db.vacuum_expired().unwrap(), 0);
+        assert_eq!(db.data_db.all_rows("Sessions".to_owned()).unwrap().len(), 2);
+
+        // Rows can only expire by timestamps
+        let invalid = Table::new("Invalid", vec![TableField::new("seen".to_owned(), FieldKind::Text)]).with_ttl("seen", hour);
+        match db.apply(Delta::CreateTable(invalid)) {
+            Err(ApplyError::InvalidSchema(ref name)) if name == "Invalid" => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+
+        match Delta::from_bytes(&Delta::CreateTable(sessions.clone()).to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert_eq!(table, sessions),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(Delta::CreateTable(sessions).to_json().contains("\"ttl\":{\"field\":\"seen\",\"seconds\":3600}"));
+    }
+
+    #[test]
+    fn test_partitioning() {
+        let mut db = SrimDB::new();
+        let fields = || vec![
+            TableField::new("day".to_owned(), FieldKind::Date),
+            TableField::new("reading".to_owned(), FieldKind::Integer(IntSize::N64, true)),
+        ];
+        let readings = Table::new("Readings", fields()).with_partitioning("day", Partitioning::Range(vec![Value::Date(10), Value::Date(20)]));
+        db.apply(Delta::CreateTable(readings.clone())).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Hashed", fields()).with_partitioning("reading", Partitioning::Hash(4)))).unwrap();
+        let row = |day: i32, reading: i128| Row::new(vec![Value::Date(day), Value::Signed(reading)]);
+        let rows = vec![row(5, 1), row(15, 2), row(25, 3), row(12, 4), row(20, 5)];
+        db.apply(Delta::AddRows("Readings".to_owned(), rows.clone())).unwrap();
+        db.apply(Delta::AddRows("Hashed".to_owned(), rows.clone())).unwrap();
+        let sizes = |db: &SrimDB, name: &str| db.partitions(name).iter().map(|p| p.rows).collect::<Vec<_>>();
+        let scans = |db: &SrimDB, name: &str| db.partitions(name).iter().map(|p| p.scans).collect::<Vec<_>>();
+        assert_eq!(sizes(&db, "Readings"), vec![1, 2, 2]);
+        assert_eq!(sizes(&db, "Hashed").iter().sum::<usize>(), 5);
+
+        // Filters on the field only read the partitions that can match
+        let filter = |name: &str, function: &str, field: &str, value: Value| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new(function.to_owned(), vec![
+                Argument::QueryField(QueryField::new(field.to_owned())),
+                Argument::Value(value),
+            ])),
+            Box::new(Query::Table(name.to_owned())),
+        );
+        assert_eq!(db.query(filter("Readings", "gte", "day", Value::Date(20))).unwrap().rows(), vec![row(25, 3), row(20, 5)]);
+        assert_eq!(scans(&db, "Readings"), vec![0, 0, 1]);
+        assert_eq!(db.query(filter("Readings", "lt", "day", Value::Date(13))).unwrap().rows(), vec![row(5, 1), row(12, 4)]);
+        assert_eq!(scans(&db, "Readings"), vec![1, 1, 1]);
+        assert_eq!(db.query(filter("Hashed", "strict_eq", "reading", Value::Signed(4))).unwrap().rows(), vec![row(12, 4)]);
+        assert_eq!(scans(&db, "Hashed").iter().sum::<u64>(), 1);
+
+        // Rows move between partitions as they change
+        db.apply(Delta::UpdateWhere("Readings".to_owned(), Condition::Value(Value::Boolean(true)), vec![
+            ("day".to_owned(), Argument::Value(Value::Date(1))),
+        ])).unwrap();
+        assert_eq!(sizes(&db, "Readings"), vec![5, 0, 0]);
+
+        let descending = Table::new("Invalid", fields()).with_partitioning("day", Partitioning::Range(vec![Value::Date(20), Value::Date(10)]));
+        match db.apply(Delta::CreateTable(descending)) {
+            Err(ApplyError::InvalidSchema(ref name)) if name == "Invalid" => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+
+        match Delta::from_bytes(&Delta::CreateTable(readings.clone()).to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert_eq!(table, readings),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(Delta::CreateTable(readings).to_json().contains("\"partitioning\":{\"field\":\"day\",\"by\":{\"Range\":["));
+    }
+
+    #[test]
+    fn test_generated_fields() {
+        let mut db = SrimDB::new();
+        let field = |name: &str| Argument::QueryField(QueryField::new(name.to_owned()));
+        let int = || FieldKind::Integer(IntSize::N64, true);
+        let items = Table::new("Items", vec![
+            TableField::new("price".to_owned(), int()),
+            TableField::new("quantity".to_owned(), int()),
+            TableField::new("total".to_owned(), int()).with_generated(FunctionCall::new("mul".to_owned(), vec![field("price"), field("quantity")])),
+        ]).with_key_fields(vec!["price".to_owned()]);
+        db.apply(Delta::CreateTable(items.clone())).unwrap();
+        let row = |values: [i128; 3]| Row::new(values.iter().map(|v| Value::Signed(*v)).collect());
+        let all = || Query::Table("Items".to_owned());
+
+        // Given values are replaced, and the fields are recomputed on updates
+        db.apply(Delta::AddRows("Items".to_owned(), vec![row([3, 2, 0]), row([5, 1, 99])])).unwrap();
+        db.apply(Delta::AddNamedRow("Items".to_owned(), vec![
+            ("price".to_owned(), Value::Signed(7)),
+            ("quantity".to_owned(), Value::Signed(3)),
+        ])).unwrap();
+        assert_eq!(db.query(all()).unwrap().rows(), vec![row([3, 2, 6]), row([5, 1, 5]), row([7, 3, 21])]);
+        db.apply(Delta::UpdateWhere("Items".to_owned(), Condition::Value(Value::Boolean(true)), vec![
+            ("quantity".to_owned(), Argument::Value(Value::Signed(10))),
+        ])).unwrap();
+        db.apply(Delta::RenameColumn("Items".to_owned(), "price".to_owned(), "cost".to_owned())).unwrap();
+        db.apply(Delta::Upsert("Items".to_owned(), row([5, 4, 0]))).unwrap();
+        assert_eq!(db.query(all()).unwrap().rows(), vec![row([3, 10, 30]), row([5, 4, 20]), row([7, 10, 70])]);
+
+        let invalid = Table::new("Invalid", vec![
+            TableField::new("total".to_owned(), int()).with_generated(FunctionCall::new("neg".to_owned(), vec![field("missing")])),
+        ]);
+        match db.apply(Delta::CreateTable(invalid)) {
+            Err(ApplyError::InvalidSchema(ref name)) if name == "Invalid" => {},
+            other => panic!("Expected InvalidSchema, got {:?}", other),
+        }
+
+        match Delta::from_bytes(&Delta::CreateTable(items.clone()).to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert_eq!(table, items),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(Delta::CreateTable(items).to_json().contains("\"generated\":{"));
+    }
+
+    #[test]
+    fn test_collation() {
+        let mut db = SrimDB::new();
+        let users = Table::new("Users", vec![
+            TableField::new("name".to_owned(), FieldKind::Text).with_collation(Collation::CaseInsensitive),
+            TableField::new("city".to_owned(), FieldKind::Text).with_collation(Collation::Dictionary),
+        ]).with_key_fields(vec!["name".to_owned()]).with_unique_key().with_index(IndexKind::BTree, vec!["city".to_owned()]);
+        db.apply(Delta::CreateTable(users.clone())).unwrap();
+        let row = |name: &str, city: &str| Row::new(vec![Value::Text(name.into()), Value::Text(city.into())]);
+        db.apply(Delta::AddRows("Users".to_owned(), vec![row("bob", "Zürich"), row("Carol", "zurich"), row("Alice", "Aarau")])).unwrap();
+        match db.apply(Delta::AddRow("Users".to_owned(), row("BOB", "Bern"))) {
+            Err(ApplyError::DuplicateKey(ref table)) if table == "Users" => {},
+            other => panic!("Expected DuplicateKey, got {:?}", other),
+        }
+
+        // Comparisons, lookups in the index, ordering and distinct rows agree
+        let filter = |field: &str, value: &str| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new(field.to_owned())),
+                Argument::Value(Value::Text(value.into())),
+            ])),
+            Box::new(Query::Table("Users".to_owned())),
+        );
+        assert_eq!(db.query(filter("name", "BOB")).unwrap().rows(), vec![row("bob", "Zürich")]);
+        assert_eq!(db.query(filter("city", "ZURICH")).unwrap().rows(), vec![row("bob", "Zürich"), row("Carol", "zurich")]);
+        let ordered = db.query(Query::OrderBy(QueryField::new("name".to_owned()), Box::new(Query::Table("Users".to_owned())))).unwrap();
+        assert_eq!(ordered.rows(), vec![row("Alice", "Aarau"), row("bob", "Zürich"), row("Carol", "zurich")]);
+        let cities = Query::Project(vec![QueryField::new("city".to_owned())], Box::new(Query::Table("Users".to_owned())));
+        assert_eq!(db.query(Query::Distinct(Box::new(cities))).unwrap().rows().len(), 2);
+
+        let functions = db.data_db.function_dict();
+        let collate = |text: &str, name: &str| FunctionCall::new("collate".to_owned(), vec![
+            Argument::Value(Value::Text(text.into())),
+            Argument::Value(Value::Text(name.into())),
+        ]).apply(functions);
+        assert_eq!(collate("Ärger", "dictionary").unwrap(), Value::Text("arger".into()));
+        match collate("Ärger", "swedish") {
+            Err(QueryError::NoSuchCollation(ref name)) if name == "swedish" => {},
+            other => panic!("Expected NoSuchCollation, got {:?}", other),
+        }
+
+        match Delta::from_bytes(&Delta::CreateTable(users.clone()).to_bytes()).unwrap() {
+            Delta::CreateTable(table) => assert_eq!(table, users),
+            other => panic!("Expected CreateTable, got {:?}", other),
+        }
+        assert!(Delta::CreateTable(users).to_json().contains("\"collation\":\"case_insensitive\""));
+    }
+
+    #[test]
+    fn test_real_equality() {
+        use std::collections::HashSet;
+        use std::f64::NAN;
+
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Reals", vec![
+            TableField::new("r".to_owned(), FieldKind::Real).nullable(),
+        ]).with_index(IndexKind::BTree, vec!["r".to_owned()]))).unwrap();
+        let row = |r: Value| Row::new(vec![r]);
+        let values = vec![Value::Real(NAN), Value::Null, Value::Real(-0.0), Value::Real(1.0), Value::Real(-NAN), Value::Real(0.0)];
+        db.apply(Delta::AddRows("Reals".to_owned(), values.iter().cloned().map(row).collect())).unwrap();
+
+        // NaN equals itself and -0 equals +0, in sets of values and rows alike
+        let set: HashSet<Value> = values.iter().cloned().collect();
+        assert_eq!(set.len(), 4);
+        let distinct = db.query(Query::Distinct(Box::new(Query::Table("Reals".to_owned())))).unwrap();
+        assert_eq!(distinct.rows(), vec![row(Value::Real(NAN)), row(Value::Null), row(Value::Real(-0.0)), row(Value::Real(1.0))]);
+        let both = Query::Intersection(Box::new(Query::Table("Reals".to_owned())), Box::new(Query::FromValue(TableField::new("r".to_owned(), FieldKind::Real), Value::Real(NAN))));
+        assert_eq!(db.query(both).unwrap().rows().len(), 2);
+
+        // NaN sorts after all other reals, with or without the index
+        let ordered = vec![row(Value::Real(-0.0)), row(Value::Real(0.0)), row(Value::Real(1.0)), row(Value::Real(NAN)), row(Value::Real(-NAN)), row(Value::Null)];
+        let order_by = |source: Query| Query::OrderBy(QueryField::new("r".to_owned()), Box::new(source));
+        assert_eq!(db.query(order_by(Query::Table("Reals".to_owned()))).unwrap().rows(), ordered);
+        let unindexed = Query::Filter(Condition::Value(Value::Boolean(true)), Box::new(Query::Table("Reals".to_owned())));
+        assert_eq!(db.query(order_by(unindexed)).unwrap().rows(), ordered);
+        let greater = Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("gt".to_owned(), vec![
+                Argument::QueryField(QueryField::new("r".to_owned())),
+                Argument::Value(Value::Real(0.5)),
+            ])),
+            Box::new(Query::Table("Reals".to_owned())),
+        );
+        assert_eq!(db.query(greater).unwrap().rows().len(), 3);
+    }
+
+    #[test]
+    fn test_checked_casts() {
+        assert_eq!(Value::Unsigned(7).cast_to(ValueKind::Signed).unwrap(), Value::Signed(7));
+        assert_eq!(Value::Real(-3.0).cast_to(ValueKind::Signed).unwrap(), Value::Signed(-3));
+        assert_eq!(Value::Decimal(400, 2).cast_to(ValueKind::Unsigned).unwrap(), Value::Unsigned(4));
+        let overflowing = vec![
+            (Value::Unsigned(u128::max_value()), ValueKind::Signed),
+            (Value::Signed(-1), ValueKind::Unsigned),
+            (Value::Real(2.5), ValueKind::Signed),
+            (Value::Real(1e40), ValueKind::Unsigned),
+            (Value::Real(::std::f64::NAN), ValueKind::Signed),
+            (Value::Decimal(-259, 2), ValueKind::Signed),
+        ];
+        for (value, kind) in overflowing {
+            match value.cast_to(kind) {
+                Err(QueryError::CastOverflow(k)) if k == kind => {},
+                other => panic!("Expected CastOverflow, got {:?}", other),
+            }
+        }
+
+        // Only when asked for
+        assert_eq!(Value::Unsigned(u128::max_value()).cast_to_in(ValueKind::Signed, CastMode::Lossy).unwrap(), Value::Signed(-1));
+        assert_eq!(Value::Real(1e40).cast_to_in(ValueKind::Unsigned, CastMode::Lossy).unwrap(), Value::Unsigned(u128::max_value()));
+        let field = FieldKind::Integer(IntSize::N32, true);
+        assert_eq!(Value::Real(-2.7).cast_to_field_kind_in(field.clone(), CastMode::Lossy).unwrap(), Value::Signed(-2));
+        assert_eq!(Value::Real(1e12).cast_to_field_kind_in(field.clone(), CastMode::Lossy).unwrap(), Value::Signed(i32::max_value() as i128));
+        assert_eq!(Value::Unsigned(1 << 32).cast_to_field_kind_in(field.clone(), CastMode::Lossy).unwrap(), Value::Signed(0));
+        assert_eq!(Value::Signed(-1).cast_to_field_kind_in(FieldKind::Integer(IntSize::N8, false), CastMode::Lossy).unwrap(), Value::Unsigned(255));
+        match Value::Unsigned(1 << 32).cast_to_field_kind(field.clone()) {
+            Err(QueryError::CastOverflow(ValueKind::Signed)) => {},
+            other => panic!("Expected CastOverflow, got {:?}", other),
+        }
+
+        // Rows are checked the same way, and mixed integers compare exactly
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Counts", vec![TableField::new("n".to_owned(), field)]))).unwrap();
+        match db.apply(Delta::AddRow("Counts".to_owned(), Row::new(vec![Value::Real(0.5)]))) {
+            Err(ApplyError::OutOfRange { ref table, ref field }) if table == "Counts" && field == "n" => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_eq!(Value::Unsigned(u128::max_value()).compare(&Value::Signed(-1)).unwrap(), Some(::std::cmp::Ordering::Greater));
+        match Value::Unsigned(u128::max_value()).binop_add(Value::Signed(-1)) {
+            Err(QueryError::CastOverflow(ValueKind::Signed)) => {},
+            other => panic!("Expected CastOverflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_operation_coercion() {
+        let mut db = SrimDB::new();
+        let amount = |signed| vec![TableField::new("amount".to_owned(), FieldKind::Integer(IntSize::N32, signed))];
+        db.apply(Delta::CreateTable(Table::new("Credits", amount(false)))).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Debits", amount(true)))).unwrap();
+        db.apply(Delta::AddRows("Credits".to_owned(), vec![Row::new(vec![Value::Unsigned(5)]), Row::new(vec![Value::Unsigned(7)])])).unwrap();
+        db.apply(Delta::AddRows("Debits".to_owned(), vec![Row::new(vec![Value::Signed(-3)]), Row::new(vec![Value::Signed(5)])])).unwrap();
+
+        let table = |name: &str| Box::new(Query::Table(name.to_owned()));
+        let amounts = |result: QueryResult| -> Vec<Value> { result.rows().iter().map(|row| row.value(0).clone()).collect() };
+        let signed = |values: &[i128]| -> Vec<Value> { values.iter().map(|v| Value::Signed(*v)).collect() };
+        assert_eq!(amounts(db.query(Query::Union(table("Credits"), table("Debits"))).unwrap()), signed(&[5, 7, -3, 5]));
+        assert_eq!(amounts(db.query(Query::Intersection(table("Credits"), table("Debits"))).unwrap()), signed(&[5]));
+        assert_eq!(amounts(db.query(Query::Difference(table("Credits"), table("Debits"))).unwrap()), signed(&[7]));
+
+        // Materialized unions stay coerced as their tables change
+        db.apply(Delta::CreateMaterializedView("Ledger".to_owned(), Query::Union(table("Credits"), table("Debits")))).unwrap();
+        db.apply(Delta::AddRow("Credits".to_owned(), Row::new(vec![Value::Unsigned(9)]))).unwrap();
+        assert_eq!(amounts(db.query(Query::Table("Ledger".to_owned())).unwrap()), signed(&[5, 7, 9, -3, 5]));
+
+        let label = Query::FromValue(TableField::new("amount".to_owned(), FieldKind::Text), Value::Text("none".into()));
+        match db.query(Query::Union(table("Credits"), Box::new(label))) {
+            Err(QueryError::IncompatibleColumns(ref field, ValueKind::Unsigned, ValueKind::Text)) if field == "amount" => {},
+            other => panic!("Expected IncompatibleColumns, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_qualified_projection() {
+        let db = setup_simple_company_employee_scenario();
+        let field = |table: &str, name: &str| QueryField::new(name.to_owned()).from_table(table.to_owned());
+        let companies = Query::Project(
+            vec![QueryField::new("name".to_owned()), QueryField::new("city".to_owned())],
+            Box::new(Query::Table("Companies".to_owned())),
+        );
+        let joined = Query::JoinOn(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(field("Companies", "name")), Argument::QueryField(field("Employees", "company")),
+            ])),
+            Box::new(companies),
+            Box::new(Query::Table("Employees".to_owned())),
+        );
+        let names = Query::Project(vec![field("Companies", "name"), field("Employees", "name")], Box::new(joined));
+        let result = db.query(names.clone()).unwrap();
+        assert_eq!(result.query_fields(), &[field("Companies", "name"), field("Employees", "name")][..]);
+        assert_eq!(result.rows()[0].values(), vec![Value::Text("Company 0".into()), Value::Text("Person 0".into())]);
+
+        // Renames keep the table unless given another one
+        let renamed = Query::Rename(field("Employees", "name"), QueryField::new("person".to_owned()), Box::new(names));
+        let renamed = Query::Rename(field("Companies", "name"), field("Employers", "title"), Box::new(renamed));
+        let result = db.query(renamed).unwrap();
+        assert_eq!(result.query_fields(), &[field("Employers", "title"), field("Employees", "person")][..]);
+    }
+
+    #[test]
+    fn test_result_schema() {
+        let mut db = setup_simple_company_employee_scenario();
+        let kinds = |result: QueryResult| -> Vec<(FieldName, FieldKind, bool)> {
+            result.fields().into_iter().map(|f| (f.name(), f.kind(), f.is_nullable())).collect()
+        };
+        let kind = |name: &str, kind: FieldKind, nullable: bool| (name.to_owned(), kind, nullable);
+        let table = |name: &str| Box::new(Query::Table(name.to_owned()));
+
+        let names = Query::Project(vec![QueryField::new("id".to_owned()), QueryField::new("name".to_owned())], table("Companies"));
+        assert_eq!(kinds(db.query(names.clone()).unwrap()), vec![
+            kind("id", FieldKind::Integer(IntSize::N64, false), false),
+            kind("name", FieldKind::Text, false),
+        ]);
+
+        // Set operations widen kinds to hold the values of both sides
+        let offset = Query::Project(vec![QueryField::new("id".to_owned()), QueryField::new("name".to_owned())], Box::new(Query::JoinOn(
+            Condition::Value(Value::Boolean(true)),
+            Box::new(Query::FromValue(TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N64, true)), Value::Signed(-1))),
+            Box::new(Query::FromValue(TableField::new("name".to_owned(), FieldKind::Text).nullable(), Value::Null)),
+        )));
+        let both = db.query(Query::Union(Box::new(names), Box::new(offset))).unwrap();
+        assert_eq!(both.rows()[0].value(0), &Value::Signed(0));
+        assert_eq!(kinds(both), vec![
+            kind("id", FieldKind::Integer(IntSize::N128, true), false),
+            kind("name", FieldKind::Text, true),
+        ]);
+
+        // Kinds of system tables are inferred from their values, and unknown ones are nullable blobs
+        let system = db.query(Query::Project(
+            vec![QueryField::new("position".to_owned()), QueryField::new("nullable".to_owned()), QueryField::new("default".to_owned())],
+            Box::new(Query::Table(FIELDS_TABLE.to_owned())),
+        )).unwrap();
+        assert_eq!(kinds(system), vec![
+            kind("position", FieldKind::Integer(IntSize::N128, false), false),
+            kind("nullable", FieldKind::Boolean, false),
+            kind("default", FieldKind::Blob, true),
+        ]);
+        assert_eq!(kinds(db.query(Query::Empty(vec!["x".to_owned()])).unwrap()), vec![kind("x", FieldKind::Blob, true)]);
+
+        // Booleans can also be stored
+        db.apply(Delta::CreateTable(Table::new("Flags", vec![TableField::new("on".to_owned(), FieldKind::Boolean)]))).unwrap();
+        db.apply(Delta::AddRow("Flags".to_owned(), Row::new(vec![Value::Boolean(true)]))).unwrap();
+        let flags = db.query(Query::Table("Flags".to_owned())).unwrap();
+        assert_eq!(flags.rows(), vec![Row::new(vec![Value::Boolean(true)])]);
+        assert_eq!(kinds(flags), vec![kind("on", FieldKind::Boolean, false)]);
+    }
+
+    #[test]
+    fn test_typed_getters() {
+        let db = setup_simple_company_employee_scenario();
+        let result = db.query(Query::Table("Companies".to_owned())).unwrap();
+        assert_eq!(result.get::<u64>(3, "id").unwrap(), 3);
+        assert_eq!(result.get::<i64>(3, "id").unwrap(), 3);
+        assert_eq!(result.get::<String>(3, "name").unwrap(), "Company 3");
+        assert_eq!(result.rows()[3].get::<String>(2).unwrap(), "City 3");
+        match result.get::<String>(0, "ceo") {
+            Err(QueryError::NoSuchField(ref qf)) if qf.field == "ceo" => {},
+            other => panic!("Expected NoSuchField, got {:?}", other),
+        }
+
+        let row = Row::new(vec![Value::Real(0.5), Value::Boolean(true), Value::Blob(vec![1, 2]), Value::Signed(-1), Value::Null]);
+        assert_eq!(row.get::<f64>(0).unwrap(), 0.5);
+        assert_eq!(row.get::<bool>(1).unwrap(), true);
+        assert_eq!(row.get::<Vec<u8>>(2).unwrap(), vec![1, 2]);
+        assert_eq!(row.get::<i64>(3).unwrap(), -1);
+        for result in vec![row.get::<u64>(3), row.get::<u64>(5), result.get::<u64>(100, "id")] {
+            match result {
+                Err(QueryError::OutOfRange) => {},
+                other => panic!("Expected OutOfRange, got {:?}", other),
+            }
+        }
+        for result in vec![row.get::<String>(0).map(|_| ()), row.get::<bool>(4).map(|_| ())] {
+            match result {
+                Err(QueryError::IncompatibleTypes) => {},
+                other => panic!("Expected IncompatibleTypes, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_math_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("sqrt", vec![Value::Unsigned(16)]).unwrap(), Value::Real(4.0));
+        assert_eq!(apply("floor", vec![Value::Real(-1.5)]).unwrap(), Value::Real(-2.0));
+        assert_eq!(apply("ceil", vec![Value::Decimal(-15, 1)]).unwrap(), Value::Real(-1.0));
+        assert_eq!(apply("round", vec![Value::Real(2.5)]).unwrap(), Value::Real(3.0));
+        assert_eq!(apply("ln", vec![Value::Signed(1)]).unwrap(), Value::Real(0.0));
+        assert_eq!(apply("exp", vec![Value::Unsigned(0)]).unwrap(), Value::Real(1.0));
+        assert_eq!(apply("sqrt", vec![Value::Null]).unwrap(), Value::Null);
+        for value in vec![Value::Text("4".into()), Value::Blob(vec![4])] {
+            match apply("sqrt", vec![value]) {
+                Err(QueryError::InvalidArgument(_, 0, _)) => {},
+                other => panic!("Expected InvalidArgument, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_modes() {
+        let call = |db: &SrimDB, name: &str, values: Vec<Value>| {
+            FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(db.data_db.function_dict())
+        };
+        let max = || Value::Unsigned(u128::max_value());
+        let db = SrimDB::new();
+        assert_eq!(call(&db, "add", vec![max(), Value::Unsigned(2)]).unwrap(), max());
+        assert_eq!(call(&db, "sub", vec![Value::Unsigned(1), Value::Unsigned(2)]).unwrap(), Value::Unsigned(0));
+
+        let db = SrimDB::new().with_arithmetic_mode(ArithmeticMode::Wrapping);
+        assert_eq!(call(&db, "add", vec![max(), Value::Unsigned(2)]).unwrap(), Value::Unsigned(1));
+        assert_eq!(call(&db, "neg", vec![Value::Signed(i128::min_value())]).unwrap(), Value::Signed(i128::min_value()));
+
+        let mut db = SrimDB::new().with_arithmetic_mode(ArithmeticMode::Checked).with_journal();
+        match call(&db, "mul", vec![Value::Signed(i128::max_value()), Value::Signed(2)]) {
+            Err(QueryError::OutOfRange) => {},
+            other => panic!("Expected OutOfRange, got {:?}", other),
+        }
+        assert_eq!(call(&db, "pow", vec![Value::Signed(-2), Value::Unsigned(3)]).unwrap(), Value::Signed(-8));
+
+        // The mode also applies to past states
+        db.apply(Delta::CreateTable(Table::new("Totals", vec![
+            TableField::new("total".to_owned(), FieldKind::Integer(IntSize::N128, false)),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Totals".to_owned(), Row::new(vec![max()]))).unwrap();
+        let overflowing = Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("gt".to_owned(), vec![
+                Argument::FunctionCall(FunctionCall::new("add".to_owned(), vec![
+                    Argument::QueryField(QueryField::new("total".to_owned())), Argument::Value(Value::Unsigned(1)),
+                ])),
+                Argument::Value(Value::Unsigned(0)),
+            ])),
+            Box::new(Query::Table("Totals".to_owned())),
+        );
+        assert!(db.query(overflowing.clone()).is_err());
+        db.apply(Delta::AddRow("Totals".to_owned(), Row::new(vec![Value::Unsigned(0)]))).unwrap();
+        assert!(db.query_at(2, overflowing).is_err());
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let db = SrimDB::new();
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let text = |t: &str| Value::Text(t.into());
+        assert_eq!(apply("upper", vec![text("Straße")]).unwrap(), text("STRASSE"));
+        assert_eq!(apply("lower", vec![text("ÅSA")]).unwrap(), text("åsa"));
+        assert_eq!(apply("length", vec![text("héllo")]).unwrap(), Value::Unsigned(5));
+        assert_eq!(apply("substr", vec![text("héllo"), Value::Unsigned(2), Value::Unsigned(3)]).unwrap(), text("éll"));
+        assert_eq!(apply("substr", vec![text("héllo"), Value::Unsigned(4)]).unwrap(), text("lo"));
+        assert_eq!(apply("substr", vec![text("héllo"), Value::Signed(-1), Value::Unsigned(3)]).unwrap(), text("h"));
+        assert_eq!(apply("trim", vec![text("  a b \n")]).unwrap(), text("a b"));
+        assert_eq!(apply("replace", vec![text("a-b-c"), text("-"), text("+")]).unwrap(), text("a+b+c"));
+        assert_eq!(apply("concat", vec![text("a"), text("b"), text("c")]).unwrap(), text("abc"));
+        assert_eq!(apply("concat", vec![text("a"), Value::Null]).unwrap(), Value::Null);
+        assert!(apply("substr", vec![text("a"), Value::Unsigned(1), Value::Signed(-1)]).is_err());
+        assert!(apply("upper", vec![Value::Signed(1)]).is_err());
+    }
+
+    #[test]
+    fn test_pattern_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Files", vec![TableField::new("path".to_owned(), FieldKind::Text)]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        db.apply(Delta::AddRows("Files".to_owned(), ["src/lib.rs", "src/query.rs", "README.md", "src_old/x.rs", "src%/y.rs"].iter()
+            .map(|path| Row::new(vec![text(path)])).collect())).unwrap();
+
+        let like = |p: &str| Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("like".to_owned(), vec![
+                Argument::QueryField(QueryField::new("path".to_owned())), Argument::Value(text(p)),
+            ])),
+            Box::new(Query::Table("Files".to_owned())),
+        );
+        let queries = || vec![like("src/%"), like("src_%"), like("src\\%%"), like("%.md"), like("src/___.rs")];
+        let paths = |db: &SrimDB| -> Vec<Vec<Value>> {
+            queries().into_iter().map(|q| db.query(q).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()).collect()
+        };
+        let scanned = paths(&db);
+        assert_eq!(scanned, vec![
+            vec![text("src/lib.rs"), text("src/query.rs")],
+            vec![text("src/lib.rs"), text("src/query.rs"), text("src_old/x.rs"), text("src%/y.rs")],
+            vec![text("src%/y.rs")],
+            vec![text("README.md")],
+            vec![text("src/lib.rs")],
+        ]);
+        // Patterns with a literal prefix are answered with an ordered index
+        db.apply(Delta::CreateIndex("Files".to_owned(), vec!["path".to_owned()], IndexKind::BTree)).unwrap();
+        assert_eq!(paths(&db), scanned);
+        assert_eq!(db.indexes()[0].uses, 4);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        assert_eq!(apply("glob", vec![text("photo7.JPG"), text("*[0-9].[Jj]??")]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("glob", vec![text("photo.jpg"), text("[!p]*")]).unwrap(), Value::Boolean(false));
+        assert_eq!(apply("like", vec![text("aXbXc"), text("a%b%c")]).unwrap(), Value::Boolean(true));
+        assert_eq!(apply("like", vec![Value::Null, text("%")]).unwrap(), Value::Null);
+        assert_eq!(pattern::prefix_end("ab\u{10FFFF}"), Some("ac".to_owned()));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn test_regex_functions() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Logs", vec![TableField::new("line".to_owned(), FieldKind::Text)]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        db.apply(Delta::AddRows("Logs".to_owned(), vec![
+            Row::new(vec![text("GET /index.html 200")]), Row::new(vec![text("POST /login 403")]),
+        ])).unwrap();
+        let failed = Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("regex_match".to_owned(), vec![
+                Argument::QueryField(QueryField::new("line".to_owned())), Argument::Value(text(r" [45]\d\d$")),
+            ])),
+            Box::new(Query::Table("Logs".to_owned())),
+        );
+        assert_eq!(db.query(failed).unwrap().rows().len(), 1);
+
+        let functions = db.data_db.function_dict();
+        let apply = |name: &str, values: Vec<Value>| FunctionCall::new(name.to_owned(), values.into_iter().map(Argument::Value).collect()).apply(functions);
+        let request = r"^(?P<method>[A-Z]+) (\S+)";
+        assert_eq!(apply("regex_extract", vec![text("GET /a 200"), text(request), Value::Unsigned(2)]).unwrap(), text("/a"));
+        assert_eq!(apply("regex_extract", vec![text("GET /a 200"), text(request), text("method")]).unwrap(), text("GET"));
+        assert_eq!(apply("regex_extract", vec![text("get /a"), text(request)]).unwrap(), Value::Null);
+        match apply("regex_match", vec![text("a"), text("(")]) {
+            Err(QueryError::InvalidPattern(_)) => {},
+            other => panic!("Expected InvalidPattern, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unique_indexes() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Users", vec![
+            TableField::new("name".to_owned(), FieldKind::Text),
+            TableField::new("email".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let user = |name: &str, email: Value| Row::new(vec![Value::Text(name.into()), email]);
+        let email = |e: &str| Value::Text(e.into());
+        db.apply(Delta::AddRows("Users".to_owned(), vec![
+            user("alice", email("a@example.com")), user("bob", email("b@example.com")), user("eve", email("a@example.com")),
+        ])).unwrap();
+
+        let create = Delta::CreateIndex("Users".to_owned(), vec!["email".to_owned()], IndexKind::Unique);
+        let expect_duplicate = |result: Result<usize, ApplyError>| match result {
+            Err(ApplyError::DuplicateIndexKey(ref table, ref fields)) if table == "Users" && fields == &["email".to_owned()] => {},
+            other => panic!("Expected DuplicateIndexKey, got {:?}", other),
+        };
+        expect_duplicate(db.apply(create.clone()));
+        assert!(db.data_db.table("Users".to_owned()).unwrap().indexes().is_empty());
+
+        db.apply(Delta::RemoveRow("Users".to_owned(), user("eve", email("a@example.com")))).unwrap();
+        db.apply(create).unwrap();
+        expect_duplicate(db.apply(Delta::AddRow("Users".to_owned(), user("eve", email("b@example.com")))));
+        expect_duplicate(db.apply(Delta::AddRows("Users".to_owned(), vec![
+            user("carol", email("c@example.com")), user("dave", email("c@example.com")),
+        ])));
+        expect_duplicate(db.apply(Delta::UpdateRow(
+            "Users".to_owned(), user("bob", email("b@example.com")), RowUpdate::Replace(user("bob", email("a@example.com"))),
+        )));
+
+        // Nulls are never equal
+        db.apply(Delta::AddRow("Users".to_owned(), user("carol", Value::Null))).unwrap();
+        db.apply(Delta::AddRow("Users".to_owned(), user("dave", Value::Null))).unwrap();
+
+        assert!(db.data_db.index_lookup(&"Users".to_owned(), &[1], &[email("b@example.com")]).is_some());
+        let with_email = |e: &str| db.query(Query::Filter(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new("email".to_owned())), Argument::Value(email(e)),
+            ])),
+            Box::new(Query::Table("Users".to_owned())),
+        )).unwrap().rows();
+        assert_eq!(with_email("b@example.com"), vec![user("bob", email("b@example.com"))]);
+        assert_eq!(with_email("c@example.com"), vec![]);
+    }
+
+    #[test]
+    fn test_composite_indexes() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Offices", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("company".to_owned(), FieldKind::Text),
+            TableField::new("city".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        let office = |id: u128, company: &str, city: Value| Row::new(vec![Value::Unsigned(id), text(company), city]);
+        db.apply(Delta::AddRows("Offices".to_owned(), vec![
+            office(1, "Initech", text("Austin")), office(2, "Acme", text("Oslo")), office(3, "Initech", Value::Null),
+            office(4, "Acme", text("Austin")), office(5, "Initech", text("Oslo")), office(6, "Acme", text("Austin")),
+        ])).unwrap();
+
+        let eq = |field: &str, value: &str| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new(field.to_owned())), Argument::Value(text(value)),
+        ]));
+        let offices = || Box::new(Query::Table("Offices".to_owned()));
+        let queries = || vec![
+            Query::Filter(eq("city", "Austin"), Box::new(Query::Filter(eq("company", "Acme"), offices()))),
+            Query::Filter(eq("company", "Initech"), offices()),
+            Query::Filter(Condition::FunctionCall(FunctionCall::new("lt".to_owned(), vec![
+                Argument::QueryField(QueryField::new("company".to_owned())), Argument::Value(text("B")),
+            ])), offices()),
+            Query::OrderBy(QueryField::new("company".to_owned()), offices()),
+        ];
+        let ids = |db: &SrimDB| -> Vec<Vec<Value>> {
+            queries().into_iter().map(|q| db.query(q).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()).collect()
+        };
+        let scanned = ids(&db);
+        let unsigned = |ids: &[u128]| -> Vec<Value> { ids.iter().map(|i| Value::Unsigned(*i)).collect() };
+        assert_eq!(scanned[0], unsigned(&[4, 6]));
+        assert_eq!(scanned[1], unsigned(&[1, 3, 5]));
+        assert_eq!(scanned[3], unsigned(&[2, 4, 6, 1, 3, 5]));
+
+        let fields = vec!["company".to_owned(), "city".to_owned()];
+        db.apply(Delta::CreateIndex("Offices".to_owned(), fields.clone(), IndexKind::BTree)).unwrap();
+        // Any prefix of the fields, in any order
+        assert!(db.data_db.index_lookup(&"Offices".to_owned(), &[1], &[text("Initech")]).is_some());
+        assert!(db.data_db.index_lookup(&"Offices".to_owned(), &[2, 1], &[text("Austin"), text("Acme")]).is_some());
+        assert!(db.data_db.index_lookup(&"Offices".to_owned(), &[2], &[text("Austin")]).is_none());
+        assert_eq!(ids(&db), scanned);
+
+        db.apply(Delta::CreateIndex("Offices".to_owned(), fields, IndexKind::Hash)).unwrap();
+        assert_eq!(
+            db.data_db.index_lookup(&"Offices".to_owned(), &[1, 2], &[text("Acme"), text("Austin")]).unwrap(),
+            db.query(queries().remove(0)).unwrap().rows().into_iter().map(Arc::new).collect::<Vec<_>>(),
+        );
+        assert_eq!(ids(&db), scanned);
+    }
+
+    #[test]
+    fn test_expression_indexes() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Scores", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("score".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+        ]))).unwrap();
+        db.apply(Delta::AddRows("Scores".to_owned(), (1..7).map(|i| Row::new(vec![Value::Unsigned(i), Value::Unsigned(i % 3)])).collect())).unwrap();
+
+        let plus_one = |field: QueryField| FunctionCall::new("add".to_owned(), vec![
+            Argument::QueryField(field), Argument::Value(Value::Unsigned(1)),
+        ]);
+        let score = || QueryField::new("score".to_owned());
+        let qualified = || QueryField::new("score".to_owned()).from_table("Scores".to_owned());
+        let compare = |name: &str, field: QueryField| Condition::FunctionCall(FunctionCall::new(name.to_owned(), vec![
+            Argument::FunctionCall(plus_one(field)), Argument::Value(Value::Unsigned(2)),
+        ]));
+        let queries = || vec![
+            Query::Filter(compare("strict_eq", score()), Box::new(Query::Table("Scores".to_owned()))),
+            Query::Filter(compare("strict_eq", qualified()), Box::new(Query::Table("Scores".to_owned()))),
+            Query::Filter(compare("gt", score()), Box::new(Query::Table("Scores".to_owned()))),
+        ];
+        let ids = |db: &SrimDB| -> Vec<Vec<Value>> {
+            queries().into_iter().map(|q| db.query(q).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()).collect()
+        };
+        let scanned = ids(&db);
+        assert_eq!(scanned[0], vec![Value::Unsigned(1), Value::Unsigned(4)]);
+
+        db.apply(Delta::CreateExpressionIndex("Scores".to_owned(), plus_one(score()), IndexKind::BTree)).unwrap();
+        // Written qualified or not, it's the same expression
+        assert!(db.data_db.expression_lookup(&"Scores".to_owned(), &plus_one(qualified()), &Value::Unsigned(2)).is_some());
+        assert_eq!(ids(&db), scanned);
+        match db.apply(Delta::CreateExpressionIndex("Scores".to_owned(), plus_one(qualified()), IndexKind::BTree)) {
+            Err(ApplyError::ExpressionIndexExists(..)) => {},
+            other => panic!("Expected ExpressionIndexExists, got {:?}", other),
+        }
+        let unknown = plus_one(QueryField::new("missing".to_owned()));
+        match db.apply(Delta::CreateExpressionIndex("Scores".to_owned(), unknown, IndexKind::Hash)) {
+            Err(ApplyError::NoSuchField(_, ref field)) if field == "missing" => {},
+            other => panic!("Expected NoSuchField, got {:?}", other),
+        }
+        let expect_duplicate = |result: Result<usize, ApplyError>| match result {
+            Err(ApplyError::DuplicateIndexValue(ref table, _)) if table == "Scores" => {},
+            other => panic!("Expected DuplicateIndexValue, got {:?}", other),
+        };
+        expect_duplicate(db.apply(Delta::CreateExpressionIndex("Scores".to_owned(), plus_one(score()), IndexKind::Unique)));
+
+        let create = Delta::CreateExpressionIndex("Scores".to_owned(), plus_one(QueryField::new("id".to_owned())), IndexKind::Unique);
+        assert_eq!(Delta::from_bytes(&create.to_bytes()).unwrap().to_bytes(), create.to_bytes());
+        db.apply(create).unwrap();
+        expect_duplicate(db.apply(Delta::AddRows("Scores".to_owned(), vec![Row::new(vec![Value::Unsigned(6), Value::Unsigned(0)])])));
+
+        db.apply(Delta::ForceDropColumn("Scores".to_owned(), "score".to_owned())).unwrap();
+        assert_eq!(db.data_db.table("Scores".to_owned()).unwrap().expression_indexes().len(), 1);
+    }
+
+    #[test]
+    fn test_partial_indexes() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Accounts", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("email".to_owned(), FieldKind::Text),
+            TableField::new("archived".to_owned(), FieldKind::Integer(IntSize::N8, false)),
+        ]).with_key_fields(vec!["id".to_owned()]))).unwrap();
+        let text = |t: &str| Value::Text(t.into());
+        let account = |id: u128, email: &str, archived: u128| Row::new(vec![Value::Unsigned(id), text(email), Value::Unsigned(archived)]);
+        db.apply(Delta::AddRows("Accounts".to_owned(), vec![
+            account(1, "a@example.com", 1), account(2, "a@example.com", 1), account(3, "a@example.com", 0),
+            account(4, "b@example.com", 0), account(5, "c@example.com", 1),
+        ])).unwrap();
+
+        let eq = |field: QueryField, value: Value| Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(field), Argument::Value(value),
+        ]));
+        let active = || eq(QueryField::new("archived".to_owned()), Value::Unsigned(0));
+        let accounts = || Box::new(Query::Table("Accounts".to_owned()));
+        let queries = || vec![
+            Query::Filter(eq(QueryField::new("email".to_owned()), text("a@example.com")), Box::new(Query::Filter(active(), accounts()))),
+            Query::Filter(eq(QueryField::new("archived".to_owned()).from_table("Accounts".to_owned()), Value::Unsigned(0)), accounts()),
+            Query::Filter(eq(QueryField::new("email".to_owned()), text("a@example.com")), accounts()),
+        ];
+        let ids = |db: &SrimDB| -> Vec<Vec<Value>> {
+            queries().into_iter().map(|q| db.query(q).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()).collect()
+        };
+        let scanned = ids(&db);
+        assert_eq!(scanned[1], vec![Value::Unsigned(3), Value::Unsigned(4)]);
+
+        // Only the active accounts must have distinct emails
+        let create = Delta::CreatePartialIndex("Accounts".to_owned(), vec!["email".to_owned()], IndexKind::Unique, active());
+        assert_eq!(Delta::from_bytes(&create.to_bytes()).unwrap().to_bytes(), create.to_bytes());
+        db.apply(create.clone()).unwrap();
+        match db.apply(create) {
+            Err(ApplyError::IndexExists(..)) => {},
+            other => panic!("Expected IndexExists, got {:?}", other),
+        }
+        assert_eq!(ids(&db), scanned);
+        let conditions = [&active()];
+        assert_eq!(db.data_db.partial_rows(&"Accounts".to_owned(), &conditions).unwrap().len(), 2);
+        assert!(db.data_db.partial_lookup(&"Accounts".to_owned(), &conditions, &[1], &[text("a@example.com")]).is_some());
+        assert!(db.data_db.partial_lookup(&"Accounts".to_owned(), &[], &[1], &[text("a@example.com")]).is_none());
+
+        match db.apply(Delta::AddRow("Accounts".to_owned(), account(6, "b@example.com", 0))) {
+            Err(ApplyError::DuplicateIndexKey(ref table, ref fields)) if table == "Accounts" && fields == &["email".to_owned()] => {},
+            other => panic!("Expected DuplicateIndexKey, got {:?}", other),
+        }
+        db.apply(Delta::AddRow("Accounts".to_owned(), account(6, "b@example.com", 1))).unwrap();
+        db.apply(Delta::UpdateRow(
+            "Accounts".to_owned(), account(4, "b@example.com", 0), RowUpdate::Set(vec![("archived".to_owned(), Value::Unsigned(1))]),
+        )).unwrap();
+        db.apply(Delta::AddRow("Accounts".to_owned(), account(7, "b@example.com", 0))).unwrap();
+        assert_eq!(db.data_db.partial_rows(&"Accounts".to_owned(), &conditions).unwrap().len(), 2);
+
+        db.apply(Delta::ForceDropColumn("Accounts".to_owned(), "archived".to_owned())).unwrap();
+        assert!(db.data_db.table("Accounts".to_owned()).unwrap().partial_indexes().is_empty());
+    }
+
+    #[test]
+    fn test_fulltext_indexes() {
+        assert_eq!(fulltext::tokens("Rust, rust and RUST-y databases"), vec!["rust", "and", "y", "databases"]);
+        assert!(fulltext::matches("A fast database, in Rust!", "rust DATABASE"));
+        assert!(!fulltext::matches("A fast database", "data"));
+
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Articles", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("body".to_owned(), FieldKind::Text).nullable(),
+        ]))).unwrap();
+        let article = |id: u128, body: Value| Row::new(vec![Value::Unsigned(id), body]);
+        let text = |t: &str| Value::Text(t.into());
+        db.apply(Delta::AddRows("Articles".to_owned(), vec![
+            article(1, text("Writing a database in Rust")), article(2, text("Rust for embedded devices")),
+            article(3, Value::Null), article(4, text("Databases: an introduction. Rust optional")),
+        ])).unwrap();
+
+        let search = |db: &SrimDB, query: &str| -> Vec<Value> {
+            db.query(Query::Filter(
+                Condition::FunctionCall(FunctionCall::new("matches".to_owned(), vec![
+                    Argument::QueryField(QueryField::new("body".to_owned())), Argument::Value(text(query)),
+                ])),
+                Box::new(Query::Table("Articles".to_owned())),
+            )).unwrap().rows().iter().map(|row| row.value(0).clone()).collect()
+        };
+        let queries = ["rust", "RUST database", "nothing", "", "introduction, databases"];
+        let scanned: Vec<Vec<Value>> = queries.iter().map(|q| search(&db, q)).collect();
+        assert_eq!(scanned[1], vec![Value::Unsigned(1)]);
+        assert_eq!(scanned[3], vec![Value::Unsigned(1), Value::Unsigned(2), Value::Unsigned(4)]);
+
+        for fields in vec![vec!["id"], vec!["body", "body"]] {
+            let fields = fields.into_iter().map(str::to_owned).collect();
+            match db.apply(Delta::CreateIndex("Articles".to_owned(), fields, IndexKind::FullText)) {
+                Err(ApplyError::InvalidSchema(_)) => {},
+                other => panic!("Expected InvalidSchema, got {:?}", other),
+            }
+        }
+        db.apply(Delta::CreateIndex("Articles".to_owned(), vec!["body".to_owned()], IndexKind::FullText)).unwrap();
+        assert!(db.data_db.text_search(&"Articles".to_owned(), &[], 1, "rust").is_some());
+        assert_eq!(queries.iter().map(|q| search(&db, q)).collect::<Vec<_>>(), scanned);
+
+        db.apply(Delta::UpdateRow(
+            "Articles".to_owned(), article(2, text("Rust for embedded devices")),
+            RowUpdate::Replace(article(2, text("An embedded database"))),
+        )).unwrap();
+        assert_eq!(search(&db, "rust"), vec![Value::Unsigned(1), Value::Unsigned(4)]);
+        assert_eq!(search(&db, "database"), vec![Value::Unsigned(1), Value::Unsigned(2)]);
+    }
+
+    #[test]
+    fn test_bloom_filters() {
+        let number = |n: u128| Row::new(vec![Value::Unsigned(n)]);
+        let rows: Vec<Row> = (0..bloom::MIN_ROWS as u128).map(number).collect();
+        let filter = bloom::BloomFilter::over(rows.iter(), &[0]).unwrap();
+        assert!(rows.iter().all(|row| filter.may_contain(row, &[0])));
+        assert!((1000..2000).filter(|n| filter.may_contain(&number(*n), &[0])).count() < 50);
+        assert!(filter.may_contain(&Row::new(vec![Value::Array(vec![])]), &[0]));
+        assert!(bloom::BloomFilter::over(vec![Row::new(vec![Value::Vector(vec![1.0])])].iter(), &[0]).is_none());
+
+        let mut db = SrimDB::new();
+        for (name, step, count) in vec![("Evens", 2, 1000), ("Triples", 3, 400)] {
+            db.apply(Delta::CreateTable(Table::new(name, vec![
+                TableField::new("n".to_owned(), FieldKind::Integer(IntSize::N32, false)).nullable(),
+            ]))).unwrap();
+            let mut rows: Vec<Row> = (0..count).map(|i| number(i * step)).collect();
+            rows.push(Row::new(vec![Value::Null]));
+            db.apply(Delta::AddRows(name.to_owned(), rows)).unwrap();
+        }
+        let table = |name: &str| Box::new(Query::Table(name.to_owned()));
+        let numbers = |query: Query| -> Vec<Value> { db.query(query).unwrap().rows().iter().map(|row| row.value(0).clone()).collect() };
+        let sixes: Vec<Value> = (0..200).map(|i| Value::Unsigned(i * 6)).collect();
+
+        let mut intersection = sixes.clone();
+        intersection.push(Value::Null);
+        assert_eq!(numbers(Query::Intersection(table("Evens"), table("Triples"))), intersection);
+        assert_eq!(numbers(Query::Intersection(table("Triples"), table("Evens"))), intersection);
+        assert_eq!(numbers(Query::Difference(table("Evens"), table("Triples"))).len(), 800);
+        assert_eq!(numbers(Query::Difference(table("Triples"), table("Evens"))).len(), 200);
+
+        let field = |table: &str| Argument::QueryField(QueryField::new("n".to_owned()).from_table(table.to_owned()));
+        let joined = numbers(Query::JoinOn(
+            Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![field("Triples"), field("Evens")])),
+            table("Evens"),
+            table("Triples"),
+        ));
+        assert_eq!(joined, sixes);
+    }
+
+    #[test]
+    fn test_index_maintenance() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Items", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("count".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+        ]))).unwrap();
+        let item = |id: u128, count: u128| Row::new(vec![Value::Unsigned(id), Value::Unsigned(count)]);
+        db.apply(Delta::AddRows("Items".to_owned(), vec![item(1, 0), item(2, 5), item(3, 5)])).unwrap();
+
+        let field = |table: &str, name: &str| QueryField::new(name.to_owned()).from_table(table.to_owned());
+        let plus_one = |qf: QueryField| FunctionCall::new("add".to_owned(), vec![
+            Argument::QueryField(qf), Argument::Value(Value::Unsigned(1)),
+        ]);
+        let in_stock = |qf: QueryField| Condition::FunctionCall(FunctionCall::new("gt".to_owned(), vec![
+            Argument::QueryField(qf), Argument::Value(Value::Unsigned(0)),
+        ]));
+        db.apply(Delta::CreateIndex("Items".to_owned(), vec!["count".to_owned()], IndexKind::Hash)).unwrap();
+        db.apply(Delta::CreateExpressionIndex("Items".to_owned(), plus_one(field("Items", "count")), IndexKind::Hash)).unwrap();
+        db.apply(Delta::CreatePartialIndex("Items".to_owned(), vec!["id".to_owned()], IndexKind::Unique, in_stock(field("Items", "count")))).unwrap();
+
+        // Indexes keep resolving fields after renames and added fields
+        db.apply(Delta::RenameColumn("Items".to_owned(), "count".to_owned(), "stock".to_owned())).unwrap();
+        db.apply(Delta::RenameTable("Items".to_owned(), "Products".to_owned())).unwrap();
+        db.apply(Delta::AddColumn(
+            "Products".to_owned(), TableField::new("note".to_owned(), FieldKind::Text).nullable(), Value::Null,
+        )).unwrap();
+        let product = |id: u128, stock: u128| Row::new(vec![Value::Unsigned(id), Value::Unsigned(stock), Value::Null]);
+        db.apply(Delta::AddRow("Products".to_owned(), product(4, 5))).unwrap();
+        match db.apply(Delta::AddRow("Products".to_owned(), product(4, 1))) {
+            Err(ApplyError::DuplicateIndexKey(ref table, _)) if table == "Products" => {},
+            other => panic!("Expected DuplicateIndexKey, got {:?}", other),
+        }
+
+        let name = "Products".to_owned();
+        let ids = |rows: Option<Vec<Arc<Row>>>| -> Vec<Value> { rows.unwrap().iter().map(|row| row.value(0).clone()).collect() };
+        let five = vec![Value::Unsigned(2), Value::Unsigned(3), Value::Unsigned(4)];
+        assert_eq!(ids(db.data_db.index_lookup(&name, &[1], &[Value::Unsigned(5)])), five);
+        assert_eq!(ids(db.data_db.expression_lookup(&name, &plus_one(field("Products", "stock")), &Value::Unsigned(6))), five);
+        assert_eq!(ids(db.data_db.partial_rows(&name, &[&in_stock(QueryField::new("stock".to_owned()))])), five);
+
+        db.apply(Delta::Truncate(name.clone())).unwrap();
+        assert_eq!(ids(db.data_db.index_lookup(&name, &[1], &[Value::Unsigned(5)])), vec![]);
+        assert_eq!(ids(db.data_db.expression_lookup(&name, &plus_one(field("Products", "stock")), &Value::Unsigned(6))), vec![]);
+
+        // Tables encoded before indexes existed lack the lists of them
+        let create = Delta::CreateTable(Table::new("Legacy", vec![TableField::new("id".to_owned(), FieldKind::Text)]));
+        let bytes = create.to_bytes();
+        let legacy = Delta::from_bytes(&bytes[..bytes.len() - 3 * 8]).unwrap();
+        assert_eq!(legacy.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_index_statistics() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Items", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("count".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+        ]))).unwrap();
+        let item = |id: u128, count: u128| Row::new(vec![Value::Unsigned(id), Value::Unsigned(count)]);
+        db.apply(Delta::AddRows("Items".to_owned(), vec![item(1, 0), item(2, 5), item(3, 5)])).unwrap();
+        db.apply(Delta::CreateIndex("Items".to_owned(), vec!["count".to_owned()], IndexKind::BTree)).unwrap();
+        db.apply(Delta::CreateIndex("Items".to_owned(), vec!["id".to_owned()], IndexKind::Hash)).unwrap();
+
+        let compare = |name: &str, field: &str, value: u128| Condition::FunctionCall(FunctionCall::new(name.to_owned(), vec![
+            Argument::QueryField(QueryField::new(field.to_owned())), Argument::Value(Value::Unsigned(value)),
+        ]));
+        let items = || Box::new(Query::Table("Items".to_owned()));
+        assert_eq!(db.query(Query::Filter(compare("gt", "count", 0), items())).unwrap().rows().len(), 2);
+        assert_eq!(db.query(Query::Filter(compare("strict_eq", "count", 5), items())).unwrap().rows().len(), 2);
+        assert_eq!(db.query(Query::Filter(compare("strict_eq", "id", 1), items())).unwrap().rows().len(), 1);
+
+        let infos = db.indexes();
+        assert_eq!(infos.len(), 2);
+        assert_eq!((infos[0].kind, &infos[0].fields, infos[0].entries, infos[0].uses), (IndexKind::BTree, &vec!["count".to_owned()], 2, 2));
+        assert_eq!((infos[1].kind, &infos[1].fields, infos[1].entries, infos[1].uses), (IndexKind::Hash, &vec!["id".to_owned()], 3, 1));
+        assert!(infos[0].size_bytes > 0);
+
+        // Counts survive rebuilding the indexes
+        db.apply(Delta::AddColumn(
+            "Items".to_owned(), TableField::new("note".to_owned(), FieldKind::Text).nullable(), Value::Null,
+        )).unwrap();
+        assert_eq!(db.indexes().iter().map(|info| info.uses).collect::<Vec<_>>(), vec![2, 1]);
+
+        let catalog = db.query(Query::Table(INDEXES_TABLE.to_owned())).unwrap();
+        assert_eq!(catalog.field_names()[..3], ["table".to_owned(), "kind".to_owned(), "fields".to_owned()]);
+        assert_eq!(catalog.rows().len(), 2);
+        assert_eq!(catalog.rows()[1].value(1), &Value::Text("Hash".into()));
+        assert_eq!(catalog.rows()[1].value(7), &Value::Unsigned(1));
+    }
+
+    #[test]
+    fn test_add_rows() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+
+        let added = db.apply(Delta::AddRows(
+            "Values".to_owned(),
+            (0..1000).map(|i| Row::new(vec![Value::Signed(i)])).collect()
+        )).unwrap();
+        assert_eq!(added, 1000);
+        assert_eq!(db.query(Query::Table("Values".to_owned())).unwrap().rows().len(), 1000);
+
+        // An invalid row rejects the whole batch
+        match db.apply(Delta::AddRows(
+            "Values".to_owned(),
+            vec![Row::new(vec![Value::Signed(1)]), Row::new(vec![Value::Text("2".into())])]
+        )) {
+            Err(ApplyError::TypeMismatch { .. }) => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Values".to_owned())).unwrap().rows().len(), 1000);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut db = setup_simple_company_employee_scenario();
+
+        assert_eq!(db.apply(Delta::Truncate("Employees".to_owned())).unwrap(), 500);
+        let result = db.query(Query::Table("Employees".to_owned())).unwrap();
+        assert_eq!(result.field_names(), vec!["id", "name", "company"]);
+        assert_eq!(result.rows().len(), 0);
+
+        assert!(db.apply(Delta::Truncate("Missing".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_add_column() {
+        let mut db = setup_simple_company_employee_scenario();
+
+        let updated = db.apply(Delta::AddColumn(
+            "Companies".to_owned(),
+            TableField::new("employees".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            Value::Unsigned(0)
+        )).unwrap();
+        assert_eq!(updated, 100);
+
+        let result = db.query(Query::Table("Companies".to_owned())).unwrap();
+        assert_eq!(result.field_names(), vec!["id", "name", "city", "employees"]);
+        assert!(result.rows().iter().all(|row| row.value(3) == &Value::Unsigned(0)));
+
+        // New rows must include the column
+        db.apply(Delta::AddRows("Companies".to_owned(), vec![Row::new(vec![
+            Value::Unsigned(100), Value::Text("Company 100".into()), Value::Text("City 0".into()), Value::Unsigned(5)
+        ])])).unwrap();
+
+        match db.apply(Delta::AddColumn("Companies".to_owned(), TableField::new("city".to_owned(), FieldKind::Text), Value::Text("".into()))) {
+            Err(ApplyError::FieldExists(_, ref field)) if field == "city" => {},
+            other => panic!("Expected FieldExists, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_drop_column() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Users", vec![
+                TableField::new("id".to_owned(),    FieldKind::Integer(IntSize::N64, false)),
+                TableField::new("name".to_owned(),  FieldKind::Text),
+                TableField::new("email".to_owned(), FieldKind::Text),
+            ]).with_key_fields(vec!["id".to_owned()])
+        )).unwrap();
+        db.apply(Delta::AddRow("Users".to_owned(), Row::new(vec![
+            Value::Unsigned(1), Value::Text("Alice".into()), Value::Text("alice@example.com".into())
+        ]))).unwrap();
+
+        assert_eq!(db.apply(Delta::DropColumn("Users".to_owned(), "email".to_owned())).unwrap(), 1);
+        let result = db.query(Query::Table("Users".to_owned())).unwrap();
+        assert_eq!(result.field_names(), vec!["id", "name"]);
+        assert_eq!(result.rows(), vec![Row::new(vec![Value::Unsigned(1), Value::Text("Alice".into())])]);
+
+        match db.apply(Delta::DropColumn("Users".to_owned(), "id".to_owned())) {
+            Err(ApplyError::KeyField(_, ref field)) if field == "id" => {},
+            other => panic!("Expected KeyField, got {:?}", other),
+        }
+        db.apply(Delta::ForceDropColumn("Users".to_owned(), "id".to_owned())).unwrap();
+        let result = db.query(Query::Table("Users".to_owned())).unwrap();
+        assert_eq!(result.field_names(), vec!["name"]);
+
+        assert!(db.apply(Delta::DropColumn("Users".to_owned(), "missing".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_rename_table_and_column() {
+        let mut db = setup_simple_company_employee_scenario();
+        db.apply(Delta::CreateTable(
+            Table::new("Offices", vec![
+                TableField::new("company".to_owned(), FieldKind::ForeignKey("Companies".to_owned())),
+            ])
+        )).unwrap();
+
+        db.apply(Delta::RenameTable("Companies".to_owned(), "Firms".to_owned())).unwrap();
+        db.apply(Delta::RenameColumn("Firms".to_owned(), "city".to_owned(), "location".to_owned())).unwrap();
+
+        assert!(db.query(Query::Table("Companies".to_owned())).is_err());
+        let result = db.query(Query::Table("Firms".to_owned())).unwrap();
+        assert_eq!(result.field_names(), vec!["id", "name", "location"]);
+        assert_eq!(result.rows().len(), 100);
+
+        // Qualified fields use the new table name
+        let result = db.query(Query::Project(
+            vec![QueryField::new("location".to_owned()).from_table("Firms".to_owned())],
+            Box::new(Query::Table("Firms".to_owned()))
+        )).unwrap();
+        assert_eq!(result.rows().len(), 100);
+
+        let offices = db.data_db.table("Offices".to_owned()).unwrap();
+        assert_eq!(offices.fields()[0].kind(), FieldKind::ForeignKey("Firms".to_owned()));
+
+        match db.apply(Delta::RenameTable("Firms".to_owned(), "Employees".to_owned())) {
+            Err(ApplyError::TableExists(_)) => {},
+            other => panic!("Expected TableExists, got {:?}", other),
+        }
+        match db.apply(Delta::RenameColumn("Firms".to_owned(), "id".to_owned(), "name".to_owned())) {
+            Err(ApplyError::FieldExists(_, _)) => {},
+            other => panic!("Expected FieldExists, got {:?}", other),
+        }
+
+        // Failing to rebuild an expression index leaves the table as it was
+        use std::sync::atomic::{AtomicBool, Ordering};
+        let failing = Arc::new(AtomicBool::new(false));
+        let fails = Arc::clone(&failing);
+        let mut db = SrimDB::new().with_function("fragile", NativeFunction::new(move |values: Vec<Value>| {
+            if fails.load(Ordering::SeqCst) { Err(QueryError::Cancelled) } else { Ok(values[0].clone()) }
+        }).with_signature(Signature::exactly(1)));
+        db.apply(Delta::CreateTable(Table::new("Notes", vec![TableField::new("text".to_owned(), FieldKind::Text)]))).unwrap();
+        db.apply(Delta::AddRow("Notes".to_owned(), Row::new(vec![Value::Text("a".into())]))).unwrap();
+        db.apply(Delta::CreateExpressionIndex("Notes".to_owned(), FunctionCall::new("fragile".to_owned(), vec![
+            Argument::QueryField(QueryField::new("text".to_owned())),
+        ]), IndexKind::Hash)).unwrap();
+        failing.store(true, Ordering::SeqCst);
+        assert!(db.apply(Delta::RenameTable("Notes".to_owned(), "Memos".to_owned())).is_err());
+        failing.store(false, Ordering::SeqCst);
+        db.apply(Delta::AddRow("Notes".to_owned(), Row::new(vec![Value::Text("b".into())]))).unwrap();
+        assert_eq!(db.query(Query::Table("Notes".to_owned())).unwrap().rows().len(), 2);
+        assert!(db.query(Query::Table("Memos".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_alter_column_type() {
+        let mut db = setup_simple_company_employee_scenario();
+
+        // Text can't be converted to integers, nothing changes
+        match db.apply(Delta::AlterColumnType("Companies".to_owned(), "name".to_owned(), FieldKind::Real)) {
+            Err(ApplyError::TypeMismatch { ref field, .. }) if field == "name" => {},
+            other => panic!("Expected TypeMismatch, got {:?}", other),
+        }
+        let rows = db.query(Query::Table("Companies".to_owned())).unwrap().rows();
+        assert_eq!(rows[7].value(1), &Value::Text("Company 7".into()));
+
+        assert_eq!(db.apply(Delta::AlterColumnType("Companies".to_owned(), "id".to_owned(), FieldKind::Real)).unwrap(), 100);
+        assert_eq!(db.apply(Delta::AlterColumnType("Companies".to_owned(), "id".to_owned(), FieldKind::Text)).unwrap(), 100);
+        let rows = db.query(Query::Table("Companies".to_owned())).unwrap().rows();
+        assert_eq!(rows[7].value(0), &Value::Text("7".into()));
+        assert_eq!(db.data_db.table("Companies".to_owned()).unwrap().fields()[0].kind(), FieldKind::Text);
+    }
+
+    #[test]
+    fn test_apply_all() {
+        let mut db = setup_simple_company_employee_scenario();
+        let company = |id: u128| Row::new(vec![
+            Value::Unsigned(id),
+            Value::Text(format!("Company {}", id).into()),
+            Value::Text("City 0".into()),
+        ]);
+
+        // Second delta fails, so the first one is not applied either
+        let result = db.apply_all(vec![
+            Delta::AddRow("Companies".to_owned(), company(100)),
+            Delta::RemoveRow("Companies".to_owned(), company(1000)),
+        ]);
+        match result {
+            Err(ApplyError::NoSuchRow(_)) => {},
+            other => panic!("Expected NoSuchRow, got {:?}", other),
+        }
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 100);
+
+        let count = db.apply_all(vec![
+            Delta::AddRow("Companies".to_owned(), company(100)),
+            Delta::AddRow("Companies".to_owned(), company(101)),
+            Delta::DropTable("Employees".to_owned()),
+        ]).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(db.query(Query::Table("Companies".to_owned())).unwrap().rows().len(), 102);
+        assert!(db.query(Query::Table("Employees".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_apply_report() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(Table::new("Values", vec![
+            TableField::new("id".to_owned(), FieldKind::Integer(IntSize::N32, false)),
+            TableField::new("name".to_owned(), FieldKind::Text),
+        ]).with_key_fields(vec!["id".to_owned()]))).unwrap();
+        let row = |id: u128, name: &str| Row::new(vec![Value::Unsigned(id), Value::Text(name.into())]);
+
+        let report = db.apply_reporting(Delta::Upsert("Values".to_owned(), row(1, "a"))).unwrap();
+        assert_eq!(report, ApplyReport { inserted: 1, ..ApplyReport::default() });
+        let report = db.apply_reporting(Delta::Upsert("Values".to_owned(), row(1, "b"))).unwrap();
+        assert_eq!(report, ApplyReport { updated: 1, ..ApplyReport::default() });
+
+        let report = db.apply_all_reporting(vec![
+            Delta::AddRows("Values".to_owned(), vec![row(2, "c"), row(3, "d")]),
+            Delta::RemoveRow("Values".to_owned(), row(1, "b")),
+            Delta::UpdateRow("Values".to_owned(), row(2, "c"), RowUpdate::Set(vec![("name".to_owned(), Value::Text("e".into()))])),
+            Delta::CreateTable(Table::new("Other", vec![TableField::new("value".to_owned(), FieldKind::Text)])),
+        ]).unwrap();
+        assert_eq!(report, ApplyReport { inserted: 2, updated: 1, removed: 1, ..ApplyReport::default() });
+        assert_eq!(report.rows(), 4);
+        assert_eq!(db.apply_reporting(Delta::Truncate("Values".to_owned())).unwrap().removed, 2);
+
+        // Rows removed by cascading foreign keys are counted by table
+        db.apply(Delta::CreateTable(Table::new("Notes", vec![
+            TableField::new("value".to_owned(), FieldKind::ForeignKey("Values".to_owned()))
+                .with_on_delete(ReferenceAction::Cascade),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Values".to_owned(), row(1, "a"))).unwrap();
+        db.apply(Delta::AddRows("Notes".to_owned(), vec![Row::new(vec![Value::Unsigned(1)])])).unwrap();
+        let report = db.apply_reporting(Delta::RemoveRow("Values".to_owned(), row(1, "a"))).unwrap();
+        assert_eq!(report.removed, 1);
+        assert_eq!(report.cascaded, vec![("Notes".to_owned(), 1)].into_iter().collect());
+        assert_eq!(report.rows(), 2);
+    }
+
+    #[test]
+    fn test_check_delta() {
+        let mut db = setup_simple_company_employee_scenario();
+        let revision = db.revision();
+        let companies = || Query::Table("Companies".to_owned());
+        let before = db.query(companies()).unwrap().rows();
+
+        db.check_delta(&Delta::Truncate("Companies".to_owned())).unwrap();
+        db.check_delta(&Delta::AddRow("Companies".to_owned(), Row::new(vec![
+            Value::Unsigned(1000), Value::Text("New".into()), Value::Text("City".into()),
+        ]))).unwrap();
+        match db.check_delta(&Delta::AddRows("Companies".to_owned(), vec![Row::new(vec![Value::Unsigned(1)])])) {
+            Err(ApplyError::WrongFieldCount { expected: 3, found: 1, .. }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(db.check_delta(&Delta::DropTable("Missing".to_owned())).is_err());
+
+        assert_eq!(db.revision(), revision);
+        assert_eq!(db.query(companies()).unwrap().rows(), before);
+        db.apply(Delta::Truncate("Companies".to_owned())).unwrap();
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut db = setup_simple_company_employee_scenario();
+        let companies = || Query::Table("Companies".to_owned());
+
+        let mut txn = db.begin();
+        assert_eq!(txn.apply(Delta::Truncate("Companies".to_owned())).unwrap(), 100);
+        assert!(txn.apply(Delta::DropTable("Missing".to_owned())).is_err());
+        txn.apply(Delta::AddRow("Companies".to_owned(), Row::new(vec![
+            Value::Unsigned(0), Value::Text("New".into()), Value::Text("City 0".into())
+        ]))).unwrap();
+
+        // Reads through the transaction see its writes, the database does not
+        assert_eq!(txn.query(companies()).unwrap().rows().len(), 1);
+        assert_eq!(db.query(companies()).unwrap().rows().len(), 100);
+        assert_eq!(txn.deltas().len(), 2);
+
+        txn.rollback();
+        assert_eq!(db.query(companies()).unwrap().rows().len(), 100);
+
+        let mut txn = db.begin();
+        txn.apply(Delta::Truncate("Companies".to_owned())).unwrap();
+        assert_eq!(txn.commit(&mut db).unwrap(), 100);
+        assert_eq!(db.query(companies()).unwrap().rows().len(), 0);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        let add = |v: i128| Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(v)]));
+        let count = |txn: &Transaction| txn.query(Query::Table("Values".to_owned())).unwrap().rows().len();
+
+        let mut txn = db.begin();
+        txn.apply(add(1)).unwrap();
+        txn.savepoint("step 2");
+        txn.apply(add(2)).unwrap();
+        txn.savepoint("step 3");
+        txn.apply(add(3)).unwrap();
+        assert_eq!(count(&txn), 3);
+
+        txn.rollback_to("step 3").unwrap();
+        assert_eq!(count(&txn), 2);
+        txn.apply(add(4)).unwrap();
+
+        txn.rollback_to("step 2").unwrap();
+        assert_eq!(count(&txn), 1);
+        assert!(txn.rollback_to("step 3").is_err());
+
+        txn.apply(add(5)).unwrap();
+        txn.release("step 2").unwrap();
+        match txn.rollback_to("step 2") {
+            Err(ApplyError::NoSuchSavepoint(_)) => {},
+            other => panic!("Expected NoSuchSavepoint, got {:?}", other),
+        }
+
+        txn.commit(&mut db).unwrap();
+        assert_eq!(db.query(Query::Table("Values".to_owned())).unwrap().rows(), vec![
+            Row::new(vec![Value::Signed(1)]),
+            Row::new(vec![Value::Signed(5)]),
+        ]);
+    }
+
+    #[test]
+    fn test_transaction_isolation() {
+        let mut db = setup_simple_company_employee_scenario();
+        let employees = || Query::Table("Employees".to_owned());
+
+        let mut t1 = db.begin();
+        let mut t2 = db.begin();
+        t1.apply(Delta::Truncate("Employees".to_owned())).unwrap();
+        t2.apply(Delta::Truncate("Companies".to_owned())).unwrap();
+
+        // Uncommitted changes are invisible to others
+        assert_eq!(t2.query(employees()).unwrap().rows().len(), 500);
+        t1.commit(&mut db).unwrap();
+
+        // Committed changes after the snapshot are invisible too
+        assert_eq!(t2.query(employees()).unwrap().rows().len(), 500);
+        assert_eq!(db.query(employees()).unwrap().rows().len(), 0);
+
+        // Disjoint tables don't conflict
+        let mut t3 = db.begin();
+        t3.apply(Delta::Truncate("Companies".to_owned())).unwrap();
+        t2.commit(&mut db).unwrap();
+
+        // Both modified "Companies"
+        match t3.commit(&mut db) {
+            Err(ApplyError::Conflict(ref table)) if table == "Companies" => {},
+            other => panic!("Expected Conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_apply_if() {
+        let mut db = SrimDB::new();
+        assert_eq!(db.revision(), 0);
+        let create = |name: &str| Delta::CreateTable(Table::new(name, vec![TableField::new("value".to_owned(), FieldKind::Text)]));
+
+        let revision = db.apply_if(0, vec![create("A"), create("B")]).unwrap();
+        assert_eq!(revision, 2);
+        assert_eq!(db.revision(), 2);
+
+        // Someone else changes the database
+        db.apply(create("C")).unwrap();
+
+        match db.apply_if(revision, vec![create("D")]) {
+            Err(ApplyError::RevisionConflict { expected: 2, current: 3 }) => {},
+            other => panic!("Expected RevisionConflict, got {:?}", other),
+        }
+        assert!(db.query(Query::Table("D".to_owned())).is_err());
+        assert_eq!(db.apply_if(db.revision(), vec![create("D")]).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let mut db = SrimDB::new().with_journal();
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        let add = |v: i128| Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(v)]));
+
+        db.apply(add(1)).unwrap();
+        assert!(db.apply(Delta::DropTable("Missing".to_owned())).is_err());
+        db.apply_all(vec![add(2), add(3)]).unwrap();
+        db.apply(Delta::RemoveRow("Values".to_owned(), Row::new(vec![Value::Signed(2)]))).unwrap();
+
+        // Failed deltas are not journaled
+        assert_eq!(db.journal().iter().map(|e| e.revision).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+
+        let mut copy = SrimDB::new();
+        assert_eq!(db.replay(0, &mut copy).unwrap(), 5);
+        let values = Query::Table("Values".to_owned());
+        assert_eq!(copy.query(values.clone()).unwrap().rows(), db.query(values.clone()).unwrap().rows());
+        assert_eq!(copy.revision(), db.revision());
+
+        // Catch up a copy taken at an earlier revision
+        let mut partial = SrimDB::new();
+        for entry in &db.journal()[..3] {
+            partial.apply(entry.delta.clone()).unwrap();
+        }
+        assert_eq!(partial.query(values.clone()).unwrap().rows().len(), 2);
+        assert_eq!(db.replay(3, &mut partial).unwrap(), 5);
+        assert_eq!(partial.query(values.clone()).unwrap().rows(), db.query(values).unwrap().rows());
+    }
+
+    #[test]
+    fn test_query_at() {
+        let mut db = SrimDB::new().with_journal();
+        let values = || Query::Table("Values".to_owned());
+        let row = |v: i128| Row::new(vec![Value::Signed(v)]);
+
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        db.apply_all(vec![
+            Delta::AddRow("Values".to_owned(), row(1)),
+            Delta::AddRow("Values".to_owned(), row(2)),
+        ]).unwrap();
+        db.apply(Delta::UpdateRow("Values".to_owned(), row(1), RowUpdate::Replace(row(10)))).unwrap();
+        db.apply(Delta::DropTable("Values".to_owned())).unwrap();
+
+        assert!(db.query_at(0, values()).is_err());
+        assert_eq!(db.query_at(1, values()).unwrap().rows(), vec![]);
+        // Revisions inside a batch are visible too
+        assert_eq!(db.query_at(2, values()).unwrap().rows(), vec![row(1)]);
+        assert_eq!(db.query_at(3, values()).unwrap().rows(), vec![row(1), row(2)]);
+        assert_eq!(db.query_at(4, values()).unwrap().rows(), vec![row(10), row(2)]);
+        assert!(db.query_at(5, values()).is_err());
+
+        match db.query_at(6, values()) {
+            Err(QueryError::NoSuchRevision(6)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // Without a journal, only the current state is available
+        let mut db = SrimDB::new();
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        assert!(db.query_at(1, values()).is_ok());
+        assert!(db.query_at(0, values()).is_err());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut db = SrimDB::new();
+        let all = db.subscribe(SubscriptionFilter::AllTables);
+        let values = db.subscribe(SubscriptionFilter::Tables(vec!["Values".to_owned()]));
+        let dropped = db.subscribe(SubscriptionFilter::AllTables);
+        drop(dropped);
+
+        let table = |name: &str| Table::new(name, vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))]);
+        db.apply(Delta::CreateTable(table("Values"))).unwrap();
+        db.apply(Delta::CreateTable(table("Other"))).unwrap();
+        db.apply_all(vec![
+            Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(1)])),
+            Delta::AddRow("Other".to_owned(), Row::new(vec![Value::Signed(2)])),
+        ]).unwrap();
+        assert!(db.apply(Delta::DropTable("Missing".to_owned())).is_err());
+
+        let revisions = |receiver: &Receiver<DeltaEvent>| receiver.try_iter().map(|e| e.revision).collect::<Vec<_>>();
+        assert_eq!(revisions(&all), vec![1, 2, 3, 4]);
+        assert_eq!(revisions(&values), vec![1, 3]);
+
+        // Renaming matches the old name
+        db.apply(Delta::RenameTable("Values".to_owned(), "Renamed".to_owned())).unwrap();
+        let event = values.try_recv().unwrap();
+        assert_eq!(event.revision, 5);
+        assert_eq!(event.tables(), vec!["Values".to_owned(), "Renamed".to_owned()]);
+    }
+
+    #[test]
+    fn test_triggers() {
+        let mut db = SrimDB::new().with_history(10);
+        db.apply(Delta::CreateTable(Table::new("Prices", vec![
+            TableField::new("item".to_owned(), FieldKind::Text),
+            TableField::new("price".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+            TableField::new("locked".to_owned(), FieldKind::Integer(IntSize::N8, true)),
+        ]).with_key_fields(vec!["item".to_owned()]))).unwrap();
+        let row = |item: &str, price: i128, locked: i128| Row::new(vec![
+            Value::Text(item.into()), Value::Signed(price), Value::Signed(locked)
+        ]);
+        let table = |name: &str| Query::Table(name.to_owned());
+
+        // Add a fee to every inserted price
+        db.apply(Delta::CreateTrigger(Trigger::new("fee", "Prices", TriggerTiming::Before, vec![TriggerEvent::Insert],
+            TriggerAction::Set(vec![("price".to_owned(), Argument::FunctionCall(FunctionCall::new("add".to_owned(), vec![
+                Argument::QueryField(QueryField::new("price".to_owned())),
+                Argument::Value(Value::Signed(1)),
+            ])))])
+        ))).unwrap();
+        // Locked rows can't be changed or removed
+        let unlocked = Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+            Argument::QueryField(QueryField::new("locked".to_owned())),
+            Argument::Value(Value::Signed(0)),
+        ]));
+        db.apply(Delta::CreateTrigger(Trigger::new("lock", "Prices", TriggerTiming::After,
+            vec![TriggerEvent::Update, TriggerEvent::Delete], TriggerAction::Check(unlocked)
+        ))).unwrap();
+
+        db.apply(Delta::AddRow("Prices".to_owned(), row("a", 10, 0))).unwrap();
+        db.apply(Delta::AddRows("Prices".to_owned(), vec![row("b", 20, 1)])).unwrap();
+        assert_eq!(db.query(table("Prices")).unwrap().rows(), vec![row("a", 11, 0), row("b", 21, 1)]);
+
+        match db.apply(Delta::RemoveRow("Prices".to_owned(), row("b", 21, 1))) {
+            Err(ApplyError::TriggerRejected(ref table, ref trigger)) if table == "Prices" && trigger == "lock" => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(db.apply(Delta::Upsert("Prices".to_owned(), row("b", 5, 1))).is_err());
+        // Updates see the new row
+        db.apply(Delta::Upsert("Prices".to_owned(), row("b", 5, 0))).unwrap();
+        db.apply(Delta::RemoveWhere("Prices".to_owned(), Condition::Value(Value::Boolean(true)))).unwrap();
+        assert_eq!(db.query(table("Prices")).unwrap().rows(), vec![]);
+
+        // Undoing restores rows exactly, without firing triggers
+        db.undo(2).unwrap();
+        assert_eq!(db.query(table("Prices")).unwrap().rows(), vec![row("a", 11, 0), row("b", 21, 1)]);
+
+        // Set is only allowed before inserts and updates
+        assert!(db.apply(Delta::CreateTrigger(Trigger::new("bad", "Prices", TriggerTiming::After, vec![TriggerEvent::Insert],
+            TriggerAction::Set(vec![])
+        ))).is_err());
+        assert!(db.apply(Delta::CreateTrigger(Trigger::new("fee", "Prices", TriggerTiming::Before, vec![TriggerEvent::Insert],
+            TriggerAction::Set(vec![])
+        ))).is_err());
+
+        // Triggers follow renames and are dropped with their table
+        db.apply(Delta::RenameTable("Prices".to_owned(), "Costs".to_owned())).unwrap();
+        db.apply(Delta::AddRow("Costs".to_owned(), row("c", 30, 0))).unwrap();
+        assert_eq!(db.query(table("Costs")).unwrap().rows()[2], row("c", 31, 0));
+        db.apply(Delta::DropTrigger("Costs".to_owned(), "lock".to_owned())).unwrap();
+        db.apply(Delta::RemoveRow("Costs".to_owned(), row("b", 21, 1))).unwrap();
+        db.apply(Delta::DropTable("Costs".to_owned())).unwrap();
+        db.apply(Delta::CreateTable(Table::new("Costs", vec![
+            TableField::new("item".to_owned(), FieldKind::Text),
+            TableField::new("price".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+            TableField::new("locked".to_owned(), FieldKind::Integer(IntSize::N8, true)),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Costs".to_owned(), row("d", 40, 0))).unwrap();
+        assert_eq!(db.query(table("Costs")).unwrap().rows(), vec![row("d", 40, 0)]);
+    }
+
+    #[test]
+    fn test_replication() {
+        struct Channel(Vec<Vec<u8>>);
+        impl Transport for Channel {
+            fn send(&mut self, message: Vec<u8>) -> io::Result<()> {
+                self.0.push(message);
+                Ok(())
+            }
+        }
+
+        let mut db = SrimDB::new().with_journal().with_history(1);
+        let mut leader = Leader::new(Channel(Vec::new()));
+        let mut follower = Follower::new();
+        let values = || Query::Table("Values".to_owned());
+        let row = |v: i128| Row::new(vec![Value::Signed(v), Value::Text(format!("{}", v).into())]);
+
+        db.apply(Delta::CreateTable(Table::new("Values", vec![
+            TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+            TableField::new("name".to_owned(), FieldKind::Text),
+        ]).with_key_fields(vec!["value".to_owned()]))).unwrap();
+        db.apply(Delta::AddRows("Values".to_owned(), vec![row(1), row(2), row(3)])).unwrap();
+        db.apply(Delta::UpdateWhere("Values".to_owned(), Condition::Value(Value::Boolean(true)), vec![
+            ("name".to_owned(), Argument::Value(Value::Text("x".into()))),
+        ])).unwrap();
+        db.undo(1).unwrap();
+        assert_eq!(leader.ship(&db).unwrap(), 5);
+        leader.ship_checksum(&db).unwrap();
+
+        let messages = std::mem::replace(&mut leader.transport().0, Vec::new());
+        follower.receive(&messages[0]).unwrap();
+        assert_eq!(follower.lag(), 4);
+        assert!(follower.receive(&messages[2]).is_err());
+        for message in &messages {
+            follower.receive(message).unwrap();
+        }
+        assert_eq!(follower.lag(), 0);
+        assert_eq!(follower.verified_revision(), Some(5));
+        assert_eq!(follower.db().query(values()).unwrap().rows(), db.query(values()).unwrap().rows());
+
+        // Redelivered messages are ignored
+        follower.receive(&messages[1]).unwrap();
+        db.apply(Delta::RemoveRow("Values".to_owned(), row(1))).unwrap();
+        leader.ship_checksum(&db).unwrap();
+        let more = std::mem::replace(&mut leader.transport().0, Vec::new());
+        assert!(follower.receive(&more[1]).is_err());
+        follower.receive(&more[0]).unwrap();
+        follower.receive(&more[1]).unwrap();
+        assert_eq!(follower.verified_revision(), Some(6));
+
+        // Changes to temporary tables leave no gaps in the shipped revisions
+        db.apply(Delta::CreateTable(Table::new("Scratch", vec![TableField::new("n".to_owned(), FieldKind::Text)]).temporary())).unwrap();
+        db.apply(Delta::RemoveRow("Values".to_owned(), row(2))).unwrap();
+        leader.ship_checksum(&db).unwrap();
+        for message in std::mem::replace(&mut leader.transport().0, Vec::new()) {
+            follower.receive(&message).unwrap();
+        }
+        assert_eq!(follower.verified_revision(), Some(7));
+
+        // A checksum of a different state at the same revision is detected
+        let mut other = SrimDB::new().with_journal();
+        other.apply(Delta::CreateTable(Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Text)]))).unwrap();
+        let mut other_leader = Leader::new(Channel(Vec::new()));
+        other_leader.ship_checksum(&other).unwrap();
+        let mut diverged = Follower::new();
+        diverged.receive(&messages[0]).unwrap();
+        match diverged.receive(&other_leader.transport().0[1]) {
+            Err(ReplicationError::Diverged(1)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let delta = db.journal()[2].delta.clone();
+        assert_eq!(format!("{:?}", Delta::from_bytes(&delta.to_bytes()).unwrap()), format!("{:?}", delta));
+        assert!(Delta::from_bytes(&messages[0][1..]).is_err());
+
+        // Registered functions aren't shipped, so the follower is configured with them
+        let accept = || NativeFunction::new(|_: Vec<Value>| Ok(Value::Boolean(true))).with_signature(Signature::exactly(1));
+        let mut configured = SrimDB::new().with_journal().with_function("accept", accept());
+        configured.apply(Delta::CreateTable(Table::new("Checked", vec![
+            TableField::new("n".to_owned(), FieldKind::Text),
+        ]).with_check("accepted", FunctionCall::new("accept".to_owned(), vec![Argument::QueryField(QueryField::new("n".to_owned()))])))).unwrap();
+        configured.apply(Delta::AddRow("Checked".to_owned(), Row::new(vec![Value::Text("a".into())]))).unwrap();
+        let mut configured_leader = Leader::new(Channel(Vec::new()));
+        configured_leader.ship_checksum(&configured).unwrap();
+        let mut used = SrimDB::new();
+        used.apply(Delta::CreateTable(Table::new("Other", vec![TableField::new("n".to_owned(), FieldKind::Text)]))).unwrap();
+        match Follower::with_db(used) {
+            Err(ReplicationError::NotEmpty(1)) => {},
+            other => panic!("Expected NotEmpty, got {:?}", other.map(|_| ())),
+        }
+        let mut follower = Follower::with_db(SrimDB::new().with_function("accept", accept())).unwrap();
+        for message in &configured_leader.transport().0 {
+            follower.receive(message).unwrap();
+        }
+        assert_eq!(follower.verified_revision(), Some(2));
+        assert!(Follower::new().receive(&configured_leader.transport().0[0]).is_err());
+        assert_eq!(configured.query_at(1, Query::Table("Checked".to_owned())).unwrap().rows(), vec![]);
+    }
+
+    #[test]
+    fn test_cdc_sinks() {
+        let (sender, receiver) = mpsc::channel();
+        let path = std::env::temp_dir().join(format!("srimdb_test_cdc_{}.bin", std::process::id()));
+        let mut db = SrimDB::new()
+            .with_cdc_sink(ChannelSink::new(sender, CdcFormat::Json))
+            .with_cdc_sink(FileSink::new(File::create(&path).unwrap(), CdcFormat::Binary));
+
+        db.apply(Delta::CreateTable(Table::new("Values", vec![
+            TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true)),
+            TableField::new("name".to_owned(), FieldKind::Text),
+        ]))).unwrap();
+        db.apply(Delta::AddRow("Values".to_owned(), Row::new(vec![Value::Signed(-1), Value::Text("a \"b\"".into())]))).unwrap();
+        assert!(db.apply(Delta::DropTable("Missing".to_owned())).is_err());
+
+        let json: Vec<_> = receiver.try_iter().map(|m| String::from_utf8(m).unwrap()).collect();
+        assert_eq!(json, vec![
+            "{\"revision\":1,\"delta\":{\"type\":\"CreateTable\",\"table\":{\"name\":\"Values\",\"fields\":[\
+                {\"name\":\"value\",\"kind\":{\"Integer\":{\"bits\":32,\"signed\":true}},\"nullable\":false},\
+                {\"name\":\"name\",\"kind\":\"Text\",\"nullable\":false}],\"key_fields\":[\"value\",\"name\"],\"unique_key\":false}}}\n".to_owned(),
+            "{\"revision\":2,\"delta\":{\"type\":\"AddRow\",\"table\":\"Values\",\"row\":[{\"Signed\":-1},{\"Text\":\"a \\\"b\\\"\"}]}}\n".to_owned(),
+        ]);
+
+        use codec::Decode;
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut bytes = &contents[..];
+        let mut revisions = Vec::new();
+        while !bytes.is_empty() {
+            let len = u64::decode(&mut bytes).unwrap() as usize;
+            let (mut message, rest) = bytes.split_at(len);
+            revisions.push(u64::decode(&mut message).unwrap());
+            Delta::from_bytes(message).unwrap();
+            bytes = rest;
+        }
+        assert_eq!(revisions, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut db = SrimDB::new().with_history(10);
+        let values = || Query::Table("Values".to_owned());
+        let rows = |db: &SrimDB| db.query(values()).map(|r| r.rows());
+        let row = |v: i128| Row::new(vec![Value::Signed(v)]);
+
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        db.apply_all(vec![
+            Delta::AddRow("Values".to_owned(), row(1)),
+            Delta::AddRows("Values".to_owned(), vec![row(2), row(3)]),
+        ]).unwrap();
+        db.apply(Delta::UpdateRow("Values".to_owned(), row(2), RowUpdate::Replace(row(20)))).unwrap();
+        db.apply(Delta::DropTable("Values".to_owned())).unwrap();
+        assert!(rows(&db).is_err());
+
+        assert_eq!(db.undo(1).unwrap(), 1);
+        assert_eq!(rows(&db).unwrap(), vec![row(1), row(20), row(3)]);
+        assert_eq!(db.undo(1).unwrap(), 1);
+        assert_eq!(rows(&db).unwrap(), vec![row(1), row(2), row(3)]);
+
+        // A batch is undone as a single step
+        assert_eq!(db.undo(1).unwrap(), 1);
+        assert_eq!(rows(&db).unwrap(), vec![]);
+
+        assert_eq!(db.redo(5).unwrap(), 3);
+        assert!(rows(&db).is_err());
+        assert_eq!(db.undo(5).unwrap(), 4);
+        assert!(rows(&db).is_err());
+        assert_eq!(db.undo(1).unwrap(), 0);
+
+        // Applying a new change forgets undone ones
+        db.redo(2).unwrap();
+        db.apply(Delta::RemoveRow("Values".to_owned(), row(1))).unwrap();
+        assert_eq!(db.redo(1).unwrap(), 0);
+        // Undoing advances the revision like any other change
+        let revision = db.revision();
+        db.undo(1).unwrap();
+        assert_eq!(rows(&db).unwrap(), vec![row(2), row(3), row(1)]);
+        assert!(db.revision() > revision);
+    }
+
+    #[test]
+    fn test_row_ids() {
+        let mut db = SrimDB::new();
+        let values = || Query::Table("Values".to_owned());
+        let row = |v: i128| Row::new(vec![Value::Signed(v)]);
+
+        db.apply(Delta::CreateTable(
+            Table::new("Values", vec![TableField::new("value".to_owned(), FieldKind::Integer(IntSize::N32, true))])
+        )).unwrap();
+        db.apply(Delta::AddRows("Values".to_owned(), vec![row(1), row(2), row(1)])).unwrap();
+        let ids: Vec<_> = db.query(values()).unwrap().shared_rows().iter().map(|r| r.id().unwrap()).collect();
+        assert!(ids[0] < ids[1] && ids[1] < ids[2]);
+
+        // The second duplicate can be targeted exactly
+        db.apply(Delta::UpdateRowById("Values".to_owned(), ids[2], RowUpdate::Replace(row(3)))).unwrap();
+        db.apply(Delta::RemoveRowById("Values".to_owned(), ids[0])).unwrap();
+        let result = db.query(values()).unwrap();
+        assert_eq!(result.rows(), vec![row(2), row(3)]);
+        assert_eq!(result.shared_rows().iter().map(|r| r.id().unwrap()).collect::<Vec<_>>(), vec![ids[1], ids[2]]);
+        match db.apply(Delta::RemoveRowById("Values".to_owned(), ids[0])) {
+            Err(ApplyError::NoSuchRow(_)) => {},
+            other => panic!("Expected NoSuchRow, got {:?}", other),
+        }
+
+        // The id can be used as a field in queries
+        let result = db.query(Query::Filter(
+            query::Condition::FunctionCall(FunctionCall::new("strict_eq".to_owned(), vec![
+                Argument::QueryField(QueryField::new(ROWID_FIELD.to_owned())),
+                Argument::Value(Value::Unsigned(ids[2] as u128)),
+            ])),
+            Box::new(values()),
+        )).unwrap();
+        assert_eq!(result.rows(), vec![row(3)]);
+    }
 }
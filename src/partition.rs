@@ -0,0 +1,178 @@
+//! Partitions of tables, see `Table::with_partitioning`
+//!
+//! The ids of the rows in each partition are kept apart like an index, so that
+//! filters constraining the field rows are partitioned by only read the rows
+//! of the partitions that can match, and the others are pruned.
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::ops::Bound;
+use std::slice;
+use std::sync::Arc;
+
+use {DataDB, Table, TableName, Row, RowId, Value};
+use table::Partitioning;
+use index::{lookup_key, Usage};
+use collation::Collation;
+
+/// Size and usage of a partition, see `SrimDB::partitions`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionInfo {
+    pub rows: usize,
+    /// Number of times the planner read the partition instead of the whole table
+    pub scans: u64,
+}
+
+impl Partitioning {
+    fn count(&self) -> usize {
+        match self {
+            Partitioning::Range(bounds) => bounds.len() + 1,
+            Partitioning::Hash(count) => *count,
+        }
+    }
+
+    /// Partition of rows with `value` in the field, none if it can't be compared with the bounds
+    fn partition(&self, value: &Value) -> Option<usize> {
+        match self {
+            Partitioning::Range(bounds) => {
+                let mut partition = 0;
+                for bound in bounds {
+                    match value.compare(bound) {
+                        Ok(Some(Ordering::Less)) => break,
+                        Ok(Some(_)) => partition += 1,
+                        _ => return None,
+                    }
+                }
+                Some(partition)
+            },
+            Partitioning::Hash(count) => {
+                let key = lookup_key(slice::from_ref(value))?;
+                let mut hasher = DefaultHasher::new();
+                hasher.write(&key);
+                Some((hasher.finish() % *count as u64) as usize)
+            },
+        }
+    }
+
+    /// First and last partition of rows with a value within the bounds, none
+    /// for hash partitions or if a bound can't be compared with the bounds
+    fn partitions_within(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<(usize, usize)> {
+        if let Partitioning::Hash(_) = self {
+            return None;
+        }
+        let first = match lower {
+            Bound::Included(value) | Bound::Excluded(value) => self.partition(value)?,
+            Bound::Unbounded => 0,
+        };
+        let last = match upper {
+            Bound::Included(value) | Bound::Excluded(value) => self.partition(value)?,
+            Bound::Unbounded => self.count() - 1,
+        };
+        Some((first, last))
+    }
+}
+
+/// Ids of the rows in each partition of a table
+#[derive(Debug, Clone)]
+pub(crate) struct Partitions {
+    column: usize,
+    /// Collation of the field, which values are partitioned by
+    collation: Collation,
+    /// With range bounds replaced by their keys in the collation
+    partitioning: Partitioning,
+    ids: Vec<BTreeSet<RowId>>,
+    scans: Vec<Usage>,
+}
+impl Partitions {
+    /// Empty partitions of the table, none if it isn't partitioned
+    pub(crate) fn new(table: &Table) -> Option<Self> {
+        let (column, partitioning) = table.partition_column()?;
+        let count = partitioning.count();
+        let collation = table.fields()[column].collation();
+        let partitioning = match partitioning {
+            Partitioning::Range(bounds) => Partitioning::Range(bounds.iter().map(|b| collation.key_value(b).into_owned()).collect()),
+            Partitioning::Hash(count) => Partitioning::Hash(*count),
+        };
+        Some(Self {
+            column,
+            collation,
+            partitioning,
+            ids: vec![BTreeSet::new(); count],
+            scans: (0..count).map(|_| Usage::default()).collect(),
+        })
+    }
+
+    fn partition_of(&self, row: &Row) -> usize {
+        self.partitioning.partition(&self.collation.key_value(row.value(self.column))).unwrap_or(0)
+    }
+
+    pub(crate) fn insert(&mut self, row: &Row) {
+        let partition = self.partition_of(row);
+        self.ids[partition].insert(row.id().expect("Partitioned rows are stored"));
+    }
+
+    pub(crate) fn remove(&mut self, row: &Row) {
+        let partition = self.partition_of(row);
+        self.ids[partition].remove(&row.id().expect("Partitioned rows are stored"));
+    }
+
+    /// Share the usage counters of `old`, which this replaces, if it has the same partitions
+    pub(crate) fn keep_usage(&mut self, old: &Partitions) {
+        if old.column == self.column && old.partitioning == self.partitioning {
+            self.scans = old.scans.clone();
+        }
+    }
+
+    pub(crate) fn infos(&self) -> Vec<PartitionInfo> {
+        self.ids.iter().zip(&self.scans).map(|(ids, scans)| PartitionInfo { rows: ids.len(), scans: scans.count() }).collect()
+    }
+
+    /// Ids of the rows in the partitions that can have rows strictly equal to
+    /// `values` in `columns` and within the ranges, none if they don't prune any
+    fn pruned(&self, columns: &[usize], values: &[Value], ranges: &[(usize, Bound<&Value>, Bound<&Value>)]) -> Option<Vec<RowId>> {
+        let mut wanted = vec![true; self.ids.len()];
+        for (_, value) in columns.iter().zip(values).filter(|(c, _)| **c == self.column) {
+            if let Some(partition) = self.partitioning.partition(&self.collation.key_value(value)) {
+                for (i, w) in wanted.iter_mut().enumerate() {
+                    *w &= i == partition;
+                }
+            }
+        }
+        for (_, lower, upper) in ranges.iter().filter(|(c, _, _)| *c == self.column) {
+            let key = |bound: Bound<&Value>| bound.map(|value| self.collation.key_value(value).into_owned());
+            let (lower, upper) = (key(*lower), key(*upper));
+            if let Some((first, last)) = self.partitioning.partitions_within(lower.as_ref(), upper.as_ref()) {
+                for (i, w) in wanted.iter_mut().enumerate() {
+                    *w &= first <= i && i <= last;
+                }
+            }
+        }
+        if wanted.iter().all(|w| *w) {
+            return None;
+        }
+        let mut ids = Vec::new();
+        for (i, _) in wanted.iter().enumerate().filter(|(_, w)| **w) {
+            self.scans[i].record();
+            ids.extend(self.ids[i].iter().cloned());
+        }
+        ids.sort();
+        Some(ids)
+    }
+}
+
+impl DataDB {
+    /// Rows of the table in the partitions that can have rows strictly equal to
+    /// `values` in `columns` and within the ranges, in table order, none if the
+    /// table isn't partitioned or they don't prune any partitions
+    pub(crate) fn partition_rows(&self, name: &TableName, columns: &[usize], values: &[Value], ranges: &[(usize, Bound<&Value>, Bound<&Value>)]) -> Option<Vec<Arc<Row>>> {
+        let ids = self.indexes.get(name)?.partitions()?.pruned(columns, values, ranges)?;
+        Some(self.rows_by_id(name, ids))
+    }
+
+    /// Partitions of table `name`, empty if it isn't partitioned
+    pub(crate) fn partition_infos(&self, name: &TableName) -> Vec<PartitionInfo> {
+        self.indexes.get(name).and_then(|indexes| indexes.partitions()).map(Partitions::infos).unwrap_or_default()
+    }
+}
@@ -0,0 +1,160 @@
+//! Wildcard patterns for matching `Text` values
+//!
+//! SQL `LIKE` patterns have `%` for any characters and `_` for one character,
+//! with `\` making the next character literal. Glob patterns have `*`, `?`,
+//! and character classes like `[a-z]` or `[!0-9]`. Both match whole texts,
+//! character by character and case-sensitively.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// Exactly one character
+    One,
+    /// Any number of characters
+    Many,
+    /// One character within one of the inclusive ranges, or outside all of them if negated
+    Class(bool, Vec<(char, char)>),
+}
+impl Token {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::One => true,
+            Token::Many => unreachable!(),
+            Token::Class(negated, ranges) => ranges.iter().any(|(low, high)| *low <= c && c <= *high) != *negated,
+        }
+    }
+}
+
+fn like_tokens(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '%' => Token::Many,
+            '_' => Token::One,
+            // A trailing backslash is literal
+            '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+            c => Token::Literal(c),
+        });
+    }
+    tokens
+}
+
+fn glob_tokens(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let token = match chars[i] {
+            '*' => Token::Many,
+            '?' => Token::One,
+            '[' => match class(&chars[i + 1..]) {
+                Some((token, length)) => {
+                    i += length;
+                    token
+                },
+                // An unclosed bracket is literal
+                None => Token::Literal('['),
+            },
+            c => Token::Literal(c),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    tokens
+}
+
+/// Character class after its opening bracket, and the number of characters
+/// it takes including the closing bracket, none if it isn't closed
+///
+/// A closing bracket right after the opening one (or `!`) is part of the class.
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = chars.first() == Some(&'!');
+    let mut i = if negated { 1 } else { 0 };
+    let start = i;
+    let mut ranges = Vec::new();
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Token::Class(negated, ranges), i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).map_or(false, |&high| high != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        }
+        else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+/// Whether the tokens match the whole text
+fn matches(tokens: &[Token], text: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    // Position after the latest `Many` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < tokens.len() && tokens[p] == Token::Many {
+            p += 1;
+            backtrack = Some((p, t));
+        }
+        else if p < tokens.len() && tokens[p].accepts(text[t]) {
+            p += 1;
+            t += 1;
+        }
+        else if let Some((after_many, tried)) = backtrack {
+            // Let the latest `Many` take one more character
+            p = after_many;
+            t = tried + 1;
+            backtrack = Some((after_many, t));
+        }
+        else {
+            return false;
+        }
+    }
+    tokens[p..].iter().all(|token| *token == Token::Many)
+}
+
+/// Whether the text matches the SQL `LIKE` pattern
+pub fn like(text: &str, pattern: &str) -> bool {
+    matches(&like_tokens(pattern), text)
+}
+
+/// Whether the text matches the glob pattern
+pub fn glob(text: &str, pattern: &str) -> bool {
+    matches(&glob_tokens(pattern), text)
+}
+
+/// Text all matches of the `LIKE` pattern start with
+pub fn like_prefix(pattern: &str) -> String {
+    like_tokens(pattern).into_iter()
+        .take_while(|token| match token {
+            Token::Literal(_) => true,
+            _ => false,
+        })
+        .map(|token| match token {
+            Token::Literal(c) => c,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Smallest text greater than all texts starting with the prefix, none if there is none
+pub fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        // Skip the surrogate range, which has no characters
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => ::std::char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
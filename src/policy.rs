@@ -0,0 +1,65 @@
+//! Row-level security policies, conditions that every query reading a table
+//! applies to its rows
+//!
+//! A policy refers to `QueryOptions::context` with `Argument::Parameter(0)`,
+//! so that for example each tenant only sees its own rows. Queries reading a
+//! table with a policy fail without a context value. Deltas are not affected.
+
+use std::sync::Arc;
+
+use {DataDB, TableName, FieldName, Condition, ApplyError};
+
+impl DataDB {
+    pub(crate) fn policy(&self, name: &TableName) -> Option<&Condition> {
+        self.policies.get(name)
+    }
+
+    /// Attach `condition` to table `name` as its policy
+    ///
+    /// Materialized views store rows without applying policies, so they can't
+    /// read the table.
+    pub(crate) fn create_policy(&mut self, name: TableName, condition: Condition) -> Result<(), ApplyError> {
+        let table = self.table(name.clone()).ok_or_else(|| ApplyError::NoSuchTable(name.clone()))?;
+        if self.policies.contains_key(&name) {
+            return Err(ApplyError::PolicyExists(name));
+        }
+        for field in condition.fields() {
+            if field.table.as_ref().map_or(false, |t| *t != name) || table.field_index(field.field.clone()).is_none() {
+                return Err(ApplyError::NoSuchField(name, field.field));
+            }
+        }
+        condition.check(self.function_dict()).map_err(ApplyError::QueryError)?;
+        if let Some(view) = self.materialized.iter().find(|(_, view)| self.base_tables(view.query()).contains(&name)).map(|(view, _)| view) {
+            return Err(ApplyError::TableInUse(name, view.clone()));
+        }
+        Arc::make_mut(&mut self.policies).insert(name, condition);
+        Ok(())
+    }
+
+    pub(crate) fn drop_policy(&mut self, name: TableName) -> Result<(), ApplyError> {
+        if !self.policies.contains_key(&name) {
+            return Err(ApplyError::NoSuchPolicy(name));
+        }
+        Arc::make_mut(&mut self.policies).remove(&name);
+        Ok(())
+    }
+
+    /// Fail if the policy of table `name` reads field `field`, which then can't be dropped
+    pub(crate) fn check_not_policy_field(&self, name: &TableName, field: &FieldName) -> Result<(), ApplyError> {
+        match self.policy(name) {
+            Some(policy) if policy.fields().iter().any(|f| f.field == *field) => {
+                Err(ApplyError::PolicyField(name.clone(), field.clone()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep the policy of table `from` with the table, after renaming it to `to`
+    pub(crate) fn rename_policy(&mut self, from: &TableName, to: &TableName) {
+        if self.policies.contains_key(from) {
+            let policies = Arc::make_mut(&mut self.policies);
+            let policy = policies.remove(from).unwrap().unqualified(from);
+            policies.insert(to.clone(), policy);
+        }
+    }
+}
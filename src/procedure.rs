@@ -0,0 +1,110 @@
+//! Stored procedures, named lists of deltas and queries that
+//! `SrimDB::call_procedure` runs atomically
+//!
+//! Steps refer to the call arguments with `Argument::Parameter` in the
+//! conditions and assignments of `RemoveWhere` and `UpdateWhere`, in the values
+//! of `ProcedureStep::Insert` and in the function calls of queries. Each step
+//! sees the changes of the steps before it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use {DataDB, Delta, Query, QueryField, Argument, Value, TableName, FieldName, FunctionName, QueryError, ApplyError};
+
+#[derive(Debug, Clone)]
+pub enum ProcedureStep {
+    Apply(Delta),
+    /// Add a row from values of the named fields like `Delta::AddNamedRow`,
+    /// with the function calls among them evaluated when the procedure is called
+    Insert(TableName, Vec<(FieldName, Argument)>),
+    /// Execute a query, whose result the call returns
+    Query(Query),
+}
+impl ProcedureStep {
+    /// Number of parameters the step refers to, see `FunctionCall::parameter_count`
+    fn parameter_count(&self) -> usize {
+        let assigned = |list: &[(FieldName, Argument)]| list.iter().map(|(_, arg)| arg.parameter_count()).max().unwrap_or(0);
+        match self {
+            ProcedureStep::Apply(Delta::RemoveWhere(_, condition)) => condition.parameter_count(),
+            ProcedureStep::Apply(Delta::UpdateWhere(_, condition, list)) => condition.parameter_count().max(assigned(list)),
+            ProcedureStep::Apply(_) => 0,
+            ProcedureStep::Insert(_, values) => assigned(values),
+            ProcedureStep::Query(query) => query.parameter_count(),
+        }
+    }
+
+    /// The step with its parameters bound to `arguments`
+    fn bound(&self, db: &DataDB, arguments: &[Value]) -> Result<BoundStep, QueryError> {
+        let assigned = |list: &[(FieldName, Argument)]| list.iter().map(|(field, arg)| (field.clone(), arg.bound(arguments))).collect();
+        Ok(match self {
+            ProcedureStep::Apply(Delta::RemoveWhere(name, condition)) => {
+                BoundStep::Apply(Delta::RemoveWhere(name.clone(), condition.bound(arguments)))
+            },
+            ProcedureStep::Apply(Delta::UpdateWhere(name, condition, list)) => {
+                BoundStep::Apply(Delta::UpdateWhere(name.clone(), condition.bound(arguments), assigned(list)))
+            },
+            ProcedureStep::Apply(delta) => BoundStep::Apply(delta.clone()),
+            ProcedureStep::Insert(name, values) => {
+                let mut row = Vec::new();
+                for (field, arg) in values {
+                    let value = arg.bound(arguments).evaluate(db.function_dict(), &|qf: &QueryField| {
+                        Err(QueryError::UnboundField(qf.clone()))
+                    })?;
+                    row.push((field.clone(), value));
+                }
+                BoundStep::Apply(Delta::AddNamedRow(name.clone(), row))
+            },
+            ProcedureStep::Query(query) => BoundStep::Query(query.bound(arguments)),
+        })
+    }
+}
+
+/// Step of a procedure call, with the arguments of the call bound
+pub(crate) enum BoundStep {
+    Apply(Delta),
+    Query(Query),
+}
+
+/// Number of arguments procedure `steps` take
+pub(crate) fn arity(steps: &[ProcedureStep]) -> usize {
+    steps.iter().map(|step| step.parameter_count()).max().unwrap_or(0)
+}
+
+impl DataDB {
+    pub(crate) fn create_procedure(&mut self, name: FunctionName, steps: Vec<ProcedureStep>) -> Result<(), ApplyError> {
+        if self.procedures.contains_key(&name) {
+            return Err(ApplyError::ProcedureExists(name));
+        }
+        Arc::make_mut(&mut self.procedures).insert(name, steps);
+        Ok(())
+    }
+
+    pub(crate) fn drop_procedure(&mut self, name: FunctionName) -> Result<(), ApplyError> {
+        if !self.procedures.contains_key(&name) {
+            return Err(ApplyError::NoSuchProcedure(name));
+        }
+        Arc::make_mut(&mut self.procedures).remove(&name);
+        Ok(())
+    }
+
+    pub(crate) fn procedure_dict(&self) -> &HashMap<FunctionName, Vec<ProcedureStep>> {
+        &self.procedures
+    }
+
+    pub(crate) fn procedure(&self, name: &str) -> Option<&[ProcedureStep]> {
+        self.procedures.get(name).map(|steps| &steps[..])
+    }
+
+    /// Steps of procedure `name` with parameters bound to `arguments`
+    pub(crate) fn bound_procedure(&self, name: &str, arguments: &[Value]) -> Result<Vec<BoundStep>, ApplyError> {
+        let steps = self.procedure(name).ok_or_else(|| ApplyError::NoSuchProcedure(name.to_owned()))?;
+        let arity = arity(steps);
+        if arguments.len() < arity {
+            return Err(ApplyError::QueryError(QueryError::NotEnoughArguments(arity)));
+        }
+        if arguments.len() > arity {
+            return Err(ApplyError::QueryError(QueryError::TooManyArguments(name.to_owned(), arity)));
+        }
+        steps.iter().map(|step| step.bound(self, arguments)).collect::<Result<_, _>>().map_err(ApplyError::QueryError)
+    }
+}
@@ -1,16 +1,38 @@
+use std::borrow::Cow;
 use std::fmt;
-use std::collections::HashMap;
+use std::mem;
+use std::slice;
+use std::ops::Bound;
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
 
 use TableName;
+use Table;
 use FieldName;
 use FunctionName;
 use TableField;
+use FieldKind;
 use Row;
 use QueryError;
 use DataDB;
 use Value;
+use ValueKind;
 use TypeError;
-use function::{Function, FunctionCall};
+use function::{Function, FunctionCall, Argument, Signature};
+use bloom::{self, BloomFilter};
+use collation::{self, Collation};
+use vector;
+use pattern;
+use random;
+use temporal;
+#[cfg(feature = "regex")]
+use regex_functions;
+use json;
+use procedure;
 
 #[derive(Debug, Clone)]
 pub enum Query {
@@ -27,6 +49,10 @@ pub enum Query {
     FromFunctionCall(TableField, FunctionCall),
 
     /// Multiset Union
+    ///
+    /// Like in the other set operations, the values of each field are cast
+    /// to the most generic kind among them, so `Signed` and `Unsigned` fields
+    /// combine as `Signed`; fields without a common kind are an error
     Union(Box<Query>, Box<Query>),
 
     /// Multiset Intersection
@@ -38,80 +64,436 @@ pub enum Query {
     /// Remove duplicates
     Distinct(Box<Query>),
 
-    /// Pick fields $0 in $1
+    /// Pick fields $0 in $1, keeping the tables they are qualified with
     Project(Vec<QueryField>, Box<Query>),
 
     /// Filter the result set of query
     Filter(Condition, Box<Query>),
 
-    /// Rename $0 to $1 in $2
-    Rename(QueryField, FieldName, Box<Query>),
+    /// Rename $0 to $1 in $2, keeping the table $0 is qualified with unless $1 has one
+    Rename(QueryField, QueryField, Box<Query>),
 
     /// Select all rows
     JoinOn(Condition, Box<Query>, Box<Query>),
+
+    /// Row for each element of array field $0 in $1, with the element in place of the array;
+    /// rows with an empty or null array are dropped
+    Unnest(QueryField, Box<Query>),
+
+    /// The $2 rows of $3 whose vector field $0 has the highest cosine similarity
+    /// with $1, most similar first; rows with a null or zero vector, or a NaN component, are skipped
+    NearestNeighbors(QueryField, Vec<f32>, usize, Box<Query>),
+
+    /// Rows of $1 sorted by field $0, smallest first; rows with a null in it
+    /// come last, and equal rows keep their order
+    OrderBy(QueryField, Box<Query>),
+
+    /// Rows returned by the table function the call calls, like `generate_series`
+    FromTableFunction(FunctionCall),
 }
 impl Query {
-    pub(crate) fn execute(&self, db: &DataDB) -> Result<QueryResult, QueryError> {
+    pub(crate) fn execute(&self, ctx: &ExecutionContext) -> Result<QueryResult, QueryError> {
+        ctx.check_cancelled()?;
+        let held_before = ctx.held.get();
+        let result = self.execute_node(ctx)?;
+        ctx.check_cancelled()?;
+
+        // Inputs of this node are released when it returns
+        ctx.hold(result.approx_size_bytes())?;
+        ctx.held.set(held_before + result.approx_size_bytes());
+        Ok(result)
+    }
+
+    /// Names of the tables and views the query reads
+    pub fn tables(&self) -> Vec<&TableName> {
+        use Query::*;
+        match self {
+            Table(name) => vec![name],
+            Empty(_) | FromValue(_, _) | FromFunctionCall(_, _) | FromTableFunction(_) => Vec::new(),
+            Union(q1, q2) | Intersection(q1, q2) | Difference(q1, q2) | JoinOn(_, q1, q2) => {
+                let mut tables = q1.tables();
+                tables.extend(q2.tables());
+                tables
+            },
+            Distinct(subquery)
+            | Project(_, subquery)
+            | Filter(_, subquery)
+            | Rename(_, _, subquery)
+            | Unnest(_, subquery)
+            | NearestNeighbors(_, _, _, subquery)
+            | OrderBy(_, subquery) => subquery.tables(),
+        }
+    }
+
+    /// Table each result row comes from one row of, if the query is only
+    /// filters, projections and renames of it
+    pub(crate) fn row_source(&self) -> Option<&TableName> {
+        use Query::*;
+        match self {
+            Table(name) => Some(name),
+            Filter(_, subquery) | Project(_, subquery) | Rename(_, _, subquery) => subquery.row_source(),
+            _ => None,
+        }
+    }
+
+    /// Result of a query with a `row_source`, as if its table only had the rows
+    pub(crate) fn execute_over(&self, db: &DataDB, rows: Vec<Arc<Row>>) -> Result<QueryResult, QueryError> {
+        use Query::*;
+        match self {
+            Table(name) => {
+                let table = db.table(name.clone()).ok_or_else(|| QueryError::NoSuchTable(name.clone()))?;
+                Ok(QueryResult::from_table_rows(&table, rows))
+            },
+            Filter(condition, subquery) => subquery.execute_over(db, rows)?.filter(db.function_dict(), condition),
+            Project(fields, subquery) => subquery.execute_over(db, rows)?.project(fields),
+            Rename(from, to, subquery) => subquery.execute_over(db, rows)?.rename(from, to),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Number of parameters the function calls in the query refer to, see `FunctionCall::parameter_count`
+    pub(crate) fn parameter_count(&self) -> usize {
+        use Query::*;
+        match self {
+            Empty(_) | Table(_) | FromValue(_, _) => 0,
+            FromFunctionCall(_, fc) | FromTableFunction(fc) => fc.parameter_count(),
+            Union(q1, q2) | Intersection(q1, q2) | Difference(q1, q2) => q1.parameter_count().max(q2.parameter_count()),
+            JoinOn(condition, q1, q2) => condition.parameter_count().max(q1.parameter_count()).max(q2.parameter_count()),
+            Filter(condition, subquery) => condition.parameter_count().max(subquery.parameter_count()),
+            Distinct(subquery)
+            | Project(_, subquery)
+            | Rename(_, _, subquery)
+            | Unnest(_, subquery)
+            | NearestNeighbors(_, _, _, subquery)
+            | OrderBy(_, subquery) => subquery.parameter_count(),
+        }
+    }
+
+    /// The same query with parameters in its function calls bound to `parameters`, see `FunctionCall::bound`
+    pub(crate) fn bound(&self, parameters: &[Value]) -> Query {
+        use Query::*;
+        let bind = |query: &Query| Box::new(query.bound(parameters));
+        match self {
+            Empty(_) | Table(_) | FromValue(_, _) => self.clone(),
+            FromFunctionCall(field, fc) => FromFunctionCall(field.clone(), fc.bound(parameters)),
+            FromTableFunction(fc) => FromTableFunction(fc.bound(parameters)),
+            Union(q1, q2) => Union(bind(q1), bind(q2)),
+            Intersection(q1, q2) => Intersection(bind(q1), bind(q2)),
+            Difference(q1, q2) => Difference(bind(q1), bind(q2)),
+            Distinct(subquery) => Distinct(bind(subquery)),
+            Project(fields, subquery) => Project(fields.clone(), bind(subquery)),
+            Filter(condition, subquery) => Filter(condition.bound(parameters), bind(subquery)),
+            Rename(from, to, subquery) => Rename(from.clone(), to.clone(), bind(subquery)),
+            JoinOn(condition, q1, q2) => JoinOn(condition.bound(parameters), bind(q1), bind(q2)),
+            Unnest(field, subquery) => Unnest(field.clone(), bind(subquery)),
+            NearestNeighbors(field, vector, k, subquery) => NearestNeighbors(field.clone(), vector.clone(), *k, bind(subquery)),
+            OrderBy(field, subquery) => OrderBy(field.clone(), bind(subquery)),
+        }
+    }
+
+    /// Check the function calls in the query, see `FunctionCall::check`
+    pub(crate) fn check(&self, db: &DataDB) -> Result<(), QueryError> {
+        let fd = db.function_dict();
+        use Query::*;
+        match self {
+            Empty(_) | Table(_) | FromValue(_, _) => Ok(()),
+            FromFunctionCall(_, fc) => match fc.fields().into_iter().next() {
+                Some(qf) => Err(QueryError::UnboundField(qf)),
+                None => fc.check(fd),
+            },
+            FromTableFunction(fc) => {
+                if let Some(qf) = fc.fields().into_iter().next() {
+                    return Err(QueryError::UnboundField(qf));
+                }
+                let function = db.table_function_dict().get(&fc.target).ok_or_else(|| QueryError::NoSuchFunction(fc.target.clone()))?;
+                function.signature().check(&fc.target, &fc.argument_kinds(fd)?)
+            },
+            Union(q1, q2) | Intersection(q1, q2) | Difference(q1, q2) => {
+                q1.check(db)?;
+                q2.check(db)
+            },
+            Distinct(subquery)
+            | Project(_, subquery)
+            | Rename(_, _, subquery)
+            | Unnest(_, subquery)
+            | NearestNeighbors(_, _, _, subquery)
+            | OrderBy(_, subquery) => subquery.check(db),
+            Filter(condition, subquery) => {
+                condition.check(fd)?;
+                subquery.check(db)
+            },
+            JoinOn(condition, q1, q2) => {
+                condition.check(fd)?;
+                q1.check(db)?;
+                q2.check(db)
+            },
+        }
+    }
+
+    fn execute_node(&self, ctx: &ExecutionContext) -> Result<QueryResult, QueryError> {
+        let db = ctx.db;
         use Query::*;
         match self {
             Empty(fields) => Ok(QueryResult::new(fields.clone().iter().map(|n| QueryField::new(n.clone())).collect(), Vec::new())),
-            Table(name) => QueryResult::from_db_table(&db, name.clone()),
+            Table(name) if db.materialized_view(name).is_some() => {
+                let view = db.materialized_view(name).unwrap();
+                Ok(QueryResult::new(view.fields().to_vec(), view.rows().to_vec())
+                    .with_collations(view.collations().to_vec())
+                    .with_schema(view.schema().to_vec()))
+            },
+            Table(name) => match db.view(name) {
+                Some(view) => Ok(view.execute(ctx)?.qualified(name)),
+                None if db.table(name.clone()).is_some() => {
+                    let mut result = QueryResult::from_db_table(&db, name.clone())?;
+                    for condition in ctx.row_filters(name)? {
+                        result = result.filter(db.function_dict(), &condition)?;
+                    }
+                    Ok(result)
+                },
+                None => QueryResult::system_table(&db, name).ok_or_else(|| QueryError::NoSuchTable(name.clone())),
+            },
             FromValue(field, value) => {
-                Ok(QueryResult::new(vec![QueryField::new(field.name())], vec![Row::new(vec![value.clone()])]))
+                Ok(QueryResult::new(vec![QueryField::new(field.name())], vec![Arc::new(Row::new(vec![value.clone()]))]).with_schema(vec![Some(field.clone())]))
             },
             FromFunctionCall(field, fc) => {
                 let fd = db.function_dict();
-                let value = (*fc).resolve_args(&|_qf: &QueryField| {
-                    panic!("FromFunctionCall references a field"); // TODO: just return QueryError?
-                })?.apply(&fd)?;
+                let value = (*fc).resolve_args(&|qf: &QueryField| Err(QueryError::UnboundField(qf.clone())))?.apply(&fd)?;
 
-                Ok(QueryResult::new(vec![QueryField::new(field.name())], vec![Row::new(vec![value])]))
+                Ok(QueryResult::new(vec![QueryField::new(field.name())], vec![Arc::new(Row::new(vec![value]))]).with_schema(vec![Some(field.clone())]))
+            },
+            FromTableFunction(fc) => {
+                let fd = db.function_dict();
+                let function = db.table_function_dict().get(&fc.target).ok_or_else(|| QueryError::NoSuchFunction(fc.target.clone()))?;
+                let unbound = |qf: &QueryField| Err(QueryError::UnboundField(qf.clone()));
+                let arguments = fc.arguments.iter().map(|arg| arg.evaluate(&fd, &unbound)).collect::<Result<Vec<_>, _>>()?;
+
+                // Rows are accounted for as they are produced, since there can be any number of them
+                let mut rows = Vec::new();
+                for (i, row) in function.call(&fc.target, arguments)?.enumerate() {
+                    if i % 1024 == 0 {
+                        ctx.check_cancelled()?;
+                    }
+                    ctx.hold(mem::size_of::<Arc<Row>>() + row.approx_size_bytes())?;
+                    rows.push(Arc::new(row));
+                }
+                Ok(QueryResult::new(function.fields().into_iter().map(QueryField::new).collect(), rows).with_inferred_schema())
             },
             Union(q1, q2) => {
-                let v1 = q1.execute(&db)?;
-                let v2 = q2.execute(&db)?;
+                let v1 = q1.execute(ctx)?;
+                let v2 = q2.execute(ctx)?;
                 v1.union(&v2)
             },
             Intersection(q1, q2) => {
-                let v1 = q1.execute(&db)?;
-                let v2 = q2.execute(&db)?;
+                let v1 = q1.execute(ctx)?;
+                let v2 = q2.execute(ctx)?;
                 v1.intersection(&v2)
             },
             Difference(q1, q2) => {
-                let v1 = q1.execute(&db)?;
-                let v2 = q2.execute(&db)?;
+                let v1 = q1.execute(ctx)?;
+                let v2 = q2.execute(ctx)?;
                 v1.difference(&v2)
             },
             Distinct(subquery) => {
-                subquery.execute(&db)?.distinct()
+                subquery.execute(ctx)?.distinct()
             },
             Project(fields, subquery) => {
-                subquery.execute(&db)?.project(fields)
+                subquery.execute(ctx)?.project(fields)
             },
             Filter(condition, subquery) => {
                 let fd = db.function_dict();
-                subquery.execute(&db)?.filter(&fd, condition)
+
+                // Filters directly over a table are narrowed down with its indexes
+                let mut conditions = vec![condition];
+                let mut source = &**subquery;
+                while let Filter(condition, subquery) = source {
+                    conditions.push(condition);
+                    source = subquery;
+                }
+                if let Table(name) = source {
+                    let row_filters = ctx.row_filters(name)?;
+                    let mut conditions = conditions.clone();
+                    conditions.extend(row_filters.iter());
+                    let terms: Vec<Condition> = conditions.iter().flat_map(|c| c.and_terms()).collect();
+                    let mut index_conditions = conditions.clone();
+                    index_conditions.extend(terms.iter());
+                    if let Some(mut result) = QueryResult::from_index(&db, name, &index_conditions) {
+                        for condition in conditions.iter().rev() {
+                            result = result.filter(&fd, condition)?;
+                        }
+                        return Ok(result);
+                    }
+                }
+                subquery.execute(ctx)?.filter(&fd, condition)
             },
             Rename(from, to, subquery) => {
-                subquery.execute(&db)?.rename(from, to)
+                subquery.execute(ctx)?.rename(from, to)
             },
             JoinOn(condition, q1, q2) => {
                 let fd = db.function_dict();
-                let v1 = q1.execute(&db)?;
-                let v2 = q2.execute(&db)?;
+                let mut v1 = q1.execute(ctx)?;
+                let mut v2 = q2.execute(ctx)?;
+                ctx.check_cancelled()?;
+                QueryResult::skip_unjoinable(&mut v1, &mut v2, condition);
+
+                // The cross product is materialized before filtering
+                let width = v1.fields.len() + v2.fields.len();
+                let pair_bytes = mem::size_of::<Arc<Row>>() + mem::size_of::<Row>() + width * mem::size_of::<Value>();
+                let held = ctx.held.get();
+                ctx.hold(v1.rows.len().saturating_mul(v2.rows.len()).saturating_mul(pair_bytes))?;
+                ctx.held.set(held);
+
                 v1.join_on(&fd, &v2, condition)
+            },
+            Unnest(field, subquery) => {
+                subquery.execute(ctx)?.unnest(field)
+            },
+            NearestNeighbors(field, vector, k, subquery) => {
+                subquery.execute(ctx)?.nearest_neighbors(field, vector, *k)
+            },
+            OrderBy(field, subquery) => {
+                if let Table(name) = &**subquery {
+                    if let Some(result) = QueryResult::ordered_by_index(&db, name, field) {
+                        return Ok(result);
+                    }
+                }
+                subquery.execute(ctx)?.order_by(field)
+            },
+        }
+    }
+}
+
+/// Shared flag for aborting running queries from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Queries using this token stop at the next operator boundary
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Execution options for a single query
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Abort the query if it runs longer than this
+    pub timeout: Option<Duration>,
+    /// Abort the query when the token is cancelled
+    pub cancel_token: Option<CancelToken>,
+    /// Seed the random functions, so that they repeat their results
+    pub seed: Option<u64>,
+    /// Value of the session, like a tenant id, that row-level security
+    /// policies refer to, see `Delta::CreatePolicy`
+    pub context: Option<Value>,
+}
+
+/// Per-execution state shared by all nodes of a query
+pub(crate) struct ExecutionContext<'a> {
+    pub(crate) db: &'a DataDB,
+    memory_limit: Option<usize>,
+    deadline: Option<Instant>,
+    cancel_token: Option<CancelToken>,
+    /// Approximate bytes held by live intermediate results
+    held: Cell<usize>,
+    /// Keeps regular expressions compiled while the query executes
+    #[cfg(feature = "regex")]
+    patterns: regex_functions::CacheScope,
+    /// Seeds the random functions while the query executes
+    seed: Option<random::SeedScope>,
+    context: Option<Value>,
+    /// Time rows expire relative to, the same for the whole query
+    now: i64,
+}
+impl<'a> ExecutionContext<'a> {
+    pub(crate) fn new(db: &'a DataDB) -> Self {
+        Self {
+            db,
+            memory_limit: None,
+            deadline: None,
+            cancel_token: None,
+            held: Cell::new(0),
+            #[cfg(feature = "regex")]
+            patterns: regex_functions::CacheScope::new(),
+            seed: None,
+            context: None,
+            now: temporal::now(),
+        }
+    }
+
+    pub(crate) fn with_memory_limit(self, memory_limit: Option<usize>) -> Self {
+        Self { memory_limit, ..self }
+    }
+
+    pub(crate) fn with_options(self, options: QueryOptions) -> Self {
+        Self {
+            deadline: options.timeout.map(|t| Instant::now() + t),
+            cancel_token: options.cancel_token,
+            seed: Some(random::SeedScope::new(options.seed)),
+            context: options.context,
+            ..self
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), QueryError> {
+        if let Some(ref token) = self.cancel_token {
+            if token.is_cancelled() {
+                return Err(QueryError::Cancelled);
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(QueryError::Cancelled);
             }
         }
+        Ok(())
+    }
+
+    /// Conditions the rows of table `name` are read through: its row-level
+    /// security policy with the context bound, and skipping expired rows
+    fn row_filters(&self, name: &TableName) -> Result<Vec<Condition>, QueryError> {
+        let mut conditions = Vec::new();
+        match (self.db.policy(name), &self.context) {
+            (None, _) => {},
+            (Some(policy), Some(context)) => conditions.push(policy.bound(slice::from_ref(context))),
+            (Some(_), None) => return Err(QueryError::NoContext(name.clone())),
+        }
+        conditions.extend(self.db.table(name.clone()).and_then(|table| table.unexpired_condition(self.now)));
+        Ok(conditions)
+    }
+
+    fn hold(&self, bytes: usize) -> Result<(), QueryError> {
+        let held = self.held.get() + bytes;
+        if let Some(limit) = self.memory_limit {
+            if held > limit {
+                return Err(QueryError::MemoryLimitExceeded);
+            }
+        }
+        self.held.set(held);
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Condition {
     Value(Value),
     QueryField(QueryField),
     FunctionCall(FunctionCall),
 }
 impl Condition {
+    pub(crate) fn check(&self, function_dict: &HashMap<FunctionName, Function>) -> Result<(), QueryError> {
+        match self {
+            Condition::FunctionCall(fc) => fc.check(function_dict),
+            _ => Ok(()),
+        }
+    }
+
     pub(crate) fn test(&self,
         function_dict: &HashMap<FunctionName, Function>,
         resolve: &Fn(&QueryField) -> Result<Value, QueryError>,
@@ -119,6 +501,8 @@ impl Condition {
         match self {
             Condition::Value(v) => match v {
                 Value::Boolean(b) => Ok(*b),
+                // Unknown doesn't satisfy the condition
+                Value::Null => Ok(false),
                 _ => Err(QueryError::TypeError(TypeError::NotBoolean))
             },
             Condition::QueryField(qf) => {
@@ -132,10 +516,114 @@ impl Condition {
             },
         }
     }
+
+    /// Conditions that must all hold for this one to hold, if it is a call of `and`,
+    /// including those of nested calls
+    fn and_terms(&self) -> Vec<Condition> {
+        let fc = match self {
+            Condition::FunctionCall(fc) if fc.target == "and" => fc,
+            _ => return Vec::new(),
+        };
+        let mut terms = Vec::new();
+        for argument in &fc.arguments {
+            let term = match argument {
+                Argument::FunctionCall(fc) => Condition::FunctionCall(fc.clone()),
+                Argument::Value(v) => Condition::Value(v.clone()),
+                Argument::QueryField(qf) => Condition::QueryField(qf.clone()),
+                // Not bound outside composed functions
+                Argument::Parameter(_) => continue,
+            };
+            terms.extend(term.and_terms());
+            terms.push(term);
+        }
+        terms
+    }
+
+    /// Fields referenced by the condition
+    pub(crate) fn fields(&self) -> Vec<QueryField> {
+        match self {
+            Condition::Value(_) => Vec::new(),
+            Condition::QueryField(qf) => vec![qf.clone()],
+            Condition::FunctionCall(fc) => fc.fields(),
+        }
+    }
+
+    /// Replace references to field `from` with references to field `to`
+    pub(crate) fn rename_field(&mut self, from: &FieldName, to: &FieldName) {
+        match self {
+            Condition::Value(_) => {},
+            Condition::QueryField(qf) => if qf.field == *from {
+                qf.field = to.clone();
+            },
+            Condition::FunctionCall(fc) => fc.rename_field(from, to),
+        }
+    }
+
+    pub(crate) fn parameter_count(&self) -> usize {
+        match self {
+            Condition::FunctionCall(fc) => fc.parameter_count(),
+            _ => 0,
+        }
+    }
+
+    /// The same condition with parameters bound to `parameters`, see `FunctionCall::bound`
+    pub(crate) fn bound(&self, parameters: &[Value]) -> Condition {
+        match self {
+            Condition::FunctionCall(fc) => Condition::FunctionCall(fc.bound(parameters)),
+            condition => condition.clone(),
+        }
+    }
+
+    /// The same condition with references to fields of table `table` unqualified
+    pub(crate) fn unqualified(&self, table: &TableName) -> Condition {
+        match self {
+            Condition::QueryField(qf) if qf.table.as_ref() == Some(table) => Condition::QueryField(QueryField::new(qf.field.clone())),
+            Condition::FunctionCall(fc) => Condition::FunctionCall(fc.unqualified(table)),
+            condition => condition.clone(),
+        }
+    }
 }
 
 
-#[derive(Debug, Clone)]
+/// Pseudo-field holding the id of stored rows, unless the table has a field with this name
+pub const ROWID_FIELD: &str = "rowid";
+
+/// System table listing the indexes of all tables, unless a table or view has this name
+///
+/// Its fields are those of `IndexInfo`, with the kind as text and the expression
+/// and predicate in the JSON encoding of deltas.
+pub const INDEXES_TABLE: &str = "__indexes";
+
+/// System table listing the tables, views and materialized views, unless a
+/// table or view has this name
+///
+/// Its fields are `name`, `kind`, the number of `rows` (null for views), the
+/// `key_fields`, whether the table is `temporary`, and the `query` of views
+/// in the JSON encoding of deltas.
+pub const TABLES_TABLE: &str = "__tables";
+
+/// System table listing the fields of all tables, unless a table or view has this name
+///
+/// Its fields are the `table`, `name`, `position` counting from zero, `kind`
+/// as text, whether the field is `nullable` and a `key` field, and its
+/// `default` in the JSON encoding of deltas.
+pub const FIELDS_TABLE: &str = "__fields";
+
+/// System table listing the functions, table functions and procedures, unless
+/// a table or view has this name
+///
+/// Its fields are `name`, `kind`, `min_arity`, `max_arity` (null if there is
+/// no limit), the kind the function `returns` as text if it is known, and the
+/// `body` of composed functions and the steps of procedures in the JSON
+/// encoding of deltas. Overloaded functions have a row per overload.
+pub const FUNCTIONS_TABLE: &str = "__functions";
+
+/// Whether `Query::Table` reads a system table by `name` if there is no table or view with it
+pub(crate) fn is_system_table(name: &str) -> bool {
+    [INDEXES_TABLE, TABLES_TABLE, FIELDS_TABLE, FUNCTIONS_TABLE].contains(&name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct QueryField {
     pub table: Option<TableName>,
     pub field: FieldName
@@ -165,81 +653,571 @@ impl fmt::Display for QueryField {
     }
 }
 
-#[derive(Debug)]
+/// Rows with their values replaced by their keys in the collations, see `Collation::key`
+fn keyed_rows<'a>(collations: &[Collation], rows: &'a [Arc<Row>]) -> Cow<'a, [Arc<Row>]> {
+    if collation::all_binary(collations) {
+        return Cow::Borrowed(rows);
+    }
+    Cow::Owned(rows.iter().map(|row| Arc::new(Row::new(collation::key_values(collations, &row.values())))).collect())
+}
+
+/// Field `name` holding the values of `column` in `rows`, of the most
+/// generic of their kinds and nullable if some are null, none if all are
+/// null or their kinds have nothing in common
+fn inferred_field(name: &FieldName, rows: &[Arc<Row>], column: usize) -> Option<TableField> {
+    let (mut kind, mut nullable): (Option<FieldKind>, bool) = (None, false);
+    for value in rows.iter().map(|row| row.value(column)) {
+        if value.is_null() {
+            nullable = true;
+        }
+        else if let Some(value_kind) = FieldKind::of_value(value) {
+            kind = Some(match kind {
+                Some(kind) => kind.more_generic(&value_kind)?,
+                None => value_kind,
+            });
+        }
+    }
+    let field = TableField::new(name.clone(), kind?);
+    Some(if nullable { field.nullable() } else { field })
+}
+
+/// Field or expression of a condition that an index may be over
+enum IndexTerm {
+    Column(usize),
+    Expression(FunctionCall),
+}
+
+#[derive(Debug, Clone)]
 pub struct QueryResult {
     fields: Vec<QueryField>,
-    rows: Vec<Row>
+    rows: Vec<Arc<Row>>,
+    /// Collation of each field, see `TableField::with_collation`; empty if all are binary
+    collations: Vec<Collation>,
+    /// Field each column has the kind and nullability of, none where they
+    /// aren't known; empty if none are, see `fields`
+    schema: Vec<Option<TableField>>,
 }
 impl QueryResult {
     pub fn field_names(&self) -> Vec<FieldName> {
         self.fields.iter().map(|f| f.field.clone()).collect()
     }
 
+    /// Fields with the tables they are qualified with, if any
+    pub fn query_fields(&self) -> &[QueryField] {
+        &self.fields
+    }
+
     pub fn rows(&self) -> Vec<Row> {
-        self.rows.clone()
+        self.rows.iter().map(|row| (**row).clone()).collect()
     }
 
-    pub(super) fn new(fields: Vec<QueryField>, rows: Vec<Row>) -> Self {
-        Self { fields, rows }
+    /// Value of field `field` in row `row` converted to `T`, see `Row::get`
+    pub fn get<T: TryFrom<Value, Error = QueryError>>(&self, row: usize, field: &str) -> Result<T, QueryError> {
+        let qf = QueryField::new(field.to_owned());
+        match self.match_field(&qf).as_slice() {
+            [] => Err(QueryError::NoSuchField(qf)),
+            [column] => self.rows.get(row).ok_or(QueryError::OutOfRange)?.get(*column),
+            _ => Err(QueryError::AmbiguousField(qf)),
+        }
+    }
+
+    /// Rows without copying, shared with the database and other results
+    pub fn shared_rows(&self) -> &[Arc<Row>] {
+        &self.rows
+    }
+
+    /// Approximate number of bytes held by this result
+    ///
+    /// Rows shared with the database or with other results only count
+    /// as the size of the pointer.
+    pub fn approx_size_bytes(&self) -> usize {
+        let fields: usize = self.fields.iter()
+            .map(|f| mem::size_of::<QueryField>() + f.field.len() + f.table.as_ref().map_or(0, |t| t.len()))
+            .sum();
+        let rows: usize = self.rows.iter()
+            .map(|row| mem::size_of::<Arc<Row>>() + if Arc::strong_count(row) == 1 { row.approx_size_bytes() } else { 0 })
+            .sum();
+        fields + rows
+    }
+
+    pub(super) fn new(fields: Vec<QueryField>, rows: Vec<Arc<Row>>) -> Self {
+        Self { fields, rows, collations: Vec::new(), schema: Vec::new() }
+    }
+
+    /// Rows with the fields of the table, compared in their collations
+    pub(crate) fn from_table_rows(table: &Table, rows: Vec<Arc<Row>>) -> Self {
+        Self::new(Self::table_fields(table), rows)
+            .with_collations(table.collations())
+            .with_schema(table.fields().iter().cloned().map(Some).collect())
+    }
+
+    /// The result with other rows, of the same fields
+    fn with_rows(&self, rows: Vec<Arc<Row>>) -> Self {
+        Self { fields: self.fields.clone(), rows, collations: self.collations.clone(), schema: self.schema.clone() }
+    }
+
+    pub(crate) fn with_schema(self, schema: Vec<Option<TableField>>) -> Self {
+        let schema = if schema.iter().all(Option::is_none) { Vec::new() } else { schema };
+        Self { schema, ..self }
+    }
+
+    /// The result with the kinds of its fields inferred from their values,
+    /// for sources without a schema
+    fn with_inferred_schema(self) -> Self {
+        let schema = (0..self.fields.len()).map(|i| inferred_field(&self.fields[i].field, &self.rows, i)).collect();
+        self.with_schema(schema)
+    }
+
+    /// Fields of the result, with the kinds known for them during execution
+    /// and their collations
+    ///
+    /// Fields whose kind isn't known, like those with only nulls or those of
+    /// `Query::Empty`, are nullable blobs.
+    pub fn fields(&self) -> Vec<TableField> {
+        self.fields.iter().enumerate()
+            .map(|(i, qf)| {
+                let field = match self.schema_field(i) {
+                    Some(field) if field.is_nullable() => TableField::new(qf.field.clone(), field.kind()).nullable(),
+                    Some(field) => TableField::new(qf.field.clone(), field.kind()),
+                    None => TableField::new(qf.field.clone(), FieldKind::Blob).nullable(),
+                };
+                field.with_collation(self.collation(i))
+            })
+            .collect()
+    }
+
+    fn schema_field(&self, column: usize) -> Option<&TableField> {
+        self.schema.get(column).and_then(Option::as_ref)
+    }
+
+    fn schema_of(&self, columns: &[usize]) -> Vec<Option<TableField>> {
+        columns.iter().map(|&i| self.schema_field(i).cloned()).collect()
+    }
+
+    pub(crate) fn schema(&self) -> Vec<Option<TableField>> {
+        self.schema_of(&(0..self.fields.len()).collect::<Vec<_>>())
+    }
+
+    pub(crate) fn with_collations(self, collations: Vec<Collation>) -> Self {
+        let collations = if collation::all_binary(&collations) { Vec::new() } else { collations };
+        Self { collations, ..self }
+    }
+
+    /// Collation of each field
+    pub fn collations(&self) -> Vec<Collation> {
+        (0..self.fields.len()).map(|i| self.collation(i)).collect()
+    }
+
+    fn collation(&self, column: usize) -> Collation {
+        self.collations.get(column).cloned().unwrap_or_default()
+    }
+
+    /// `condition` comparing texts of fields in their collations, see `collation::collated`
+    pub(crate) fn collated<'a>(&self, condition: &'a Condition) -> Cow<'a, Condition> {
+        if self.collations.is_empty() {
+            return Cow::Borrowed(condition);
+        }
+        Cow::Owned(collation::collated(condition, &|qf: &QueryField| match self.match_field(qf).as_slice() {
+            [i] => self.collation(*i),
+            _ => Collation::Binary,
+        }))
     }
 
     pub(super) fn from_db_table(db: &DataDB, table_name: TableName) -> Result<Self, QueryError> {
         if let Some(table) = db.table(table_name.clone()) {
-            let fields = table.fields().iter()
-                .map(|f| QueryField::new(f.name()).from_table(table_name.clone()))
-                .collect();
-
-            Ok(Self { fields, rows: db.all_rows(table_name).unwrap() })
+            Ok(Self::from_table_rows(&table, db.all_rows(table_name).unwrap()))
         }
         else {
             Err(QueryError::NoSuchTable(table_name))
         }
     }
 
-    pub fn union(&self, other: &QueryResult) -> Result<QueryResult, QueryError> {
-        if self.field_names() != other.field_names() {
-            return Err(QueryError::DifferentFields);
+    /// Rows of the table that may satisfy all of the conditions, found with an
+    /// index, none if no index can narrow them down
+    ///
+    /// Conditions of the form `strict_eq(field, value)` are looked up together,
+    /// and otherwise one of the form `strict_eq(expression, value)`, then one of
+    /// the form `matches(x, query)` and then one of the form `lt(x, value)`,
+    /// `gt(x, value)`, `lte(x, value)`, `gte(x, value)`, `between(x, low, high)` or
+    /// `like(x, pattern)` with a pattern starting with a literal prefix, where `x`
+    /// is a field or an expression. Partial indexes are used if their
+    /// predicate is one of the conditions, which otherwise selects their rows.
+    fn from_index(db: &DataDB, table_name: &TableName, conditions: &[&Condition]) -> Option<Self> {
+        let table = db.table(table_name.clone())?;
+        let column = |argument: &Argument| match argument {
+            Argument::QueryField(qf) if qf.table.as_ref().map_or(true, |t| t == table_name) => {
+                table.field_index(qf.field.clone()).map(IndexTerm::Column)
+            },
+            Argument::FunctionCall(fc) => Some(IndexTerm::Expression(fc.clone())),
+            _ => None,
+        };
+        let constant = |argument: &Argument| match argument {
+            Argument::Value(v) => Some(v.clone()),
+            _ => None,
+        };
+
+        let (mut columns, mut values, mut expressions, mut ranges, mut searches) = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for condition in conditions {
+            let fc = match condition {
+                Condition::FunctionCall(fc) => fc,
+                _ => continue,
+            };
+            match (fc.target.as_str(), fc.arguments.as_slice()) {
+                ("strict_eq", [a, b]) => {
+                    match column(a).zip(constant(b)).or_else(|| column(b).zip(constant(a))) {
+                        Some((IndexTerm::Column(column), value)) => {
+                            columns.push(column);
+                            values.push(value);
+                        },
+                        Some((IndexTerm::Expression(expression), value)) => expressions.push((expression, value)),
+                        None => {},
+                    }
+                },
+                ("lt", [a, b]) | ("gt", [b, a]) => {
+                    if let Some((column, value)) = column(a).zip(constant(b)) {
+                        ranges.push((column, Bound::Unbounded, Bound::Excluded(value)));
+                    }
+                    else if let Some((column, value)) = column(b).zip(constant(a)) {
+                        ranges.push((column, Bound::Excluded(value), Bound::Unbounded));
+                    }
+                },
+                ("lte", [a, b]) | ("gte", [b, a]) => {
+                    if let Some((column, value)) = column(a).zip(constant(b)) {
+                        ranges.push((column, Bound::Unbounded, Bound::Included(value)));
+                    }
+                    else if let Some((column, value)) = column(b).zip(constant(a)) {
+                        ranges.push((column, Bound::Included(value), Bound::Unbounded));
+                    }
+                },
+                ("between", [a, low, high]) => {
+                    if let (Some(column), Some(low), Some(high)) = (column(a), constant(low), constant(high)) {
+                        ranges.push((column, Bound::Included(low), Bound::Included(high)));
+                    }
+                },
+                ("like", [a, b]) => {
+                    if let (Some(column), Some(Value::Text(p))) = (column(a), constant(b)) {
+                        let prefix = pattern::like_prefix(&p);
+                        if !prefix.is_empty() {
+                            let upper = pattern::prefix_end(&prefix).map_or(Bound::Unbounded, |end| Bound::Excluded(Value::Text(end.into())));
+                            ranges.push((column, Bound::Included(Value::Text(prefix.into())), upper));
+                        }
+                    }
+                },
+                ("matches", [a, b]) => {
+                    if let (Some(column), Some(Value::Text(query))) = (column(a), constant(b)) {
+                        searches.push((column, query));
+                    }
+                },
+                _ => {},
+            }
         }
-        let mut rows: Vec<Row> = Vec::new();
-        rows.extend(self.rows.clone());
-        rows.extend(other.rows.clone());
-        Ok(QueryResult::new(self.fields.clone(), rows))
+
+        let rows = db.index_lookup(table_name, &columns, &values)
+            .or_else(|| db.partial_lookup(table_name, conditions, &columns, &values))
+            .or_else(|| expressions.iter().filter_map(|(expression, value)| db.expression_lookup(table_name, expression, value)).next())
+            .or_else(|| searches.iter().filter_map(|(term, query)| match term {
+                IndexTerm::Column(column) => db.text_search(table_name, conditions, *column, query),
+                IndexTerm::Expression(expression) => db.expression_search(table_name, expression, query),
+            }).next())
+            .or_else(|| ranges.iter().filter_map(|(term, lower, upper)| match term {
+                IndexTerm::Column(column) => db.index_range(table_name, *column, lower.as_ref(), upper.as_ref())
+                    .or_else(|| db.partial_range(table_name, conditions, *column, lower.as_ref(), upper.as_ref())),
+                IndexTerm::Expression(expression) => db.expression_range(table_name, expression, lower.as_ref(), upper.as_ref()),
+            }).next())
+            .or_else(|| db.partial_rows(table_name, conditions))
+            .or_else(|| {
+                let column_ranges: Vec<_> = ranges.iter().filter_map(|(term, lower, upper)| match term {
+                    IndexTerm::Column(column) => Some((*column, lower.as_ref(), upper.as_ref())),
+                    IndexTerm::Expression(_) => None,
+                }).collect();
+                db.partition_rows(table_name, &columns, &values, &column_ranges)
+            })?;
+        Some(Self::from_table_rows(&table, rows))
     }
 
-    pub fn intersection(&self, other: &QueryResult) -> Result<QueryResult, QueryError> {
-        if self.field_names() != other.field_names() {
-            return Err(QueryError::DifferentFields);
+    /// Rows of the table sorted by the field using an index, none if no index covers it
+    fn ordered_by_index(db: &DataDB, table_name: &TableName, field: &QueryField) -> Option<Self> {
+        if field.table.as_ref().map_or(false, |t| t != table_name) {
+            return None;
         }
-        let mut rows: Vec<Row> = Vec::new();
-        for row in self.rows() {
-            if other.rows.contains(&row) {
-                rows.push(row.clone());
+        let table = db.table(table_name.clone())?;
+        let column = table.field_index(field.field.clone())?;
+        let rows = db.index_order(table_name, column)?;
+        Some(Self::from_table_rows(&table, rows))
+    }
+
+    /// Rows of system table `name`, none if there is no such system table
+    fn system_table(db: &DataDB, name: &str) -> Option<Self> {
+        match name {
+            INDEXES_TABLE => Some(Self::indexes_table(db)),
+            TABLES_TABLE => Some(Self::tables_table(db)),
+            FIELDS_TABLE => Some(Self::fields_table(db)),
+            FUNCTIONS_TABLE => Some(Self::functions_table(db)),
+            _ => None,
+        }.map(Self::with_inferred_schema)
+    }
+
+    fn system_fields(table: &str, names: &[&str]) -> Vec<QueryField> {
+        names.iter().map(|f| QueryField::new((*f).to_owned()).from_table(table.to_owned())).collect()
+    }
+
+    fn tables_table(db: &DataDB) -> Self {
+        let fields = Self::system_fields(TABLES_TABLE, &["name", "kind", "rows", "key_fields", "temporary", "query"]);
+        let text = |t: &str| Value::Text(t.into());
+        let mut rows: Vec<Arc<Row>> = db.tables.iter().map(|table| {
+            let keys = table.fields().into_iter().enumerate()
+                .filter(|(i, _)| table.is_key_field(*i))
+                .map(|(_, f)| text(&f.name()))
+                .collect();
+            Arc::new(Row::new(vec![
+                text(&table.name()),
+                text("table"),
+                Value::Unsigned(db.table_rows[&table.name()].len() as u128),
+                Value::Array(keys),
+                Value::Boolean(table.is_temporary()),
+                Value::Null,
+            ]))
+        }).collect();
+        let mut views: Vec<(&TableName, &str, &Query)> = db.views.iter().map(|(name, query)| (name, "view", query))
+            .chain(db.materialized.iter().map(|(name, view)| (name, "materialized view", view.query())))
+            .collect();
+        views.sort_by_key(|(name, _, _)| *name);
+        rows.extend(views.into_iter().map(|(name, kind, query)| Arc::new(Row::new(vec![
+            text(name),
+            text(kind),
+            db.materialized_view(name).map_or(Value::Null, |view| Value::Unsigned(view.rows().len() as u128)),
+            Value::Array(Vec::new()),
+            Value::Boolean(false),
+            text(&json::to_json(query)),
+        ]))));
+        Self::new(fields, rows)
+    }
+
+    fn fields_table(db: &DataDB) -> Self {
+        let fields = Self::system_fields(FIELDS_TABLE, &["table", "name", "position", "kind", "nullable", "key", "default"]);
+        let rows = db.tables.iter().flat_map(|table| {
+            table.fields().into_iter().enumerate().map(move |(i, field)| Arc::new(Row::new(vec![
+                Value::Text(table.name().into()),
+                Value::Text(field.name().into()),
+                Value::Unsigned(i as u128),
+                Value::Text(format!("{:?}", field.kind()).into()),
+                Value::Boolean(field.is_nullable()),
+                Value::Boolean(table.is_key_field(i)),
+                field.default().map_or(Value::Null, |d| Value::Text(json::to_json(&d).into())),
+            ])))
+        }).collect();
+        Self::new(fields, rows)
+    }
+
+    fn functions_table(db: &DataDB) -> Self {
+        let fields = Self::system_fields(FUNCTIONS_TABLE, &["name", "kind", "min_arity", "max_arity", "returns", "body"]);
+        let row = |name: &str, kind: &str, signature: Signature, body: Value| Arc::new(Row::new(vec![
+            Value::Text(name.into()),
+            Value::Text(kind.into()),
+            Value::Unsigned(signature.min_arity as u128),
+            signature.max_arity.map_or(Value::Null, |max| Value::Unsigned(max as u128)),
+            signature.returns.map_or(Value::Null, |kind| Value::Text(format!("{:?}", kind).into())),
+            body,
+        ]));
+        let mut names: Vec<&FunctionName> = db.function_dict().keys().collect();
+        names.sort();
+        let mut rows = Vec::new();
+        for name in names {
+            let function = &db.function_dict()[name];
+            match function {
+                Function::Native(native) => rows.push(row(name, "native", native.signature(), Value::Null)),
+                Function::Overloaded(overloads) => {
+                    rows.extend(overloads.iter().map(|native| row(name, "overloaded", native.signature(), Value::Null)));
+                },
+                Function::Composed(body) => {
+                    rows.push(row(name, "composed", function.signature(), Value::Text(json::to_json(body).into())));
+                },
             }
         }
-        Ok(QueryResult::new(self.fields.clone(), rows))
+        let mut names: Vec<&FunctionName> = db.table_function_dict().keys().collect();
+        names.sort();
+        for name in names {
+            rows.push(row(name, "table", db.table_function_dict()[name].signature(), Value::Null));
+        }
+        let mut names: Vec<&FunctionName> = db.procedure_dict().keys().collect();
+        names.sort();
+        for name in names {
+            let steps = &db.procedure_dict()[name];
+            rows.push(row(name, "procedure", Signature::exactly(procedure::arity(steps)), Value::Text(json::to_json(steps).into())));
+        }
+        Self::new(fields, rows)
+    }
+
+    fn indexes_table(db: &DataDB) -> Self {
+        let fields = Self::system_fields(INDEXES_TABLE, &["table", "kind", "fields", "expression", "predicate", "entries", "size_bytes", "uses"]);
+        let rows = db.index_infos().into_iter().map(|info| Arc::new(Row::new(vec![
+            Value::Text(info.table.into()),
+            Value::Text(format!("{:?}", info.kind).into()),
+            Value::Array(info.fields.into_iter().map(|f| Value::Text(f.into())).collect()),
+            info.expression.map_or(Value::Null, |e| Value::Text(json::to_json(&e).into())),
+            info.predicate.map_or(Value::Null, |p| Value::Text(json::to_json(&p).into())),
+            Value::Unsigned(info.entries as u128),
+            Value::Unsigned(info.size_bytes as u128),
+            Value::Unsigned(u128::from(info.uses)),
+        ]))).collect();
+        Self::new(fields, rows)
+    }
+
+    /// The result with its fields qualified with table `name` instead, like the result of a view
+    fn qualified(self, name: &TableName) -> Self {
+        let fields = self.fields.into_iter().map(|f| QueryField::new(f.field).from_table(name.clone())).collect();
+        Self { fields, ..self }
+    }
+
+    fn table_fields(table: &Table) -> Vec<QueryField> {
+        table.fields().iter()
+            .map(|f| QueryField::new(f.name()).from_table(table.name()))
+            .collect()
+    }
+
+    /// Rows of both results, with values coerced like in `shared_kinds`
+    pub fn union(&self, other: &QueryResult) -> Result<QueryResult, QueryError> {
+        let (schema, kinds) = self.shared_kinds(other)?;
+        let mut rows: Vec<Arc<Row>> = Vec::new();
+        rows.extend(self.coerced(&kinds)?.rows.iter().cloned());
+        rows.extend(other.coerced(&kinds)?.rows.iter().cloned());
+        Ok(QueryResult::new(self.fields.clone(), rows).with_collations(self.shared_collations(other)).with_schema(schema))
+    }
+
+    pub fn intersection(&self, other: &QueryResult) -> Result<QueryResult, QueryError> {
+        let (schema, kinds) = self.shared_kinds(other)?;
+        let collations = self.shared_collations(other);
+        let rows = self.coerced(&kinds)?.rows_found_in(&*other.coerced(&kinds)?, &collations, true);
+        Ok(QueryResult::new(self.fields.clone(), rows).with_collations(collations).with_schema(schema))
     }
 
     pub fn difference(&self, other: &QueryResult) -> Result<QueryResult, QueryError> {
+        let (schema, kinds) = self.shared_kinds(other)?;
+        let collations = self.shared_collations(other);
+        let rows = self.coerced(&kinds)?.rows_found_in(&*other.coerced(&kinds)?, &collations, false);
+        Ok(QueryResult::new(self.fields.clone(), rows).with_collations(collations).with_schema(schema))
+    }
+
+    /// Kinds that the values of each field are cast to for a set operation
+    /// with `other`, the most generic of those in either result or their
+    /// fields, so that for example `Signed` and `Unsigned` columns combine
+    /// as `Signed`, with the fields of the result
+    fn shared_kinds(&self, other: &QueryResult) -> Result<(Vec<Option<TableField>>, Vec<ValueKind>), QueryError> {
         if self.field_names() != other.field_names() {
             return Err(QueryError::DifferentFields);
         }
-        let mut rows: Vec<Row> = Vec::new();
-        for row in self.rows() {
-            if !other.rows.contains(&row) {
+        let schema: Vec<Option<TableField>> = self.fields.iter().enumerate()
+            .map(|(i, qf)| match (self.schema_field(i), other.schema_field(i)) {
+                (Some(a), Some(b)) => a.kind().more_generic(&b.kind()).map(|kind| {
+                    let field = TableField::new(qf.field.clone(), kind);
+                    if a.is_nullable() || b.is_nullable() { field.nullable() } else { field }
+                }),
+                // Values of fields without a known kind are all null, if any
+                (Some(field), None) | (None, Some(field)) => Some(field.clone().nullable()),
+                (None, None) => None,
+            })
+            .collect();
+        let kinds = schema.iter()
+            .map(|field| field.as_ref().and_then(|f| f.kind().value_kind()).unwrap_or(ValueKind::Null))
+            .collect();
+        let kinds = self.generalized_kinds(kinds)?;
+        Ok((schema, other.generalized_kinds(kinds)?))
+    }
+
+    /// `kinds` made general enough for the values of each field
+    fn generalized_kinds(&self, mut kinds: Vec<ValueKind>) -> Result<Vec<ValueKind>, QueryError> {
+        for row in &self.rows {
+            for (i, kind) in kinds.iter_mut().enumerate() {
+                let value_kind = row.value(i).kind();
+                *kind = kind.more_generic(value_kind)
+                    .ok_or_else(|| QueryError::IncompatibleColumns(self.fields[i].field.clone(), *kind, value_kind))?;
+            }
+        }
+        Ok(kinds)
+    }
+
+    /// The result with the values of each field cast to the most generic
+    /// kind among them, like in a union of results that each have one kind
+    pub(crate) fn unified(self) -> Result<QueryResult, QueryError> {
+        let kinds = self.generalized_kinds(vec![ValueKind::Null; self.fields.len()])?;
+        let coerced = match self.coerced(&kinds)? {
+            Cow::Owned(coerced) => Some(coerced),
+            Cow::Borrowed(_) => None,
+        };
+        Ok(coerced.unwrap_or(self))
+    }
+
+    /// The result with the values that aren't null or of `kinds` cast to them
+    fn coerced<'a>(&'a self, kinds: &[ValueKind]) -> Result<Cow<'a, QueryResult>, QueryError> {
+        let matches = |row: &Row| kinds.iter().enumerate().all(|(i, kind)| row.value(i).is_null() || row.value(i).kind() == *kind);
+        if self.rows.iter().all(|row| matches(row)) {
+            return Ok(Cow::Borrowed(self));
+        }
+        let mut rows = Vec::with_capacity(self.rows.len());
+        for row in &self.rows {
+            if matches(row) {
                 rows.push(row.clone());
+                continue;
             }
+            let values = row.values().into_iter().zip(kinds.iter())
+                .map(|(value, kind)| if value.is_null() { Ok(value) } else { value.cast_to(*kind) })
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.push(Arc::new(Row::new(values).with_id(row.id())));
         }
-        Ok(QueryResult::new(self.fields.clone(), rows))
+        Ok(Cow::Owned(self.with_rows(rows)))
     }
 
+    /// Collations of the fields of a set operation with `other`, those of
+    /// this result unless only `other` has one other than binary
+    fn shared_collations(&self, other: &QueryResult) -> Vec<Collation> {
+        (0..self.fields.len())
+            .map(|i| match self.collation(i) {
+                Collation::Binary => other.collation(i),
+                collation => collation,
+            })
+            .collect()
+    }
+
+    /// Rows equal to a row of `other` in the collations, or if `found` is false those that aren't
+    ///
+    /// For large inputs, a bloom filter over the smaller one skips rows that
+    /// can't equal any row of the other one.
+    fn rows_found_in(&self, other: &QueryResult, collations: &[Collation], found: bool) -> Vec<Arc<Row>> {
+        let columns: Vec<usize> = (0..self.fields.len()).collect();
+        let (own, theirs) = (keyed_rows(collations, &self.rows), keyed_rows(collations, &other.rows));
+        let mut others: Vec<&Arc<Row>> = theirs.iter().collect();
+        let mut filter = None;
+        if own.len().max(theirs.len()) >= bloom::MIN_ROWS {
+            if own.len() < theirs.len() {
+                if let Some(own) = BloomFilter::over(own.iter().map(|r| &**r), &columns) {
+                    others.retain(|row| own.may_contain(row, &columns));
+                }
+            }
+            else {
+                filter = BloomFilter::over(theirs.iter().map(|r| &**r), &columns);
+            }
+        }
+        let others: HashSet<&Arc<Row>> = others.into_iter().collect();
+        self.rows.iter().zip(own.iter())
+            .filter(|(_, key)| {
+                let is_in = filter.as_ref().map_or(true, |f| f.may_contain(key, &columns)) && others.contains(key);
+                is_in == found
+            })
+            .map(|(row, _)| row.clone())
+            .collect()
+    }
+
+    /// Rows without those equal to an earlier one in the collations of the fields
     pub fn distinct(&self) -> Result<QueryResult, QueryError> {
-        let mut rows: Vec<Row> = Vec::new();
-        for row in self.rows() {
-            if !rows.contains(&row) {
+        let keys = keyed_rows(&self.collations, &self.rows);
+        let mut seen: HashSet<&Arc<Row>> = HashSet::new();
+        let mut rows: Vec<Arc<Row>> = Vec::new();
+        for (row, key) in self.rows.iter().zip(keys.iter()) {
+            if seen.insert(key) {
                 rows.push(row.clone());
             }
         }
-        Ok(QueryResult::new(self.fields.clone(), rows))
+        Ok(self.with_rows(rows))
     }
 
     pub fn match_field(&self, qf: &QueryField) -> Vec<usize> {
@@ -268,42 +1246,52 @@ impl QueryResult {
             }
 
             let m = matching[0];
-            result_fields.push(field.clone());
+            result_fields.push(self.fields[m].clone());
             result_columns.push(m);
         }
 
-        Ok(QueryResult {
-            fields: result_fields,
-            rows: self.rows.iter().map(|row| row.pick_columns(&result_columns)).collect()
-        })
+        let rows = self.rows.iter().map(|row| Arc::new(row.pick_columns(&result_columns))).collect();
+        let collations = result_columns.iter().map(|&i| self.collation(i)).collect();
+        Ok(QueryResult::new(result_fields, rows).with_collations(collations).with_schema(self.schema_of(&result_columns)))
     }
 
-    pub fn filter(&self, function_dict: &HashMap<FunctionName, Function>, condition: &Condition) -> Result<QueryResult, QueryError> {
-        let mut rows: Vec<Row> = Vec::new();
-        for row in self.rows.clone() {
-            let ok = condition.test(function_dict, &|qf: &QueryField| {
-                let matching = self.match_field(&qf);
-                if matching.is_empty() {
-                    return Err(QueryError::NoSuchField(qf.clone()));
-                }
-                if matching.len() > 1 {
-                    return Err(QueryError::AmbiguousField(qf.clone()));
+    /// Value of field `qf` in `row`, which must have the fields of this result
+    pub(crate) fn resolve_field(&self, qf: &QueryField, row: &Row) -> Result<Value, QueryError> {
+        let matching = self.match_field(&qf);
+        if matching.is_empty() {
+            if qf.field == ROWID_FIELD {
+                if let Some(id) = row.id() {
+                    return Ok(Value::Unsigned(id as u128));
                 }
+            }
+            return Err(QueryError::NoSuchField(qf.clone()));
+        }
+        if matching.len() > 1 {
+            return Err(QueryError::AmbiguousField(qf.clone()));
+        }
 
-                Ok(row.values()[matching[0]].clone())
-            })?;
-            if ok {
-                rows.push(row);
+        Ok(row.value(matching[0]).clone())
+    }
+
+    /// Evaluate `condition` for a row with the fields of this result, comparing
+    /// texts of fields in their collations
+    pub(crate) fn test_row(&self, function_dict: &HashMap<FunctionName, Function>, condition: &Condition, row: &Row) -> Result<bool, QueryError> {
+        self.collated(condition).test(function_dict, &|qf: &QueryField| self.resolve_field(qf, row))
+    }
+
+    pub fn filter(&self, function_dict: &HashMap<FunctionName, Function>, condition: &Condition) -> Result<QueryResult, QueryError> {
+        let condition = self.collated(condition);
+        let mut rows: Vec<Arc<Row>> = Vec::new();
+        for row in &self.rows {
+            if condition.test(function_dict, &|qf: &QueryField| self.resolve_field(qf, row))? {
+                rows.push(row.clone());
             }
         }
 
-        Ok(QueryResult {
-            fields: self.fields.clone(),
-            rows,
-        })
+        Ok(self.with_rows(rows))
     }
 
-    pub fn rename(&self, from: &QueryField, to: &FieldName) -> Result<QueryResult, QueryError> {
+    pub fn rename(&self, from: &QueryField, to: &QueryField) -> Result<QueryResult, QueryError> {
         let matching = self.match_field(&from);
         if matching.is_empty() {
             return Err(QueryError::NoSuchField(from.clone()));
@@ -313,28 +1301,167 @@ impl QueryResult {
         }
 
         let mut fields = self.fields.clone();
-        fields[matching[0]] = QueryField::new(to.clone());
+        let table = to.table.clone().or_else(|| fields[matching[0]].table.clone());
+        fields[matching[0]] = QueryField { table, field: to.field.clone() };
 
         Ok(QueryResult {
             fields,
             rows: self.rows.clone(),
+            collations: self.collations.clone(),
+            schema: self.schema.clone(),
         })
     }
 
+    pub fn unnest(&self, field: &QueryField) -> Result<QueryResult, QueryError> {
+        let matching = self.match_field(&field);
+        if matching.is_empty() {
+            return Err(QueryError::NoSuchField(field.clone()));
+        }
+        if matching.len() > 1 {
+            return Err(QueryError::AmbiguousField(field.clone()));
+        }
+
+        let column = matching[0];
+        let mut rows = Vec::new();
+        for row in &self.rows {
+            let elements = match row.value(column) {
+                Value::Array(elements) => elements,
+                Value::Null => continue,
+                _ => return Err(QueryError::TypeError(TypeError::NotArray)),
+            };
+            for element in elements {
+                let mut values = row.values();
+                values[column] = element.clone();
+                rows.push(Arc::new(Row::new(values)));
+            }
+        }
+
+        // Elements may be null even if arrays can't
+        let mut schema = self.schema();
+        schema[column] = match self.schema_field(column).map(TableField::kind) {
+            Some(FieldKind::Array(kind)) => Some(TableField::new(self.fields[column].field.clone(), *kind).nullable()),
+            _ => None,
+        };
+        Ok(self.with_rows(rows).with_schema(schema))
+    }
+
+    pub fn nearest_neighbors(&self, field: &QueryField, target: &[f32], k: usize) -> Result<QueryResult, QueryError> {
+        let matching = self.match_field(&field);
+        if matching.is_empty() {
+            return Err(QueryError::NoSuchField(field.clone()));
+        }
+        if matching.len() > 1 {
+            return Err(QueryError::AmbiguousField(field.clone()));
+        }
+
+        let column = matching[0];
+        let mut scored = Vec::new();
+        for row in &self.rows {
+            let similarity = match row.value(column) {
+                Value::Vector(v) if v.len() == target.len() => vector::cosine_similarity(v, target),
+                Value::Null => None,
+                _ => return Err(QueryError::IncompatibleTypes),
+            };
+            match similarity {
+                Some(similarity) if !similarity.is_nan() => scored.push((similarity, row.clone())),
+                _ => {},
+            }
+        }
+        // Stable, so equally similar rows keep their order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(self.with_rows(scored.into_iter().take(k).map(|(_, row)| row).collect()))
+    }
+
+    pub fn order_by(&self, field: &QueryField) -> Result<QueryResult, QueryError> {
+        let matching = self.match_field(&field);
+        if matching.is_empty() {
+            return Err(QueryError::NoSuchField(field.clone()));
+        }
+        if matching.len() > 1 {
+            return Err(QueryError::AmbiguousField(field.clone()));
+        }
+
+        let column = matching[0];
+        let collation = self.collation(column);
+        let (mut ordered, mut unordered) = (Vec::new(), Vec::new());
+        for row in &self.rows {
+            match row.value(column) {
+                Value::Null => unordered.push(row.clone()),
+                _ => ordered.push(row.clone()),
+            }
+        }
+        // Compare every row before sorting, as the sort can't fail
+        if let Some(first) = ordered.first() {
+            for row in &ordered {
+                if first.value(column).compare(row.value(column))?.is_none() {
+                    return Err(QueryError::IncompatibleTypes);
+                }
+            }
+        }
+        // Stable, so equal rows keep their order
+        ordered.sort_by(|a, b| {
+            let (a, b) = (collation.key_value(a.value(column)), collation.key_value(b.value(column)));
+            a.compare(&b).ok().and_then(|o| o).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+        ordered.extend(unordered);
+
+        Ok(self.with_rows(ordered))
+    }
+
+    /// Drop rows that can't be joined if `condition` is `strict_eq` of a field of each side
+    ///
+    /// Rows with a null in the field are dropped, and for large inputs a bloom
+    /// filter over the values of the smaller side skips rows of the larger one
+    /// without an equal value.
+    pub(crate) fn skip_unjoinable(left: &mut QueryResult, right: &mut QueryResult, condition: &Condition) {
+        let (a, b) = match condition {
+            Condition::FunctionCall(fc) => match (fc.target.as_str(), fc.arguments.as_slice()) {
+                ("strict_eq", [Argument::QueryField(a), Argument::QueryField(b)]) => (a, b),
+                _ => return,
+            },
+            _ => return,
+        };
+        // Fields matching more than once are left for the join to report
+        let column = |result: &QueryResult, other: &QueryResult, qf: &QueryField| match result.match_field(qf).as_slice() {
+            [i] if other.match_field(qf).is_empty() => Some(*i),
+            _ => None,
+        };
+        let (i, j) = match (column(left, right, a), column(right, left, b), column(left, right, b), column(right, left, a)) {
+            (Some(i), Some(j), _, _) | (_, _, Some(i), Some(j)) => (i, j),
+            _ => return,
+        };
+
+        left.rows.retain(|row| !row.value(i).is_null());
+        right.rows.retain(|row| !row.value(j).is_null());
+        // Values equal in a collation may differ
+        if left.collation(i) != Collation::Binary || right.collation(j) != Collation::Binary {
+            return;
+        }
+        if left.rows.len().max(right.rows.len()) < bloom::MIN_ROWS {
+            return;
+        }
+        let (smaller, i, larger, j) = if left.rows.len() < right.rows.len() { (&*left, i, right, j) } else { (&*right, j, left, i) };
+        if let Some(filter) = BloomFilter::over(smaller.rows.iter().map(|r| &**r), &[i]) {
+            larger.rows.retain(|row| filter.may_contain(row, &[j]));
+        }
+    }
+
     pub fn join_on(&self, function_dict: &HashMap<FunctionName, Function>, other: &QueryResult, condition: &Condition) -> Result<QueryResult, QueryError> {
         let mut fields = self.fields.clone();
         fields.extend(other.fields.clone());
+        let mut collations = self.collations();
+        collations.extend(other.collations());
+        let mut schema = self.schema();
+        schema.extend(other.schema());
 
         let mut rows = Vec::new();
-        for row1 in self.rows.clone() {
-            for row2 in other.rows.clone() {
-                rows.push(row1.concat(row2));
+        for row1 in &self.rows {
+            for row2 in &other.rows {
+                rows.push(Arc::new(row1.concat(row2)));
             }
         }
 
-        (QueryResult {
-            fields,
-            rows
-        }).filter(function_dict, condition)
+        QueryResult::new(fields, rows).with_collations(collations).with_schema(schema).filter(function_dict, condition)
     }
 }
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What kind of operation a log event describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEventKind {
+    Query,
+    Delta,
+}
+impl LogEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogEventKind::Query => "query",
+            LogEventKind::Delta => "delta",
+        }
+    }
+}
+
+/// A single executed query or applied delta
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    /// Sequential id, unique per database handle
+    pub id: u64,
+    pub kind: LogEventKind,
+    /// Wall-clock time the operation started
+    pub timestamp: SystemTime,
+    /// Debug representation of the query or delta
+    pub statement: String,
+    pub duration: Duration,
+    /// Number of rows in the result, for successful queries
+    pub rows: Option<usize>,
+    /// Debug representation of the error, if the operation failed
+    pub error: Option<String>,
+}
+impl LogEvent {
+    /// Single-line JSON object, suitable for line-delimited log ingestion
+    pub fn to_json(&self) -> String {
+        let timestamp_ms = self.timestamp.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64)
+            .unwrap_or(0);
+        let duration_us = self.duration.as_secs() * 1_000_000 + (self.duration.subsec_nanos() / 1000) as u64;
+
+        let mut json = format!(
+            "{{\"id\":{},\"timestamp_ms\":{},\"type\":\"{}\",\"statement\":\"{}\",\"duration_us\":{}",
+            self.id, timestamp_ms, self.kind.name(), json_escape(&self.statement), duration_us
+        );
+        if let Some(rows) = self.rows {
+            json.push_str(&format!(",\"rows\":{}", rows));
+        }
+        match self.error {
+            Some(ref error) => json.push_str(&format!(",\"outcome\":\"error\",\"error\":\"{}\"}}", json_escape(error))),
+            None => json.push_str(",\"outcome\":\"ok\"}"),
+        }
+        json
+    }
+}
+
+/// Destination for log events
+pub enum QueryLog {
+    /// Append one JSON object per line to a file
+    File(File),
+    /// Pass each event to a callback
+    Callback(Box<Fn(&LogEvent) + Send + Sync>),
+}
+impl QueryLog {
+    pub(crate) fn emit(&self, event: &LogEvent) -> io::Result<()> {
+        match self {
+            QueryLog::File(file) => {
+                let mut line = event.to_json();
+                line.push('\n');
+                (&*file).write_all(line.as_bytes())
+            },
+            QueryLog::Callback(callback) => {
+                callback(event);
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn json_escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"'  => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
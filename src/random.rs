@@ -0,0 +1,84 @@
+//! Random bits for the random builtins
+//!
+//! Without a seed the bits are unpredictable, but not cryptographically
+//! secure. A query executed with `QueryOptions::seed` draws them from a
+//! generator seeded with it instead, so the same query over the same rows
+//! gives the same results.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::SystemTime;
+
+thread_local! {
+    /// State of the seeded generator, while a seeded query executes
+    static STATE: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Draws random bits from a generator seeded with the seed on this thread
+/// until dropped, see `ExecutionContext`
+pub(crate) struct SeedScope {
+    /// State of an enclosing scope, restored on drop
+    previous: Option<u64>,
+}
+impl SeedScope {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        let previous = STATE.with(|state| state.get());
+        // Unseeded queries nested in seeded ones stay seeded
+        if seed.is_some() {
+            STATE.with(|state| state.set(seed));
+        }
+        SeedScope { previous }
+    }
+}
+impl Drop for SeedScope {
+    fn drop(&mut self) {
+        STATE.with(|state| state.set(self.previous));
+    }
+}
+
+/// Next output of SplitMix64
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Unpredictable bits
+fn unseeded() -> u64 {
+    // Every `RandomState` is seeded differently
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    hasher.finish()
+}
+
+/// Random bits, from the seeded generator if there is one
+pub(crate) fn next_u64() -> u64 {
+    STATE.with(|state| match state.get() {
+        Some(mut s) => {
+            let bits = split_mix(&mut s);
+            state.set(Some(s));
+            bits
+        },
+        None => unseeded(),
+    })
+}
+
+/// Uniformly distributed real in `[0, 1)`
+pub(crate) fn next_real() -> f64 {
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Uniformly distributed integer in the inclusive range, which must not be empty
+pub(crate) fn next_in(low: i128, high: i128) -> i128 {
+    debug_assert!(low <= high);
+    let span = (high as u128).wrapping_sub(low as u128).wrapping_add(1);
+    let bits = u128::from(next_u64()) << 64 | u128::from(next_u64());
+    // A span of zero is the whole range; the modulo bias is negligible unless the span is huge
+    let offset = if span == 0 { bits } else { bits % span };
+    (low as u128).wrapping_add(offset) as i128
+}
@@ -0,0 +1,95 @@
+//! Regular expression builtins, with the `regex` feature
+//!
+//! Patterns use the syntax of the `regex` crate. While a query executes,
+//! each distinct pattern is compiled only once.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use Value;
+use QueryError;
+
+thread_local! {
+    /// Compiled patterns and the number of live scopes, while any scope is live
+    static CACHE: RefCell<Option<(usize, HashMap<String, Regex>)>> = RefCell::new(None);
+}
+
+/// Keeps compiled patterns cached on this thread until dropped, see `ExecutionContext`
+pub(crate) struct CacheScope(());
+impl CacheScope {
+    pub(crate) fn new() -> Self {
+        CACHE.with(|cache| cache.borrow_mut().get_or_insert_with(|| (0, HashMap::new())).0 += 1);
+        CacheScope(())
+    }
+}
+impl Drop for CacheScope {
+    fn drop(&mut self) {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let scopes = cache.as_ref().map_or(0, |(scopes, _)| *scopes);
+            if scopes <= 1 {
+                *cache = None;
+            }
+            else if let Some((scopes, _)) = cache.as_mut() {
+                *scopes -= 1;
+            }
+        });
+    }
+}
+
+fn compiled(pattern: &str) -> Result<Regex, QueryError> {
+    let cached = CACHE.with(|cache| cache.borrow().as_ref().and_then(|(_, patterns)| patterns.get(pattern).cloned()));
+    if let Some(regex) = cached {
+        return Ok(regex);
+    }
+    let regex = Regex::new(pattern).map_err(|error| QueryError::InvalidPattern(error.to_string()))?;
+    CACHE.with(|cache| {
+        if let Some((_, patterns)) = cache.borrow_mut().as_mut() {
+            patterns.insert(pattern.to_owned(), regex.clone());
+        }
+    });
+    Ok(regex)
+}
+
+/// Text and pattern of the first two arguments, none if either is null
+fn text_and_pattern(values: &[Value]) -> Result<Option<(&str, Regex)>, QueryError> {
+    if values.len() < 2 {
+        return Err(QueryError::NotEnoughArguments(2));
+    }
+    match (&values[0], &values[1]) {
+        (Value::Text(text), Value::Text(pattern)) => Ok(Some((text, compiled(pattern)?))),
+        (Value::Null, _) | (_, Value::Null) => Ok(None),
+        _ => Err(QueryError::IncompatibleTypes),
+    }
+}
+
+/// Whether the pattern matches anywhere in the text
+pub(crate) fn f_regex_match(values: Vec<Value>) -> Result<Value, QueryError> {
+    Ok(text_and_pattern(&values)?.map_or(Value::Null, |(text, regex)| Value::Boolean(regex.is_match(text))))
+}
+
+/// Text captured by a group of the first match, given by its index or name,
+/// or the whole match without a group
+///
+/// The result is null if the pattern doesn't match or the group didn't participate.
+pub(crate) fn f_regex_extract(values: Vec<Value>) -> Result<Value, QueryError> {
+    let (text, regex) = match text_and_pattern(&values)? {
+        Some(text_and_regex) => text_and_regex,
+        None => return Ok(Value::Null),
+    };
+    let captures = match regex.captures(text) {
+        Some(captures) => captures,
+        None => return Ok(Value::Null),
+    };
+    let group = match values.get(2) {
+        None => captures.get(0),
+        Some(Value::Unsigned(i)) => captures.get(*i as usize),
+        Some(Value::Signed(i)) if *i >= 0 => captures.get(*i as usize),
+        Some(Value::Text(name)) => captures.name(name),
+        Some(Value::Null) => return Ok(Value::Null),
+        Some(_) => return Err(QueryError::IncompatibleTypes),
+    };
+    Ok(group.map_or(Value::Null, |group| Value::Text(group.as_str().into())))
+}
@@ -0,0 +1,243 @@
+//! Leader-follower replication over the delta stream
+//!
+//! The leader ships journaled deltas as binary messages over a `Transport`
+//! provided by the embedder; the follower applies them in revision order.
+//! Periodic checksum messages let the follower verify it holds the same
+//! state as the leader.
+
+use std::io;
+
+use {SrimDB, Delta, Revision, ApplyError};
+use codec::{Encode, Decode, DecodeError, decode_exact};
+
+/// Carries messages from the leader to its followers
+pub trait Transport {
+    /// Deliver one message; messages must arrive in the order they were sent
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Delta {
+        revision: Revision,
+        /// Latest revision of the leader when the message was sent
+        leader_revision: Revision,
+        fires_triggers: bool,
+        delta: Delta,
+    },
+    Checksum {
+        revision: Revision,
+        checksum: u64,
+    },
+}
+impl Encode for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Delta { revision, leader_revision, fires_triggers, delta } => {
+                out.push(0);
+                revision.encode(out);
+                leader_revision.encode(out);
+                fires_triggers.encode(out);
+                delta.encode(out);
+            },
+            Message::Checksum { revision, checksum } => {
+                out.push(1);
+                revision.encode(out);
+                checksum.encode(out);
+            },
+        }
+    }
+}
+impl Decode for Message {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Message::Delta {
+                revision: u64::decode(input)?,
+                leader_revision: u64::decode(input)?,
+                fires_triggers: bool::decode(input)?,
+                delta: Delta::decode(input)?,
+            }),
+            1 => Ok(Message::Checksum {
+                revision: u64::decode(input)?,
+                checksum: u64::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag("Message", tag)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplicationError {
+    Decode(DecodeError),
+    /// Message for revision $1 arrived when revision $0 was expected
+    Gap { expected: Revision, found: Revision },
+    /// A shipped delta failed on the follower
+    Apply(ApplyError),
+    /// State differs from the leader at this revision
+    Diverged(Revision),
+    /// Database given to `Follower::with_db` already has changes, up to revision $0
+    NotEmpty(Revision),
+}
+
+/// Ships the journal of a database, see `SrimDB::with_journal`
+pub struct Leader<T: Transport> {
+    transport: T,
+    shipped: Revision,
+}
+impl<T: Transport> Leader<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport, shipped: 0 }
+    }
+
+    /// Send all journaled deltas not sent yet, returning how many were sent
+    ///
+    /// Followers must start from an empty database, so the leader must have
+    /// been journaling since revision 0. On a send error, the failed delta
+    /// is sent again by the next call.
+    pub fn ship(&mut self, db: &SrimDB) -> io::Result<usize> {
+        let mut count = 0;
+        let shipped = self.shipped;
+        for entry in db.journal().iter().filter(|e| e.revision > shipped) {
+            let message = Message::Delta {
+                revision: entry.revision,
+                leader_revision: db.revision(),
+                fires_triggers: entry.fires_triggers,
+                delta: entry.delta.clone(),
+            };
+            let mut bytes = Vec::new();
+            message.encode(&mut bytes);
+            self.transport.send(bytes)?;
+            self.shipped = entry.revision;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Ship pending deltas followed by a checksum of the current state
+    pub fn ship_checksum(&mut self, db: &SrimDB) -> io::Result<()> {
+        self.ship(db)?;
+        let mut bytes = Vec::new();
+        Message::Checksum { revision: db.revision(), checksum: db.checksum() }.encode(&mut bytes);
+        self.transport.send(bytes)
+    }
+
+    /// Revision of the last shipped delta
+    pub fn shipped_revision(&self) -> Revision {
+        self.shipped
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+/// Read-only replica that applies deltas received from a `Leader`
+pub struct Follower {
+    db: SrimDB,
+    leader_revision: Revision,
+    verified_revision: Option<Revision>,
+}
+impl Default for Follower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Follower {
+    pub fn new() -> Self {
+        Self {
+            db: SrimDB::new(),
+            leader_revision: 0,
+            verified_revision: None,
+        }
+    }
+
+    /// Follower applying deltas to `db`, failing if it already has changes
+    ///
+    /// Functions registered with `SrimDB::with_function` or
+    /// `SrimDB::with_table_function` and the arithmetic mode aren't shipped,
+    /// so `db` must be configured like the leader.
+    pub fn with_db(db: SrimDB) -> Result<Self, ReplicationError> {
+        if db.revision() != 0 {
+            return Err(ReplicationError::NotEmpty(db.revision()));
+        }
+        Ok(Self { db, ..Self::new() })
+    }
+
+    /// Apply a message sent by the leader
+    ///
+    /// Deltas already applied are ignored, so messages can be redelivered.
+    pub fn receive(&mut self, message: &[u8]) -> Result<(), ReplicationError> {
+        match decode_exact(message).map_err(ReplicationError::Decode)? {
+            Message::Delta { revision, leader_revision, fires_triggers, delta } => {
+                self.leader_revision = self.leader_revision.max(leader_revision);
+                let expected = self.db.revision() + 1;
+                if revision < expected {
+                    return Ok(());
+                }
+                if revision > expected {
+                    return Err(ReplicationError::Gap { expected, found: revision });
+                }
+                self.db.data_db.fire_triggers = fires_triggers;
+                let result = self.db.apply(delta);
+                self.db.data_db.fire_triggers = true;
+                result.map_err(ReplicationError::Apply)?;
+                Ok(())
+            },
+            Message::Checksum { revision, checksum } => {
+                if revision != self.db.revision() {
+                    return Err(ReplicationError::Gap { expected: self.db.revision(), found: revision });
+                }
+                if checksum != self.db.checksum() {
+                    return Err(ReplicationError::Diverged(revision));
+                }
+                self.verified_revision = Some(revision);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn db(&self) -> &SrimDB {
+        &self.db
+    }
+
+    /// Number of revisions the leader was known to be ahead
+    pub fn lag(&self) -> u64 {
+        self.leader_revision.saturating_sub(self.db.revision())
+    }
+
+    /// Latest revision at which the state matched the leader's checksum
+    pub fn verified_revision(&self) -> Option<Revision> {
+        self.verified_revision
+    }
+}
+
+/// 64-bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl SrimDB {
+    /// Hash of the schema, rows and triggers, to compare databases for equality
+    ///
+    /// Equal databases have equal checksums only if their tables and rows
+    /// are in the same order, as they are for replicas applying the same deltas.
+    /// Temporary tables are left out, as they aren't replicated.
+    pub fn checksum(&self) -> u64 {
+        let mut bytes = Vec::new();
+        for table in self.data_db.tables.iter().filter(|t| !t.is_temporary()) {
+            table.encode(&mut bytes);
+            for row in self.data_db.table_rows[&table.name()].iter() {
+                row.encode(&mut bytes);
+            }
+        }
+        for trigger in self.data_db.triggers() {
+            trigger.encode(&mut bytes);
+        }
+        fnv1a(&bytes)
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::mpsc::Sender;
+
+use {Delta, Revision, TableName};
+
+/// An applied delta, sent to subscribers, see `SrimDB::subscribe`
+#[derive(Debug, Clone)]
+pub struct DeltaEvent {
+    /// Revision after the delta, the same as before it if it changed only temporary tables
+    pub revision: Revision,
+    pub delta: Delta,
+    tables: Vec<TableName>,
+}
+impl DeltaEvent {
+    /// Tables the delta modified, including those modified by cascading foreign keys
+    pub fn tables(&self) -> Vec<TableName> {
+        self.tables.clone()
+    }
+}
+
+/// Which changes a subscriber receives
+#[derive(Debug, Clone)]
+pub enum SubscriptionFilter {
+    AllTables,
+    /// Changes to any of these tables, including rows removed from them by
+    /// cascading foreign keys; a renamed table matches by both names
+    Tables(Vec<TableName>),
+}
+impl SubscriptionFilter {
+    fn matches(&self, tables: &[TableName]) -> bool {
+        match self {
+            SubscriptionFilter::AllTables => true,
+            SubscriptionFilter::Tables(names) => tables.iter().any(|t| names.contains(t)),
+        }
+    }
+}
+
+pub(crate) struct Subscribers {
+    list: Vec<(SubscriptionFilter, Sender<DeltaEvent>)>,
+}
+impl Subscribers {
+    pub(crate) fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    pub(crate) fn add(&mut self, filter: SubscriptionFilter, sender: Sender<DeltaEvent>) {
+        self.list.push((filter, sender));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Send the event for `delta`, which modified `tables`, to all matching
+    /// subscribers, forgetting those that hung up
+    pub(crate) fn notify(&mut self, revision: Revision, delta: &Delta, tables: &[TableName]) {
+        self.list.retain(|(filter, sender)| {
+            let event = || DeltaEvent { revision, delta: delta.clone(), tables: tables.to_vec() };
+            !filter.matches(tables) || sender.send(event()).is_ok()
+        });
+    }
+}
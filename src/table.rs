@@ -1,13 +1,47 @@
+use std::mem;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
 use TableName;
 use FieldName;
 use FieldKind;
 use Value;
+use ApplyError;
+use RowUpdate;
+use RowId;
+use QueryError;
+use function::{FunctionCall, Argument};
+use collation::Collation;
+use query::{Condition, QueryField};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Table {
     name: TableName,
     fields: Vec<TableField>,
     key_field_mask: Vec<bool>,
+    /// Reject rows with the same key as an existing row
+    unique_key: bool,
+    /// Named conditions every row must satisfy
+    checks: Vec<(String, FunctionCall)>,
+    /// Named sets of fields no two rows may have equal values in, as masks like `key_field_mask`
+    uniques: Vec<(String, Vec<bool>)>,
+    /// Indexes created with `Delta::CreateIndex`, over positions of fields in order
+    indexes: Vec<(IndexKind, Vec<usize>)>,
+    /// Indexes created with `Delta::CreateExpressionIndex`
+    expression_indexes: Vec<(IndexKind, FunctionCall)>,
+    /// Indexes created with `Delta::CreatePartialIndex`, over positions of fields
+    /// of the rows satisfying the condition
+    partial_indexes: Vec<(IndexKind, Vec<usize>, Condition)>,
+    /// Changes to the table are not journaled, see `Table::temporary`
+    temporary: bool,
+    quota: Option<Quota>,
+    /// Position of the timestamp field rows expire by, and how long they are kept
+    ttl: Option<(usize, Duration)>,
+    /// Position of the field rows are partitioned by, and how
+    partitioning: Option<(usize, Partitioning)>,
 }
 impl Table {
     pub fn new(name: &str, fields: Vec<TableField>) -> Self {
@@ -15,6 +49,16 @@ impl Table {
             name: name.to_owned(),
             key_field_mask: vec![true; fields.clone().len()],
             fields,
+            unique_key: false,
+            checks: Vec::new(),
+            uniques: Vec::new(),
+            indexes: Vec::new(),
+            expression_indexes: Vec::new(),
+            partial_indexes: Vec::new(),
+            temporary: false,
+            quota: None,
+            ttl: None,
+            partitioning: None,
         }
     }
 
@@ -33,10 +77,315 @@ impl Table {
         self
     }
 
+    /// Reject inserts and updates that would give two rows the same key fields
+    pub fn with_unique_key(self) -> Self {
+        Self { unique_key: true, ..self }
+    }
+
+    pub fn has_unique_key(&self) -> bool {
+        self.unique_key
+    }
+
+    /// Make the table last only as long as the `SrimDB` it is created in
+    ///
+    /// Deltas changing only temporary tables don't advance the revision and
+    /// are not journaled or passed to change data capture sinks, so they
+    /// aren't replicated. Views
+    /// and foreign keys of other tables can't refer to temporary tables.
+    pub fn temporary(self) -> Self {
+        Self { temporary: true, ..self }
+    }
+
+    pub fn is_temporary(&self) -> bool {
+        self.temporary
+    }
+
+    /// Limit the number or size of the rows, see `Quota`
+    pub fn with_quota(self, quota: Quota) -> Self {
+        Self { quota: Some(quota), ..self }
+    }
+
+    pub fn quota(&self) -> Option<Quota> {
+        self.quota
+    }
+
+    /// Expire rows once `retention` has passed since the time in timestamp field `field`
+    ///
+    /// Queries skip expired rows, and `SrimDB::vacuum_expired` removes them.
+    /// Rows with a null in the field never expire. Materialized views keep
+    /// expired rows until they are removed.
+    pub fn with_ttl(mut self, field: &str, retention: Duration) -> Self {
+        match self.field_index(field.to_owned()) {
+            Some(i) => self.ttl = Some((i, retention)),
+            None => panic!("Field '{}' does not exists in table '{}'", field, self.name),
+        }
+        self
+    }
+
+    /// Field rows expire by and their retention
+    pub fn ttl(&self) -> Option<(FieldName, Duration)> {
+        self.ttl.map(|(i, retention)| (self.fields[i].name(), retention))
+    }
+
+    /// Keep the rows in partitions by the value of field `field`, see `Partitioning`
+    ///
+    /// Filters constraining the field only read the partitions that can have
+    /// matching rows, see `SrimDB::partitions`.
+    pub fn with_partitioning(mut self, field: &str, partitioning: Partitioning) -> Self {
+        match self.field_index(field.to_owned()) {
+            Some(i) => self.partitioning = Some((i, partitioning)),
+            None => panic!("Field '{}' does not exists in table '{}'", field, self.name),
+        }
+        self
+    }
+
+    /// Field rows are partitioned by and how
+    pub fn partitioning(&self) -> Option<(FieldName, Partitioning)> {
+        self.partitioning.as_ref().map(|(i, partitioning)| (self.fields[*i].name(), partitioning.clone()))
+    }
+
+    pub(crate) fn partition_column(&self) -> Option<(usize, &Partitioning)> {
+        self.partitioning.as_ref().map(|(i, partitioning)| (*i, partitioning))
+    }
+
+    /// Timestamp before which rows have expired at time `now`, in microseconds
+    fn ttl_cutoff(retention: Duration, now: i64) -> Value {
+        let micros = retention.as_micros().min(i64::max_value() as u128) as i64;
+        Value::Timestamp(now.saturating_sub(micros))
+    }
+
+    /// Condition the rows that haven't expired at time `now` satisfy, none without a TTL
+    pub(crate) fn unexpired_condition(&self, now: i64) -> Option<Condition> {
+        self.ttl.map(|(i, retention)| Condition::FunctionCall(FunctionCall::new("ifnull".to_owned(), vec![
+            Argument::FunctionCall(FunctionCall::new("gt".to_owned(), vec![
+                Argument::QueryField(QueryField::new(self.fields[i].name())),
+                Argument::Value(Self::ttl_cutoff(retention, now)),
+            ])),
+            Argument::Value(Value::Boolean(true)),
+        ])))
+    }
+
+    /// Condition the rows that have expired at time `now` satisfy, none without a TTL
+    pub(crate) fn expired_condition(&self, now: i64) -> Option<Condition> {
+        self.ttl.map(|(i, retention)| Condition::FunctionCall(FunctionCall::new("lte".to_owned(), vec![
+            Argument::QueryField(QueryField::new(self.fields[i].name())),
+            Argument::Value(Self::ttl_cutoff(retention, now)),
+        ])))
+    }
+
+    /// Reject inserts and updates unless `check` returns true or null for the row
+    pub fn with_check(mut self, name: &str, check: FunctionCall) -> Self {
+        self.checks.push((name.to_owned(), check));
+        self
+    }
+
+    pub fn checks(&self) -> Vec<(String, FunctionCall)> {
+        self.checks.clone()
+    }
+
+    /// Reject inserts and updates that would give two rows equal values in all of the fields
+    ///
+    /// Rows with a null in any of the fields are exempt.
+    pub fn with_unique(mut self, name: &str, field_names: Vec<FieldName>) -> Self {
+        assert!(!field_names.is_empty(), "Unique constraint '{}' has no fields", name);
+        let mut mask = vec![false; self.fields.len()];
+        for field_name in field_names {
+            if let Some(i) = self.field_index(field_name.clone()) {
+                mask[i] = true;
+            }
+            else {
+                panic!("Field '{}' does not exists in table '{}'", field_name, self.name);
+            }
+        }
+        self.uniques.push((name.to_owned(), mask));
+        self
+    }
+
+    /// Names and fields of the unique constraints
+    pub fn unique_constraints(&self) -> Vec<(String, Vec<FieldName>)> {
+        self.uniques.iter().map(|(name, mask)| {
+            let fields = self.fields.iter().zip(mask).filter(|(_, m)| **m).map(|(f, _)| f.name()).collect();
+            (name.clone(), fields)
+        }).collect()
+    }
+
+    pub(crate) fn unique_masks(&self) -> Vec<(String, Vec<bool>)> {
+        self.uniques.clone()
+    }
+
+    /// Maintain an index of the kind over the fields
+    pub fn with_index(mut self, kind: IndexKind, field_names: Vec<FieldName>) -> Self {
+        let columns = self.index_positions(field_names);
+        self.indexes.push((kind, columns));
+        self
+    }
+
+    fn index_positions(&self, field_names: Vec<FieldName>) -> Vec<usize> {
+        assert!(!field_names.is_empty(), "Index has no fields");
+        field_names.into_iter().map(|field_name| match self.field_index(field_name.clone()) {
+            Some(i) => i,
+            None => panic!("Field '{}' does not exists in table '{}'", field_name, self.name),
+        }).collect()
+    }
+
+    /// Kinds and fields of the indexes
+    pub fn indexes(&self) -> Vec<(IndexKind, Vec<FieldName>)> {
+        self.indexes.iter()
+            .map(|(kind, columns)| (*kind, columns.iter().map(|i| self.fields[*i].name()).collect()))
+            .collect()
+    }
+
+    pub(crate) fn index_columns(&self) -> Vec<(IndexKind, Vec<usize>)> {
+        self.indexes.clone()
+    }
+
+    /// Maintain an index of the kind over the value of the expression for each row
+    ///
+    /// The expression must only reference fields of the table, and give the
+    /// same value for the same row every time.
+    pub fn with_expression_index(mut self, kind: IndexKind, expression: FunctionCall) -> Self {
+        self.expression_indexes.push((kind, expression));
+        self
+    }
+
+    /// Kinds and expressions of the expression indexes
+    pub fn expression_indexes(&self) -> Vec<(IndexKind, FunctionCall)> {
+        self.expression_indexes.clone()
+    }
+
+    /// Maintain an index of the kind over the fields of the rows satisfying `predicate`
+    ///
+    /// The predicate must only reference fields of the table. A unique index
+    /// only requires the matching rows to have distinct values.
+    pub fn with_partial_index(mut self, kind: IndexKind, field_names: Vec<FieldName>, predicate: Condition) -> Self {
+        let columns = self.index_positions(field_names);
+        self.partial_indexes.push((kind, columns, predicate));
+        self
+    }
+
+    /// Kinds, fields and predicates of the partial indexes
+    pub fn partial_indexes(&self) -> Vec<(IndexKind, Vec<FieldName>, Condition)> {
+        self.partial_indexes.iter()
+            .map(|(kind, columns, predicate)| (*kind, columns.iter().map(|i| self.fields[*i].name()).collect(), predicate.clone()))
+            .collect()
+    }
+
+    pub(crate) fn partial_index_columns(&self) -> Vec<(IndexKind, Vec<usize>, Condition)> {
+        self.partial_indexes.clone()
+    }
+
     pub fn name(&self) -> TableName {
         self.name.clone()
     }
 
+    /// Rename the table, leaving references of its indexes to its fields unqualified
+    pub(crate) fn rename(&mut self, name: TableName) {
+        for (_, expression) in self.expression_indexes.iter_mut() {
+            *expression = expression.unqualified(&self.name);
+        }
+        for (_, _, predicate) in self.partial_indexes.iter_mut() {
+            *predicate = predicate.unqualified(&self.name);
+        }
+        for expression in self.fields.iter_mut().filter_map(|f| f.generated.as_mut()) {
+            *expression = expression.unqualified(&self.name);
+        }
+        for (_, check) in self.checks.iter_mut() {
+            *check = check.unqualified(&self.name);
+        }
+        self.name = name;
+    }
+
+    pub(crate) fn rename_field(&mut self, index: usize, name: FieldName) {
+        for (_, expression) in self.expression_indexes.iter_mut() {
+            expression.rename_field(&self.fields[index].name, &name);
+        }
+        for (_, _, predicate) in self.partial_indexes.iter_mut() {
+            predicate.rename_field(&self.fields[index].name, &name);
+        }
+        let old = self.fields[index].name.clone();
+        for expression in self.fields.iter_mut().filter_map(|f| f.generated.as_mut()) {
+            expression.rename_field(&old, &name);
+        }
+        for (_, check) in self.checks.iter_mut() {
+            check.rename_field(&old, &name);
+        }
+        self.fields[index].name = name;
+    }
+
+    pub(crate) fn set_field_kind(&mut self, index: usize, kind: FieldKind) {
+        self.fields[index].kind = kind;
+    }
+
+    /// Point foreign keys referencing table `from` to table `to`
+    pub(crate) fn rename_references(&mut self, from: &TableName, to: &TableName) {
+        for field in self.fields.iter_mut() {
+            if field.kind == FieldKind::ForeignKey(from.clone()) {
+                field.kind = FieldKind::ForeignKey(to.clone());
+            }
+        }
+    }
+
+    /// Append a non-key field
+    pub(crate) fn add_field(&mut self, field: TableField) {
+        self.fields.push(field);
+        self.key_field_mask.push(false);
+        for (_, mask) in self.uniques.iter_mut() {
+            mask.push(false);
+        }
+    }
+
+    pub fn is_key_field(&self, index: usize) -> bool {
+        self.key_field_mask[index]
+    }
+
+    /// Remove a field; if no key fields remain, the whole row becomes the key
+    ///
+    /// Unique constraints left without fields, checks and indexes over the field
+    /// and a TTL or partitioning by it are removed. Generated fields reading it
+    /// keep their values but are no longer generated.
+    pub(crate) fn remove_field(&mut self, index: usize) {
+        let name = self.fields.remove(index).name;
+        for field in self.fields.iter_mut() {
+            if field.generated.as_ref().map_or(false, |expression| expression.fields().iter().any(|qf| qf.field == name)) {
+                field.generated = None;
+            }
+        }
+        self.ttl = match self.ttl {
+            Some((i, _)) if i == index => None,
+            Some((i, retention)) if i > index => Some((i - 1, retention)),
+            ttl => ttl,
+        };
+        self.partitioning = match self.partitioning.take() {
+            Some((i, _)) if i == index => None,
+            Some((i, partitioning)) if i > index => Some((i - 1, partitioning)),
+            partitioning => partitioning,
+        };
+        self.checks.retain(|(_, check)| check.fields().iter().all(|qf| qf.field != name));
+        self.expression_indexes.retain(|(_, expression)| expression.fields().iter().all(|qf| qf.field != name));
+        self.partial_indexes.retain(|(_, columns, predicate)| {
+            !columns.contains(&index) && predicate.fields().iter().all(|qf| qf.field != name)
+        });
+        for (_, columns, _) in self.partial_indexes.iter_mut() {
+            for column in columns.iter_mut().filter(|c| **c > index) {
+                *column -= 1;
+            }
+        }
+        self.key_field_mask.remove(index);
+        if !self.key_field_mask.contains(&true) {
+            self.key_field_mask = vec![true; self.fields.len()];
+        }
+        for (_, mask) in self.uniques.iter_mut() {
+            mask.remove(index);
+        }
+        self.uniques.retain(|(_, mask)| mask.contains(&true));
+        self.indexes.retain(|(_, columns)| !columns.contains(&index));
+        for (_, columns) in self.indexes.iter_mut() {
+            for column in columns.iter_mut().filter(|c| **c > index) {
+                *column -= 1;
+            }
+        }
+    }
+
     pub fn fields(&self) -> Vec<TableField> {
         self.fields.clone()
     }
@@ -49,41 +398,396 @@ impl Table {
         }
         None
     }
+
+    /// Indices of fields referencing table `name`
+    pub(crate) fn references_to(&self, name: &TableName) -> Vec<usize> {
+        self.fields.iter().enumerate()
+            .filter(|(_, f)| f.kind == FieldKind::ForeignKey(name.clone()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Values of the key fields of `row`
+    pub fn key_values(&self, row: &Row) -> Vec<Value> {
+        self.key_field_mask.iter().zip(&row.values)
+            .filter(|(is_key, _)| **is_key)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// Position of the only key field, none if the key has several fields
+    pub(crate) fn key_field(&self) -> Option<usize> {
+        let mut keys = self.key_field_mask.iter().enumerate().filter(|(_, is_key)| **is_key).map(|(i, _)| i);
+        match (keys.next(), keys.next()) {
+            (Some(i), None) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Rows have equal values in all key fields, in their collations; null keys are never equal
+    pub fn same_key(&self, a: &Row, b: &Row) -> bool {
+        self.key_field_mask.iter().zip(&self.fields).zip(a.values.iter().zip(&b.values))
+            .all(|((is_key, field), (va, vb))| !is_key || (field.collation.key_value(va) == field.collation.key_value(vb) && !va.is_null()))
+    }
+
+    /// Check that the table has fields, that their names are non-empty and
+    /// distinct, that their kinds are valid, that indexes are over kinds they
+    /// support, that rows expire by a timestamp field, that partitions are valid,
+    /// that generated fields read fields of the table, see `TableField::with_generated`,
+    /// that checks only read fields of the table and that only text fields have collations
+    pub(crate) fn check_schema(&self) -> Result<(), ApplyError> {
+        let unsupported = |kind: &IndexKind, columns: &Vec<usize>| match kind {
+            IndexKind::BTree => columns.iter().any(|&i| !self.fields[i].kind.is_ordered()),
+            IndexKind::FullText => columns.len() != 1 || self.fields[columns[0]].kind != FieldKind::Text,
+            IndexKind::Hash | IndexKind::Unique => false,
+        };
+        let invalid = self.fields.is_empty() || self.fields.iter().enumerate().any(|(i, field)| {
+            field.name.is_empty() || self.fields[..i].iter().any(|f| f.name == field.name) || !field.kind.is_valid()
+        }) || self.indexes.iter().any(|(kind, columns)| unsupported(kind, columns))
+            || self.partial_indexes.iter().any(|(kind, columns, _)| unsupported(kind, columns))
+            || self.ttl.map_or(false, |(i, _)| self.fields[i].kind != FieldKind::Timestamp)
+            || self.partitioning.as_ref().map_or(false, |(i, partitioning)| !self.supports_partitioning(*i, partitioning))
+            || self.fields.iter().enumerate().any(|(i, field)| !self.reads_prior_fields(i, field))
+            || self.checks.iter().any(|(_, check)| !self.reads_own_fields(check))
+            || self.fields.iter().any(|field| field.collation != Collation::Binary && field.kind != FieldKind::Text);
+        if invalid {
+            Err(ApplyError::InvalidSchema(self.name.clone()))
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Whether rows can be partitioned by the field at position `index`: range
+    /// bounds must be ascending non-null values of its kind, which must be ordered
+    fn supports_partitioning(&self, index: usize, partitioning: &Partitioning) -> bool {
+        let field = &self.fields[index];
+        match partitioning {
+            Partitioning::Range(bounds) => {
+                field.kind.is_ordered()
+                    && bounds.iter().all(|b| !b.is_null() && field.coerce(b.clone()).ok().as_ref() == Some(b))
+                    && bounds.windows(2).all(|w| w[0].compare(&w[1]).ok() == Some(Some(Ordering::Less)))
+            },
+            Partitioning::Hash(count) => *count > 0,
+        }
+    }
+
+    /// Whether the expression only reads fields of the table
+    fn reads_own_fields(&self, expression: &FunctionCall) -> bool {
+        expression.fields().iter().all(|qf| {
+            qf.table.as_ref().map_or(true, |t| *t == self.name) && self.field_index(qf.field.clone()).is_some()
+        })
+    }
+
+    /// Whether the field at position `index` is not generated, or only reads fields
+    /// of the table that are not generated after it
+    fn reads_prior_fields(&self, index: usize, field: &TableField) -> bool {
+        let expression = match field.generated {
+            Some(ref expression) => expression,
+            None => return true,
+        };
+        expression.fields().iter().all(|qf| {
+            qf.table.as_ref().map_or(true, |t| *t == self.name) && match self.field_index(qf.field.clone()) {
+                Some(i) => i < index || (i > index && self.fields[i].generated.is_none()),
+                None => false,
+            }
+        })
+    }
+
+    /// Collation of each field, see `TableField::with_collation`
+    pub(crate) fn collations(&self) -> Vec<Collation> {
+        self.fields.iter().map(|field| field.collation).collect()
+    }
+
+    pub(crate) fn has_generated_fields(&self) -> bool {
+        self.fields.iter().any(|field| field.generated.is_some())
+    }
+
+    /// Check that the row fits the schema, converting values to the field kinds
+    pub fn validate_row(&self, row: Row) -> Result<Row, ApplyError> {
+        if row.values.len() != self.fields.len() {
+            return Err(ApplyError::WrongFieldCount {
+                table: self.name.clone(),
+                expected: self.fields.len(),
+                found: row.values.len(),
+            });
+        }
+
+        let mut values = Vec::with_capacity(row.values.len());
+        for (field, value) in self.fields.iter().zip(row.values) {
+            // Generated values are computed from the validated row, see `DataDB::generated_row`
+            if field.generated.is_some() {
+                values.push(Value::Null);
+                continue;
+            }
+            values.push(self.coerced(field, value)?);
+        }
+        Ok(Row::new(values))
+    }
+
+    /// `value` converted to be stored in `field`
+    pub(crate) fn coerced(&self, field: &TableField, value: Value) -> Result<Value, ApplyError> {
+        if value.is_null() && !field.nullable {
+            return Err(ApplyError::NotNull { table: self.name.clone(), field: field.name() });
+        }
+        field.coerce(value).map_err(|e| match e {
+            QueryError::OutOfRange | QueryError::CastOverflow(_) => ApplyError::OutOfRange { table: self.name.clone(), field: field.name() },
+            _ => ApplyError::TypeMismatch { table: self.name.clone(), field: field.name() },
+        })
+    }
+
+    /// The validated result of applying `update` to `row`
+    pub(crate) fn updated_row(&self, row: Row, update: RowUpdate) -> Result<Row, ApplyError> {
+        let new_row = match update {
+            RowUpdate::Replace(new_row) => new_row,
+            RowUpdate::Set(assignments) => {
+                let mut values = row.into_values();
+                for (field_name, value) in assignments {
+                    let i = self.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(self.name.clone(), field_name))?;
+                    values[i] = value;
+                }
+                Row::new(values)
+            }
+        };
+        self.validate_row(new_row)
+    }
+}
+
+/// Limits on the rows of a table, checked after each delta changing it
+///
+/// A delta leaving the table over a limit fails, unless `evict` is set, in
+/// which case the oldest rows are removed like with `Delta::RemoveRowById`
+/// until it is within the limits. Undoing the delta doesn't restore them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quota {
+    pub max_rows: Option<usize>,
+    /// Total of `Row::approx_size_bytes` of the rows
+    pub max_bytes: Option<usize>,
+    pub evict: bool,
+}
+impl Quota {
+    /// Number of oldest rows to remove for the rest to fit the limits
+    pub(crate) fn excess(&self, rows: &[Arc<Row>]) -> usize {
+        let mut excess = self.max_rows.map_or(0, |max| rows.len().saturating_sub(max));
+        if let Some(max) = self.max_bytes {
+            let mut size: usize = rows.iter().map(|row| row.approx_size_bytes()).sum();
+            let mut removed = 0;
+            while size > max && removed < rows.len() {
+                size -= rows[removed].approx_size_bytes();
+                removed += 1;
+            }
+            excess = excess.max(removed);
+        }
+        excess
+    }
+}
+
+/// How the rows of a table are assigned to partitions, see `Table::with_partitioning`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partitioning {
+    /// By ranges between ascending bounds: rows with a value below the first
+    /// bound are in the first partition, those with a value from a bound up to
+    /// the next one in the partition after it. Rows with a null are in the first one.
+    Range(Vec<Value>),
+    /// By the hash of the value into $0 partitions, which only filters on strict
+    /// equality with the field can prune
+    Hash(usize),
+}
+
+/// Data structure of an index, see `Delta::CreateIndex`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// Answers lookups of rows strictly equal to given values in all of the fields
+    Hash,
+    /// Answers range conditions and ordering on its field, which must be of an ordered kind
+    BTree,
+    /// Answers the same lookups as `Hash`, and rejects rows with the same values
+    /// as another row in all of the fields, unless one of them is null
+    Unique,
+    /// Answers `matches` conditions on its only field, which must be of kind `Text`
+    FullText,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// What happens to rows referencing a removed row through a foreign key,
+/// or a row whose key is updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceAction {
+    /// Fail the removal or update
+    Restrict,
+    /// Remove the referencing rows too
+    Cascade,
+}
+
+/// Value for a field omitted from an inserted row, see `Delta::AddNamedRow`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldDefault {
+    Value(Value),
+    /// Called without access to the row, each time a default is needed
+    FunctionCall(FunctionCall),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TableField {
     name: FieldName,
-    kind: FieldKind
+    kind: FieldKind,
+    nullable: bool,
+    on_delete: ReferenceAction,
+    default: Option<FieldDefault>,
+    /// Expression the value is computed with, see `TableField::with_generated`
+    generated: Option<FunctionCall>,
+    collation: Collation,
 }
 impl TableField {
     pub fn new(name: FieldName, kind: FieldKind) -> Self {
-        Self { name, kind }
+        Self { name, kind, nullable: false, on_delete: ReferenceAction::Restrict, default: None, generated: None, collation: Collation::Binary }
+    }
+
+    /// Use `default` when an inserted row omits this field
+    pub fn with_default(self, default: FieldDefault) -> Self {
+        Self { default: Some(default), ..self }
+    }
+
+    pub fn default(&self) -> Option<FieldDefault> {
+        self.default.clone()
+    }
+
+    /// Compute the value with `expression` over the other fields of the row each
+    /// time the row is inserted or updated, ignoring values given for this field
+    ///
+    /// The expression can read generated fields before this one in the table.
+    pub fn with_generated(self, expression: FunctionCall) -> Self {
+        Self { generated: Some(expression), ..self }
+    }
+
+    pub fn generated(&self) -> Option<FunctionCall> {
+        self.generated.clone()
+    }
+
+    /// Compare the values of this `Text` field in `collation`, in queries,
+    /// keys, constraints and indexes alike
+    pub fn with_collation(self, collation: Collation) -> Self {
+        Self { collation, ..self }
+    }
+
+    pub fn collation(&self) -> Collation {
+        self.collation
+    }
+
+    /// Allow storing `Value::Null` in this field
+    pub fn nullable(self) -> Self {
+        Self { nullable: true, ..self }
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        self.nullable
+    }
+
+    /// This field with values of another kind, binary unless text
+    pub(crate) fn with_kind(&self, kind: FieldKind) -> Self {
+        let collation = if kind == FieldKind::Text { self.collation } else { Collation::Binary };
+        Self { kind, collation, ..self.clone() }
+    }
+
+    /// Set what happens when a row this foreign key references is removed or its key changes
+    pub fn with_on_delete(self, on_delete: ReferenceAction) -> Self {
+        Self { on_delete, ..self }
+    }
+
+    pub fn on_delete(&self) -> ReferenceAction {
+        self.on_delete
     }
 
     pub fn name(&self) -> FieldName {
         self.name.clone()
     }
+
+    pub fn kind(&self) -> FieldKind {
+        self.kind.clone()
+    }
+
+    /// Convert a value to be stored in this field
+    pub fn coerce(&self, value: Value) -> Result<Value, QueryError> {
+        if value.is_null() && !self.nullable {
+            return Err(QueryError::IncompatibleTypes);
+        }
+        match self.kind {
+            // Referenced keys are checked against the other table
+            FieldKind::ForeignKey(_) => Ok(value),
+            ref kind => value.cast_to_field_kind(kind.clone()),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Values of a row; rows stored in a table also carry their row id
+#[derive(Debug, Clone)]
 pub struct Row {
-    values: Vec<Value>
+    values: Vec<Value>,
+    id: Option<RowId>,
+}
+/// Rows are equal if their values are, regardless of their ids
+impl PartialEq for Row {
+    fn eq(&self, other: &Row) -> bool {
+        self.values == other.values
+    }
+}
+impl Eq for Row {}
+impl Hash for Row {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.values.hash(state);
+    }
 }
 
 impl Row {
     pub fn new(values: Vec<Value>) -> Self {
-        Self { values }
+        Self { values, id: None }
+    }
+    /// Id of the stored row this row is, or was taken from
+    pub fn id(&self) -> Option<RowId> {
+        self.id
+    }
+    pub(crate) fn with_id(mut self, id: Option<RowId>) -> Row {
+        self.id = id;
+        self
     }
     pub fn pick_columns(&self, columns: &Vec<usize>) -> Self {
-        Self::new(columns.iter().map(|i| self.values[*i].clone()).collect())
+        Self::new(columns.iter().map(|i| self.values[*i].clone()).collect()).with_id(self.id)
     }
     pub fn values(&self) -> Vec<Value> {
         self.values.clone()
     }
-    pub fn concat(&self, other: Row) -> Row {
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+    pub fn value(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+    /// Value at `index` converted to `T`, like `i64`, `bool` or `String`,
+    /// failing with `QueryError::OutOfRange` if there's no such value
+    pub fn get<T: TryFrom<Value, Error = QueryError>>(&self, index: usize) -> Result<T, QueryError> {
+        T::try_from(self.values.get(index).ok_or(QueryError::OutOfRange)?.clone())
+    }
+    /// Approximate number of bytes used by this row, including heap data
+    pub fn approx_size_bytes(&self) -> usize {
+        mem::size_of::<Row>() + self.values.iter().map(|v| v.approx_size_bytes()).sum::<usize>()
+    }
+    pub(crate) fn with_value(&self, value: Value) -> Row {
+        let mut values = self.values.clone();
+        values.push(value);
+        Row::new(values).with_id(self.id)
+    }
+    pub(crate) fn with_value_at(&self, index: usize, value: Value) -> Row {
+        let mut values = self.values.clone();
+        values[index] = value;
+        Row::new(values).with_id(self.id)
+    }
+    pub(crate) fn without_value(&self, index: usize) -> Row {
+        let mut values = self.values.clone();
+        values.remove(index);
+        Row::new(values).with_id(self.id)
+    }
+    pub fn concat(&self, other: &Row) -> Row {
         let mut values = self.values.clone();
-        values.extend(other.values);
+        values.extend(other.values.iter().cloned());
         Row::new(values)
     }
 }
@@ -0,0 +1,354 @@
+//! Calendar conversions for `Date`, `Time` and `Timestamp` values
+//!
+//! Dates are days since 1970-01-01 in the proleptic Gregorian calendar, times
+//! are microseconds since midnight and timestamps are microseconds since
+//! 1970-01-01T00:00:00Z. Timestamps are always UTC: offsets in parsed text are
+//! applied and formatted timestamps end with `Z`.
+
+use std::time::SystemTime;
+
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+/// Days since the epoch of a calendar date, none if the date doesn't exist
+pub fn date(year: i64, month: u32, day: u32) -> Option<i32> {
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    // Count years from March, so that leap days are at the end of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + i64::from(day_of_year);
+    let days = era.checked_mul(146_097)?.checked_add(day_of_era - 719_468)?;
+    if days < i64::from(i32::min_value()) || days > i64::from(i32::max_value()) {
+        return None;
+    }
+    Some(days as i32)
+}
+
+/// Year, month and day of a date
+pub fn civil(days: i32) -> (i64, u32, u32) {
+    let days = i64::from(days) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Microseconds since midnight, none if out of range
+pub fn time(hour: u32, minute: u32, second: u32, micros: u32) -> Option<u64> {
+    if hour > 23 || minute > 59 || second > 59 || micros >= 1_000_000 {
+        return None;
+    }
+    Some(((u64::from(hour) * 60 + u64::from(minute)) * 60 + u64::from(second)) * 1_000_000 + u64::from(micros))
+}
+
+/// Current time in microseconds since the epoch
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }
+}
+
+/// Microseconds since the epoch of a date at a time of day, none on overflow
+pub fn timestamp(date: i32, time: u64) -> Option<i64> {
+    i64::from(date).checked_mul(MICROS_PER_DAY)?.checked_add(time as i64)
+}
+
+/// Date and time of day of a timestamp
+pub fn split_timestamp(timestamp: i64) -> (i32, u64) {
+    (timestamp.div_euclid(MICROS_PER_DAY) as i32, timestamp.rem_euclid(MICROS_PER_DAY) as u64)
+}
+
+fn number(text: &str, digits: usize) -> Option<u32> {
+    if text.len() != digits || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Parse `YYYY-MM-DD`, with an optional sign and more digits for the year
+pub fn parse_date(text: &str) -> Option<i32> {
+    let (sign, rest) = match text.as_bytes().first() {
+        Some(b'-') => (-1, &text[1..]),
+        Some(b'+') => (1, &text[1..]),
+        _ => (1, text),
+    };
+    let mut parts = rest.rsplitn(3, '-');
+    let day = number(parts.next()?, 2)?;
+    let month = number(parts.next()?, 2)?;
+    let year = parts.next()?;
+    if year.len() < 4 || !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    date(sign * year.parse::<i64>().ok()?, month, day)
+}
+
+/// Parse `HH:MM:SS` with up to six fractional digits of seconds
+pub fn parse_time(text: &str) -> Option<u64> {
+    let (clock, fraction) = match text.find('.') {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None => (text, "0"),
+    };
+    if fraction.is_empty() || fraction.len() > 6 {
+        return None;
+    }
+    let micros = number(fraction, fraction.len())? * 10u32.pow(6 - fraction.len() as u32);
+    let mut parts = clock.split(':');
+    let hour = number(parts.next()?, 2)?;
+    let minute = number(parts.next()?, 2)?;
+    let second = number(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    time(hour, minute, second, micros)
+}
+
+/// Parse a date and a time separated by `T` or a space, followed by `Z`, an
+/// offset `+HH:MM`/`-HH:MM` or nothing for UTC
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let separator = text.find(|c| c == 'T' || c == ' ')?;
+    let date = parse_date(&text[..separator])?;
+    let rest = &text[separator + 1..];
+
+    let (clock, offset) = if rest.ends_with('Z') {
+        (&rest[..rest.len() - 1], 0)
+    }
+    else if let Some(i) = rest.rfind(|c| c == '+' || c == '-') {
+        let offset = &rest[i + 1..];
+        if offset.len() != 5 || offset.get(2..3) != Some(":") {
+            return None;
+        }
+        let minutes = i64::from(number(&offset[..2], 2)? * 60 + number(&offset[3..], 2)?);
+        let sign = if rest[i..].starts_with('-') { -1 } else { 1 };
+        (&rest[..i], sign * minutes * 60 * MICROS_PER_SECOND)
+    }
+    else {
+        (rest, 0)
+    };
+    timestamp(date, parse_time(clock)?)?.checked_sub(offset)
+}
+
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil(days);
+    if year < 0 {
+        format!("-{:04}-{:02}-{:02}", -year, month, day)
+    }
+    else {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// Format as `HH:MM:SS`, with fractional seconds only if nonzero
+pub fn format_time(micros: u64) -> String {
+    let seconds = micros / 1_000_000;
+    let clock = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match micros % 1_000_000 {
+        0 => clock,
+        fraction => format!("{}.{}", clock, format!("{:06}", fraction).trim_end_matches('0')),
+    }
+}
+
+/// Format as `YYYY-MM-DDTHH:MM:SSZ` in UTC
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (date, time) = split_timestamp(timestamp);
+    format!("{}T{}Z", format_date(date), format_time(time))
+}
+
+/// Calendar or clock unit for date arithmetic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Microsecond,
+    Second,
+    Minute,
+    Hour,
+    Day,
+    /// Seven days, starting on Monday
+    Week,
+    Month,
+    Year,
+}
+impl Unit {
+    /// Unit with the name, like `day`, optionally in plural
+    pub fn parse(name: &str) -> Option<Unit> {
+        let name = name.to_lowercase();
+        Some(match name.trim_end_matches('s') {
+            "microsecond" => Unit::Microsecond,
+            "second" => Unit::Second,
+            "minute" => Unit::Minute,
+            "hour" => Unit::Hour,
+            "day" => Unit::Day,
+            "week" => Unit::Week,
+            "month" => Unit::Month,
+            "year" => Unit::Year,
+            _ => return None,
+        })
+    }
+
+    /// Length in microseconds, none for months and years whose length varies
+    pub fn micros(self) -> Option<i64> {
+        match self {
+            Unit::Microsecond => Some(1),
+            Unit::Second => Some(MICROS_PER_SECOND),
+            Unit::Minute => Some(60 * MICROS_PER_SECOND),
+            Unit::Hour => Some(3600 * MICROS_PER_SECOND),
+            Unit::Day => Some(MICROS_PER_DAY),
+            Unit::Week => Some(7 * MICROS_PER_DAY),
+            Unit::Month | Unit::Year => None,
+        }
+    }
+}
+
+/// The date `months` months later, on the last day of the month if the day doesn't exist in it
+pub fn add_months(days: i32, months: i64) -> Option<i32> {
+    let (year, month, day) = civil(days);
+    let index = (year.checked_mul(12)? + i64::from(month) - 1).checked_add(months)?;
+    let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+    date(year, month, day.min(days_in_month(year, month)))
+}
+
+/// The timestamp `amount` units later, none on overflow
+pub fn add(timestamp: i64, amount: i64, unit: Unit) -> Option<i64> {
+    match unit.micros() {
+        Some(micros) => timestamp.checked_add(amount.checked_mul(micros)?),
+        None => {
+            let months = if unit == Unit::Year { amount.checked_mul(12)? } else { amount };
+            let (date, time) = split_timestamp(timestamp);
+            self::timestamp(add_months(date, months)?, time)
+        },
+    }
+}
+
+/// Number of whole units from `start` to `end`, negative if `end` is earlier
+pub fn diff(start: i64, end: i64, unit: Unit) -> i64 {
+    match unit.micros() {
+        Some(micros) => (i128::from(end) - i128::from(start)).checked_div(i128::from(micros)).unwrap_or(0) as i64,
+        None => {
+            let (start_date, start_time) = split_timestamp(start);
+            let (end_date, end_time) = split_timestamp(end);
+            let ((y1, m1, d1), (y2, m2, d2)) = (civil(start_date), civil(end_date));
+            let mut months = (y2 - y1) * 12 + i64::from(m2) - i64::from(m1);
+            // Don't count the last month unless it is complete
+            if months > 0 && (d2, end_time) < (d1, start_time) {
+                months -= 1;
+            }
+            else if months < 0 && (d2, end_time) > (d1, start_time) {
+                months += 1;
+            }
+            if unit == Unit::Year { months / 12 } else { months }
+        },
+    }
+}
+
+/// Start of the unit the timestamp is in, none if it is before the earliest timestamp
+pub fn truncate(timestamp: i64, unit: Unit) -> Option<i64> {
+    let date = split_timestamp(timestamp).0;
+    let start = match unit {
+        Unit::Week => date - (i64::from(date) + 3).rem_euclid(7) as i32,
+        Unit::Month => {
+            let (year, month, _) = civil(date);
+            self::date(year, month, 1).unwrap_or(date)
+        },
+        Unit::Year => self::date(civil(date).0, 1, 1).unwrap_or(date),
+        _ => return timestamp.checked_sub(timestamp.rem_euclid(unit.micros().unwrap())),
+    };
+    self::timestamp(start, 0)
+}
+
+/// Format with `strftime`-style specifiers, none if the format has an unknown one
+///
+/// Supported are `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%f` (microseconds),
+/// `%j` (day of the year), `%s` (seconds since the epoch) and `%%`.
+pub fn format(timestamp: i64, format: &str) -> Option<String> {
+    let (date, time) = split_timestamp(timestamp);
+    let (year, month, day) = civil(date);
+    let seconds = time / 1_000_000;
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'Y' => out.push_str(&format!("{:04}", year)),
+            'm' => out.push_str(&format!("{:02}", month)),
+            'd' => out.push_str(&format!("{:02}", day)),
+            'H' => out.push_str(&format!("{:02}", seconds / 3600)),
+            'M' => out.push_str(&format!("{:02}", seconds / 60 % 60)),
+            'S' => out.push_str(&format!("{:02}", seconds % 60)),
+            'f' => out.push_str(&format!("{:06}", time % 1_000_000)),
+            'j' => out.push_str(&format!("{:03}", date - self::date(year, 1, 1)? + 1)),
+            's' => out.push_str(&timestamp.div_euclid(MICROS_PER_SECOND).to_string()),
+            '%' => out.push('%'),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Parse text laid out as the format, with the specifiers of `format` except
+/// `%j` and `%s`, none if it doesn't match
+///
+/// Fields missing from the format are the first month, day and midnight.
+/// Each field has exactly as many digits as `format` writes, except years
+/// which have at least four.
+pub fn parse(text: &str, format: &str) -> Option<i64> {
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second, mut micros) = (0, 0, 0, 0);
+    let mut rest = text;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rest = rest.strip_prefix(c)?;
+            continue;
+        }
+        let spec = chars.next()?;
+        if spec == '%' {
+            rest = rest.strip_prefix('%')?;
+            continue;
+        }
+        let digits = match spec {
+            'Y' => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()).max(4),
+            'f' => 6,
+            'm' | 'd' | 'H' | 'M' | 'S' => 2,
+            _ => return None,
+        };
+        let value = number(rest.get(..digits)?, digits)?;
+        rest = &rest[digits..];
+        match spec {
+            'Y' => year = i64::from(value),
+            'm' => month = value,
+            'd' => day = value,
+            'H' => hour = value,
+            'M' => minute = value,
+            'S' => second = value,
+            _ => micros = value,
+        }
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+    timestamp(date(year, month, day)?, time(hour, minute, second, micros)?)
+}
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::slice;
+
+use {SrimDB, DataDB, Delta, Query, QueryResult, QueryOptions, QueryError, ApplyError, TableName, Role};
+use query::ExecutionContext;
+
+/// Buffered set of changes, see `SrimDB::begin`
+///
+/// A transaction works on a snapshot of the database taken when it began:
+/// queries through it see its own uncommitted changes but not changes
+/// committed by others afterwards. Committing fails with
+/// `ApplyError::Conflict` if another change modified any table this
+/// transaction modifies since the snapshot was taken.
+///
+/// Ids of rows inserted through the transaction may change when it is committed.
+pub struct Transaction {
+    /// Changes applied to the database when the transaction began, see `SrimDB::table_change`
+    base_changes: u64,
+    working: DataDB,
+    deltas: Vec<Delta>,
+    /// Tables modified by each of `deltas`, see `DataDB::apply`
+    written: Vec<Vec<TableName>>,
+    /// Name, state and number of deltas at each savepoint, oldest first
+    savepoints: Vec<(String, DataDB, usize)>,
+    memory_limit: Option<usize>,
+    role: Option<Role>,
+}
+impl Transaction {
+    pub(crate) fn new(db: &SrimDB) -> Self {
+        Self {
+            base_changes: db.changes,
+            working: db.data_db.clone(),
+            deltas: Vec::new(),
+            written: Vec::new(),
+            savepoints: Vec::new(),
+            memory_limit: db.memory_limit,
+            role: db.role.clone(),
+        }
+    }
+
+    /// Apply a change inside the transaction, returning the number of rows it affected
+    ///
+    /// A failing delta is not recorded, and the transaction stays usable.
+    pub fn apply(&mut self, mut delta: Delta) -> Result<usize, ApplyError> {
+        if let Some(ref role) = self.role {
+            role.check_delta(&delta)?;
+        }
+        // Committed named rows keep the defaults seen inside the transaction
+        self.working.concrete_deltas(slice::from_mut(&mut delta))?;
+        let (report, tables) = self.working.apply(delta.clone())?;
+        self.deltas.push(delta);
+        self.written.push(tables);
+        Ok(report.rows())
+    }
+
+    pub fn query(&self, query: Query) -> Result<QueryResult, QueryError> {
+        self.query_with(query, QueryOptions::default())
+    }
+
+    pub fn query_with(&self, query: Query, options: QueryOptions) -> Result<QueryResult, QueryError> {
+        if let Some(ref role) = self.role {
+            role.check_query(&query)?;
+        }
+        query.check(&self.working)?;
+        let ctx = ExecutionContext::new(&self.working)
+            .with_memory_limit(self.memory_limit)
+            .with_options(options);
+        query.execute(&ctx)
+    }
+
+    /// Changes applied so far, in order, with named rows as the rows they added
+    pub fn deltas(&self) -> &[Delta] {
+        &self.deltas
+    }
+
+    /// Mark the current state, so that later changes can be undone with `rollback_to`
+    ///
+    /// Reusing the name of an existing savepoint shadows it until released.
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.push((name.to_owned(), self.working.clone(), self.deltas.len()));
+    }
+
+    fn savepoint_index(&self, name: &str) -> Result<usize, ApplyError> {
+        self.savepoints.iter().rposition(|(n, _, _)| n == name)
+            .ok_or(ApplyError::NoSuchSavepoint(name.to_owned()))
+    }
+
+    /// Undo changes made after the savepoint, keeping the savepoint itself
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), ApplyError> {
+        let index = self.savepoint_index(name)?;
+        self.savepoints.truncate(index + 1);
+        let (_, ref state, delta_count) = self.savepoints[index];
+        self.working = state.clone();
+        self.deltas.truncate(delta_count);
+        self.written.truncate(delta_count);
+        Ok(())
+    }
+
+    /// Forget the savepoint and all savepoints made after it, keeping the changes
+    pub fn release(&mut self, name: &str) -> Result<(), ApplyError> {
+        let index = self.savepoint_index(name)?;
+        self.savepoints.truncate(index);
+        Ok(())
+    }
+
+    /// Apply all buffered changes to the database atomically
+    pub fn commit(self, db: &mut SrimDB) -> Result<usize, ApplyError> {
+        let written: HashSet<&TableName> = self.written.iter().flatten().collect();
+        for table in written {
+            if db.table_change(&table).map_or(false, |c| c > self.base_changes) {
+                return Err(ApplyError::Conflict(table.clone()));
+            }
+        }
+        db.apply_all(self.deltas)
+    }
+
+    /// Discard all buffered changes
+    pub fn rollback(self) {}
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use {DataDB, Table, TableName, FieldName, Row, QueryField, QueryResult, ApplyError};
+use query::Condition;
+use function::Argument;
+
+/// Kind of row-level change a trigger reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerTiming {
+    /// Before the row is validated against the schema; may transform it
+    Before,
+    /// After the row is validated; may only reject it
+    After,
+}
+
+#[derive(Debug, Clone)]
+pub enum TriggerAction {
+    /// Reject the whole delta unless the condition holds for the row
+    Check(Condition),
+    /// Set fields of the row, evaluating arguments against the row before the trigger;
+    /// only allowed for `Before` triggers on inserts and updates
+    Set(Vec<(FieldName, Argument)>),
+}
+
+/// Action run for each row inserted, updated or removed by a delta
+///
+/// For inserts and updates the action sees the new row, for deletes the
+/// removed row. Triggers of a table fire in creation order. Truncating a
+/// table fires no triggers.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table: TableName,
+    pub timing: TriggerTiming,
+    pub events: Vec<TriggerEvent>,
+    pub action: TriggerAction,
+}
+impl Trigger {
+    pub fn new(name: &str, table: &str, timing: TriggerTiming, events: Vec<TriggerEvent>, action: TriggerAction) -> Self {
+        Self {
+            name: name.to_owned(),
+            table: table.to_owned(),
+            timing,
+            events,
+            action,
+        }
+    }
+
+    fn fires(&self, table: &TableName, timing: TriggerTiming, event: TriggerEvent) -> bool {
+        self.table == *table && self.timing == timing && self.events.contains(&event)
+    }
+}
+
+pub(crate) fn table_fields(table: &Table) -> QueryResult {
+    QueryResult::from_table_rows(table, Vec::new())
+}
+
+impl DataDB {
+    pub(crate) fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    fn trigger_index(&self, table: &TableName, name: &str) -> Option<usize> {
+        self.triggers.iter().position(|t| t.table == *table && t.name == name)
+    }
+
+    pub(crate) fn create_trigger(&mut self, trigger: Trigger) -> Result<(), ApplyError> {
+        let table = self.table(trigger.table.clone()).ok_or(ApplyError::NoSuchTable(trigger.table.clone()))?;
+        if self.trigger_index(&trigger.table, &trigger.name).is_some() {
+            return Err(ApplyError::TriggerExists(trigger.table, trigger.name));
+        }
+        if let TriggerAction::Set(ref assignments) = trigger.action {
+            if trigger.timing == TriggerTiming::After || trigger.events.contains(&TriggerEvent::Delete) {
+                return Err(ApplyError::InvalidTrigger(trigger.table, trigger.name));
+            }
+            for (field_name, _) in assignments {
+                if table.field_index(field_name.clone()).is_none() {
+                    return Err(ApplyError::NoSuchField(trigger.table, field_name.clone()));
+                }
+            }
+        }
+        Arc::make_mut(&mut self.triggers).push(trigger);
+        Ok(())
+    }
+
+    pub(crate) fn drop_trigger(&mut self, table: TableName, name: String) -> Result<(), ApplyError> {
+        let index = self.trigger_index(&table, &name).ok_or(ApplyError::NoSuchTrigger(table, name))?;
+        Arc::make_mut(&mut self.triggers).remove(index);
+        Ok(())
+    }
+
+    /// Run `timing` triggers of the table for `event`, returning the possibly transformed row
+    fn run_triggers(&self, name: &TableName, timing: TriggerTiming, event: TriggerEvent, mut row: Row) -> Result<Row, ApplyError> {
+        if !self.fire_triggers || !self.triggers.iter().any(|t| t.fires(name, timing, event)) {
+            return Ok(row);
+        }
+
+        let table = self.table(name.clone()).ok_or(ApplyError::NoSuchTable(name.clone()))?;
+        let fields = table_fields(&table);
+        for trigger in self.triggers.iter().filter(|t| t.fires(name, timing, event)) {
+            match trigger.action {
+                TriggerAction::Check(ref condition) => {
+                    if !fields.test_row(&self.functions, condition, &row).map_err(ApplyError::QueryError)? {
+                        return Err(ApplyError::TriggerRejected(name.clone(), trigger.name.clone()));
+                    }
+                },
+                TriggerAction::Set(ref assignments) => {
+                    let mut values = row.values();
+                    for (field_name, argument) in assignments {
+                        let i = table.field_index(field_name.clone()).ok_or(ApplyError::NoSuchField(name.clone(), field_name.clone()))?;
+                        values[i] = argument.evaluate(&self.functions, &|qf: &QueryField| fields.resolve_field(qf, &row))
+                            .map_err(ApplyError::QueryError)?;
+                    }
+                    row = Row::new(values);
+                },
+            }
+        }
+        Ok(row)
+    }
+
+    /// Run `Before` triggers, validate the row and compute its generated fields,
+    /// and then run `After` triggers
+    pub(crate) fn triggered_row(&self, table: &Table, event: TriggerEvent, row: Row) -> Result<Row, ApplyError> {
+        let name = table.name();
+        let row = self.run_triggers(&name, TriggerTiming::Before, event, row)?;
+        let row = self.generated_row(table, table.validate_row(row)?)?;
+        let row = self.run_triggers(&name, TriggerTiming::After, event, row)?;
+        // Generated fields stay consistent with changes by `After` triggers
+        self.generated_row(table, row)
+    }
+
+    /// Run triggers for removing `row`, which may veto it
+    pub(crate) fn triggered_delete(&self, name: &TableName, row: &Row) -> Result<(), ApplyError> {
+        self.run_triggers(name, TriggerTiming::Before, TriggerEvent::Delete, row.clone())?;
+        self.run_triggers(name, TriggerTiming::After, TriggerEvent::Delete, row.clone())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+//! Text form of `Uuid` values, as five groups of lowercase hex digits like
+//! `123e4567-e89b-42d3-a456-426614174000`
+
+/// Lengths of the hyphen-separated groups of hex digits
+const GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
+
+/// Parse the hyphenated form, in either case
+pub fn parse(text: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = text.split('-').collect();
+    if groups.len() != GROUPS.len() || groups.iter().zip(&GROUPS).any(|(g, len)| g.len() != *len) {
+        return None;
+    }
+    let digits: String = groups.concat();
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+pub fn format(bytes: &[u8; 16]) -> String {
+    let digits: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let mut text = String::with_capacity(36);
+    let mut start = 0;
+    for (i, len) in GROUPS.iter().enumerate() {
+        if i > 0 {
+            text.push('-');
+        }
+        text.push_str(&digits[start..start + len]);
+        start += len;
+    }
+    text
+}
+
+/// Version 4 UUID from random bytes, setting the version and variant bits
+pub fn v4(mut random: [u8; 16]) -> [u8; 16] {
+    random[6] = (random[6] & 0x0f) | 0x40;
+    random[8] = (random[8] & 0x3f) | 0x80;
+    random
+}
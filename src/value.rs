@@ -1,38 +1,76 @@
+use std::mem;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 use std::ops::{Add, BitOr};
 
 use FieldKind;
 use QueryError;
+use SharedText;
+use temporal;
+use decimal;
+use uuid;
+use geo;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ValueKind {
+    Null,
     Boolean,
     Unsigned,
     Signed,
     Real,
     Text,
     Blob,
+    Date,
+    Time,
+    Timestamp,
+    Decimal,
+    Uuid,
+    Array,
+    GeoPoint,
+    Vector,
 }
 impl ValueKind {
     pub(crate) fn more_generic(&self, other: ValueKind)  -> Option<ValueKind> {
-        if *self == other {
+        if *self == other || other == ValueKind::Null {
             Some(*self)
         }
+        else if *self == ValueKind::Null {
+            Some(other)
+        }
         else {
             use self::ValueKind::*;
             match self {
                 Unsigned => match other {
                     Signed => Some(Signed),
                     Real => Some(Real),
+                    Decimal => Some(Decimal),
                     _ => None
                 },
                 Signed => match other {
                     Unsigned => Some(Signed),
                     Real => Some(Real),
+                    Decimal => Some(Decimal),
                     _ => None
                 },
                 Real => match other {
                     Unsigned => Some(Real),
                     Signed => Some(Real),
+                    Decimal => Some(Real),
+                    _ => None
+                },
+                Decimal => match other {
+                    Unsigned => Some(Decimal),
+                    Signed => Some(Decimal),
+                    Real => Some(Real),
+                    _ => None
+                },
+                Date => match other {
+                    Timestamp => Some(Timestamp),
+                    _ => None
+                },
+                Timestamp => match other {
+                    Date => Some(Timestamp),
                     _ => None
                 },
                 _ => None
@@ -41,6 +79,61 @@ impl ValueKind {
     }
 }
 
+/// How integer arithmetic handles results that don't fit the kind of its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticMode {
+    /// Clamp to the nearest value of the kind
+    Saturating,
+    /// Wrap around in two's complement
+    Wrapping,
+    /// Fail with `QueryError::OutOfRange`
+    Checked,
+}
+impl ArithmeticMode {
+    /// The result in this mode, given the results in each mode
+    fn select<T>(self, checked: Option<T>, wrapping: T, saturating: T) -> Result<T, QueryError> {
+        match self {
+            ArithmeticMode::Saturating => Ok(saturating),
+            ArithmeticMode::Wrapping => Ok(wrapping),
+            ArithmeticMode::Checked => checked.ok_or(QueryError::OutOfRange),
+        }
+    }
+}
+impl Default for ArithmeticMode {
+    fn default() -> Self {
+        ArithmeticMode::Saturating
+    }
+}
+
+/// How casts between numeric kinds handle values the target kind can't hold exactly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastMode {
+    /// Fail with `QueryError::CastOverflow`
+    Checked,
+    /// Wrap integers around, and truncate reals towards zero, saturating at the
+    /// limits of the kind
+    Lossy,
+}
+impl Default for CastMode {
+    fn default() -> Self {
+        CastMode::Checked
+    }
+}
+
+/// Lower bound of reals that fit an `i128`; the upper bound is its negation
+const I128_MIN_REAL: f64 = -170141183460469231731687303715884105728.0;
+/// Upper bound of reals that fit an `u128`, exclusive
+const U128_END_REAL: f64 = 340282366920938463463374607431768211456.0;
+
+/// Integer with the value of the real, none if it is fractional or out of range
+fn real_to_signed(r: f64) -> Option<i128> {
+    if r.fract() == 0.0 && r >= I128_MIN_REAL && r < -I128_MIN_REAL { Some(r as i128) } else { None }
+}
+
+fn real_to_unsigned(r: f64) -> Option<u128> {
+    if r.fract() == 0.0 && r >= 0.0 && r < U128_END_REAL { Some(r as u128) } else { None }
+}
+
 macro unwrap_binop($type:path, $v1:ident, $v2:ident, $op:path) {
     $type(
         $op(
@@ -51,8 +144,17 @@ macro unwrap_binop($type:path, $v1:ident, $v2:ident, $op:path) {
 }
 
 /// Types for data processing
-#[derive(Debug, Clone, PartialEq)]
+///
+/// `Null` is unknown in comparisons and conditions, but equal to itself
+/// when rows are compared, as in set operations and `Distinct`.
+///
+/// Reals are equal and ordered like numbers, except that -0 equals +0 and
+/// NaN equals itself and is greater than all other reals, see `real_cmp`.
+/// So are the coordinates of geographic points and the elements of vectors.
+#[derive(Debug, Clone)]
 pub enum Value {
+    /// Missing or unknown value, only allowed in nullable fields
+    Null,
     /// Boolean (true/false) value
     Boolean(bool),
     /// Unsigned integer
@@ -62,24 +164,155 @@ pub enum Value {
     /// IEEE 754 double-precision binary floating-point format (binary64)
     Real(f64),
     /// UTF-8 Text
-    Text(String),
+    Text(SharedText),
     /// Arbitrary binary data
     Blob(Vec<u8>),
+    /// Days since 1970-01-01
+    Date(i32),
+    /// Microseconds since midnight
+    Time(u64),
+    /// Microseconds since 1970-01-01T00:00:00Z
+    Timestamp(i64),
+    /// Decimal(mantissa, scale), the exact number mantissa × 10^-scale
+    ///
+    /// Values with different scales are distinct, even if they are numerically equal.
+    Decimal(i128, u8),
+    /// Universally unique identifier
+    Uuid([u8; 16]),
+    /// Ordered list of values
+    Array(Vec<Value>),
+    /// GeoPoint(latitude, longitude) in degrees
+    GeoPoint(f64, f64),
+    /// Embedding or other fixed-dimension vector
+    Vector(Vec<f32>),
+}
+/// Total order of reals: numeric, with -0 equal to +0, and NaN equal to
+/// itself and after all other reals
+pub fn real_cmp(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => a.partial_cmp(&b).unwrap(),
+        (false, true) => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (true, true) => Ordering::Equal,
+    }
+}
+
+/// Bits of the real, the same for all reals equal in the order of `real_cmp`
+fn real_bits(r: f64) -> u64 {
+    if r.is_nan() {
+        ::std::f64::NAN.to_bits()
+    }
+    else if r == 0.0 {
+        0
+    }
+    else {
+        r.to_bits()
+    }
 }
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        use self::Value::*;
+        match (self, other) {
+            (Null, Null) => true,
+            (Boolean(a), Boolean(b)) => a == b,
+            (Unsigned(a), Unsigned(b)) => a == b,
+            (Signed(a), Signed(b)) => a == b,
+            (Real(a), Real(b)) => real_cmp(*a, *b) == Ordering::Equal,
+            (Text(a), Text(b)) => a == b,
+            (Blob(a), Blob(b)) => a == b,
+            (Date(a), Date(b)) => a == b,
+            (Time(a), Time(b)) => a == b,
+            (Timestamp(a), Timestamp(b)) => a == b,
+            (Decimal(m1, s1), Decimal(m2, s2)) => decimal::normalize(*m1, *s1) == decimal::normalize(*m2, *s2),
+            (Uuid(a), Uuid(b)) => a == b,
+            (Array(a), Array(b)) => a == b,
+            (GeoPoint(lat1, lon1), GeoPoint(lat2, lon2)) => {
+                real_cmp(*lat1, *lat2) == Ordering::Equal && real_cmp(*lon1, *lon2) == Ordering::Equal
+            },
+            (Vector(a), Vector(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| real_cmp(f64::from(*x), f64::from(*y)) == Ordering::Equal)
+            },
+            _ => false,
+        }
+    }
+}
+impl Eq for Value {}
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use self::Value::*;
+        mem::discriminant(self).hash(state);
+        match self {
+            Null => {},
+            Boolean(b) => b.hash(state),
+            Unsigned(v) => v.hash(state),
+            Signed(v) => v.hash(state),
+            Real(r) => real_bits(*r).hash(state),
+            Text(t) => t.hash(state),
+            Blob(b) => b.hash(state),
+            Date(d) => d.hash(state),
+            Time(t) => t.hash(state),
+            Timestamp(t) => t.hash(state),
+            Decimal(m, s) => decimal::normalize(*m, *s).hash(state),
+            Uuid(u) => u.hash(state),
+            Array(a) => a.hash(state),
+            GeoPoint(lat, lon) => (real_bits(*lat), real_bits(*lon)).hash(state),
+            Vector(v) => {
+                v.len().hash(state);
+                for e in v {
+                    real_bits(f64::from(*e)).hash(state);
+                }
+            },
+        }
+    }
+}
+
 impl Value {
     pub fn kind(&self) -> ValueKind {
         use self::Value::*;
         match self {
+            Null => ValueKind::Null,
             Boolean(_) => ValueKind::Boolean,
             Unsigned(_) => ValueKind::Unsigned,
             Signed(_) => ValueKind::Signed,
             Real(_) => ValueKind::Real,
             Text(_) => ValueKind::Text,
             Blob(_) => ValueKind::Blob,
+            Date(_) => ValueKind::Date,
+            Time(_) => ValueKind::Time,
+            Timestamp(_) => ValueKind::Timestamp,
+            Decimal(_, _) => ValueKind::Decimal,
+            Uuid(_) => ValueKind::Uuid,
+            Array(_) => ValueKind::Array,
+            GeoPoint(_, _) => ValueKind::GeoPoint,
+            Vector(_) => ValueKind::Vector,
         }
     }
 
+    /// Approximate number of bytes used by this value, including heap data
+    pub fn approx_size_bytes(&self) -> usize {
+        mem::size_of::<Value>() + match self {
+            Value::Text(t) => t.len(),
+            Value::Blob(b) => b.len(),
+            Value::Array(a) => a.iter().map(|v| v.approx_size_bytes()).sum(),
+            Value::Vector(v) => v.len() * mem::size_of::<f32>(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
     pub fn binop_add(&self, other: Value) -> Result<Value, QueryError> {
+        self.binop_add_in(other, ArithmeticMode::Saturating)
+    }
+
+    /// Sum, with integer overflow handled according to `mode`
+    pub fn binop_add_in(&self, other: Value, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        if self.is_null() || other.is_null() {
+            return Ok(Value::Null);
+        }
         if let Some(result_kind) = self.kind().more_generic(other.kind()) {
             let c1 = self.cast_to(result_kind)?;
             let c2 = other.cast_to(result_kind)?;
@@ -93,14 +326,21 @@ impl Value {
                 // )
 
 
+                ValueKind::Null     => Ok(Null),
                 ValueKind::Boolean  => Ok(unwrap_binop!(Boolean,  c1, c2,   bool::bitor)),
-                ValueKind::Unsigned => Ok(unwrap_binop!(Unsigned, c1, c2,   u128::saturating_add)),
-                ValueKind::Signed   => Ok(unwrap_binop!(Signed,   c1, c2,   i128::saturating_add)),
+                ValueKind::Unsigned => match (c1, c2) {
+                    (Unsigned(a), Unsigned(b)) => mode.select(a.checked_add(b), a.wrapping_add(b), a.saturating_add(b)).map(Unsigned),
+                    _ => unreachable!(),
+                },
+                ValueKind::Signed   => match (c1, c2) {
+                    (Signed(a), Signed(b)) => mode.select(a.checked_add(b), a.wrapping_add(b), a.saturating_add(b)).map(Signed),
+                    _ => unreachable!(),
+                },
                 ValueKind::Real     => Ok(unwrap_binop!(Real,     c1, c2,    f64::add)),
                 ValueKind::Text     => {
                     let v1 = match c1 {Value::Text(b) => b, _ => unreachable!()};
                     let v2 = match c2 {Value::Text(b) => b, _ => unreachable!()};
-                    Ok(Value::Text(v1 + &v2))
+                    Ok(Value::Text(format!("{}{}", v1, v2).into()))
                 },
                 ValueKind::Blob     => {
                     let v1 = match c1 {Value::Blob(b) => b, _ => unreachable!()};
@@ -109,6 +349,25 @@ impl Value {
                     v.extend(v2.clone());
                     Ok(Value::Blob(v))
                 },
+                ValueKind::Array    => match (c1, c2) {
+                    (Array(mut v1), Array(v2)) => {
+                        v1.extend(v2);
+                        Ok(Array(v1))
+                    },
+                    _ => unreachable!(),
+                },
+                ValueKind::Decimal  => match (c1, c2) {
+                    (Decimal(m1, s1), Decimal(m2, s2)) => decimal::add((m1, s1), (m2, s2))
+                        .map(|(m, s)| Decimal(m, s))
+                        .ok_or(QueryError::OutOfRange),
+                    _ => unreachable!(),
+                },
+                ValueKind::Date
+                | ValueKind::Time
+                | ValueKind::Timestamp
+                | ValueKind::Uuid
+                | ValueKind::GeoPoint
+                | ValueKind::Vector => Err(QueryError::IncompatibleTypes),
             }
         }
         else {
@@ -116,56 +375,501 @@ impl Value {
         }
     }
 
+    /// Both values cast to their more generic numeric kind, none if either is null
+    fn numeric_operands(&self, other: &Value) -> Result<Option<(Value, Value)>, QueryError> {
+        if self.is_null() || other.is_null() {
+            return Ok(None);
+        }
+        match self.kind().more_generic(other.kind()) {
+            Some(kind @ ValueKind::Unsigned)
+            | Some(kind @ ValueKind::Signed)
+            | Some(kind @ ValueKind::Real)
+            | Some(kind @ ValueKind::Decimal) => Ok(Some((self.cast_to(kind)?, other.cast_to(kind)?))),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+
+    pub fn binop_sub(&self, other: Value) -> Result<Value, QueryError> {
+        self.binop_sub_in(other, ArithmeticMode::Saturating)
+    }
+
+    /// Difference, with integer overflow handled according to `mode`
+    pub fn binop_sub_in(&self, other: Value, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self.numeric_operands(&other)? {
+            None => Ok(Null),
+            Some((Unsigned(a), Unsigned(b))) => mode.select(a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b)).map(Unsigned),
+            Some((Signed(a), Signed(b))) => mode.select(a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b)).map(Signed),
+            Some((Real(a), Real(b))) => Ok(Real(a - b)),
+            Some((Decimal(m1, s1), Decimal(m2, s2))) => decimal::sub((m1, s1), (m2, s2))
+                .map(|(m, s)| Decimal(m, s))
+                .ok_or(QueryError::OutOfRange),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn binop_mul(&self, other: Value) -> Result<Value, QueryError> {
+        self.binop_mul_in(other, ArithmeticMode::Saturating)
+    }
+
+    /// Product, with integer overflow handled according to `mode`
+    pub fn binop_mul_in(&self, other: Value, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self.numeric_operands(&other)? {
+            None => Ok(Null),
+            Some((Unsigned(a), Unsigned(b))) => mode.select(a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b)).map(Unsigned),
+            Some((Signed(a), Signed(b))) => mode.select(a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b)).map(Signed),
+            Some((Real(a), Real(b))) => Ok(Real(a * b)),
+            Some((Decimal(m1, s1), Decimal(m2, s2))) => decimal::mul((m1, s1), (m2, s2))
+                .map(|(m, s)| Decimal(m, s))
+                .ok_or(QueryError::OutOfRange),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Quotient, truncated towards zero for integers
+    pub fn binop_div(&self, other: Value) -> Result<Value, QueryError> {
+        self.binop_div_in(other, ArithmeticMode::Saturating)
+    }
+
+    /// Quotient, with integer overflow handled according to `mode`
+    pub fn binop_div_in(&self, other: Value, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self.numeric_operands(&other)? {
+            None => Ok(Null),
+            Some((_, ref divisor)) if divisor.is_zero() => Err(QueryError::DivisionByZero),
+            Some((Unsigned(a), Unsigned(b))) => Ok(Unsigned(a / b)),
+            // Only i128::MIN / -1 overflows
+            Some((Signed(a), Signed(b))) => mode.select(a.checked_div(b), a.wrapping_div(b), a.checked_div(b).unwrap_or(i128::max_value())).map(Signed),
+            Some((Real(a), Real(b))) => Ok(Real(a / b)),
+            Some((Decimal(m1, s1), Decimal(m2, s2))) => decimal::div((m1, s1), (m2, s2))
+                .map(|(m, s)| Decimal(m, s))
+                .ok_or(QueryError::OutOfRange),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Remainder of the quotient truncated towards zero, with the sign of the dividend
+    pub fn binop_mod(&self, other: Value) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self.numeric_operands(&other)? {
+            None => Ok(Null),
+            Some((_, ref divisor)) if divisor.is_zero() => Err(QueryError::DivisionByZero),
+            Some((Unsigned(a), Unsigned(b))) => Ok(Unsigned(a % b)),
+            Some((Signed(a), Signed(b))) => Ok(Signed(a.wrapping_rem(b))),
+            Some((Real(a), Real(b))) => Ok(Real(a % b)),
+            Some((Decimal(m1, s1), Decimal(m2, s2))) => decimal::rem((m1, s1), (m2, s2))
+                .map(|(m, s)| Decimal(m, s))
+                .ok_or(QueryError::OutOfRange),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Base raised to the power of the exponent
+    ///
+    /// Integer and decimal bases keep their kind with a non-negative integer
+    /// exponent, otherwise the result is real.
+    pub fn binop_pow(&self, exponent: Value) -> Result<Value, QueryError> {
+        self.binop_pow_in(exponent, ArithmeticMode::Saturating)
+    }
+
+    /// Power, with integer overflow handled according to `mode`
+    pub fn binop_pow_in(&self, exponent: Value, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        let integer_exponent = match exponent {
+            Unsigned(e) => Some(e),
+            Signed(e) if e >= 0 => Some(e as u128),
+            _ => None,
+        };
+        let saturated = |e: u128| if e > u128::from(u32::max_value()) { u32::max_value() } else { e as u32 };
+        match (self, integer_exponent) {
+            (_, _) if self.is_null() || exponent.is_null() => Ok(Null),
+            (Unsigned(a), Some(e)) => {
+                let e = saturated(e);
+                mode.select(a.checked_pow(e), a.wrapping_pow(e), a.saturating_pow(e)).map(Unsigned)
+            },
+            (Signed(a), Some(e)) => {
+                let e = saturated(e);
+                mode.select(a.checked_pow(e), a.wrapping_pow(e), a.saturating_pow(e)).map(Signed)
+            },
+            (Decimal(m, s), Some(e)) => {
+                let scale = u128::from(*s).checked_mul(e).filter(|&scale| scale <= u128::from(decimal::MAX_PRECISION));
+                let mantissa = scale.and_then(|_| m.checked_pow(saturated(e)));
+                match (mantissa, scale) {
+                    (Some(m), Some(s)) => Ok(Decimal(m, s as u8)),
+                    _ => Err(QueryError::OutOfRange),
+                }
+            },
+            (Unsigned(_), None) | (Signed(_), None) | (Real(_), _) | (Decimal(..), None) => {
+                if self.numeric_operands(&exponent)?.is_none() {
+                    return Ok(Null);
+                }
+                match (self.cast_to(ValueKind::Real)?, exponent.cast_to(ValueKind::Real)?) {
+                    (Real(a), Real(e)) => Ok(Real(a.powf(e))),
+                    _ => Err(QueryError::IncompatibleTypes),
+                }
+            },
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+
+    pub fn negate(&self) -> Result<Value, QueryError> {
+        self.negate_in(ArithmeticMode::Saturating)
+    }
+
+    /// Negation, with integer overflow handled according to `mode`
+    ///
+    /// Unsigned integers become signed.
+    pub fn negate_in(&self, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self {
+            Null => Ok(Null),
+            // -(2^127) is the smallest signed integer
+            Unsigned(a) => {
+                let checked = if *a <= 1 << 127 { Some((*a as i128).wrapping_neg()) } else { None };
+                mode.select(checked, (*a as i128).wrapping_neg(), checked.unwrap_or(i128::min_value())).map(Signed)
+            },
+            Signed(a) => mode.select(a.checked_neg(), a.wrapping_neg(), a.saturating_neg()).map(Signed),
+            Real(a) => Ok(Real(-a)),
+            Decimal(m, s) => m.checked_neg().map(|m| Decimal(m, *s)).ok_or(QueryError::OutOfRange),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+
+    pub fn abs(&self) -> Result<Value, QueryError> {
+        self.abs_in(ArithmeticMode::Saturating)
+    }
+
+    /// Absolute value, with integer overflow handled according to `mode`
+    pub fn abs_in(&self, mode: ArithmeticMode) -> Result<Value, QueryError> {
+        use self::Value::*;
+        match self {
+            Null | Unsigned(_) => Ok(self.clone()),
+            Signed(a) => mode.select(a.checked_abs(), a.wrapping_abs(), a.saturating_abs()).map(Signed),
+            Real(a) => Ok(Real(a.abs())),
+            Decimal(m, s) => m.checked_abs().map(|m| Decimal(m, *s)).ok_or(QueryError::OutOfRange),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Value::Unsigned(v) => *v == 0,
+            Value::Signed(v) => *v == 0,
+            Value::Real(v) => *v == 0.0,
+            Value::Decimal(m, _) => *m == 0,
+            _ => false,
+        }
+    }
+
+    /// The value as a value of kind `to`, failing with `QueryError::CastOverflow`
+    /// if a number doesn't fit it exactly
     pub fn cast_to(&self, to: ValueKind) -> Result<Value, QueryError> {
-        if self.kind() == to {
+        self.cast_to_in(to, CastMode::Checked)
+    }
+
+    /// The value as a value of kind `to`, with numbers that don't fit it
+    /// exactly handled according to `mode`
+    pub fn cast_to_in(&self, to: ValueKind, mode: CastMode) -> Result<Value, QueryError> {
+        if self.kind() == to || self.is_null() {
             return Ok(self.clone());
         }
+        let lossy = mode == CastMode::Lossy;
+        let overflow = || QueryError::CastOverflow(to);
 
         match to {
             ValueKind::Boolean => Err(QueryError::IncompatibleTypes),
             ValueKind::Unsigned => match self {
-                Value::Signed(v)    => Ok(Value::Unsigned(*v as u128)),
+                Value::Signed(v) if *v >= 0 || lossy => Ok(Value::Unsigned(*v as u128)),
+                Value::Real(v) if lossy && *v > -1.0 => Ok(Value::Unsigned(*v as u128)),
+                Value::Real(v) => real_to_unsigned(*v).map(Value::Unsigned).ok_or_else(overflow),
+                Value::Decimal(m, s) if *m >= 0 && (lossy || decimal::is_integral(*m, *s)) => Ok(Value::Unsigned(decimal::truncate(*m, *s) as u128)),
+                Value::Signed(_) | Value::Decimal(_, _) => Err(overflow()),
+                Value::Text(v)      => v.trim().parse().map(Value::Unsigned).map_err(|_| QueryError::IncompatibleTypes),
                 _ => Err(QueryError::IncompatibleTypes)
             },
             ValueKind::Signed => match self {
-                Value::Unsigned(v)  => Ok(Value::Signed(*v as i128)),
-                Value::Real(v)      => Ok(Value::Signed(*v as i128)),
+                Value::Unsigned(v) if *v <= i128::max_value() as u128 || lossy => Ok(Value::Signed(*v as i128)),
+                Value::Real(v) if lossy => Ok(Value::Signed(*v as i128)),
+                Value::Real(v) => real_to_signed(*v).map(Value::Signed).ok_or_else(overflow),
+                Value::Decimal(m, s) if lossy || decimal::is_integral(*m, *s) => Ok(Value::Signed(decimal::truncate(*m, *s))),
+                Value::Unsigned(_) | Value::Decimal(_, _) => Err(overflow()),
+                Value::Text(v)      => v.trim().parse().map(Value::Signed).map_err(|_| QueryError::IncompatibleTypes),
                 _ => Err(QueryError::IncompatibleTypes)
             },
             ValueKind::Real => match self {
                 Value::Signed(v)    => Ok(Value::Real(*v as f64)),
                 Value::Unsigned(v)  => Ok(Value::Real(*v as f64)),
+                Value::Decimal(m, s) => Ok(Value::Real(decimal::to_real(*m, *s))),
+                Value::Text(v)      => v.trim().parse().map(Value::Real).map_err(|_| QueryError::IncompatibleTypes),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Decimal => match self {
+                Value::Signed(v)    => Ok(Value::Decimal(*v, 0)),
+                Value::Unsigned(v) if *v <= i128::max_value() as u128 || lossy => Ok(Value::Decimal(*v as i128, 0)),
+                Value::Unsigned(_)  => Err(overflow()),
+                // The shortest text that reads back as the same number
+                Value::Real(v) if v.is_finite() => Value::Text(format!("{:?}", v).into()).cast_to(ValueKind::Decimal),
+                Value::Text(v)      => decimal::parse(v).map(|(m, s)| Value::Decimal(m, s)).ok_or(QueryError::IncompatibleTypes),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Text => match self {
+                Value::Boolean(v)   => Ok(Value::Text(v.to_string().into())),
+                Value::Unsigned(v)  => Ok(Value::Text(v.to_string().into())),
+                Value::Signed(v)    => Ok(Value::Text(v.to_string().into())),
+                Value::Real(v)      => Ok(Value::Text(v.to_string().into())),
+                Value::Blob(v)      => String::from_utf8(v.clone()).map(|v| Value::Text(v.into())).map_err(|_| QueryError::IncompatibleTypes),
+                Value::Decimal(m, s) => Ok(Value::Text(decimal::format(*m, *s).into())),
+                Value::Uuid(v)      => Ok(Value::Text(uuid::format(v).into())),
+                Value::GeoPoint(lat, lon) => Ok(Value::Text(geo::format(*lat, *lon).into())),
+                Value::Date(v)      => Ok(Value::Text(temporal::format_date(*v).into())),
+                Value::Time(v)      => Ok(Value::Text(temporal::format_time(*v).into())),
+                Value::Timestamp(v) => Ok(Value::Text(temporal::format_timestamp(*v).into())),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Blob => match self {
+                Value::Text(v)      => Ok(Value::Blob(v.as_bytes().to_vec())),
+                Value::Uuid(v)      => Ok(Value::Blob(v.to_vec())),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Uuid => match self {
+                Value::Text(v)      => uuid::parse(v).map(Value::Uuid).ok_or(QueryError::IncompatibleTypes),
+                Value::Blob(v) if v.len() == 16 => {
+                    let mut bytes = [0; 16];
+                    bytes.copy_from_slice(v);
+                    Ok(Value::Uuid(bytes))
+                },
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Vector => match self {
+                Value::Array(values) => values.iter()
+                    .map(|v| match v.cast_to(ValueKind::Real)? {
+                        Value::Real(r) => Ok(r as f32),
+                        _ => Err(QueryError::IncompatibleTypes),
+                    })
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map(Value::Vector),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::GeoPoint => match self {
+                Value::Text(v)      => geo::parse(v).map(|(lat, lon)| Value::GeoPoint(lat, lon)).ok_or(QueryError::IncompatibleTypes),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Date => match self {
+                Value::Timestamp(v) => Ok(Value::Date(temporal::split_timestamp(*v).0)),
+                Value::Text(v)      => temporal::parse_date(v).map(Value::Date).ok_or(QueryError::IncompatibleTypes),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Time => match self {
+                Value::Timestamp(v) => Ok(Value::Time(temporal::split_timestamp(*v).1)),
+                Value::Text(v)      => temporal::parse_time(v).map(Value::Time).ok_or(QueryError::IncompatibleTypes),
+                _ => Err(QueryError::IncompatibleTypes)
+            },
+            ValueKind::Timestamp => match self {
+                Value::Date(v)      => temporal::timestamp(*v, 0).map(Value::Timestamp).ok_or(QueryError::OutOfRange),
+                Value::Text(v)      => temporal::parse_timestamp(v).map(Value::Timestamp).ok_or(QueryError::IncompatibleTypes),
                 _ => Err(QueryError::IncompatibleTypes)
             },
             _ => Err(QueryError::IncompatibleTypes)
         }
     }
 
+    /// The value converted to be stored in a field of kind `to`, failing with
+    /// `QueryError::CastOverflow` if a number doesn't fit it exactly
     pub fn cast_to_field_kind(&self, to: FieldKind) -> Result<Value, QueryError> {
+        self.cast_to_field_kind_in(to, CastMode::Checked)
+    }
+
+    /// The value converted to be stored in a field of kind `to`, with numbers
+    /// that don't fit it exactly handled according to `mode`
+    ///
+    /// In `CastMode::Lossy`, integers wrap around to the size of the field and
+    /// reals saturate at its limits. Decimals with too many digits fail in any mode.
+    pub fn cast_to_field_kind_in(&self, to: FieldKind, mode: CastMode) -> Result<Value, QueryError> {
         use Value::*;
+        if self.is_null() {
+            return Ok(Null);
+        }
         match to {
-            FieldKind::Integer(_size, signed) => match (self, signed) {
-                (&Unsigned(value), true )   => Ok(Signed(value as i128)),
-                (&Unsigned(value), false)   => Ok(Unsigned(value)),
-                (&Signed(value),   true )   => Ok(Signed(value)),
-                (&Signed(value),   false)   => Ok(Unsigned(value as u128)),
-                (&Real(value),     true )   => Ok(Signed(value as i128)),
-                _ => Err(QueryError::IncompatibleTypes)
+            FieldKind::Integer(size, signed) => {
+                let lossy = mode == CastMode::Lossy;
+                let kind = if signed { ValueKind::Signed } else { ValueKind::Unsigned };
+                let value = match self {
+                    &Unsigned(_) | &Signed(_) => self.cast_to_in(kind, mode)?,
+                    &Real(_) if signed => match self.cast_to_in(kind, mode)? {
+                        Signed(v) if lossy => return Ok(Signed(size.saturate_signed(v))),
+                        value => value,
+                    },
+                    _ => return Err(QueryError::IncompatibleTypes)
+                };
+                match value {
+                    Unsigned(v) if size.fits_unsigned(v) => Ok(Unsigned(v)),
+                    Signed(v) if size.fits_signed(v) => Ok(Signed(v)),
+                    Unsigned(v) if lossy => Ok(Unsigned(size.wrap_unsigned(v))),
+                    Signed(v) if lossy => Ok(Signed(size.wrap_signed(v))),
+                    _ => Err(QueryError::CastOverflow(kind)),
+                }
             },
             FieldKind::Real => match self {
                 &Unsigned(value)    => Ok(Real(value as f64)),
                 &Signed(value)      => Ok(Real(value as f64)),
                 &Real(value)        => Ok(Real(value)),
+                &Decimal(m, s)      => Ok(Real(decimal::to_real(m, s))),
                 _ => Err(QueryError::IncompatibleTypes)
             },
             FieldKind::Text => match self {
-                &Boolean(value)     => Ok(Text(value.to_string())),
-                &Unsigned(value)    => Ok(Text(value.to_string())),
-                &Signed(value)      => Ok(Text(value.to_string())),
-                &Real(value)        => Ok(Text(value.to_string())),
+                &Boolean(value)     => Ok(Text(value.to_string().into())),
+                &Unsigned(value)    => Ok(Text(value.to_string().into())),
+                &Signed(value)      => Ok(Text(value.to_string().into())),
+                &Real(value)        => Ok(Text(value.to_string().into())),
                 &Text(ref value)    => Ok(Text(value.clone())),
+                // Only explicitly, since blobs are rarely text
+                &Blob(_)            => Err(QueryError::IncompatibleTypes),
+                _ => self.cast_to_in(ValueKind::Text, mode),
+            },
+            FieldKind::Blob => match self {
+                &Blob(ref value)    => Ok(Blob(value.clone())),
+                &Text(_)            => Err(QueryError::IncompatibleTypes),
+                _ => self.cast_to_in(ValueKind::Blob, mode),
+            },
+            FieldKind::Uuid => self.cast_to(ValueKind::Uuid),
+            FieldKind::Vector(dimension) => match self.cast_to(ValueKind::Vector)? {
+                Vector(ref values) if values.len() != dimension as usize => Err(QueryError::IncompatibleTypes),
+                vector => Ok(vector),
+            },
+            FieldKind::GeoPoint => match self.cast_to(ValueKind::GeoPoint)? {
+                GeoPoint(lat, lon) => geo::normalize(lat, lon)
+                    .map(|(lat, lon)| GeoPoint(lat, lon))
+                    .ok_or(QueryError::OutOfRange),
+                _ => unreachable!(),
+            },
+            FieldKind::Array(ref kind) => match self {
+                &Array(ref values) => values.iter()
+                    .map(|v| v.cast_to_field_kind((**kind).clone()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Array),
                 _ => Err(QueryError::IncompatibleTypes)
             },
+            FieldKind::Date => match self {
+                // Only midnight converts to a date without losing the time of day
+                &Timestamp(value) if value.rem_euclid(temporal::MICROS_PER_DAY) != 0 => Err(QueryError::IncompatibleTypes),
+                _ => self.cast_to(ValueKind::Date),
+            },
+            FieldKind::Decimal(precision, scale) => match self.cast_to(ValueKind::Decimal)? {
+                Decimal(mantissa, from) => {
+                    // Rounding would lose digits, so only exact conversions are allowed
+                    let mantissa = decimal::rescale(mantissa, from, scale).ok_or(QueryError::IncompatibleTypes)?;
+                    if decimal::digits(mantissa) > precision {
+                        return Err(QueryError::CastOverflow(ValueKind::Decimal));
+                    }
+                    Ok(Decimal(mantissa, scale))
+                },
+                _ => unreachable!(),
+            },
+            FieldKind::Time => self.cast_to(ValueKind::Time),
+            FieldKind::Timestamp => self.cast_to(ValueKind::Timestamp),
+            FieldKind::Boolean => self.cast_to(ValueKind::Boolean),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+
+    /// Order of two values of compatible kinds, none if either is null
+    ///
+    /// Dates compare with timestamps as midnight UTC.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, QueryError> {
+        use Value::*;
+        if self.is_null() || other.is_null() {
+            return Ok(None);
+        }
+        // Exactly, as not all of them have a common kind
+        match (self, other) {
+            (Unsigned(a), Signed(b)) => return Ok(Some(if *b < 0 { Ordering::Greater } else { a.cmp(&(*b as u128)) })),
+            (Signed(a), Unsigned(b)) => return Ok(Some(if *a < 0 { Ordering::Less } else { (*a as u128).cmp(b) })),
+            _ => {},
+        }
+        let kind = self.kind().more_generic(other.kind()).ok_or(QueryError::IncompatibleTypes)?;
+        match (self.cast_to(kind)?, other.cast_to(kind)?) {
+            (Boolean(a), Boolean(b))        => Ok(a.partial_cmp(&b)),
+            (Unsigned(a), Unsigned(b))      => Ok(a.partial_cmp(&b)),
+            (Signed(a), Signed(b))          => Ok(a.partial_cmp(&b)),
+            (Real(a), Real(b))              => Ok(Some(real_cmp(a, b))),
+            (Text(a), Text(b))              => Ok(a.as_str().partial_cmp(b.as_str())),
+            (Blob(a), Blob(b))              => Ok(a.partial_cmp(&b)),
+            (Date(a), Date(b))              => Ok(a.partial_cmp(&b)),
+            (Time(a), Time(b))              => Ok(a.partial_cmp(&b)),
+            (Timestamp(a), Timestamp(b))    => Ok(a.partial_cmp(&b)),
+            (Decimal(m1, s1), Decimal(m2, s2)) => Ok(Some(decimal::compare((m1, s1), (m2, s2)))),
+            (Uuid(a), Uuid(b))              => Ok(a.partial_cmp(&b)),
+            // Lexicographically, so unknown elements make the order unknown
+            (Array(a), Array(b))            => {
+                for (x, y) in a.iter().zip(&b) {
+                    match x.compare(y)? {
+                        Some(Ordering::Equal) => {},
+                        order => return Ok(order),
+                    }
+                }
+                Ok(Some(a.len().cmp(&b.len())))
+            },
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+
+// Conversions for `Row::get`, which fail with `QueryError::IncompatibleTypes`
+// for values of other kinds, null included, and with `QueryError::OutOfRange`
+// for integers that don't fit
+impl TryFrom<Value> for i64 {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Signed(v) => i64::try_from(v).map_err(|_| QueryError::OutOfRange),
+            Value::Unsigned(v) => i64::try_from(v).map_err(|_| QueryError::OutOfRange),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+impl TryFrom<Value> for u64 {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Signed(v) => u64::try_from(v).map_err(|_| QueryError::OutOfRange),
+            Value::Unsigned(v) => u64::try_from(v).map_err(|_| QueryError::OutOfRange),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+impl TryFrom<Value> for f64 {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Real(v) => Ok(v),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+impl TryFrom<Value> for bool {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Boolean(v) => Ok(v),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+impl TryFrom<Value> for String {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Text(v) => Ok(v.as_str().to_owned()),
+            _ => Err(QueryError::IncompatibleTypes),
+        }
+    }
+}
+impl TryFrom<Value> for Vec<u8> {
+    type Error = QueryError;
+    fn try_from(value: Value) -> Result<Self, QueryError> {
+        match value {
+            Value::Blob(v) => Ok(v),
             _ => Err(QueryError::IncompatibleTypes),
         }
     }
@@ -0,0 +1,18 @@
+//! Similarity of `Vector` values, computed in double precision
+
+/// Sum of the products of the elements, none if the dimensions differ
+pub fn dot_product(a: &[f32], b: &[f32]) -> Option<f64> {
+    if a.len() != b.len() {
+        return None;
+    }
+    Some(a.iter().zip(b).map(|(x, y)| f64::from(*x) * f64::from(*y)).sum())
+}
+
+/// Cosine of the angle between the vectors, none if the dimensions differ or either is zero
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f64> {
+    let norms = dot_product(a, a)?.sqrt() * dot_product(b, b)?.sqrt();
+    if norms == 0.0 {
+        return None;
+    }
+    Some(dot_product(a, b)? / norms)
+}
@@ -0,0 +1,295 @@
+//! Named views, stored queries that `Query::Table` reads like tables
+//!
+//! A view is executed each time it is read, so it always reflects the current
+//! rows. A materialized view stores its rows instead, and they are updated as
+//! the tables it reads change if its query is a union of filters, projections
+//! and renames of tables; otherwise only `Delta::RefreshView` updates them.
+//! The fields of both are qualified with the name of the view.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use {DataDB, TableName, TableField, RowId, Row, Query, QueryField, QueryResult, Collation, ApplyError};
+use query::{self, ExecutionContext};
+
+/// Stored rows of a materialized view
+#[derive(Clone)]
+pub(crate) struct MaterializedView {
+    query: Query,
+    fields: Vec<QueryField>,
+    collations: Vec<Collation>,
+    /// Kinds of the fields, see `QueryResult::fields`
+    schema: Vec<Option<TableField>>,
+    /// Rows of each branch of the union the query is, none if the query can't
+    /// be maintained incrementally
+    branches: Option<Vec<Branch>>,
+    rows: Arc<Vec<Arc<Row>>>,
+}
+impl MaterializedView {
+    pub(crate) fn query(&self) -> &Query {
+        &self.query
+    }
+
+    pub(crate) fn fields(&self) -> &[QueryField] {
+        &self.fields
+    }
+
+    pub(crate) fn collations(&self) -> &[Collation] {
+        &self.collations
+    }
+
+    pub(crate) fn schema(&self) -> &[Option<TableField>] {
+        &self.schema
+    }
+
+    pub(crate) fn rows(&self) -> &[Arc<Row>] {
+        &self.rows
+    }
+}
+
+/// Part of a materialized view whose rows each come from one row of `table`
+#[derive(Clone)]
+struct Branch {
+    query: Query,
+    table: TableName,
+    /// By the id of the row of the table they come from
+    rows: Arc<BTreeMap<RowId, Arc<Row>>>,
+}
+
+/// Rows of the branches of a view, coerced like those of `Query::Union`
+fn unified(fields: &[QueryField], rows: Vec<Arc<Row>>) -> Result<Vec<Arc<Row>>, ApplyError> {
+    let result = QueryResult::new(fields.to_vec(), rows).unified().map_err(ApplyError::QueryError)?;
+    Ok(result.shared_rows().to_vec())
+}
+
+/// Rows that changed between two versions of a table, by id: the ids of all
+/// of them, and those that still exist after the change
+fn changed_rows(old: &[Arc<Row>], new: &[Arc<Row>]) -> (Vec<RowId>, Vec<Arc<Row>>) {
+    let (mut ids, mut current) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let (old_id, new_id) = (old.get(i).and_then(|r| r.id()), new.get(j).and_then(|r| r.id()));
+        match (old_id, new_id) {
+            (Some(a), Some(b)) if a == b => {
+                if !Arc::ptr_eq(&old[i], &new[j]) {
+                    ids.push(a);
+                    current.push(new[j].clone());
+                }
+                i += 1;
+                j += 1;
+            },
+            // Rows are sorted by id, so the smaller one isn't in the other version
+            (Some(a), Some(b)) if a < b => {
+                ids.push(a);
+                i += 1;
+            },
+            (Some(a), None) => {
+                ids.push(a);
+                i += 1;
+            },
+            (_, Some(b)) => {
+                ids.push(b);
+                current.push(new[j].clone());
+                j += 1;
+            },
+            (None, None) => break,
+        }
+    }
+    (ids, current)
+}
+
+impl DataDB {
+    /// Store `query` as view `name`
+    ///
+    /// Since the query can only read tables and views that already exist,
+    /// views defined this way can't read themselves.
+    pub(crate) fn create_view(&mut self, name: TableName, query: Query) -> Result<(), ApplyError> {
+        self.check_view_query(&name, &query)?;
+        Arc::make_mut(&mut self.views).insert(name, query);
+        Ok(())
+    }
+
+    /// Store the rows of `query` as materialized view `name`
+    ///
+    /// The query can't read tables with row-level security policies, even
+    /// through views, since the stored rows are shared by all readers.
+    pub(crate) fn create_materialized_view(&mut self, name: TableName, query: Query) -> Result<(), ApplyError> {
+        self.check_view_query(&name, &query)?;
+        if let Some(table) = self.base_tables(&query).into_iter().find(|t| self.policy(t).is_some()) {
+            return Err(ApplyError::SecuredTable(table));
+        }
+        let view = self.materialize(&name, query)?;
+        Arc::make_mut(&mut self.materialized).insert(name, view);
+        Ok(())
+    }
+
+    /// Recompute the rows of materialized view `name`
+    pub(crate) fn refresh_view(&mut self, name: TableName) -> Result<(), ApplyError> {
+        let query = match self.materialized.get(&name) {
+            Some(view) => view.query.clone(),
+            None => return Err(ApplyError::NoSuchView(name)),
+        };
+        let view = self.materialize(&name, query)?;
+        Arc::make_mut(&mut self.materialized).insert(name, view);
+        Ok(())
+    }
+
+    pub(crate) fn drop_view(&mut self, name: TableName) -> Result<(), ApplyError> {
+        if !self.views.contains_key(&name) && !self.materialized.contains_key(&name) {
+            return Err(ApplyError::NoSuchView(name));
+        }
+        self.check_not_viewed(&name)?;
+        if self.views.contains_key(&name) {
+            Arc::make_mut(&mut self.views).remove(&name);
+        }
+        else {
+            Arc::make_mut(&mut self.materialized).remove(&name);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn view(&self, name: &str) -> Option<&Query> {
+        self.views.get(name)
+    }
+
+    pub(crate) fn materialized_view(&self, name: &str) -> Option<&MaterializedView> {
+        self.materialized.get(name)
+    }
+
+    /// Fail if a view reads table or view `name`, which then can't be dropped or renamed
+    pub(crate) fn check_not_viewed(&self, name: &TableName) -> Result<(), ApplyError> {
+        let queries = self.views.iter().chain(self.materialized.iter().map(|(view, m)| (view, &m.query)));
+        for (view, query) in queries {
+            if query.tables().contains(&name) {
+                return Err(ApplyError::TableInUse(name.clone(), view.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the tables `query` reads, directly or through views
+    pub(crate) fn base_tables(&self, query: &Query) -> Vec<TableName> {
+        let mut tables = Vec::new();
+        for name in query.tables() {
+            match (self.view(name), self.materialized_view(name)) {
+                (Some(view), _) => tables.extend(self.base_tables(view)),
+                (None, Some(view)) => tables.extend(self.base_tables(view.query())),
+                (None, None) => tables.push(name.clone()),
+            }
+        }
+        tables
+    }
+
+    /// Update the rows of the materialized views that can be maintained
+    /// incrementally, after changes since `before`
+    pub(crate) fn maintain_views(&mut self, before: &DataDB) -> Result<(), ApplyError> {
+        let names: Vec<TableName> = self.materialized.keys().cloned().collect();
+        for name in names {
+            if let Some(view) = self.maintained(&self.materialized[&name], before)? {
+                Arc::make_mut(&mut self.materialized).insert(name, view);
+            }
+        }
+        Ok(())
+    }
+
+    /// The view with rows updated after changes since `before`, none if none of them affect it
+    fn maintained(&self, view: &MaterializedView, before: &DataDB) -> Result<Option<MaterializedView>, ApplyError> {
+        let mut branches = match view.branches.clone() {
+            Some(branches) => branches,
+            None => return Ok(None),
+        };
+        let mut changed = false;
+        for branch in branches.iter_mut() {
+            let (old, new) = match (before.table_rows.get(&branch.table), self.table_rows.get(&branch.table)) {
+                (Some(old), Some(new)) if !Arc::ptr_eq(old, new) => (old, new),
+                _ => continue,
+            };
+            changed = true;
+            // Rows may no longer fit the query after schema changes
+            if before.table(branch.table.clone()) != self.table(branch.table.clone()) {
+                *branch = self.branch(&branch.query, &branch.table)?;
+                continue;
+            }
+            let (ids, current) = changed_rows(old, new);
+            let result = branch.query.execute_over(self, current).map_err(ApplyError::QueryError)?;
+            let rows = Arc::make_mut(&mut branch.rows);
+            for id in ids {
+                rows.remove(&id);
+            }
+            for row in result.shared_rows() {
+                rows.insert(row.id().expect("row-wise queries keep row ids"), row.clone());
+            }
+        }
+        if !changed {
+            return Ok(None);
+        }
+        let rows = unified(&view.fields, branches.iter().flat_map(|b| b.rows.values().cloned()).collect())?;
+        Ok(Some(MaterializedView {
+            query: view.query.clone(),
+            fields: view.fields.clone(),
+            collations: view.collations.clone(),
+            schema: view.schema.clone(),
+            branches: Some(branches),
+            rows: Arc::new(rows),
+        }))
+    }
+
+    /// Execute the query of materialized view `name`
+    fn materialize(&self, name: &TableName, query: Query) -> Result<MaterializedView, ApplyError> {
+        let result = query.execute(&ExecutionContext::new(self)).map_err(ApplyError::QueryError)?;
+        let fields: Vec<QueryField> = result.field_names().into_iter().map(|f| QueryField::new(f).from_table(name.clone())).collect();
+        let collations = result.collations();
+        let schema = result.schema();
+        let branches = match self.incremental_branches(&query) {
+            Some(branches) => Some(branches.into_iter().map(|(q, table)| self.branch(q, table)).collect::<Result<Vec<_>, _>>()?),
+            None => None,
+        };
+        let rows = match branches {
+            Some(ref branches) => unified(&fields, branches.iter().flat_map(|b| b.rows.values().cloned()).collect())?,
+            None => result.shared_rows().to_vec(),
+        };
+        Ok(MaterializedView { query, fields, collations, schema, branches, rows: Arc::new(rows) })
+    }
+
+    fn branch(&self, query: &Query, table: &TableName) -> Result<Branch, ApplyError> {
+        let all = self.all_rows(table.clone()).unwrap_or_default();
+        let result = query.execute_over(self, all).map_err(ApplyError::QueryError)?;
+        let rows = result.shared_rows().iter().map(|row| (row.id().expect("row-wise queries keep row ids"), row.clone())).collect();
+        Ok(Branch { query: query.clone(), table: table.clone(), rows: Arc::new(rows) })
+    }
+
+    /// Branches of the union the query is and the tables they read, if each
+    /// of them is row-wise over a table, see `Query::row_source`
+    fn incremental_branches<'a>(&self, query: &'a Query) -> Option<Vec<(&'a Query, &'a TableName)>> {
+        match query {
+            Query::Union(q1, q2) => {
+                let mut branches = self.incremental_branches(q1)?;
+                branches.extend(self.incremental_branches(q2)?);
+                Some(branches)
+            },
+            _ => query.row_source().filter(|t| self.table_index((*t).clone()).is_some()).map(|t| vec![(query, t)]),
+        }
+    }
+
+    /// Check that `name` is free and that `query` is valid for a view
+    fn check_view_query(&self, name: &TableName, query: &Query) -> Result<(), ApplyError> {
+        if self.table_index(name.clone()).is_some() || self.views.contains_key(name) || self.materialized.contains_key(name) {
+            return Err(ApplyError::TableExists(name.clone()));
+        }
+        if let Some(missing) = query.tables().into_iter().find(|t| !self.is_readable(t)) {
+            return Err(ApplyError::NoSuchTable(missing.clone()));
+        }
+        if let Some(temporary) = query.tables().into_iter().find(|t| self.is_temporary(t)) {
+            return Err(ApplyError::TemporaryTable(temporary.clone()));
+        }
+        query.check(self).map_err(ApplyError::QueryError)
+    }
+
+    /// Whether `Query::Table` can read `name`
+    fn is_readable(&self, name: &TableName) -> bool {
+        self.table_index(name.clone()).is_some()
+            || self.views.contains_key(name)
+            || self.materialized.contains_key(name)
+            || query::is_system_table(name)
+    }
+}